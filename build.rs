@@ -0,0 +1,40 @@
+use std::{path::Path, process::Command};
+
+/// records the commit and date of the build for `mail-cli --version`, so bug reports contain
+/// the exact build.
+///
+/// the date is taken from `SOURCE_DATE_EPOCH` or else the date of the commit, so building the
+/// same sources twice gives the same binary
+fn main() {
+    let commit = git(&["rev-parse", "--short", "HEAD"]).unwrap_or(String::from("unknown"));
+    let date = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<i64>().ok())
+        .and_then(|epoch| chrono::NaiveDateTime::from_timestamp_opt(epoch, 0))
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .or_else(|| git(&["log", "-1", "--format=%cs"]))
+        .unwrap_or(String::from("unknown"));
+
+    println!("cargo:rustc-env=MAIL_CLI_GIT_HASH={commit}");
+    println!("cargo:rustc-env=MAIL_CLI_BUILD_DATE={date}");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // rebuilt when a commit is made or checked out, not for every change. outside of a git
+    // checkout cargo's default of rerunning on any change in the package applies
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
+
+/// the trimmed output of `git` run with `args`, `None` if it fails or prints nothing
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_owned())
+        .filter(|output| !output.is_empty())
+}
@@ -0,0 +1,93 @@
+//! an opt-in log of token refreshes, to find out what refreshes them more often than expected,
+//! e.g. several processes running at once. token values are never written to it
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{config::Config, utils::get_data_dir_path};
+
+pub static AUDIT_LOG_FILE_NAME: &str = "refresh_audit.log";
+
+/// the file refreshes are appended to, one line each
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    /// the audit log in the data directory if `audit_refreshes` is enabled in `config`
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        match config.audit_refreshes {
+            true => Ok(Some(Self::at(
+                get_data_dir_path()?.join(AUDIT_LOG_FILE_NAME),
+            ))),
+            false => Ok(None),
+        }
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// appends a line recording that the token of `email` was refreshed at `now`, or why
+    /// refreshing it failed. the line is written at once so lines of processes refreshing at the
+    /// same time don't interleave
+    pub fn record<T>(
+        &self,
+        now: DateTime<Utc>,
+        email: &str,
+        outcome: &anyhow::Result<T>,
+    ) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(audit_line(now, email, std::process::id(), outcome).as_bytes())?;
+
+        Ok(())
+    }
+}
+
+fn audit_line<T>(now: DateTime<Utc>, email: &str, pid: u32, outcome: &anyhow::Result<T>) -> String {
+    let outcome = match outcome {
+        Ok(_) => String::from("ok"),
+        // on a single line, providers return multi-line error bodies
+        Err(err) => format!("failed: {}", format!("{err:#}").replace(['\r', '\n'], " ")),
+    };
+
+    format!(
+        "{time} refresh {email} pid={pid} {outcome}\n",
+        time = now.to_rfc3339_opts(SecondsFormat::Secs, true)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn formats_audit_lines() {
+        let now = Utc.with_ymd_and_hms(2023, 7, 3, 10, 0, 0).unwrap();
+
+        assert_eq!(
+            audit_line(now, "a@b.c", 42, &Ok(())),
+            "2023-07-03T10:00:00Z refresh a@b.c pid=42 ok\n"
+        );
+        assert_eq!(
+            audit_line::<()>(now, "a@b.c", 42, &Err(anyhow!("invalid_grant\nrevoked"))),
+            "2023-07-03T10:00:00Z refresh a@b.c pid=42 failed: invalid_grant revoked\n"
+        );
+    }
+}
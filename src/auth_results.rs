@@ -0,0 +1,131 @@
+use std::fmt::Display;
+
+/// the SPF, DKIM and DMARC verdicts the receiving server recorded for a mail
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthResults {
+    pub spf: Option<String>,
+    pub dkim: Option<String>,
+    pub dmarc: Option<String>,
+}
+
+impl AuthResults {
+    /// parses the values of the `Authentication-Results` (RFC 8601) and `Received-SPF` headers.
+    ///
+    /// only the first (topmost) `Authentication-Results` header is used since it was added by the
+    /// server that delivered the mail, the ones further down could have been added by the sender.
+    /// `Received-SPF` is used if no SPF result was found there
+    pub fn parse<'a>(
+        authentication_results: impl IntoIterator<Item = &'a str>,
+        received_spf: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let mut results = Self::default();
+
+        if let Some(header) = authentication_results.into_iter().next() {
+            // the first element is the id of the server that checked the mail
+            for result in header.split(';').skip(1) {
+                let Some((method, verdict)) = result.trim().split_once('=') else {
+                    continue;
+                };
+                let verdict = verdict
+                    .split(|c: char| c.is_whitespace() || c == '(')
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+
+                let field = match method.trim().to_ascii_lowercase().as_str() {
+                    "spf" => &mut results.spf,
+                    "dkim" => &mut results.dkim,
+                    "dmarc" => &mut results.dmarc,
+                    _ => continue,
+                };
+
+                // a mail can have multiple DKIM signatures, one passing one is enough
+                if field.as_deref() != Some("pass") {
+                    *field = Some(verdict);
+                }
+            }
+        }
+
+        if results.spf.is_none() {
+            results.spf = received_spf
+                .into_iter()
+                .next()
+                .and_then(|header| header.split_whitespace().next())
+                .map(|verdict| verdict.to_ascii_lowercase());
+        }
+
+        results
+    }
+
+    /// whether all recorded checks passed, `false` if none were recorded
+    pub fn all_pass(&self) -> bool {
+        let verdicts = [&self.spf, &self.dkim, &self.dmarc];
+
+        verdicts.iter().any(|verdict| verdict.is_some())
+            && verdicts
+                .iter()
+                .flat_map(|verdict| verdict.as_deref())
+                .all(|verdict| verdict == "pass")
+    }
+}
+
+impl Display for AuthResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verdict = |verdict: &Option<String>| verdict.clone().unwrap_or(String::from("none"));
+        let indicator = if self.all_pass() { "✓" } else { "✗" };
+
+        write!(
+            f,
+            "{indicator} SPF {spf}, DKIM {dkim}, DMARC {dmarc}",
+            spf = verdict(&self.spf),
+            dkim = verdict(&self.dkim),
+            dmarc = verdict(&self.dmarc)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gmail_results() {
+        let header = "mx.google.com; dkim=pass header.i=@example.com header.s=s1; \
+            spf=pass (google.com: domain of a@example.com designates 1.2.3.4 as permitted sender) \
+            smtp.mailfrom=a@example.com; dmarc=pass (p=REJECT sp=REJECT dis=NONE) \
+            header.from=example.com";
+        let results = AuthResults::parse([header], []);
+
+        assert_eq!(results.spf.as_deref(), Some("pass"));
+        assert_eq!(results.dkim.as_deref(), Some("pass"));
+        assert_eq!(results.dmarc.as_deref(), Some("pass"));
+        assert!(results.all_pass());
+        assert_eq!(results.to_string(), "✓ SPF pass, DKIM pass, DMARC pass");
+    }
+
+    #[test]
+    fn parses_failures() {
+        let header = "mx.example.com;\r\n dkim=fail reason=\"signature verification failed\";\
+            \r\n dkim=neutral; spf=SoftFail smtp.mailfrom=b.example; dmarc=fail";
+        let results = AuthResults::parse([header, "evil.example; spf=pass; dmarc=pass"], []);
+
+        assert_eq!(results.spf.as_deref(), Some("softfail"));
+        assert_eq!(results.dkim.as_deref(), Some("neutral"));
+        assert_eq!(results.dmarc.as_deref(), Some("fail"));
+        assert!(!results.all_pass());
+    }
+
+    #[test]
+    fn falls_back_to_received_spf() {
+        let results = AuthResults::parse(
+            ["mx.example.com; dkim=none"],
+            ["Fail (mx.example.com: domain does not designate 1.2.3.4)"],
+        );
+
+        assert_eq!(results.spf.as_deref(), Some("fail"));
+        assert_eq!(
+            AuthResults::parse([], []).to_string(),
+            "✗ SPF none, DKIM none, DMARC none"
+        );
+    }
+}
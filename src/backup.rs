@@ -0,0 +1,229 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use imap::Session;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    mail::{OpenMode, DEFAULT_FETCH_BATCH_SIZE},
+    progress::Progress,
+    utils::{file_name_safe, write_atomically},
+};
+
+pub static MANIFEST_FILE_NAME: &str = "manifest.toml";
+
+/// records which mails of a mailbox were already exported to a backup directory.
+///
+/// UIDs are only meaningful together with the mailbox's UIDVALIDITY, if the server changes it
+/// all mails have to be exported again
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub uid_validity: u32,
+    pub uids: BTreeSet<u32>,
+}
+
+/// the manifests of all mailboxes backed up to a directory, by mailbox name
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Manifests(BTreeMap<String, Manifest>);
+
+impl Manifests {
+    /// loads the manifests in `dir`, empty if nothing was exported there yet
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(dir.join(MANIFEST_FILE_NAME)) {
+            Ok(data) => Ok(toml::from_str(&data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn store(&self, dir: &Path) -> anyhow::Result<()> {
+        write_atomically(
+            &dir.join(MANIFEST_FILE_NAME),
+            toml::to_string_pretty(self)?.as_bytes(),
+        )
+    }
+
+    pub fn get(&self, mailbox: &str) -> Option<&Manifest> {
+        self.0.get(mailbox)
+    }
+
+    pub fn insert(&mut self, mailbox: &str, manifest: Manifest) {
+        self.0.insert(mailbox.to_owned(), manifest);
+    }
+}
+
+impl Manifest {
+    /// the UIDs in `server_uids` that still have to be exported, in ascending order.
+    ///
+    /// returns all of them if the manifest was recorded for another UIDVALIDITY
+    pub fn missing_uids(&self, uid_validity: u32, server_uids: &[u32]) -> Vec<u32> {
+        server_uids
+            .iter()
+            .filter(|uid| self.uid_validity != uid_validity || !self.uids.contains(uid))
+            .copied()
+            .sorted()
+            .collect()
+    }
+}
+
+/// the directory the mails of `mailbox` with `uid_validity` are exported to, so neither other
+/// mailboxes nor a changed UIDVALIDITY mix mails with the same UID
+pub fn mails_dir(out_dir: &Path, mailbox: &str, uid_validity: u32) -> PathBuf {
+    out_dir
+        .join(file_name_safe(mailbox))
+        .join(uid_validity.to_string())
+}
+
+/// exports all mails of `mailbox` that weren't exported to `out_dir` yet as `<uid>.eml` files.
+///
+/// the manifest is updated after every batch, so an interrupted backup continues where it
+/// stopped. other mailboxes backed up to `out_dir` keep their own manifest entries. returns the
+/// number of exported mails
+pub fn backup_mailbox<T: Read + Write>(
+    session: &mut Session<T>,
+    mailbox: &str,
+    out_dir: &Path,
+    progress: Option<Progress>,
+) -> anyhow::Result<usize> {
+    let uid_validity = OpenMode::ReadOnly
+        .open(mailbox, session)?
+        .uid_validity
+        .ok_or(anyhow!(
+            "the server didn't report a UIDVALIDITY for '{mailbox}'"
+        ))?;
+
+    let server_uids = session.uid_search("ALL")?.into_iter().collect::<Vec<_>>();
+    let mut manifests = Manifests::load(out_dir)?;
+    let mut manifest = match manifests.get(mailbox) {
+        Some(manifest) if manifest.uid_validity == uid_validity => manifest.clone(),
+        _ => Manifest {
+            uid_validity,
+            uids: BTreeSet::new(),
+        },
+    };
+
+    let missing = manifest.missing_uids(uid_validity, &server_uids);
+    let dir = mails_dir(out_dir, mailbox, uid_validity);
+    fs::create_dir_all(&dir)?;
+    if let Some(progress) = &progress {
+        progress.add_total(missing.len());
+    }
+
+    let mut exported = 0;
+    for batch in missing.chunks(DEFAULT_FETCH_BATCH_SIZE) {
+        let items = session.uid_fetch(batch.iter().join(","), "BODY.PEEK[]")?;
+        for item in items.iter() {
+            let (Some(uid), Some(body)) = (item.uid, item.body()) else {
+                continue;
+            };
+
+            write_atomically(&dir.join(format!("{uid}.eml")), body)?;
+            manifest.uids.insert(uid);
+            exported += 1;
+            if let Some(progress) = &progress {
+                progress.inc();
+            }
+        }
+
+        manifests.insert(mailbox, manifest.clone());
+        manifests.store(out_dir)?;
+    }
+
+    if let Some(progress) = &progress {
+        progress.clear();
+    }
+
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_imap::{mock_session, MockMail};
+
+    #[test]
+    fn diffs_against_manifest() {
+        let manifest = Manifest {
+            uid_validity: 7,
+            uids: BTreeSet::from([1, 2, 4]),
+        };
+
+        assert_eq!(manifest.missing_uids(7, &[5, 1, 2, 3, 4]), vec![3, 5]);
+        assert_eq!(manifest.missing_uids(7, &[1, 2]), Vec::<u32>::new());
+        assert_eq!(manifest.missing_uids(8, &[2, 1]), vec![1, 2]);
+        assert_eq!(Manifest::default().missing_uids(0, &[1]), vec![1]);
+    }
+
+    #[test]
+    fn exports_only_new_mails() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = |subject: &str| MockMail::new(&format!("Subject: {subject}\n\nbody\n"));
+        let (mut session, _) = mock_session(vec![("INBOX", vec![mail("a"), mail("b")])]);
+
+        assert_eq!(
+            backup_mailbox(&mut session, "INBOX", dir.path(), None).unwrap(),
+            2
+        );
+        assert_eq!(
+            backup_mailbox(&mut session, "INBOX", dir.path(), None).unwrap(),
+            0
+        );
+
+        let manifests = Manifests::load(dir.path()).unwrap();
+        let manifest = manifests.get("INBOX").unwrap();
+        let exported =
+            fs::read_to_string(mails_dir(dir.path(), "INBOX", manifest.uid_validity).join("2.eml"));
+        assert_eq!(manifest.uids, BTreeSet::from([1, 2]));
+        assert!(exported.unwrap().starts_with("Subject: b"));
+    }
+
+    #[test]
+    fn keeps_mailboxes_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mail = |subject: &str| MockMail::new(&format!("Subject: {subject}\n\nbody\n"));
+        let (mut session, _) = mock_session(vec![
+            ("INBOX", vec![mail("a"), mail("b")]),
+            ("Lists/rust", vec![mail("c")]),
+            ("Lists_rust", vec![mail("d")]),
+        ]);
+
+        assert_eq!(
+            backup_mailbox(&mut session, "INBOX", dir.path(), None).unwrap(),
+            2
+        );
+        assert_eq!(
+            backup_mailbox(&mut session, "Lists/rust", dir.path(), None).unwrap(),
+            1
+        );
+        assert_eq!(
+            backup_mailbox(&mut session, "Lists_rust", dir.path(), None).unwrap(),
+            1
+        );
+        assert_eq!(
+            backup_mailbox(&mut session, "INBOX", dir.path(), None).unwrap(),
+            0
+        );
+
+        let manifests = Manifests::load(dir.path()).unwrap();
+        assert_eq!(manifests.get("INBOX").unwrap().uids, BTreeSet::from([1, 2]));
+        assert_eq!(
+            manifests.get("Lists/rust").unwrap().uids,
+            BTreeSet::from([1])
+        );
+
+        let read = |mailbox: &str| {
+            fs::read_to_string(mails_dir(dir.path(), mailbox, 1).join("1.eml")).unwrap()
+        };
+        assert!(read("INBOX").starts_with("Subject: a"));
+        // both are "Lists_rust" once the delimiter is replaced
+        assert!(read("Lists/rust").starts_with("Subject: c"));
+        assert!(read("Lists_rust").starts_with("Subject: d"));
+    }
+}
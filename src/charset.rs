@@ -0,0 +1,106 @@
+//! transcoding of printed mails for terminals that don't use UTF-8, e.g. legacy Windows consoles
+
+use std::{
+    fmt::Display,
+    io::{stdout, Write},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use encoding_rs::{EncoderResult, Encoding, UTF_8};
+
+/// the charset mails are printed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputCharset(&'static Encoding);
+
+impl Default for OutputCharset {
+    fn default() -> Self {
+        Self(UTF_8)
+    }
+}
+
+impl FromStr for OutputCharset {
+    type Err = anyhow::Error;
+
+    /// any label of the WHATWG encoding standard, e.g. `windows-1252`, `latin1` or `shift_jis`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoding =
+            Encoding::for_label(s.trim().as_bytes()).ok_or(anyhow!("unknown charset '{s}'"))?;
+        // UTF-16 can only be decoded, encoding_rs would silently output UTF-8 instead
+        match encoding.output_encoding() == encoding {
+            true => Ok(Self(encoding)),
+            false => Err(anyhow!("printing as {} isn't supported", encoding.name())),
+        }
+    }
+}
+
+impl Display for OutputCharset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.name())
+    }
+}
+
+impl OutputCharset {
+    /// `text` in the charset, characters it can't represent are replaced with `?`
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        if self.0 == UTF_8 {
+            return text.as_bytes().to_vec();
+        }
+
+        let mut encoder = self.0.new_encoder();
+        let mut out = Vec::with_capacity(text.len());
+        let mut rest = text;
+        loop {
+            let capacity = encoder
+                .max_buffer_length_from_utf8_without_replacement(rest.len())
+                .unwrap_or(rest.len() * 4);
+            out.reserve(capacity);
+            let mut buf = vec![0; capacity];
+            let (result, read, written) =
+                encoder.encode_from_utf8_without_replacement(rest, &mut buf, true);
+            out.extend_from_slice(&buf[..written]);
+            rest = &rest[read..];
+
+            match result {
+                EncoderResult::InputEmpty => return out,
+                EncoderResult::Unmappable(_) => out.push(b'?'),
+                EncoderResult::OutputFull => {}
+            }
+        }
+    }
+
+    /// prints `text` to stdout in the charset
+    pub fn print(&self, text: impl Display) -> std::io::Result<()> {
+        let mut stdout = stdout().lock();
+        stdout.write_all(&self.encode(&text.to_string()))?;
+        stdout.flush()
+    }
+
+    /// prints `text` and a newline to stdout in the charset
+    pub fn println(&self, text: impl Display) -> std::io::Result<()> {
+        self.print(format!("{text}\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcodes_to_legacy_charsets() {
+        let latin1 = "latin1".parse::<OutputCharset>().unwrap();
+        assert_eq!(latin1.to_string(), "windows-1252");
+        assert_eq!(latin1.encode("Grüße, 5 €"), b"Gr\xfc\xdfe, 5 \x80");
+        // not in windows-1252
+        assert_eq!(latin1.encode("Привет ok"), b"?????? ok");
+
+        let sjis = "Shift_JIS".parse::<OutputCharset>().unwrap();
+        assert_eq!(sjis.encode("日本"), b"\x93\xfa\x96\x7b");
+
+        let utf8 = OutputCharset::default();
+        assert_eq!(utf8.encode("Grüße"), "Grüße".as_bytes());
+
+        assert!("utf-16le".parse::<OutputCharset>().is_err());
+        assert!("klingon".parse::<OutputCharset>().is_err());
+    }
+}
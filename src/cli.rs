@@ -1,20 +1,57 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::HashMap, fmt::Display, io::BufRead, path::PathBuf};
 
-use clap::{Parser, Subcommand};
+use anyhow::anyhow;
+use clap::{Args, Parser, Subcommand};
 use colored::Colorize;
 use dialoguer::{theme::ColorfulTheme, Completion, Input, Select};
-use reqwest::Client;
+use itertools::Itertools;
 
 use crate::{
-    google::{request_google_oauth_token, GoogleOAuthParams, GoogleOAuthTokenRequestResponse},
-    store_accounts::{StoredAccountData, StoredAccounts},
+    charset::OutputCharset,
+    google::{
+        poll_google_device_token, request_google_device_code, request_google_oauth_token,
+        GoogleOAuthParams, GoogleOAuthTokenRequestResponse,
+    },
+    mail::{
+        flags_legend, header_table, DisplayOptions, FromStyle, Mail, MailField, OutputFormat,
+        SortBy,
+    },
+    mail_filters::{MaxAge, Since},
+    mailboxes::MailboxRole,
+    provider::Provider,
+    proxy::http_client,
+    store_accounts::{AccountsCorrupted, StoredAccountData, StoredAccounts},
+    unsubscribe::Unsubscribe,
+    yahoo::{request_yahoo_oauth_token, YahooOAuthParams, YahooOAuthTokenResponse},
 };
 
+/// the crate version with the commit and date of the build, set by `build.rs`
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("MAIL_CLI_GIT_HASH"),
+    ", built ",
+    env!("MAIL_CLI_BUILD_DATE"),
+    ")"
+);
+
 #[derive(Debug, Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version = VERSION, about, long_about = None)]
 pub struct CliArgs {
     #[command(subcommand)]
     pub command: Commands,
+    /// connect through a proxy, `socks5://[user:password@]host[:port]` or
+    /// `http://[user:password@]host[:port]`, defaults to the `ALL_PROXY` environment variable
+    #[arg(long, global = true)]
+    pub proxy: Option<String>,
+    /// never write refreshed tokens back to the accounts file, e.g. if it's mounted read-only.
+    /// they are only kept until the command finishes, so the next run has to refresh them again
+    #[arg(long, global = true)]
+    pub read_only_config: bool,
+    /// the charset mails are printed in, for terminals that don't use UTF-8. characters it
+    /// can't represent are printed as `?`. JSON output is always UTF-8
+    #[arg(long, global = true, default_value_t)]
+    pub output_charset: OutputCharset,
 }
 
 #[derive(Debug, Subcommand)]
@@ -23,21 +60,558 @@ pub enum Commands {
     Login {
         /// the mail address of the account you want to login to
         email: String,
+        /// the provider of the account
+        #[arg(long, value_enum, default_value_t)]
+        provider: Provider,
+        /// log in by entering a code on another device, for machines without a browser (only
+        /// supported for Gmail)
+        #[arg(long, conflicts_with = "app_password")]
+        device: bool,
+        /// log in with an app password generated in the account settings instead of OAuth,
+        /// for accounts that don't allow OAuth logins. the password is prompted for without
+        /// being shown
+        #[arg(long)]
+        app_password: bool,
+        /// read the app password from the first line of stdin instead of prompting for it,
+        /// e.g. to pipe it in from a password manager
+        #[arg(long, requires = "app_password")]
+        password_stdin: bool,
+    },
+    #[command(about = "log in to a stored account again, e.g. to grant it other scopes")]
+    Reauth {
+        /// the mail address of the stored account
+        email: String,
+        /// the OAuth scopes to request instead of the provider's default full mail access
+        #[arg(long)]
+        scope: Option<String>,
+        /// log in by entering a code on another device (only supported for Gmail)
+        #[arg(long)]
+        device: bool,
+    },
+    #[command(about = "print the URL of the consent page used to log in, without logging in")]
+    AuthUrl {
+        /// the mail address of the account you want to login to
+        email: String,
+        /// the provider of the account
+        #[arg(long, value_enum, default_value_t)]
+        provider: Provider,
+    },
+    #[command(about = "show whether the stored logins are still valid, without prompting")]
+    Status {
+        /// the format the status of the accounts is printed in
+        #[arg(long, value_enum, default_value_t)]
+        format: StatusFormat,
+    },
+    #[command(about = "list the mailboxes of an account with their number of unread mails")]
+    Mailboxes {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        /// maximum number of connections the unread counts are fetched over at the same time
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// fetch the unread counts again even if the last listing was less than a minute ago
+        #[arg(long)]
+        refresh: bool,
     },
     #[command(about = "read mails")]
-    Read {
-        /// number of mails to read
-        n: usize,
+    Read(Box<ReadArgs>),
+    #[command(about = "read mails with the arguments of a search saved in the config file")]
+    Saved {
+        /// the name of the search in the `searches` table of `config.toml`
+        name: String,
+        /// further `read` arguments, added after the saved ones
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    #[command(about = "summarize the mails received since a date")]
+    Digest {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox to summarize
+        mailbox: String,
+        /// only include mails received on or after this date (YYYY-MM-DD) or the day of a time
+        /// span ago (e.g. "7d"), defaults to yesterday
+        #[arg(short, long)]
+        since: Option<Since>,
+        /// print the digest as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    #[command(about = "star mails (set the \\Flagged flag)")]
+    Star {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox containing the mails
+        mailbox: String,
+        /// UIDs of the mails to star
+        #[arg(required = true)]
+        ids: Vec<u32>,
+        /// ask for each mail whether to star it, showing its sender and subject
+        #[arg(long)]
+        confirm_each: bool,
+    },
+    #[command(about = "unstar mails (clear the \\Flagged flag)")]
+    Unstar {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox containing the mails
+        mailbox: String,
+        /// UIDs of the mails to unstar
+        #[arg(required = true)]
+        ids: Vec<u32>,
+        /// ask for each mail whether to unstar it, showing its sender and subject
+        #[arg(long)]
+        confirm_each: bool,
+    },
+    #[command(
+        about = "archive mails (remove the Inbox label on Gmail, move to Archive elsewhere)"
+    )]
+    Archive {
         /// optional mail, if not set you will be prompted to select from the list of logged in
         /// accounts
-        /// if the mail you selected is not a logged in account the program will exist with a
-        /// failure
         #[arg(short, long)]
         mail: Option<String>,
         #[arg(short = 'b', long, default_value = "INBOX")]
-        /// the mailbox to read from
+        /// the mailbox containing the mails
         mailbox: String,
+        /// UIDs of the mails to archive
+        #[arg(required = true)]
+        ids: Vec<u32>,
+        /// ask for each mail whether to archive it, showing its sender and subject
+        #[arg(long)]
+        confirm_each: bool,
     },
+    #[command(about = "move mails to the Trash")]
+    Trash {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox containing the mails
+        mailbox: String,
+        /// UIDs of the mails to move to the Trash
+        #[arg(required = true)]
+        ids: Vec<u32>,
+        /// ask for each mail whether to move it to the Trash, showing its sender and subject
+        #[arg(long)]
+        confirm_each: bool,
+    },
+    #[command(about = "delete mails by moving them to the Trash, from where they can be restored")]
+    Delete {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox containing the mails
+        mailbox: String,
+        /// UIDs of the mails to delete
+        #[arg(required = true)]
+        ids: Vec<u32>,
+        /// remove the mails for good instead, after asking. mails in the Trash are always
+        /// removed for good. on Gmail this may only archive them, depending on its IMAP settings
+        #[arg(long, alias = "expunge")]
+        permanent: bool,
+        /// ask for each mail whether to delete it, showing its sender and subject
+        #[arg(long)]
+        confirm_each: bool,
+    },
+    #[command(about = "move mails from the Trash back to another mailbox")]
+    Restore {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        /// UIDs of the mails in the Trash
+        #[arg(required = true)]
+        ids: Vec<u32>,
+        /// the mailbox to move the mails to
+        #[arg(short, long, default_value = "INBOX")]
+        to: String,
+        /// ask for each mail whether to restore it, showing its sender and subject
+        #[arg(long)]
+        confirm_each: bool,
+    },
+    #[command(about = "permanently remove the mails marked as deleted from a mailbox")]
+    Expunge {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox to remove the mails from
+        mailbox: String,
+        /// only show how many mails would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    #[command(about = "permanently delete all mails in the Trash, or the spam mailbox")]
+    EmptyTrash {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        /// empty the mailbox the server marks as junk instead
+        #[arg(long)]
+        spam: bool,
+        /// only show how many mails would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    #[command(about = "unsubscribe from the mailing list a mail was sent by")]
+    Unsubscribe {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox containing the mail
+        mailbox: String,
+        /// UID of the mail
+        id: u32,
+    },
+    #[command(
+        about = "print a reply to the sender and all recipients of a mail, to edit and send \
+        with a mail client"
+    )]
+    ReplyAll {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox containing the mail
+        mailbox: String,
+        /// UID of the mail
+        id: u32,
+    },
+    #[command(
+        about = "print a message forwarding a mail with the original attached, to edit and \
+        send with a mail client"
+    )]
+    Forward {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox containing the mail
+        mailbox: String,
+        /// UID of the mail
+        id: u32,
+        /// the addresses to forward to
+        #[arg(short, long, required = true)]
+        to: Vec<String>,
+    },
+    #[command(
+        about = "save a new mail as a draft, to finish and send with a mail client. the body is \
+        read from stdin unless given with `--body`"
+    )]
+    SaveDraft {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long)]
+        /// the mailbox to save the draft to, the one the server marks as drafts if not set
+        mailbox: Option<String>,
+        /// the addresses to send the mail to
+        #[arg(short, long)]
+        to: Vec<String>,
+        /// the addresses to send a copy to
+        #[arg(short, long)]
+        cc: Vec<String>,
+        #[arg(short, long, default_value = "")]
+        subject: String,
+        /// the text of the mail
+        #[arg(long)]
+        body: Option<String>,
+    },
+    #[command(
+        about = "copy a mail to a mailbox of another account, with its flags and the date it \
+        was received"
+    )]
+    Transfer {
+        /// the account to copy from, if not set you will be prompted to select from the list of
+        /// logged in accounts
+        #[arg(long)]
+        src_mail: Option<String>,
+        #[arg(long, default_value = "INBOX")]
+        /// the mailbox containing the mail
+        src_mailbox: String,
+        /// UID of the mail
+        id: u32,
+        /// the account to copy to
+        #[arg(long)]
+        dest_mail: String,
+        #[arg(long, default_value = "INBOX")]
+        /// the mailbox to copy the mail to
+        dest_mailbox: String,
+    },
+    #[command(about = "show the mail with a Message-ID")]
+    ShowId {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox to search in
+        mailbox: String,
+        /// the Message-ID, with or without angle brackets
+        message_id: String,
+    },
+    #[command(about = "print new mails as they arrive")]
+    Watch {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox to watch
+        mailbox: String,
+        /// seconds between polls and reconnection attempts, at least 10
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+    },
+    #[command(
+        about = "print new mails as they arrive, polling less often while none do. continues \
+        after the last mail the previous run saw"
+    )]
+    Poll {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox to poll
+        mailbox: String,
+        /// seconds between polls while mails arrive, at least 10. doubles after each poll
+        /// without new mails
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+        /// the most seconds between two polls
+        #[arg(long, default_value_t = 900)]
+        max_interval: u64,
+    },
+    #[command(about = "show which user an account logs in as and the server it connects to")]
+    Whoami {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+    },
+    #[command(about = "list the IMAP extensions the server of an account supports")]
+    Capabilities {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+    },
+    #[command(about = "export all mails of a mailbox as .eml files, only new mails on re-runs")]
+    Backup {
+        /// optional mail, if not set you will be prompted to select from the list of logged in
+        /// accounts
+        #[arg(short, long)]
+        mail: Option<String>,
+        #[arg(short = 'b', long, default_value = "INBOX")]
+        /// the mailbox to back up
+        mailbox: String,
+        /// the directory to export the mails to
+        #[arg(short, long)]
+        output_dir: PathBuf,
+        /// don't show a progress indicator while exporting
+        #[arg(short, long)]
+        quiet: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct ReadArgs {
+    /// number of mails to read, defaults to `mail_count` from the config file or 10
+    pub n: Option<usize>,
+    /// optional mail, if not set you will be prompted to select from the list of logged in
+    /// accounts
+    /// if the mail you selected is not a logged in account the program will exist with a
+    /// failure
+    #[arg(short, long)]
+    pub mail: Option<String>,
+    #[arg(short = 'b', long, default_value = "INBOX")]
+    /// the mailbox to read from, can be given multiple times to read the `n` most recent
+    /// mails of each mailbox
+    pub mailbox: Vec<String>,
+    /// read from the mailbox with this role instead, e.g. the sent mails wherever the provider
+    /// keeps them
+    #[arg(long, value_enum, conflicts_with = "mailbox")]
+    pub role: Option<MailboxRole>,
+    /// truncate the displayed body of each mail after this many bytes
+    #[arg(long)]
+    pub limit_body_bytes: Option<usize>,
+    /// only show the first n lines of each body
+    #[arg(long, value_name = "N")]
+    pub head: Option<usize>,
+    /// only show the last n lines of each body
+    #[arg(long, value_name = "N", conflicts_with = "head")]
+    pub tail: Option<usize>,
+    /// only show mails for which this shell command exits successfully when given the raw mail
+    /// on stdin (e.g. "grep -qi invoice"), run once per mail. only gets the headers with
+    /// `--no-body`
+    #[arg(long)]
+    pub filter_command: Option<String>,
+    /// how the sender is shown
+    #[arg(long, value_enum, default_value_t)]
+    pub from_style: FromStyle,
+    /// what the `n` most recent mails are picked by, `arrival` is faster on large mailboxes
+    #[arg(long, value_enum, default_value_t)]
+    pub sort_by: SortBy,
+    /// group the mails by thread, uses Gmail's thread ids when available
+    #[arg(long)]
+    pub threads: bool,
+    /// only read mails with this Gmail label
+    #[arg(long)]
+    pub label: Option<String>,
+    /// only read mails matching this query in Gmail's search syntax
+    /// (e.g. "has:attachment larger:5M from:boss")
+    #[arg(long)]
+    pub gmail_search: Option<String>,
+    /// only read mails sent after this date (YYYY-MM-DD) or time span ago (e.g. "30m", "24h",
+    /// "7d", "2w"), with `--count-only` only whole days are compared
+    #[arg(long)]
+    pub since: Option<Since>,
+    /// leave out mails older than this time span (e.g. "30d") even if they are among the `n`
+    /// most recent, defaults to `max_age` from the config file
+    #[arg(long)]
+    pub max_age: Option<MaxAge>,
+    /// only read flagged (starred) mails
+    #[arg(long, alias = "flagged-only")]
+    pub flagged: bool,
+    /// only read mails with a subject containing this text, matches are highlighted
+    #[arg(long)]
+    pub subject: Option<String>,
+    /// only read mails with a sender containing this text, matches are highlighted
+    #[arg(long)]
+    pub from: Option<String>,
+    /// only read mails containing this text in their headers or body, matches are
+    /// highlighted
+    #[arg(long)]
+    pub text: Option<String>,
+    /// don't color the output, e.g. the highlighted matches
+    #[arg(long)]
+    pub no_color: bool,
+    /// don't show a progress indicator while fetching
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// strftime style format of the send date (e.g. "%Y-%m-%d %H:%M"),
+    /// defaults to `date_format` from the config file
+    #[arg(long)]
+    pub date_format: Option<String>,
+    /// show dates in the local timezone instead of the offset used by the sender
+    #[arg(long, conflicts_with = "utc")]
+    pub local: bool,
+    /// show dates in UTC instead of the offset used by the sender
+    #[arg(long)]
+    pub utc: bool,
+    /// show dates of the last week relative to now, e.g. "2 hours ago" or "yesterday"
+    #[arg(long)]
+    pub relative_dates: bool,
+    /// only show the first of multiple mails with the same Message-ID, e.g. a mail that is
+    /// in multiple Gmail labels
+    #[arg(long)]
+    pub dedup: bool,
+    /// also show the header with this name (e.g. "Authentication-Results"), can be given
+    /// multiple times
+    #[arg(long, conflicts_with = "all_headers")]
+    pub show_header: Vec<String>,
+    /// show all headers of the mails
+    #[arg(long)]
+    pub all_headers: bool,
+    /// show whether the mails passed the SPF, DKIM and DMARC checks of your provider
+    #[arg(long)]
+    pub show_auth: bool,
+    /// render HTML mails with formatting and their links as numbered footnotes
+    #[arg(long)]
+    pub rich: bool,
+    /// show the HTML part of mails that also have a plain text one, converted to text. the
+    /// plain text part is shown by default
+    #[arg(long)]
+    pub prefer_html: bool,
+    /// the format the mails are displayed in
+    #[arg(long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+    /// comma separated fields included in the JSON output (e.g. "from,subject,date"), all by
+    /// default
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub fields: Vec<MailField>,
+    /// only fetch the headers and print a table of the send date, sender and subject of the
+    /// mails
+    #[arg(long, conflicts_with_all = ["threads", "format", "rich", "structure"])]
+    pub no_body: bool,
+    /// show the mails the same `--no-body` query found when it last ran, without connecting.
+    /// queries older than `summary_cache_ttl` are dropped whenever another one is fetched
+    #[arg(long, requires = "no_body", conflicts_with_all = ["role", "no_peek", "count_only"])]
+    pub offline: bool,
+    /// show the MIME part tree of the mails (content types, sizes, encodings and filenames)
+    /// instead of their body
+    #[arg(long)]
+    pub structure: bool,
+    /// draw the image parts of the mails below them in terminals supporting the kitty or iTerm2
+    /// graphics protocol, other terminals and formats they can't draw get a list of the images
+    #[arg(long, conflicts_with_all = ["no_body", "threads", "format", "structure", "browse"])]
+    pub inline_images: bool,
+    /// show the messages attached to the mails, e.g. forwarded mails or the original of a
+    /// bounce, in nested blocks after their body
+    #[arg(long, conflicts_with = "no_body")]
+    pub nested: bool,
+    /// save the messages attached to the mails to this directory as `<mailbox>-<uid>-<n>.eml`
+    /// files, where `<mailbox>` ends with a hash of the mailbox name. existing files aren't
+    /// overwritten
+    #[arg(long, value_name = "DIR", conflicts_with = "no_body")]
+    pub extract_nested: Option<PathBuf>,
+    /// fetch the mails with `BODY.PEEK`, which leaves their `\Seen` flag unchanged so they stay
+    /// unread in other mail clients, from mailboxes opened read-only with `EXAMINE`. this is the
+    /// default
+    #[arg(long, overrides_with = "no_peek")]
+    pub peek: bool,
+    /// fetch the mails with `BODY`, which marks them as seen like opening them in a mail client
+    /// would
+    #[arg(long, alias = "mark-seen", overrides_with = "peek")]
+    pub no_peek: bool,
+    /// print how much data was downloaded
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// choose one of the links in the displayed mails to open in the browser
+    #[arg(long)]
+    pub open_links: bool,
+    /// only print the number of mails matching the filters (ignoring `n`), without fetching
+    /// them
+    #[arg(long)]
+    pub count_only: bool,
+    /// list the mails and pick the ones to read by their number, until the input is empty
+    #[arg(long, conflicts_with_all = ["no_body", "threads", "format", "count_only"])]
+    pub browse: bool,
+    /// maximum number of mailboxes read at the same time, each with its own connection. kept
+    /// low by default because providers limit the number of simultaneous connections
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatusFormat {
+    /// one line per account
+    #[default]
+    Text,
+    Json,
 }
 
 pub struct CompletionOptions<'a>(Vec<&'a str>);
@@ -58,10 +632,17 @@ impl<'a> Completion for CompletionOptions<'a> {
     }
 }
 
-/// at the moment this function creates its own client and auth parameters (specifically for
-/// google/gmail), in the future when there are multiple email providers supported these should
-/// be passed in as function parameters
-pub async fn add_new_account(email: String, accounts: &mut StoredAccounts) -> anyhow::Result<()> {
+/// logs in to the account with the mail address `email` at `provider` and stores it, either with
+/// an app password (prompted for without echoing, or read from stdin) or through the provider's
+/// OAuth flow
+pub async fn add_new_account(
+    email: String,
+    accounts: &mut StoredAccounts,
+    provider: Provider,
+    device: bool,
+    app_password: bool,
+    password_stdin: bool,
+) -> anyhow::Result<()> {
     if accounts.stored_accounts().contains_key(&email) {
         let selection = Select::with_theme(&ColorfulTheme::default())
             .with_prompt(format!(
@@ -77,22 +658,396 @@ pub async fn add_new_account(email: String, accounts: &mut StoredAccounts) -> an
         }
     }
 
-    let client = Client::new();
-    let auth_params = GoogleOAuthParams::default();
+    let account = match (provider, app_password) {
+        (_, true) => {
+            let password = match password_stdin {
+                true => password_from_stdin(&mut std::io::stdin().lock())?,
+                false => rpassword::prompt_password("app password: ")?,
+            };
+
+            StoredAccountData::with_app_password(password, provider)
+        }
+        (provider, false) => oauth_login(&email, provider, device, None).await?,
+    };
+
+    accounts.insert(email, account)
+}
+
+/// runs the OAuth flow of [`add_new_account`] again for the stored account `email`, requesting
+/// `scope` instead of the provider's default if given. only the tokens are replaced, everything
+/// else stored for the account is kept
+pub async fn reauthorize_account(
+    email: String,
+    accounts: &mut StoredAccounts,
+    scope: Option<String>,
+    device: bool,
+) -> anyhow::Result<()> {
+    let Some(account) = accounts.stored_accounts().get(&email).cloned() else {
+        return Err(anyhow!(
+            "no account is stored for {email}, log in to it with `mail-cli login {email}` instead"
+        ));
+    };
+    if account.app_password.is_some() {
+        return Err(anyhow!(
+            "{email} is logged in with an app password, which has no scopes to change"
+        ));
+    }
+
+    let tokens = oauth_login(&email, account.provider, device, scope).await?;
+    accounts.insert(email, account.reauthorized(tokens))
+}
+
+/// runs the OAuth flow of `provider` for `email`, optionally requesting `scope` instead of the
+/// default one
+async fn oauth_login(
+    email: &str,
+    provider: Provider,
+    device: bool,
+    scope: Option<String>,
+) -> anyhow::Result<StoredAccountData> {
+    match provider {
+        Provider::Google => google_oauth_login(email, device, scope).await,
+        Provider::Yahoo => {
+            if device {
+                return Err(anyhow!(
+                    "device logins are only supported for Gmail accounts"
+                ));
+            }
+
+            yahoo_oauth_login(email, scope).await
+        }
+    }
+}
+
+/// reads a password from the first line of `reader`, without the line ending
+fn password_from_stdin(reader: &mut impl BufRead) -> anyhow::Result<String> {
+    let password = rpassword::read_password_from_bufread(reader)?;
+    if password.is_empty() {
+        return Err(anyhow!("no password was given on stdin"));
+    }
+
+    Ok(password)
+}
+
+/// runs Google's OAuth flow for `email`, see [`add_new_account`]
+async fn google_oauth_login(
+    email: &str,
+    device: bool,
+    scope: Option<String>,
+) -> anyhow::Result<StoredAccountData> {
+    let client = http_client()?;
+    let mut auth_params = GoogleOAuthParams::default();
+    if let Some(scope) = scope {
+        auth_params = auth_params.with_scopes(scope);
+    }
+
+    let response = if device {
+        let device = request_google_device_code(&client, &auth_params).await?;
+        print_info(format!(
+            "visit {url} on any device and enter the code {code}",
+            url = device.verification_url,
+            code = device.user_code
+        ));
+
+        poll_google_device_token(&client, &auth_params, &device).await?
+    } else {
+        let auth_params = auth_params.clone().with_pkce()?;
+        let code = Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "visit this link: {url}\nand paste the code from it here",
+                url = auth_params.get_token_request_url(Some(email))
+            ))
+            .interact_text()?;
+
+        request_google_oauth_token(&client, &auth_params, &code).await?
+    };
+
+    let GoogleOAuthTokenRequestResponse {
+        access_token,
+        refresh_token,
+        expires_in,
+        scope,
+    } = response;
+
+    Ok(StoredAccountData::new(access_token, refresh_token)
+        .with_expiry(expires_in)
+        .with_scope(scope.unwrap_or(auth_params.scopes().to_owned())))
+}
+
+/// runs Yahoo's OAuth flow for `email`, see [`add_new_account`]
+async fn yahoo_oauth_login(
+    email: &str,
+    scope: Option<String>,
+) -> anyhow::Result<StoredAccountData> {
+    let mut auth_params = YahooOAuthParams::default();
+    if let Some(scope) = scope {
+        auth_params = auth_params.with_scopes(scope);
+    }
 
     let code = Input::<String>::with_theme(&ColorfulTheme::default())
         .with_prompt(format!(
             "visit this link: {url}\nand paste the code from it here",
-            url = auth_params.get_token_request_url()
+            url = auth_params.get_token_request_url(Some(email))
         ))
         .interact_text()?;
 
-    let GoogleOAuthTokenRequestResponse {
+    let YahooOAuthTokenResponse {
         access_token,
         refresh_token,
-    } = request_google_oauth_token(&client, &auth_params, &code).await?;
+        expires_in,
+        scope,
+    } = request_yahoo_oauth_token(&http_client()?, &auth_params, &code).await?;
+
+    Ok(StoredAccountData::new(access_token, refresh_token)
+        .with_provider(Provider::Yahoo)
+        .with_expiry(expires_in)
+        .with_scope(scope.unwrap_or(auth_params.scopes().to_owned())))
+}
+
+/// loads the stored accounts, offering to reset them if the accounts file is corrupted
+pub fn load_accounts() -> anyhow::Result<StoredAccounts> {
+    let err = match StoredAccounts::load_data() {
+        Ok(accounts) => return Ok(accounts),
+        Err(err) => err,
+    };
+
+    let corrupted = err.downcast::<AccountsCorrupted>()?;
+    print_info(&corrupted);
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("do you want to reset the stored accounts")
+        .default(1)
+        .items(&["yes", "no"])
+        .interact()?;
+
+    if selection == 0 {
+        StoredAccounts::reset(corrupted.path)
+    } else {
+        Err(corrupted.into())
+    }
+}
+
+/// asks whether the `count` mails marked as deleted in `mailbox` should be removed for good,
+/// `false` without asking if there are none
+pub fn confirm_expunge(mailbox: &str, count: usize) -> anyhow::Result<bool> {
+    let Some(prompt) = expunge_prompt(mailbox, count) else {
+        print_info(format!("no mails in '{mailbox}' are marked as deleted"));
+        return Ok(false);
+    };
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(1)
+        .items(&["yes", "no"])
+        .interact()?;
+
+    Ok(selection == 0)
+}
+
+fn expunge_prompt(mailbox: &str, count: usize) -> Option<String> {
+    match count {
+        0 => None,
+        1 => Some(format!(
+            "permanently remove the mail marked as deleted in '{mailbox}'"
+        )),
+        n => Some(format!(
+            "permanently remove the {n} mails marked as deleted in '{mailbox}'"
+        )),
+    }
+}
+
+/// asks whether all `count` mails of `mailbox` should be deleted for good. as a typo or a
+/// recalled command would lose many mails, the count has to be typed instead of picking yes
+pub fn confirm_empty(mailbox: &str, count: usize) -> anyhow::Result<bool> {
+    let Some(prompt) = empty_prompt(mailbox, count) else {
+        print_info(format!("'{mailbox}' is already empty"));
+        return Ok(false);
+    };
+
+    let input = Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .allow_empty(true)
+        .interact_text()?;
+
+    Ok(confirms_count(&input, count))
+}
+
+fn empty_prompt(mailbox: &str, count: usize) -> Option<String> {
+    (count > 0).then(|| {
+        format!(
+            "permanently delete all {count} mail(s) in '{mailbox}', they can't be restored. \
+            type {count} to confirm"
+        )
+    })
+}
+
+fn confirms_count(input: &str, count: usize) -> bool {
+    input.trim().parse() == Ok(count)
+}
+
+/// asks whether the `count` mails of `mailbox` should be deleted for good
+pub fn confirm_permanent_delete(mailbox: &str, count: usize) -> anyhow::Result<bool> {
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!(
+            "permanently delete {count} mail(s) from '{mailbox}', they can't be restored. if the \
+            server doesn't support UIDPLUS, mails other clients marked as deleted in the meantime \
+            are removed as well"
+        ))
+        .default(1)
+        .items(&["yes", "no"])
+        .interact()?;
+
+    Ok(selection == 0)
+}
+
+/// an answer to whether one mail of a batch should be changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Yes,
+    No,
+    /// this and all remaining mails
+    All,
+    /// none of the remaining mails
+    Quit,
+}
+
+/// asks for each of `mails` whether to `action` (e.g. "delete") it, showing its sender and
+/// subject, and returns the UIDs of those the user agreed to
+pub fn confirm_each(
+    action: &str,
+    mails: &[(u32, anyhow::Result<Mail>)],
+) -> anyhow::Result<Vec<u32>> {
+    let options = DisplayOptions::default();
+    decide_each(mails, |(uid, mail)| {
+        let summary = match mail {
+            Ok(mail) => format!(
+                "{from}: {subject}",
+                from = mail.display(&options).from(),
+                subject = mail.subject().unwrap_or("(no subject)")
+            ),
+            Err(err) => format!("[{err:#}]"),
+        };
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("{action} mail {uid} from {summary}"))
+            .default(1)
+            .items(&["yes", "no", "all remaining", "quit"])
+            .interact()?;
+
+        Ok(match selection {
+            0 => Decision::Yes,
+            1 => Decision::No,
+            2 => Decision::All,
+            _ => Decision::Quit,
+        })
+    })
+}
+
+/// the UIDs of the `mails` `decide` agrees to, asking about each in order until it answers
+/// [`Decision::All`] or [`Decision::Quit`]
+fn decide_each<T>(
+    mails: &[(u32, T)],
+    mut decide: impl FnMut(&(u32, T)) -> anyhow::Result<Decision>,
+) -> anyhow::Result<Vec<u32>> {
+    let mut uids = Vec::new();
+    for (idx, mail) in mails.iter().enumerate() {
+        match decide(mail)? {
+            Decision::Yes => uids.push(mail.0),
+            Decision::No => {}
+            Decision::All => {
+                uids.extend(mails[idx..].iter().map(|(uid, _)| *uid));
+                break;
+            }
+            Decision::Quit => break,
+        }
+    }
+
+    Ok(uids)
+}
+
+/// lets the user pick one of `methods` to unsubscribe with, `None` if they cancel
+pub fn select_unsubscribe_method(methods: Vec<Unsubscribe>) -> anyhow::Result<Option<Unsubscribe>> {
+    let items = methods
+        .iter()
+        .map(|method| method.to_string())
+        .chain([String::from("cancel")])
+        .collect::<Vec<_>>();
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("how do you want to unsubscribe")
+        .default(0)
+        .items(&items)
+        .interact()?;
+
+    Ok(methods.into_iter().nth(selection))
+}
+
+/// shows a numbered list of `mails` and then the mail whose number is entered, until the input
+/// is empty
+pub fn browse_mails(
+    mails: &[anyhow::Result<Mail>],
+    options: &DisplayOptions,
+    charset: OutputCharset,
+    width: usize,
+) -> anyhow::Result<()> {
+    let number_width = mails.len().to_string().len();
+    let list = header_table(mails, options, width.saturating_sub(number_width + 2))
+        .lines()
+        .enumerate()
+        .map(|(idx, row)| format!("{number:>number_width$}  {row}", number = idx + 1))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let legend = flags_legend(mails);
+
+    loop {
+        if let Some(legend) = &legend {
+            print_info(legend);
+        }
+        charset.println(format!("{list}\n"))?;
+        let input = Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt("number of the mail to read (empty to quit)")
+            .allow_empty(true)
+            .interact_text()?;
+
+        match parse_selection(&input, mails.len()) {
+            Ok(Some(idx)) => match &mails[idx] {
+                Ok(mail) => charset.println(format!("\n{}\n", mail.display(options)))?,
+                Err(err) => charset.println(format!("\n[{err:#}]\n"))?,
+            },
+            Ok(None) => return Ok(()),
+            Err(err) => print_info(err),
+        }
+    }
+}
+
+/// the index of the item with the 1-based number in `input` out of `count` items, `None` if
+/// `input` is empty
+fn parse_selection(input: &str, count: usize) -> anyhow::Result<Option<usize>> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+
+    match input.parse::<usize>() {
+        Ok(number) if (1..=count).contains(&number) => Ok(Some(number - 1)),
+        _ => Err(anyhow!("enter a number from 1 to {count}")),
+    }
+}
+
+/// lets the user pick one of `links` to open, `None` if they cancel
+pub fn select_link(links: &[String]) -> anyhow::Result<Option<&String>> {
+    let items = links
+        .iter()
+        .map(|link| link.as_str())
+        .chain(["cancel"])
+        .collect::<Vec<_>>();
 
-    accounts.insert(email, StoredAccountData::new(access_token, refresh_token))
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("which link do you want to open")
+        .default(0)
+        .items(&items)
+        .interact()?;
+
+    Ok(links.get(selection))
 }
 
 pub fn select_account(
@@ -117,20 +1072,232 @@ pub fn select_account(
         );
 
         let completion = CompletionOptions(mails);
-        let picked = match Input::<String>::with_theme(&ColorfulTheme::default())
+        let picked = Input::<String>::with_theme(&ColorfulTheme::default())
             .with_prompt(prompt)
             .completion_with(&completion)
             .interact_text()
-            .ok()
-        {
-            Some(str) => str,
-            None => return None,
-        };
+            .ok()?;
 
         accounts.get(&picked).map(|data| (picked, data.to_owned()))
     }
 }
 
+/// parses the arguments of a saved search like those of `read`
+#[derive(Debug, Parser)]
+#[command(name = "mail-cli read")]
+struct SavedSearchArgs {
+    #[command(flatten)]
+    args: ReadArgs,
+}
+
+/// the `read` arguments of the search saved as `name`, followed by `extra`
+pub fn expand_saved_search(
+    searches: &HashMap<String, String>,
+    name: &str,
+    extra: Vec<String>,
+) -> anyhow::Result<ReadArgs> {
+    let Some(saved) = searches.get(name) else {
+        return Err(match searches.is_empty() {
+            true => anyhow!("no searches are saved in the config file"),
+            false => anyhow!(
+                "no search named '{name}' saved, there are: {}",
+                searches.keys().sorted().join(", ")
+            ),
+        });
+    };
+
+    let words = split_args(saved)
+        .map_err(|err| anyhow!("failed to parse the saved search '{name}': {err}"))?;
+    let parsed = SavedSearchArgs::try_parse_from(
+        std::iter::once(String::from("read"))
+            .chain(words)
+            .chain(extra),
+    )
+    .map_err(|err| anyhow!("invalid saved search '{name}': {err}"))?;
+
+    Ok(parsed.args)
+}
+
+/// splits `line` into words at whitespace like a shell, single and double quotes group words
+/// and a backslash escapes the next character outside of single quotes
+fn split_args(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(char) = chars.next() {
+        match (quote, char) {
+            (Some(open), char) if char == open => quote = None,
+            (None | Some('"'), '\\') => {
+                let escaped = chars.next().ok_or(anyhow!("trailing backslash"))?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            (Some(_), char) => word.get_or_insert_with(String::new).push(char),
+            (None, '\'' | '"') => {
+                quote = Some(char);
+                word.get_or_insert_with(String::new);
+            }
+            (None, char) if char.is_whitespace() => words.extend(word.take()),
+            (None, char) => word.get_or_insert_with(String::new).push(char),
+        }
+    }
+
+    if let Some(quote) = quote {
+        return Err(anyhow!("unclosed {quote}"));
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+/// prints a notice for the user to stderr, so it never mixes with output written to stdout
+/// like the mails of `read --format eml`
 pub fn print_info<D: Display>(str: D) {
-    println!("{i} {str}", i = String::from("!").blue())
+    eprintln!("{i} {str}", i = String::from("!").blue())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    use crate::mail::MailBox;
+
+    #[test]
+    fn expands_saved_searches() {
+        let searches = HashMap::from([
+            (
+                String::from("boss"),
+                String::from("5 --from boss@x.com --subject 'quarterly report' -b \"Work Inbox\""),
+            ),
+            (String::from("broken"), String::from("--subject 'open")),
+            (String::from("unknown"), String::from("--no-such-flag")),
+        ]);
+
+        let args = expand_saved_search(&searches, "boss", vec![String::from("--quiet")]).unwrap();
+        assert_eq!(args.n, Some(5));
+        assert_eq!(args.from.as_deref(), Some("boss@x.com"));
+        assert_eq!(args.subject.as_deref(), Some("quarterly report"));
+        assert_eq!(args.mailbox, vec![String::from("Work Inbox")]);
+        assert!(args.quiet);
+
+        let err = expand_saved_search(&searches, "missing", Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("boss, broken, unknown"));
+        assert!(expand_saved_search(&searches, "broken", Vec::new()).is_err());
+        assert!(expand_saved_search(&searches, "unknown", Vec::new()).is_err());
+
+        assert_eq!(
+            split_args(r#"a\ b "c \"d\"" '' e'f'"#).unwrap(),
+            ["a b", "c \"d\"", "", "ef"]
+        );
+    }
+
+    #[test]
+    fn peeks_unless_told_not_to() {
+        let fetch_query = |args: &[&str]| {
+            let args = CliArgs::try_parse_from([&["mail-cli", "read"], args].concat()).unwrap();
+            let Commands::Read(args) = args.command else {
+                unreachable!()
+            };
+
+            MailBox::new("INBOX")
+                .with_mark_seen(args.no_peek)
+                .fetch_query()
+        };
+
+        assert_eq!(fetch_query(&[]), "(UID FLAGS BODY.PEEK[])");
+        assert_eq!(fetch_query(&["--peek"]), "(UID FLAGS BODY.PEEK[])");
+        assert_eq!(fetch_query(&["--no-peek"]), "(UID FLAGS BODY[])");
+        assert_eq!(fetch_query(&["--mark-seen"]), "(UID FLAGS BODY[])");
+        assert_eq!(
+            fetch_query(&["--no-peek", "--peek"]),
+            "(UID FLAGS BODY.PEEK[])"
+        );
+        assert_eq!(
+            fetch_query(&["--peek", "--mark-seen"]),
+            "(UID FLAGS BODY[])"
+        );
+    }
+
+    #[test]
+    fn parses_selection() {
+        assert_eq!(parse_selection("", 3).unwrap(), None);
+        assert_eq!(parse_selection("  ", 3).unwrap(), None);
+        assert_eq!(parse_selection("1", 3).unwrap(), Some(0));
+        assert_eq!(parse_selection(" 3\n", 3).unwrap(), Some(2));
+        assert!(parse_selection("0", 3).is_err());
+        assert!(parse_selection("4", 3).is_err());
+        assert!(parse_selection("two", 3).is_err());
+    }
+
+    #[test]
+    fn version_includes_build_info() {
+        let version = CliArgs::command().render_version();
+
+        assert!(version.starts_with(&format!("mail-cli {}", env!("CARGO_PKG_VERSION"))));
+        assert!(version.contains("built "));
+    }
+
+    #[test]
+    fn reads_password_from_stdin() {
+        assert_eq!(
+            password_from_stdin(&mut "hunter2\n".as_bytes()).unwrap(),
+            "hunter2"
+        );
+        assert_eq!(
+            password_from_stdin(&mut "with spaces \r\nnext line\n".as_bytes()).unwrap(),
+            "with spaces "
+        );
+        assert!(password_from_stdin(&mut "".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn asks_to_expunge_only_deleted_mails() {
+        assert_eq!(expunge_prompt("INBOX", 0), None);
+        assert_eq!(
+            expunge_prompt("INBOX", 1).unwrap(),
+            "permanently remove the mail marked as deleted in 'INBOX'"
+        );
+        assert!(expunge_prompt("Spam", 12)
+            .unwrap()
+            .starts_with("permanently remove the 12 mails"));
+    }
+
+    #[test]
+    fn aggregates_decisions_for_each_mail() {
+        let mails = [(3, ()), (5, ()), (8, ()), (13, ())];
+        let decide = |decisions: &[Decision]| {
+            let mut decisions = decisions.iter();
+            let mut asked = 0;
+            let uids = decide_each(&mails, |_| {
+                asked += 1;
+                Ok(*decisions.next().unwrap())
+            })
+            .unwrap();
+            (uids, asked)
+        };
+
+        use Decision::*;
+        assert_eq!(decide(&[Yes, No, Yes, No]), (vec![3, 8], 4));
+        // the remaining mails aren't asked about
+        assert_eq!(decide(&[No, All]), (vec![5, 8, 13], 2));
+        assert_eq!(decide(&[Yes, Quit]), (vec![3], 2));
+        assert_eq!(decide(&[Quit]), (vec![], 1));
+
+        assert!(decide_each(&mails, |_| Err(anyhow!("interrupted"))).is_err());
+    }
+
+    #[test]
+    fn asks_to_type_count_before_emptying() {
+        assert_eq!(empty_prompt("[Gmail]/Trash", 0), None);
+        assert!(empty_prompt("[Gmail]/Trash", 42)
+            .unwrap()
+            .starts_with("permanently delete all 42 mail(s) in '[Gmail]/Trash'"));
+
+        assert!(confirms_count(" 42\n", 42));
+        for input in ["", "y", "yes", "41", "420"] {
+            assert!(!confirms_count(input, 42), "{input}");
+        }
+    }
 }
@@ -0,0 +1,71 @@
+use std::{collections::HashMap, fs};
+
+use serde::Deserialize;
+
+use crate::{mail_filters::MaxAge, utils::get_data_dir_path};
+
+/// user defaults loaded from `config.toml` in the data directory, every value can be
+/// overridden by the corresponding command line option
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// strftime style format used to display dates
+    pub date_format: Option<String>,
+    /// maximum number of mails fetched with a single IMAP command
+    pub fetch_batch_size: Option<usize>,
+    /// how often the connection is reopened if it drops while reading mails
+    pub fetch_retries: Option<usize>,
+    /// number of mails to read if none is given
+    pub mail_count: Option<usize>,
+    /// mailbox archived mails are moved to on servers other than Gmail
+    pub archive_mailbox: Option<String>,
+    /// seconds the mails found by a `read --no-body` query are reused for, 0 to always fetch
+    /// them
+    pub summary_cache_ttl: Option<i64>,
+    /// time span (e.g. "30d") after which mails are left out of `read`, however recent they are
+    /// compared to the others
+    pub max_age: Option<MaxAge>,
+    /// `read` arguments by name, run with `mail-cli saved <name>`
+    pub searches: HashMap<String, String>,
+    /// append a line to `refresh_audit.log` in the data directory each time a token is
+    /// refreshed, with the time, the account and whether it worked
+    pub audit_refreshes: bool,
+}
+
+/// number of mails to read if neither the command line nor the config file set one
+pub const DEFAULT_MAIL_COUNT: usize = 10;
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let path = get_data_dir_path()?.join("config.toml");
+        let data_str = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::NotFound => String::new(),
+                _ => return Err(err.into()),
+            },
+        };
+
+        Ok(toml::from_str(&data_str)?)
+    }
+
+    /// the number of mails to read, `n` from the command line takes precedence over the config
+    /// file which takes precedence over [`DEFAULT_MAIL_COUNT`]
+    pub fn mail_count(&self, n: Option<usize>) -> usize {
+        n.or(self.mail_count).unwrap_or(DEFAULT_MAIL_COUNT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_mail_count() {
+        let config: Config = toml::from_str("mail_count = 25").unwrap();
+
+        assert_eq!(config.mail_count(Some(3)), 3);
+        assert_eq!(config.mail_count(None), 25);
+        assert_eq!(Config::default().mail_count(None), DEFAULT_MAIL_COUNT);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use chrono::NaiveDate;
+use imap::{types::Flag, Session};
+use itertools::Itertools;
+use mail_parser::{HeaderValue, Message};
+use serde::Serialize;
+
+use crate::{
+    mail::OpenMode,
+    mail_filters::{HeaderField, HeaderFilter},
+};
+
+/// the parts of a mail that are relevant for a digest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestEntry {
+    pub sender: String,
+    pub subject: Option<String>,
+    pub unread: bool,
+}
+
+/// compact summary of the mails received since a date
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Digest {
+    pub since: String,
+    pub total: usize,
+    pub unread: usize,
+    /// number of mails per sender, most frequent sender first
+    pub by_sender: Vec<SenderCount>,
+    pub subjects: Vec<DigestSubject>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SenderCount {
+    pub sender: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DigestSubject {
+    pub subject: String,
+    pub unread: bool,
+}
+
+impl Digest {
+    pub fn new(since: NaiveDate, entries: Vec<DigestEntry>) -> Self {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for entry in &entries {
+            *counts.entry(&entry.sender).or_default() += 1;
+        }
+
+        let by_sender = counts
+            .into_iter()
+            .sorted_by(|(sender_a, count_a), (sender_b, count_b)| {
+                count_b.cmp(count_a).then(sender_a.cmp(sender_b))
+            })
+            .map(|(sender, count)| SenderCount {
+                sender: sender.to_owned(),
+                count,
+            })
+            .collect();
+
+        Self {
+            since: since.to_string(),
+            total: entries.len(),
+            unread: entries.iter().filter(|entry| entry.unread).count(),
+            by_sender,
+            subjects: entries
+                .iter()
+                .map(|entry| DigestSubject {
+                    subject: entry.subject.clone().unwrap_or(String::from("-")),
+                    unread: entry.unread,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Digest since {}", self.since)?;
+        writeln!(f, "{} mails, {} unread", self.total, self.unread)?;
+
+        if !self.by_sender.is_empty() {
+            writeln!(f, "\nBy sender:")?;
+            for SenderCount { sender, count } in &self.by_sender {
+                writeln!(f, "  {count:>4}  {sender}")?;
+            }
+        }
+
+        if !self.subjects.is_empty() {
+            writeln!(f, "\nSubjects:")?;
+            for DigestSubject { subject, unread } in &self.subjects {
+                let marker = if *unread { "*" } else { "-" };
+                writeln!(f, "  {marker} {}", subject.trim())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// fetches only the headers needed for a digest of all mails in `mailbox` received on or after
+/// `since`. the mailbox is opened read-only so no mail is marked as seen
+pub fn fetch_digest_entries<T: Read + Write>(
+    session: &mut Session<T>,
+    mailbox: &str,
+    since: NaiveDate,
+) -> anyhow::Result<Vec<DigestEntry>> {
+    OpenMode::ReadOnly.open(mailbox, session)?;
+
+    let ord_nums = session.search(format!("SINCE {}", since.format("%d-%b-%Y")))?;
+    if ord_nums.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let filter_str = HeaderFilter::new(
+        HashSet::from([HeaderField::From(None), HeaderField::Subject(None)]),
+        false,
+    )
+    .filter_str()
+    .unwrap_or_default();
+
+    let items = session.fetch(
+        ord_nums.into_iter().sorted().join(","),
+        format!("(FLAGS BODY.PEEK[{filter_str}])"),
+    )?;
+
+    Ok(items
+        .iter()
+        .map(|item| {
+            let msg = Message::parse(item.header().unwrap_or(&[]));
+            DigestEntry {
+                sender: msg
+                    .as_ref()
+                    .and_then(|msg| sender(msg.from()))
+                    .unwrap_or(String::from("-")),
+                subject: msg
+                    .as_ref()
+                    .and_then(|msg| msg.subject())
+                    .map(|val| val.to_owned()),
+                unread: !item.flags().contains(&Flag::Seen),
+            }
+        })
+        .collect())
+}
+
+/// returns the address (or the name if there is no address) of the first sender
+fn sender(from: &HeaderValue) -> Option<String> {
+    let addr = match from {
+        HeaderValue::Address(addr) => addr,
+        HeaderValue::AddressList(list) => list.first()?,
+        HeaderValue::Group(group) => group.addresses.first()?,
+        HeaderValue::GroupList(groups) => groups.first()?.addresses.first()?,
+        _ => return None,
+    };
+
+    addr.address
+        .as_ref()
+        .map(|address| address.to_lowercase())
+        .or(addr.name.as_ref().map(|name| name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_imap::{mock_session, MockMail};
+
+    fn entry(sender: &str, subject: &str, unread: bool) -> DigestEntry {
+        DigestEntry {
+            sender: sender.to_owned(),
+            subject: Some(subject.to_owned()),
+            unread,
+        }
+    }
+
+    #[test]
+    fn aggregates_entries() {
+        let since = NaiveDate::from_ymd_opt(2023, 5, 1).unwrap();
+        let digest = Digest::new(
+            since,
+            vec![
+                entry("bob@example.com", "a", true),
+                entry("alice@example.com", "b", false),
+                entry("bob@example.com", "c", false),
+            ],
+        );
+
+        assert_eq!(digest.total, 3);
+        assert_eq!(digest.unread, 1);
+        assert_eq!(
+            digest.by_sender,
+            vec![
+                SenderCount {
+                    sender: String::from("bob@example.com"),
+                    count: 2
+                },
+                SenderCount {
+                    sender: String::from("alice@example.com"),
+                    count: 1
+                },
+            ]
+        );
+        assert!(digest.to_string().contains("  * a\n  - b\n  - c\n"));
+    }
+
+    #[test]
+    fn fetches_entries_from_headers() {
+        let mails = vec![
+            MockMail::new("From: Bob <Bob@Example.com>\nSubject: hi\n\nbody\n"),
+            MockMail::new("From: alice@example.com\nSubject: hey\n\nbody\n")
+                .with_flags(&["\\Seen"]),
+        ];
+        let (mut session, commands) = mock_session(vec![("INBOX", mails)]);
+
+        let entries = fetch_digest_entries(
+            &mut session,
+            "INBOX",
+            NaiveDate::from_ymd_opt(2023, 5, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                entry("bob@example.com", "hi", true),
+                entry("alice@example.com", "hey", false)
+            ]
+        );
+        assert!(commands
+            .borrow()
+            .iter()
+            .any(|cmd| cmd == "SEARCH SINCE 01-May-2023"));
+    }
+}
@@ -0,0 +1,218 @@
+//! new mails composed from the command line and saved to the drafts mailbox with `APPEND`, to be
+//! finished and sent from any mail client
+
+use std::io::{Read, Write};
+
+use chrono::{DateTime, FixedOffset};
+use imap::{types::Flag, Session};
+
+use crate::{
+    headers::{check_single_line, encode_text, encode_typed_mailbox},
+    mailboxes::MailboxRole,
+};
+
+/// a new mail that isn't sent yet
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Draft {
+    pub from: String,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub subject: String,
+    pub body: String,
+}
+
+/// the flags drafts are appended with. `\Seen` keeps them from counting as unread, as clients do
+/// with the drafts they save
+pub const DRAFT_FLAGS: [Flag<'static>; 2] = [Flag::Draft, Flag::Seen];
+
+impl Draft {
+    /// an error if a header value of the draft contains line breaks, which would end the header
+    pub fn check(&self) -> anyhow::Result<()> {
+        check_single_line("sender", &self.from)?;
+        for to in &self.to {
+            check_single_line("recipient", to)?;
+        }
+        for cc in &self.cc {
+            check_single_line("cc recipient", cc)?;
+        }
+        check_single_line("subject", &self.subject)
+    }
+
+    /// the draft as an RFC 5322 message written at `date`, with CRLF line endings as `APPEND`
+    /// expects. non-ASCII names and subjects are encoded, see [`Draft::check`] for when it fails
+    pub fn to_message(&self, date: DateTime<FixedOffset>) -> anyhow::Result<String> {
+        self.check()?;
+
+        let mut headers = vec![
+            (String::from("Date"), date.to_rfc2822()),
+            (String::from("From"), encode_typed_mailbox(&self.from)),
+        ];
+        if !self.to.is_empty() {
+            headers.push((String::from("To"), encode_mailboxes(&self.to)));
+        }
+        if !self.cc.is_empty() {
+            headers.push((String::from("Cc"), encode_mailboxes(&self.cc)));
+        }
+        headers.push((String::from("Subject"), encode_text(&self.subject)));
+        headers.push((String::from("MIME-Version"), String::from("1.0")));
+        headers.push((
+            String::from("Content-Type"),
+            String::from("text/plain; charset=utf-8"),
+        ));
+        headers.push((
+            String::from("Content-Transfer-Encoding"),
+            String::from("8bit"),
+        ));
+
+        let mut message = headers
+            .into_iter()
+            .map(|(name, value)| format!("{name}: {value}\r\n"))
+            .collect::<String>();
+        message.push_str("\r\n");
+        for line in self.body.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+
+        Ok(message)
+    }
+
+    /// appends the draft to `mailbox`, or the mailbox marked as `\Drafts` if not given, with
+    /// `date` as its internal date. returns the name of the mailbox it was saved to
+    pub fn save<T: Read + Write>(
+        &self,
+        mailbox: Option<&str>,
+        date: DateTime<FixedOffset>,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<String> {
+        let mailbox = match mailbox {
+            Some(mailbox) => mailbox.to_owned(),
+            None => MailboxRole::Drafts.resolve(session)?,
+        };
+        session.append_with_flags_and_date(&mailbox, self.to_message(date)?, &DRAFT_FLAGS, date)?;
+
+        Ok(mailbox)
+    }
+}
+
+fn encode_mailboxes(mailboxes: &[String]) -> String {
+    mailboxes
+        .iter()
+        .map(|mailbox| encode_typed_mailbox(mailbox))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    use crate::{
+        mail::MailBox,
+        mock_imap::{connect, MockImapStream},
+    };
+
+    #[test]
+    fn appends_to_drafts_mailbox() {
+        let stream = MockImapStream::new(vec![("INBOX", vec![]), ("Brouillons", vec![])])
+            .with_mailbox_attributes("Brouillons", &["\\Drafts"]);
+        let commands = stream.command_log();
+        let mut session = connect(stream)
+            .login("user", "password")
+            .map_err(|(err, _)| err)
+            .unwrap();
+
+        let draft = Draft {
+            from: String::from("me@example.com"),
+            to: vec![String::from("bob@example.com")],
+            cc: Vec::new(),
+            subject: String::from("plans"),
+            body: String::from("see you\n\nthere"),
+        };
+        let date = FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2023, 7, 3, 10, 0, 0)
+            .unwrap();
+        let message = draft.to_message(date).unwrap();
+        assert_eq!(
+            message,
+            "Date: Mon, 03 Jul 2023 10:00:00 +0200\r\n\
+            From: me@example.com\r\n\
+            To: bob@example.com\r\n\
+            Subject: plans\r\n\
+            MIME-Version: 1.0\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Transfer-Encoding: 8bit\r\n\
+            \r\n\
+            see you\r\n\r\nthere\r\n"
+        );
+
+        assert_eq!(draft.save(None, date, &mut session).unwrap(), "Brouillons");
+        assert!(commands.borrow().contains(&format!(
+            "APPEND \"Brouillons\" (\\Draft \\Seen) \"03-Jul-2023 10:00:00 +0200\" {{{}}}",
+            message.len()
+        )));
+
+        let saved = MailBox::new("Brouillons")
+            .fetch_mail_by_uid(1, &mut session)
+            .unwrap();
+        assert_eq!(saved.subject(), Some("plans"));
+        assert_eq!(saved.raw(), message.as_bytes());
+
+        // an explicit mailbox doesn't need a `\Drafts` attribute
+        draft.save(Some("INBOX"), date, &mut session).unwrap();
+        assert!(commands
+            .borrow()
+            .last()
+            .is_some_and(|command| command.starts_with("APPEND \"INBOX\" ")));
+    }
+
+    #[test]
+    fn encodes_headers_and_refuses_line_breaks() {
+        let date = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2023, 7, 3, 10, 0, 0)
+            .unwrap();
+        let draft = Draft {
+            from: String::from("me@example.com"),
+            to: vec![String::from("Jürgen <j@example.com>")],
+            cc: vec![String::from("\"Bob\" <bob@example.com>")],
+            subject: String::from("Grüße"),
+            body: String::from("hallo"),
+        };
+
+        let message = draft.to_message(date).unwrap();
+        let (headers, _) = message.split_once("\r\n\r\n").unwrap();
+        assert!(headers.is_ascii());
+        assert!(headers.contains("\r\nTo: =?utf-8?B?SsO8cmdlbg==?= <j@example.com>\r\n"));
+        assert!(headers.contains("\r\nCc: \"Bob\" <bob@example.com>\r\n"));
+        assert!(headers.contains("\r\nSubject: =?utf-8?B?R3LDvMOfZQ==?=\r\n"));
+
+        let injected = Draft {
+            subject: String::from("plans\r\nBcc: x@evil.com"),
+            ..draft.clone()
+        };
+        assert!(injected.to_message(date).is_err());
+        let injected = Draft {
+            to: vec![String::from("bob@example.com\nBcc: x@evil.com")],
+            ..draft
+        };
+        assert!(injected.check().is_err());
+    }
+
+    #[test]
+    fn fails_without_drafts_mailbox() {
+        let stream = MockImapStream::new(vec![("INBOX", vec![])]);
+        let mut session = connect(stream)
+            .login("user", "password")
+            .map_err(|(err, _)| err)
+            .unwrap();
+        let date = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2023, 7, 3, 10, 0, 0)
+            .unwrap();
+
+        assert!(Draft::default().save(None, date, &mut session).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! telling failures that go away by trying again later from ones that don't
+
+use std::time::Duration;
+
+/// number of times opening a session or a mailbox is retried after the server was temporarily
+/// unavailable
+pub const SESSION_RETRIES: u32 = 3;
+
+/// wait before the first retry of opening a session or a mailbox, doubled for every further one
+pub const SESSION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// response codes of the server rejecting a command for now, see RFC 5530
+const TRANSIENT_RESPONSE_CODES: [&str; 3] = ["[unavailable]", "[inuse]", "[limit]"];
+
+/// response codes of the server rejecting the credentials, see RFC 5530
+const AUTH_RESPONSE_CODES: [&str; 3] = [
+    "[authenticationfailed]",
+    "[authorizationfailed]",
+    "[expired]",
+];
+
+/// why opening a session failed, which decides whether refreshing the access token or trying
+/// again later can help
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionFailure {
+    /// the server rejected the access token
+    Auth,
+    /// the server or the connection to it is unavailable for now, e.g. Gmail answering "Please
+    /// try again later" while the account is locked by other sessions
+    Transient,
+    Other,
+}
+
+impl SessionFailure {
+    /// classifies an error of opening a session. the only command that can be answered with
+    /// `NO` or `BAD` then is `AUTHENTICATE`, so those answers mean the token was rejected unless
+    /// they say the server is unavailable
+    pub fn of(err: &anyhow::Error) -> Self {
+        let (message, rejected) = match (
+            err.downcast_ref::<imap::Error>(),
+            err.downcast_ref::<std::io::Error>(),
+        ) {
+            (Some(imap::Error::Io(err)), _) | (None, Some(err)) => {
+                return match err.kind() {
+                    std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::UnexpectedEof => Self::Transient,
+                    _ => Self::Other,
+                };
+            }
+            (Some(imap::Error::ConnectionLost), _) => return Self::Transient,
+            (Some(imap::Error::No(message) | imap::Error::Bad(message)), _) => {
+                (message.clone(), true)
+            }
+            _ => (err.to_string(), false),
+        };
+
+        let message = message.to_lowercase();
+        if is_transient_response(&message) {
+            Self::Transient
+        } else if rejected
+            || AUTH_RESPONSE_CODES
+                .iter()
+                .any(|code| message.contains(code))
+            || message.contains("invalid credentials")
+            || message.contains("authentication failed")
+        {
+            Self::Auth
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// whether `err` is the server answering a command with `NO` or `BAD` because it is unavailable
+/// for now, e.g. Gmail answering `SELECT` with "Please try again later". unlike
+/// [`SessionFailure::of`] a lost connection doesn't count, repeating the command can't help then
+pub fn is_unavailable(err: &imap::Error) -> bool {
+    match err {
+        imap::Error::No(message) | imap::Error::Bad(message) => {
+            is_transient_response(&message.to_lowercase())
+        }
+        _ => false,
+    }
+}
+
+/// whether the lowercase `message` of a `NO` or `BAD` response says to try again later
+fn is_transient_response(message: &str) -> bool {
+    TRANSIENT_RESPONSE_CODES
+        .iter()
+        .any(|code| message.contains(code))
+        || message.contains("try again later")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn classifies_session_failures() {
+        let failure = |err: anyhow::Error| SessionFailure::of(&err);
+
+        assert_eq!(
+            failure(
+                imap::Error::No(String::from(
+                    "[AUTHENTICATIONFAILED] Invalid credentials (Failure)"
+                ))
+                .into()
+            ),
+            SessionFailure::Auth
+        );
+        assert_eq!(
+            failure(anyhow!("authentication failed")),
+            SessionFailure::Auth
+        );
+        assert_eq!(
+            failure(imap::Error::No(String::from("[UNAVAILABLE] Please try again later")).into()),
+            SessionFailure::Transient
+        );
+        assert_eq!(
+            failure(imap::Error::Bad(String::from("[INUSE] Mailbox in use")).into()),
+            SessionFailure::Transient
+        );
+        // Gmail's answer to a malformed token
+        assert_eq!(
+            failure(imap::Error::Bad(String::from("Invalid SASL argument. q4mb1234")).into()),
+            SessionFailure::Auth
+        );
+        assert_eq!(
+            failure(imap::Error::No(String::from("[ALERT] Account disabled")).into()),
+            SessionFailure::Auth
+        );
+        assert_eq!(
+            failure(imap::Error::ConnectionLost.into()),
+            SessionFailure::Transient
+        );
+        assert_eq!(
+            failure(std::io::Error::from(std::io::ErrorKind::NotFound).into()),
+            SessionFailure::Other
+        );
+        assert_eq!(
+            failure(anyhow!("invalid IMAP host 'x'")),
+            SessionFailure::Other
+        );
+    }
+
+    #[test]
+    fn only_rejections_for_now_are_unavailable() {
+        assert!(is_unavailable(&imap::Error::No(String::from(
+            "[UNAVAILABLE] Please try again later"
+        ))));
+        assert!(is_unavailable(&imap::Error::Bad(String::from(
+            "[LIMIT] Too many sessions"
+        ))));
+        assert!(!is_unavailable(&imap::Error::No(String::from(
+            "[NONEXISTENT] Unknown Mailbox"
+        ))));
+        assert!(!is_unavailable(&imap::Error::ConnectionLost));
+    }
+}
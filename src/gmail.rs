@@ -0,0 +1,476 @@
+//! support for Gmail's IMAP extensions (`X-GM-EXT-1`).
+//!
+//! the `imap-proto` version used by `imap` can't parse the `X-GM-*` fetch attributes and treats
+//! any response containing them as a fatal error. to still be able to use them the transport is
+//! wrapped in a [`GmailExtStream`] that removes these attributes from untagged `FETCH` responses
+//! before they reach the parser and records their values in a [`GmailAttributeStore`].
+//!
+//! the same goes for the untagged response to RFC 2971 `ID`, which servers other than Gmail send
+//! as well. it is taken out of the stream as a whole and recorded for [`crate::server::server_id`]
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use imap::Session;
+
+use crate::mail_filters::quote;
+
+pub const GMAIL_EXT_CAPABILITY: &str = "X-GM-EXT-1";
+
+const THREAD_ID_ATTR: &str = "X-GM-THRID";
+const MESSAGE_ID_ATTR: &str = "X-GM-MSGID";
+const LABELS_ATTR: &str = "X-GM-LABELS";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GmailAttributes {
+    pub thread_id: Option<u64>,
+    pub message_id: Option<u64>,
+    pub labels: Option<Vec<String>>,
+}
+
+/// Gmail attributes of the fetched messages by their sequence number, and the last `ID`
+/// response
+#[derive(Debug, Clone, Default)]
+pub struct GmailAttributeStore {
+    attributes: Arc<Mutex<HashMap<u32, GmailAttributes>>>,
+    id_response: Arc<Mutex<Option<String>>>,
+}
+
+#[derive(Debug)]
+pub struct GmailExtStream<T> {
+    inner: T,
+    store: GmailAttributeStore,
+    unprocessed: Vec<u8>,
+    processed: VecDeque<u8>,
+    literal_remaining: usize,
+    /// sequence number of the `FETCH` response that is continued after a literal
+    continued_fetch: Option<u32>,
+}
+
+impl GmailAttributeStore {
+    /// removes and returns all attributes recorded so far
+    pub fn take(&self) -> HashMap<u32, GmailAttributes> {
+        std::mem::take(
+            &mut *self
+                .attributes
+                .lock()
+                .expect("gmail attribute store poisoned"),
+        )
+    }
+
+    /// removes and returns the untagged `ID` response line recorded since the last call
+    pub fn take_id_response(&self) -> Option<String> {
+        self.id_response
+            .lock()
+            .expect("gmail attribute store poisoned")
+            .take()
+    }
+
+    fn record(&self, seq: u32, update: impl FnOnce(&mut GmailAttributes)) {
+        let mut map = self
+            .attributes
+            .lock()
+            .expect("gmail attribute store poisoned");
+        update(map.entry(seq).or_default());
+    }
+}
+
+impl<T> GmailExtStream<T> {
+    pub fn new(inner: T, store: GmailAttributeStore) -> Self {
+        Self {
+            inner,
+            store,
+            unprocessed: Vec::new(),
+            processed: VecDeque::new(),
+            literal_remaining: 0,
+            continued_fetch: None,
+        }
+    }
+
+    fn process_line(&mut self, line: Vec<u8>) {
+        if self.continued_fetch.is_none() && is_id_response(&line) {
+            *self
+                .store
+                .id_response
+                .lock()
+                .expect("gmail attribute store poisoned") =
+                Some(String::from_utf8_lossy(&line).into_owned());
+            return;
+        }
+
+        let literal = literal_len(&line);
+        let seq = self.continued_fetch.take().or_else(|| fetch_seq(&line));
+
+        let line = match seq {
+            Some(seq) => {
+                let str = String::from_utf8_lossy(&line);
+                let (stripped, attributes) = strip_gmail_attributes(&str);
+                for (name, value) in attributes {
+                    self.store.record(seq, |attrs| match name {
+                        THREAD_ID_ATTR => attrs.thread_id = value.parse().ok(),
+                        MESSAGE_ID_ATTR => attrs.message_id = value.parse().ok(),
+                        _ => attrs.labels = Some(parse_labels(&value)),
+                    });
+                }
+
+                if literal.is_some() {
+                    self.continued_fetch = Some(seq);
+                }
+
+                stripped.into_bytes()
+            }
+            None => line,
+        };
+
+        self.literal_remaining = literal.unwrap_or(0);
+        self.processed.extend(line);
+    }
+}
+
+impl<T: Read> Read for GmailExtStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.processed.is_empty() {
+            if self.literal_remaining > 0 && !self.unprocessed.is_empty() {
+                let n = self.literal_remaining.min(self.unprocessed.len());
+                self.processed.extend(self.unprocessed.drain(..n));
+                self.literal_remaining -= n;
+            } else if let Some(pos) = self.unprocessed.windows(2).position(|w| w == b"\r\n") {
+                let line = self.unprocessed.drain(..pos + 2).collect();
+                self.process_line(line);
+            } else {
+                let mut chunk = [0; 4096];
+                let n = self.inner.read(&mut chunk)?;
+                if n == 0 {
+                    self.processed.extend(self.unprocessed.drain(..));
+                    break;
+                }
+
+                self.unprocessed.extend_from_slice(&chunk[..n]);
+            }
+        }
+
+        let n = buf.len().min(self.processed.len());
+        for (dst, src) in buf.iter_mut().zip(self.processed.drain(..n)) {
+            *dst = src;
+        }
+
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for GmailExtStream<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// checks if the server supports Gmail's IMAP extensions
+pub fn supports_gmail_ext<T: Read + Write>(session: &mut Session<T>) -> anyhow::Result<bool> {
+    Ok(session.capabilities()?.has_str(GMAIL_EXT_CAPABILITY))
+}
+
+/// fetches the thread id and labels of the messages in `seq_set`.
+///
+/// the session has to be running on top of a [`GmailExtStream`] that records into `store`
+pub fn fetch_gmail_attributes<T: Read + Write>(
+    session: &mut Session<T>,
+    store: &GmailAttributeStore,
+    seq_set: &str,
+) -> anyhow::Result<HashMap<u32, GmailAttributes>> {
+    // the UID is requested as well since an empty attribute list can't be parsed after the
+    // Gmail attributes have been stripped
+    session.fetch(seq_set, format!("(UID {THREAD_ID_ATTR} {LABELS_ATTR})"))?;
+
+    Ok(store.take())
+}
+
+/// builds an `X-GM-RAW` search key for a query in Gmail's search syntax
+pub fn raw_search(query: &str) -> String {
+    format!("X-GM-RAW {}", quote(query))
+}
+
+/// builds an `X-GM-RAW` search key matching all messages with the given label
+pub fn label_search(label: &str) -> String {
+    raw_search(&format!("label:{}", quote(label)))
+}
+
+/// builds a `STORE` query removing the Gmail `label` from mails, system labels like `\\Inbox`
+/// are used as is, others are quoted
+pub fn remove_label_query(label: &str) -> String {
+    let label = match label.starts_with('\\') {
+        true => label.to_owned(),
+        false => quote(label),
+    };
+
+    format!("-{LABELS_ATTR}.SILENT ({label})")
+}
+
+/// returns the sequence number if `line` is an untagged `FETCH` response
+fn fetch_seq(line: &[u8]) -> Option<u32> {
+    let line = std::str::from_utf8(line).ok()?;
+    let mut parts = line.strip_prefix("* ")?.splitn(3, ' ');
+    let seq = parts.next()?.parse().ok()?;
+
+    parts
+        .next()
+        .filter(|kind| kind.eq_ignore_ascii_case("FETCH"))
+        .map(|_| seq)
+}
+
+/// checks if `line` is an untagged `ID` response, which `imap-proto` can't parse
+fn is_id_response(line: &[u8]) -> bool {
+    line.get(..5)
+        .is_some_and(|start| start.eq_ignore_ascii_case(b"* ID "))
+}
+
+/// returns the length of the literal announced at the end of `line` (`{n}\r\n`)
+fn literal_len(line: &[u8]) -> Option<usize> {
+    let line = std::str::from_utf8(line).ok()?.strip_suffix("}\r\n")?;
+    let start = line.rfind('{')?;
+
+    line[start + 1..].parse().ok()
+}
+
+/// removes all `X-GM-*` attributes from a `FETCH` response line.
+///
+/// returns the remaining line and the removed attributes as `(name, raw value)` pairs
+fn strip_gmail_attributes(line: &str) -> (String, Vec<(&'static str, String)>) {
+    let mut out = String::with_capacity(line.len());
+    let mut attributes = Vec::new();
+    let mut rest = line;
+
+    while !rest.is_empty() {
+        if rest.starts_with('"') {
+            let len = quoted_len(rest);
+            out.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+
+        let at_item_start = out.ends_with(['(', ' ']);
+        let attr = [THREAD_ID_ATTR, MESSAGE_ID_ATTR, LABELS_ATTR]
+            .into_iter()
+            .find(|attr| at_item_start && starts_with_item(rest, attr));
+
+        match attr {
+            Some(attr) => {
+                let value_start = attr.len() + 1;
+                let value_len = if rest[value_start..].starts_with('(') {
+                    list_len(&rest[value_start..])
+                } else {
+                    rest[value_start..]
+                        .find([' ', ')', '\r'])
+                        .unwrap_or(rest.len() - value_start)
+                };
+
+                attributes.push((attr, rest[value_start..value_start + value_len].to_owned()));
+                rest = &rest[value_start + value_len..];
+
+                if out.ends_with(' ') {
+                    out.pop();
+                } else if rest.starts_with(' ') {
+                    rest = &rest[1..];
+                }
+            }
+            None => {
+                let ch = rest.chars().next().unwrap_or_default();
+                out.push(ch);
+                rest = &rest[ch.len_utf8()..];
+            }
+        }
+    }
+
+    (out, attributes)
+}
+
+fn starts_with_item(str: &str, name: &str) -> bool {
+    str.len() > name.len()
+        && str[..name.len()].eq_ignore_ascii_case(name)
+        && str[name.len()..].starts_with(' ')
+}
+
+/// length of the quoted string at the start of `str` including the quotes
+fn quoted_len(str: &str) -> usize {
+    let mut escaped = false;
+    for (idx, ch) in str.char_indices().skip(1) {
+        match ch {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return idx + 1,
+            _ => escaped = false,
+        }
+    }
+
+    str.len()
+}
+
+/// length of the parenthesized list at the start of `str` including the parentheses
+fn list_len(str: &str) -> usize {
+    let mut depth = 0;
+    let mut idx = 0;
+
+    while idx < str.len() {
+        match str[idx..].chars().next().unwrap_or_default() {
+            '"' => {
+                idx += quoted_len(&str[idx..]);
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return idx + 1;
+                }
+            }
+            _ => {}
+        }
+
+        idx += str[idx..].chars().next().map(char::len_utf8).unwrap_or(1);
+    }
+
+    str.len()
+}
+
+/// parses a label list like `(\Inbox "Some Label" Work)`
+fn parse_labels(list: &str) -> Vec<String> {
+    let mut labels = Vec::new();
+    let mut rest = list.trim().trim_start_matches('(').trim_end_matches(')');
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+
+        if rest.starts_with('"') {
+            let len = quoted_len(rest);
+            let quoted = &rest[1..len.saturating_sub(1).max(1)];
+            labels.push(quoted.replace("\\\"", "\"").replace("\\\\", "\\"));
+            rest = &rest[len..];
+        } else {
+            let len = rest.find(' ').unwrap_or(rest.len());
+            labels.push(rest[..len].to_owned());
+            rest = &rest[len..];
+        }
+    }
+
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_gmail_attributes_from_fetch_lines() {
+        let (line, attrs) = strip_gmail_attributes(
+            "* 1 FETCH (X-GM-THRID 123 UID 5 X-GM-LABELS (\\Inbox \"a b\"))\r\n",
+        );
+
+        assert_eq!(line, "* 1 FETCH (UID 5)\r\n");
+        assert_eq!(
+            attrs,
+            vec![
+                (THREAD_ID_ATTR, String::from("123")),
+                (LABELS_ATTR, String::from("(\\Inbox \"a b\")"))
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_attribute_names_in_quoted_strings() {
+        let line = "* 1 FETCH (UID 5 ENVELOPE (NIL \"X-GM-THRID 1\"))\r\n";
+        let (stripped, attrs) = strip_gmail_attributes(line);
+
+        assert_eq!(stripped, line);
+        assert!(attrs.is_empty());
+    }
+
+    #[test]
+    fn builds_raw_search() {
+        assert_eq!(
+            raw_search(r#"has:attachment subject:"a\b""#),
+            r#"X-GM-RAW "has:attachment subject:\"a\\b\"""#
+        );
+    }
+
+    #[test]
+    fn builds_label_search() {
+        assert_eq!(label_search("Work"), r#"X-GM-RAW "label:\"Work\"""#);
+        assert_eq!(
+            label_search("my \"label\""),
+            r#"X-GM-RAW "label:\"my \\\"label\\\"\"""#
+        );
+    }
+
+    #[test]
+    fn builds_remove_label_query() {
+        assert_eq!(
+            remove_label_query("\\Inbox"),
+            "-X-GM-LABELS.SILENT (\\Inbox)"
+        );
+        assert_eq!(
+            remove_label_query("Work stuff"),
+            "-X-GM-LABELS.SILENT (\"Work stuff\")"
+        );
+    }
+
+    #[test]
+    fn parses_labels() {
+        assert_eq!(
+            parse_labels("(\\Inbox \"Some \\\"Label\\\"\" Work)"),
+            vec!["\\Inbox", "Some \"Label\"", "Work"]
+        );
+        assert!(parse_labels("()").is_empty());
+    }
+
+    #[test]
+    fn stream_records_attributes_and_keeps_literals_intact() {
+        let body = "X-GM-THRID 999\r\n";
+        let response = format!(
+            "* 2 FETCH (X-GM-THRID 42 BODY[] {{{len}}}\r\n{body} X-GM-MSGID 7)\r\na1 OK done\r\n",
+            len = body.len()
+        );
+        let store = GmailAttributeStore::default();
+        let mut stream = GmailExtStream::new(response.as_bytes(), store.clone());
+
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+
+        assert_eq!(
+            out,
+            format!("* 2 FETCH (BODY[] {{16}}\r\n{body})\r\na1 OK done\r\n")
+        );
+        assert_eq!(
+            store.take().get(&2),
+            Some(&GmailAttributes {
+                thread_id: Some(42),
+                message_id: Some(7),
+                labels: None,
+            })
+        );
+    }
+
+    #[test]
+    fn stream_takes_out_id_responses() {
+        let response = "* ID (\"name\" \"Dovecot\")\r\na1 OK done\r\n* 1 EXISTS\r\n";
+        let store = GmailAttributeStore::default();
+        let mut stream = GmailExtStream::new(response.as_bytes(), store.clone());
+
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+
+        assert_eq!(out, "a1 OK done\r\n* 1 EXISTS\r\n");
+        assert_eq!(
+            store.take_id_response().as_deref(),
+            Some("* ID (\"name\" \"Dovecot\")\r\n")
+        );
+        assert_eq!(store.take_id_response(), None);
+    }
+}
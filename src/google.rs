@@ -1,32 +1,91 @@
+use std::time::Duration;
+
 use anyhow::anyhow;
-use reqwest::{Client, StatusCode};
+use reqwest::{Client, StatusCode, Url};
 use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::{
+    pkce::Pkce,
+    provider::{OAuthProvider, RefreshedToken},
+    proxy::http_client,
+};
 
 pub static GOOGLE_AUTH_ROOT_URL: &str = "https://oauth2.googleapis.com/token";
+pub static GOOGLE_DEVICE_CODE_URL: &str = "https://oauth2.googleapis.com/device/code";
+/// the Gmail API profile of the account an access token belongs to, readable with the mail
+/// scopes mail-cli asks for
+pub static GMAIL_PROFILE_URL: &str = "https://gmail.googleapis.com/gmail/v1/users/me/profile";
 pub static GOOGLE_IMAP_DOMAIN: &str = "imap.gmail.com";
 pub static GOOGLE_IMAP_PORT: u16 = 993;
+/// redirect for clients that show the authorization code to be pasted into the terminal
+pub static GOOGLE_OOB_REDIRECT_URL: &str = "urn:ietf:wg:oauth:2.0:oob";
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct GoogleOAuthTokenRequestResponse {
     pub access_token: String,
     pub refresh_token: String,
+    /// seconds until the access token expires
+    pub expires_in: Option<i64>,
+    /// the space separated scopes the user granted
+    #[serde(default)]
+    pub scope: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct GoogleOAuthTokenRefreshResponse {
     pub access_token: String,
+    /// seconds until the access token expires
+    pub expires_in: Option<i64>,
 }
 
+/// response to a device authorization request (RFC 8628), the user has to enter `user_code` at
+/// `verification_url` on another device
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoogleDeviceCodeResponse {
+    pub device_code: String,
+    pub user_code: String,
+    #[serde(alias = "verification_uri")]
+    pub verification_url: String,
+    /// seconds until `device_code` expires
+    pub expires_in: u64,
+    /// minimum number of seconds between polls of the token endpoint
+    #[serde(default = "default_device_poll_interval")]
+    pub interval: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GmailProfile {
+    email_address: String,
+}
+
+/// error returned by the token endpoint while polling for a device authorization
+#[derive(Debug, Clone, Deserialize)]
+struct DeviceTokenError {
+    error: String,
+}
+
+/// [`OAuthProvider`] for Gmail accounts, loads its [`GoogleOAuthParams`] only once a token needs
+/// to be refreshed
+#[derive(Debug, Clone, Copy)]
+pub struct Google;
+
 #[derive(Debug, Clone)]
 pub struct GoogleOAuthParams {
     client_id: String,
     client_secret: String,
     redirect_url: String,
     scopes: String,
+    /// a new verifier for every login (see [`GoogleOAuthParams::with_pkce`]), the authorization
+    /// code is only exchanged for tokens together with it
+    pkce: Option<Pkce>,
 }
 
 impl Default for GoogleOAuthParams {
-    /// loads `client_id` and `client_secret` from `.env` file
+    /// loads `client_id` and `client_secret` from `.env` file, as well as the optional
+    /// `GOOGLE_REDIRECT_URL` for clients registered with another redirect URI than the
+    /// out-of-band one (e.g. a loopback address)
     ///
     /// Panics:
     /// - if it can't load the `GOOGLE_CLIENT_ID` or `GOOGLE_CLIENT_SECRET` environment variables
@@ -34,25 +93,50 @@ impl Default for GoogleOAuthParams {
         let client_id = dotenv::var("GOOGLE_CLIENT_ID").expect("failed to load GOOGLE_CLIENT_ID");
         let client_secret =
             dotenv::var("GOOGLE_CLIENT_SECRET").expect("failed to load GOOGLE_CLIENT_SECRET");
+        let redirect_url =
+            dotenv::var("GOOGLE_REDIRECT_URL").unwrap_or(GOOGLE_OOB_REDIRECT_URL.to_owned());
 
         Self {
             client_id,
             client_secret,
-            redirect_url: "urn:ietf:wg:oauth:2.0:oob".to_owned(),
+            redirect_url,
             scopes: "https://mail.google.com".to_owned(),
+            pkce: None,
         }
     }
 }
 
 impl GoogleOAuthParams {
-    pub fn to_form_request_params<'a>(&'a self, auth_code: &'a str) -> [(&'a str, &'a str); 5] {
-        [
+    /// requests `scopes` (space separated) instead of full mail access
+    pub fn with_scopes(mut self, scopes: String) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    /// binds the authorization code to a new PKCE verifier, which is sent when exchanging it.
+    /// only useful if the same params are used for both
+    pub fn with_pkce(mut self) -> anyhow::Result<Self> {
+        self.pkce = Some(Pkce::generate()?);
+        Ok(self)
+    }
+
+    pub fn scopes(&self) -> &str {
+        &self.scopes
+    }
+
+    pub fn to_form_request_params<'a>(&'a self, auth_code: &'a str) -> Vec<(&'a str, &'a str)> {
+        let mut params = vec![
             ("grant_type", "authorization_code"),
             ("redirect_uri", &self.redirect_url),
             ("client_id", &self.client_id),
             ("client_secret", &self.client_secret),
             ("code", auth_code),
-        ]
+        ];
+        if let Some(pkce) = &self.pkce {
+            params.push(("code_verifier", &pkce.verifier));
+        }
+
+        params
     }
 
     pub fn to_form_refresh_params<'a>(&'a self, refresh_token: &'a str) -> [(&'a str, &'a str); 4] {
@@ -64,8 +148,27 @@ impl GoogleOAuthParams {
         ]
     }
 
-    pub fn get_token_request_url(&self) -> String {
-        format!(
+    pub fn to_form_device_code_params(&self) -> [(&str, &str); 2] {
+        [("client_id", &self.client_id), ("scope", &self.scopes)]
+    }
+
+    pub fn to_form_device_token_params<'a>(
+        &'a self,
+        device_code: &'a str,
+    ) -> [(&'a str, &'a str); 4] {
+        [
+            ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret),
+            ("device_code", device_code),
+        ]
+    }
+
+    /// the consent page to get an authorization code from, `login_hint` preselects the account
+    /// with that mail address. with [`GoogleOAuthParams::with_pkce`] the code can only be
+    /// exchanged with the verifier of these params
+    pub fn get_token_request_url(&self, login_hint: Option<&str>) -> String {
+        let mut url = format!(
             "https://accounts.google.com/o/oauth2/v2/auth\
           ?access_type=offline\
           &client_id={id}\
@@ -74,8 +177,22 @@ impl GoogleOAuthParams {
           &scope={scopes}",
             id = self.client_id,
             uri = self.redirect_url,
-            scopes = self.scopes
-        )
+            scopes = self.scopes,
+        );
+        if let Some(pkce) = &self.pkce {
+            url.push_str(&format!(
+                "&code_challenge={}&code_challenge_method=S256",
+                pkce.challenge()
+            ));
+        }
+
+        match (login_hint, Url::parse(&url)) {
+            (Some(login_hint), Ok(mut url)) => {
+                url.query_pairs_mut().append_pair("login_hint", login_hint);
+                url.to_string()
+            }
+            _ => url,
+        }
     }
 }
 
@@ -98,6 +215,79 @@ pub async fn request_google_oauth_token(
     }
 }
 
+/// starts the device authorization flow for logging in on a machine without a browser
+pub async fn request_google_device_code(
+    client: &Client,
+    auth_params: &GoogleOAuthParams,
+) -> anyhow::Result<GoogleDeviceCodeResponse> {
+    let res = client
+        .post(GOOGLE_DEVICE_CODE_URL)
+        .form(&auth_params.to_form_device_code_params())
+        .send()
+        .await?;
+
+    match res.status() {
+        StatusCode::OK => Ok(res.json().await?),
+        _ => Err(anyhow!(
+            "an error occurred while trying to retrieve a device code, status code {status}",
+            status = res.status().as_u16(),
+        )),
+    }
+}
+
+/// polls the token endpoint until the user authorized the device, denied it or the device code
+/// expired
+pub async fn poll_google_device_token(
+    client: &Client,
+    auth_params: &GoogleOAuthParams,
+    device: &GoogleDeviceCodeResponse,
+) -> anyhow::Result<GoogleOAuthTokenRequestResponse> {
+    let deadline = Instant::now() + Duration::from_secs(device.expires_in);
+    let mut interval = Duration::from_secs(device.interval);
+
+    loop {
+        tokio::time::sleep(interval).await;
+        if Instant::now() > deadline {
+            return Err(anyhow!(
+                "the device code expired before the login was authorized"
+            ));
+        }
+
+        let res = client
+            .post(GOOGLE_AUTH_ROOT_URL)
+            .form(&auth_params.to_form_device_token_params(&device.device_code))
+            .send()
+            .await?;
+
+        if res.status() == StatusCode::OK {
+            return Ok(res.json().await?);
+        }
+
+        let DeviceTokenError { error } = res.json().await?;
+        interval = next_device_poll_interval(&error, interval)?;
+    }
+}
+
+/// the interval to wait before polling again after the token endpoint responded with `error`.
+///
+/// Errors: if `error` means the authorization failed for good (e.g. `access_denied`)
+fn next_device_poll_interval(error: &str, interval: Duration) -> anyhow::Result<Duration> {
+    match error {
+        "authorization_pending" => Ok(interval),
+        // RFC 8628 section 3.5: the interval must be increased by 5 seconds
+        "slow_down" => Ok(interval + Duration::from_secs(5)),
+        "access_denied" => Err(anyhow!("the login was denied")),
+        "expired_token" => Err(anyhow!(
+            "the device code expired before the login was authorized"
+        )),
+        _ => Err(anyhow!("failed to authorize the device ({error})")),
+    }
+}
+
+fn default_device_poll_interval() -> u64 {
+    5
+}
+
 pub async fn refresh_google_oauth_token(
     client: &Client,
     auth_params: &GoogleOAuthParams,
@@ -117,3 +307,121 @@ pub async fn refresh_google_oauth_token(
         )),
     }
 }
+
+/// the mail address of the account `access_token` was issued for, as Google reports it
+pub async fn fetch_google_account_email(
+    client: &Client,
+    access_token: &str,
+) -> anyhow::Result<String> {
+    let res = client
+        .get(GMAIL_PROFILE_URL)
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+
+    match res.status() {
+        StatusCode::OK => Ok(res.json::<GmailProfile>().await?.email_address),
+        _ => Err(anyhow!(
+            "an error occurred while trying to retrieve the account of the access token, status \
+            code {status}",
+            status = res.status().as_u16(),
+        )),
+    }
+}
+
+impl OAuthProvider for Google {
+    fn imap_host(&self) -> (&str, u16) {
+        (GOOGLE_IMAP_DOMAIN, GOOGLE_IMAP_PORT)
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> anyhow::Result<RefreshedToken> {
+        let GoogleOAuthTokenRefreshResponse {
+            access_token,
+            expires_in,
+        } = refresh_google_oauth_token(
+            &http_client()?,
+            &GoogleOAuthParams::default(),
+            refresh_token,
+        )
+        .await?;
+
+        Ok(RefreshedToken {
+            access_token,
+            expires_in,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_request_url_has_login_hint() {
+        let params = GoogleOAuthParams {
+            client_id: String::from("id"),
+            client_secret: String::from("secret"),
+            redirect_url: String::from("urn:ietf:wg:oauth:2.0:oob"),
+            scopes: String::from("https://mail.google.com"),
+            pkce: Some(Pkce {
+                verifier: String::from("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            }),
+        };
+
+        let url = params.get_token_request_url(None);
+        assert!(url.starts_with("https://accounts.google.com/o/oauth2/v2/auth?access_type=offline"));
+        assert!(url.contains("&client_id=id&"));
+        assert!(url.contains(
+            "&code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM&code_challenge_method=S256"
+        ));
+        assert!(!url.contains("login_hint"));
+        assert!(params.to_form_request_params("code").contains(&(
+            "code_verifier",
+            "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"
+        )));
+
+        let url = params.get_token_request_url(Some("a+b@example.com"));
+        assert!(url.ends_with("&login_hint=a%2Bb%40example.com"));
+
+        // without a verifier to exchange the code with, e.g. for `auth-url`
+        let params = GoogleOAuthParams {
+            pkce: None,
+            ..params
+        };
+        assert!(!params
+            .get_token_request_url(None)
+            .contains("code_challenge"));
+        assert!(!params
+            .to_form_request_params("code")
+            .iter()
+            .any(|(name, _)| *name == "code_verifier"));
+    }
+
+    #[test]
+    fn adjusts_device_poll_interval() {
+        let interval = Duration::from_secs(5);
+
+        assert_eq!(
+            next_device_poll_interval("authorization_pending", interval).unwrap(),
+            interval
+        );
+        assert_eq!(
+            next_device_poll_interval("slow_down", interval).unwrap(),
+            Duration::from_secs(10)
+        );
+        assert!(next_device_poll_interval("access_denied", interval).is_err());
+        assert!(next_device_poll_interval("expired_token", interval).is_err());
+    }
+
+    #[test]
+    fn parses_device_code_response() {
+        let device: GoogleDeviceCodeResponse = serde_json::from_str(
+            r#"{"device_code": "abc", "user_code": "WXYZ-1234",
+                "verification_uri": "https://example.com/device", "expires_in": 1800}"#,
+        )
+        .unwrap();
+
+        assert_eq!(device.verification_url, "https://example.com/device");
+        assert_eq!(device.interval, 5);
+    }
+}
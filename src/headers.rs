@@ -0,0 +1,131 @@
+//! header values of the messages mail-cli composes. they are kept to a single line, so text
+//! from other mails can't add headers, and to ASCII with RFC 2047 encoded words
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// the longest part of the text in a single encoded word, 45 bytes are 60 base64 characters,
+/// which keeps `=?utf-8?B?...?=` within the 75 characters RFC 2047 allows
+const ENCODED_WORD_BYTES: usize = 45;
+
+/// `value` with control characters like CR and LF replaced by spaces, for header values taken
+/// from other mails
+pub fn single_line(value: &str) -> String {
+    value
+        .chars()
+        .map(|char| match char.is_control() {
+            true => ' ',
+            false => char,
+        })
+        .collect()
+}
+
+/// an error if `value` of the `name` header contains control characters like CR and LF, for
+/// header values given by the user
+pub fn check_single_line(name: &str, value: &str) -> anyhow::Result<()> {
+    match value.chars().any(|char| char.is_control()) {
+        true => Err(anyhow!(
+            "the {name} '{}' contains line breaks or other control characters",
+            value.escape_debug()
+        )),
+        false => Ok(()),
+    }
+}
+
+/// `text` as an unstructured header value like `Subject`, on a single line and with non-ASCII
+/// text as encoded words
+pub fn encode_text(text: &str) -> String {
+    let text = single_line(text);
+    match text.is_ascii() {
+        true => text,
+        false => encoded_words(&text),
+    }
+}
+
+/// `"name" <address>` for an address header, or just `address` without a name. a non-ASCII
+/// name is encoded instead of quoted
+pub fn encode_mailbox(name: Option<&str>, address: &str) -> String {
+    let address = single_line(address).replace(['<', '>', ' '], "");
+    let name = name.map(single_line);
+    match name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        Some(name) if name.is_ascii() => {
+            format!("\"{}\" <{address}>", name.replace(['\\', '"'], ""))
+        }
+        Some(name) => format!("{} <{address}>", encoded_words(name)),
+        None => address,
+    }
+}
+
+/// `mailbox` as typed by the user, `Name <address>` or just `address`, for an address header
+pub fn encode_typed_mailbox(mailbox: &str) -> String {
+    let mailbox = mailbox.trim();
+    match mailbox
+        .strip_suffix('>')
+        .and_then(|mailbox| mailbox.rsplit_once('<'))
+    {
+        Some((name, address)) => encode_mailbox(Some(name.trim().trim_matches('"')), address),
+        None => encode_mailbox(None, mailbox),
+    }
+}
+
+/// `text` as UTF-8 base64 encoded words separated by spaces, split between characters
+fn encoded_words(text: &str) -> String {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (idx, char) in text.char_indices() {
+        if idx + char.len_utf8() - start > ENCODED_WORD_BYTES {
+            words.push(&text[start..idx]);
+            start = idx;
+        }
+    }
+    words.push(&text[start..]);
+
+    words
+        .into_iter()
+        .map(|word| format!("=?utf-8?B?{}?=", STANDARD.encode(word)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mail_parser::{HeaderValue, Message};
+
+    #[test]
+    fn encodes_non_ascii_on_one_line() {
+        assert_eq!(encode_text("plans"), "plans");
+        assert_eq!(encode_text("a\r\nBcc: x@evil.com"), "a  Bcc: x@evil.com");
+        assert_eq!(encode_text("Grüße"), "=?utf-8?B?R3LDvMOfZQ==?=");
+        assert_eq!(
+            encode_mailbox(Some("Eve\nBcc: x@evil.com"), "eve@example.com"),
+            "\"Eve Bcc: x@evil.com\" <eve@example.com>"
+        );
+        assert_eq!(
+            encode_mailbox(Some("Jürgen"), "j@example.com"),
+            "=?utf-8?B?SsO8cmdlbg==?= <j@example.com>"
+        );
+        assert_eq!(encode_mailbox(Some(" "), "j@example.com"), "j@example.com");
+        assert_eq!(
+            encode_typed_mailbox("\"Jürgen\" <j@example.com>"),
+            "=?utf-8?B?SsO8cmdlbg==?= <j@example.com>"
+        );
+        assert_eq!(encode_typed_mailbox(" bob@example.com "), "bob@example.com");
+
+        assert!(check_single_line("subject", "plans").is_ok());
+        assert!(check_single_line("subject", "plans\r\nBcc: x@evil.com").is_err());
+
+        // long text is split into several words that decode to the original
+        let subject = "ü".repeat(40);
+        let encoded = encode_text(&subject);
+        assert!(encoded.split(' ').all(|word| word.len() <= 75));
+        let raw = format!("Subject: {encoded}\n\n");
+        let msg = Message::parse(raw.as_bytes()).unwrap();
+        assert_eq!(msg.subject(), Some(subject.as_str()));
+        assert!(matches!(msg.header("Subject"), Some(HeaderValue::Text(_))));
+    }
+}
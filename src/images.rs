@@ -0,0 +1,243 @@
+//! image parts of mails shown inline in terminals that support the kitty or iTerm2 graphics
+//! protocol, other terminals get a list of them
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mail_parser::{Message, MimeHeaders, PartType};
+
+use crate::progress::format_bytes;
+
+/// images larger than this are only listed, they take long to transfer to the terminal and
+/// are rarely worth viewing there
+pub const MAX_INLINE_IMAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// bytes of base64 sent per kitty graphics escape sequence, the maximum the protocol allows
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// a way of drawing images in the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsProtocol {
+    /// kitty's graphics protocol, also implemented by WezTerm and Konsole. only PNGs are sent as
+    /// they are, other formats would have to be decoded first
+    Kitty,
+    /// iTerm2's inline images, also implemented by WezTerm and mintty
+    Iterm2,
+}
+
+/// an image part of a mail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// lowercase subtype of the `image/*` content type, e.g. `png`
+    pub subtype: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl GraphicsProtocol {
+    /// the protocol the terminal supports, judged by the variables it sets in the environment
+    /// given by `env`
+    pub fn detect(env: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let term = env("TERM").unwrap_or_default();
+        let program = env("TERM_PROGRAM").unwrap_or_default();
+
+        if term == "xterm-kitty" || env("KITTY_WINDOW_ID").is_some() {
+            Some(Self::Kitty)
+        } else if ["iTerm.app", "WezTerm", "mintty"].contains(&program.as_str())
+            || env("LC_TERMINAL").is_some_and(|terminal| terminal == "iTerm2")
+        {
+            Some(Self::Iterm2)
+        } else {
+            None
+        }
+    }
+
+    /// whether images of the `image/<subtype>` type can be drawn
+    pub fn supports(&self, subtype: &str) -> bool {
+        match self {
+            Self::Kitty => subtype == "png",
+            Self::Iterm2 => ["png", "jpeg", "jpg", "gif"].contains(&subtype),
+        }
+    }
+
+    /// the escape sequence drawing `image` at the cursor
+    pub fn escape(&self, image: &Image) -> String {
+        let data = STANDARD.encode(&image.data);
+        match self {
+            Self::Kitty => {
+                let chunks = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect::<Vec<_>>();
+                chunks
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, chunk)| {
+                        let more = u8::from(idx + 1 < chunks.len());
+                        let keys = match idx {
+                            0 => format!("a=T,f=100,m={more}"),
+                            _ => format!("m={more}"),
+                        };
+                        // base64 is ASCII
+                        let chunk = String::from_utf8_lossy(chunk);
+                        format!("\x1b_G{keys};{chunk}\x1b\\")
+                    })
+                    .collect()
+            }
+            Self::Iterm2 => {
+                let name = STANDARD.encode(image.filename.as_deref().unwrap_or("image"));
+                format!(
+                    "\x1b]1337;File=name={name};size={size};inline=1:{data}\x07",
+                    size = image.data.len()
+                )
+            }
+        }
+    }
+}
+
+/// the decoded `image/*` parts of `msg`, inline ones as well as attachments. images of attached
+/// messages aren't included
+pub fn image_parts(msg: &Message) -> Vec<Image> {
+    msg.parts
+        .iter()
+        .filter_map(|part| {
+            let content_type = part.content_type()?;
+            if !content_type.ctype().eq_ignore_ascii_case("image") {
+                return None;
+            }
+            let data = match &part.body {
+                PartType::Binary(data) | PartType::InlineBinary(data) => data.to_vec(),
+                _ => return None,
+            };
+
+            Some(Image {
+                subtype: content_type.subtype().unwrap_or_default().to_lowercase(),
+                filename: part.attachment_name().map(|name| name.to_owned()),
+                data,
+            })
+        })
+        .collect()
+}
+
+/// splits `images` into those drawn with `protocol` and those only listed, because the
+/// terminal has no protocol, doesn't support their format or they are too large
+pub fn select_images(
+    images: Vec<Image>,
+    protocol: Option<GraphicsProtocol>,
+) -> (Vec<Image>, Vec<Image>) {
+    images.into_iter().partition(|image| {
+        protocol.is_some_and(|protocol| protocol.supports(&image.subtype))
+            && image.data.len() <= MAX_INLINE_IMAGE_BYTES
+    })
+}
+
+/// the image parts of the raw mail `raw`, drawn with `protocol` where possible and listed
+/// otherwise. empty if the mail has no images
+pub fn render_images(raw: &[u8], protocol: Option<GraphicsProtocol>) -> String {
+    let images = Message::parse(raw)
+        .map(|msg| image_parts(&msg))
+        .unwrap_or_default();
+    let (drawn, listed) = select_images(images, protocol);
+
+    let mut out = String::new();
+    if let Some(protocol) = protocol {
+        for image in &drawn {
+            out.push_str(&protocol.escape(image));
+            out.push('\n');
+        }
+    }
+    for image in listed {
+        out.push_str(&format!(
+            "[image/{subtype}: {name} ({size})]\n",
+            subtype = image.subtype,
+            name = image.filename.as_deref().unwrap_or("unnamed"),
+            size = format_bytes(image.data.len())
+        ));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_images_the_terminal_can_draw() {
+        let large = STANDARD.encode(vec![0; MAX_INLINE_IMAGE_BYTES + 1]);
+        let raw = format!(
+            "From: a@b.c\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            photos\r\n\
+            --b\r\n\
+            Content-Type: image/png\r\n\
+            Content-Disposition: inline; filename=\"logo.png\"\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            iVBORw==\r\n\
+            --b\r\n\
+            Content-Type: image/jpeg\r\n\
+            Content-Disposition: attachment; filename=\"photo.jpg\"\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            /9j/4A==\r\n\
+            --b\r\n\
+            Content-Type: image/png\r\n\
+            Content-Disposition: attachment; filename=\"huge.png\"\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            {large}\r\n\
+            --b\r\n\
+            Content-Type: application/pdf\r\n\
+            Content-Disposition: attachment; filename=\"doc.pdf\"\r\n\
+            \r\n\
+            %PDF\r\n\
+            --b--\r\n"
+        );
+        let msg = Message::parse(raw.as_bytes()).unwrap();
+        let images = image_parts(&msg);
+        let names = |images: &[Image]| {
+            images
+                .iter()
+                .map(|image| image.filename.clone().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&images), ["logo.png", "photo.jpg", "huge.png"]);
+        assert_eq!(images[0].data, [0x89, b'P', b'N', b'G']);
+
+        let (drawn, listed) = select_images(images.clone(), Some(GraphicsProtocol::Kitty));
+        assert_eq!(names(&drawn), ["logo.png"]);
+        assert_eq!(names(&listed), ["photo.jpg", "huge.png"]);
+
+        let (drawn, _) = select_images(images.clone(), Some(GraphicsProtocol::Iterm2));
+        assert_eq!(names(&drawn), ["logo.png", "photo.jpg"]);
+
+        let (drawn, listed) = select_images(images, None);
+        assert!(drawn.is_empty());
+        assert_eq!(listed.len(), 3);
+
+        let rendered = render_images(raw.as_bytes(), Some(GraphicsProtocol::Kitty));
+        assert!(rendered.starts_with("\x1b_Ga=T,f=100,m=0;iVBORw==\x1b\\\n"));
+        assert!(rendered.contains("[image/jpeg: photo.jpg (4 B)]\n"));
+    }
+
+    #[test]
+    fn detects_graphics_protocol() {
+        let detect = |vars: &[(&str, &str)]| {
+            GraphicsProtocol::detect(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        };
+
+        assert_eq!(
+            detect(&[("TERM", "xterm-kitty")]),
+            Some(GraphicsProtocol::Kitty)
+        );
+        assert_eq!(
+            detect(&[("TERM", "xterm-256color"), ("TERM_PROGRAM", "iTerm.app")]),
+            Some(GraphicsProtocol::Iterm2)
+        );
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), None);
+    }
+}
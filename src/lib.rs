@@ -0,0 +1,39 @@
+//! reading and changing mails over IMAP, the library behind the `mail-cli` binary.
+//!
+//! [`mail::MailBox`] reads mails over a [`imap::Session`] the caller owns: the caller connects
+//! and authenticates however it likes, the mailbox selects itself before each command
+
+pub mod audit;
+pub mod auth_results;
+pub mod backup;
+pub mod charset;
+pub mod config;
+pub mod digest;
+pub mod draft;
+pub mod failure;
+pub mod gmail;
+pub mod google;
+pub mod headers;
+pub mod images;
+pub mod mail;
+pub mod mail_filters;
+pub mod mailboxes;
+pub mod mime;
+#[cfg(any(test, feature = "mock-imap"))]
+pub mod mock_imap;
+pub mod nested;
+pub mod pkce;
+pub mod progress;
+pub mod provider;
+pub mod proxy;
+pub mod reply;
+pub mod server;
+pub mod store_accounts;
+pub mod summary_cache;
+pub mod transfer;
+pub mod unsubscribe;
+pub mod utils;
+pub mod watch;
+pub mod yahoo;
+
+pub use mail::{Mail, MailBox};
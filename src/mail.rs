@@ -1,142 +1,3780 @@
 use std::{
+    borrow::Cow,
+    collections::HashSet,
     fmt::Display,
-    net::TcpStream,
-    str::from_utf8, collections::HashSet,
+    io::{Read, Write},
+    sync::OnceLock,
+    time::Duration,
 };
 
 use anyhow::anyhow;
-use imap::Session;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc,
+};
+use clap::ValueEnum;
+use colored::Colorize;
+use dialoguer::console::{measure_text_width, pad_str, Alignment};
+use imap::{
+    types::{Fetch, Flag, NameAttribute},
+    Session,
+};
 use itertools::Itertools;
-use mail_parser::{DateTime, Message};
-use native_tls::TlsStream;
+use mail_parser::{Addr, HeaderValue, Message, MimeHeaders};
+use regex::{Regex, RegexBuilder};
 
-use crate::mail_filters::{HeaderField, HeaderFilter};
+use crate::{
+    auth_results::AuthResults,
+    failure::{is_unavailable, SESSION_RETRIES, SESSION_RETRY_DELAY},
+    gmail::{fetch_gmail_attributes, remove_label_query, supports_gmail_ext, GmailAttributeStore},
+    mail_filters::{quote, HeaderField, HeaderFilter},
+    mime::{mime_structure, render_structure, MimePart},
+    nested::{nested_messages, render_nested, NestedMessage},
+    progress::{ByteCounter, Progress},
+    unsubscribe::{parse_list_unsubscribe, Unsubscribe},
+    utils::{truncate_at_char_boundary, truncate_to_width},
+};
 
 #[derive(Debug, Clone)]
 pub struct Mail {
-    #[allow(dead_code)]
     ord_num: u32,
+    /// the UID the mail was fetched with, which the commands changing mails take
+    uid: Option<u32>,
+    /// the mailbox the mail was fetched from, UIDs are only unique within it
+    mailbox: Option<String>,
+    message_id: Option<String>,
+    /// message ids from the `In-Reply-To` and `References` headers, oldest first
+    references: Vec<String>,
+    thread_id: Option<u64>,
+    labels: Vec<String>,
     from: Option<String>,
+    /// the display names and addresses parsed from the `From` header
+    senders: Vec<(Option<String>, Option<String>)>,
     to: Option<String>,
-    date: Option<DateTime>,
+    date: Option<DateTime<FixedOffset>>,
     subject: Option<String>,
+    priority: Priority,
     body: String,
+    encryption: Option<Encryption>,
+    /// the system flags set on the mail, in the order of [`MailFlag::ALL`]
+    flags: Vec<MailFlag>,
+    list_unsubscribe: Option<String>,
+    list_unsubscribe_post: Option<String>,
+    /// all headers in the order they appear in, with folded values unfolded
+    headers: Vec<(String, String)>,
+    /// the `text/html` parts of the body, if there are any
+    html_body: Option<String>,
+    /// whether the mail is in a sent or drafts mailbox, where the recipient is more relevant
+    /// than the sender
+    outgoing: bool,
+    structure: Vec<MimePart>,
+    /// the messages attached as `message/rfc822` parts
+    nested: Vec<NestedMessage>,
+    /// the message exactly as fetched, only the header block if just that was fetched
+    raw: Vec<u8>,
+}
+
+/// how the body of an encrypted [`Mail`] is encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    Pgp,
+    Smime,
+}
+
+pub const DEFAULT_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M %:z";
+
+/// options controlling how a [`Mail`] is displayed
+#[derive(Debug, Clone)]
+pub struct DisplayOptions {
+    /// maximum number of bytes of the body to display
+    pub body_limit: Option<usize>,
+    /// only display the first or last lines of the body
+    pub body_lines: Option<BodyLines>,
+    pub from_style: FromStyle,
+    /// strftime style format of the send date
+    pub date_format: String,
+    /// timezone the send date is displayed in
+    pub timezone: DisplayTimezone,
+    /// headers displayed in addition to the default ones
+    pub extra_headers: ExtraHeaders,
+    /// display the SPF, DKIM and DMARC results
+    pub show_auth: bool,
+    /// render HTML bodies with basic formatting and footnoted links (see [`render_html`])
+    pub rich: bool,
+    /// show the HTML body converted to text (see [`html_to_text`]) even if there is a plain
+    /// text one
+    pub prefer_html: bool,
+    pub format: OutputFormat,
+    /// terms highlighted (case-insensitive) in the sender, subject and body
+    pub highlight: Vec<String>,
+    /// show the MIME part tree (see [`mime_structure`]) instead of the body
+    pub structure: bool,
+    /// show the attached messages (see [`NestedMessage::render`]) after the body
+    pub nested: bool,
+    /// fields included in the JSON output, all if empty
+    pub fields: Vec<MailField>,
+    /// show send dates of the last week relative to this time (see [`relative_date`])
+    pub relative_to: Option<DateTime<Local>>,
+}
+
+impl MailField {
+    /// the key of the field in the JSON output
+    pub fn key(&self) -> &'static str {
+        match self {
+            MailField::Uid => "uid",
+            MailField::From => "from",
+            MailField::To => "to",
+            MailField::Date => "date",
+            MailField::Subject => "subject",
+            MailField::MessageId => "message_id",
+            MailField::Labels => "labels",
+            MailField::ThreadId => "thread_id",
+            MailField::Flagged => "flagged",
+            MailField::Flags => "flags",
+            MailField::Priority => "priority",
+            MailField::Body => "body",
+        }
+    }
+}
+
+impl OpenMode {
+    /// opens `mailbox`, trying again with backoff while the server answers that it is
+    /// unavailable for now
+    pub fn open<T: Read + Write>(
+        self,
+        mailbox: &str,
+        session: &mut Session<T>,
+    ) -> imap::error::Result<imap::types::Mailbox> {
+        self.open_retrying(mailbox, session, SESSION_RETRY_DELAY)
+    }
+
+    fn open_retrying<T: Read + Write>(
+        self,
+        mailbox: &str,
+        session: &mut Session<T>,
+        delay: Duration,
+    ) -> imap::error::Result<imap::types::Mailbox> {
+        let mut retries = 0;
+        loop {
+            let opened = match self {
+                Self::ReadOnly => session.examine(mailbox),
+                Self::ReadWrite => session.select(mailbox),
+            };
+            match opened {
+                Err(err) if retries < SESSION_RETRIES && is_unavailable(&err) => {
+                    std::thread::sleep(delay * 2u32.pow(retries));
+                    retries += 1;
+                }
+                opened => return opened,
+            }
+        }
+    }
+}
+
+impl MailFlag {
+    pub const ALL: [MailFlag; 5] = [
+        MailFlag::Seen,
+        MailFlag::Answered,
+        MailFlag::Flagged,
+        MailFlag::Draft,
+        MailFlag::Deleted,
+    ];
+
+    /// `None` for flags other than the system flags, e.g. keywords
+    pub fn from_imap(flag: &Flag) -> Option<Self> {
+        match flag {
+            Flag::Seen => Some(Self::Seen),
+            Flag::Answered => Some(Self::Answered),
+            Flag::Flagged => Some(Self::Flagged),
+            Flag::Draft => Some(Self::Draft),
+            Flag::Deleted => Some(Self::Deleted),
+            _ => None,
+        }
+    }
+
+    /// the flag called `name` by [`MailFlag::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|flag| flag.name() == name)
+    }
+
+    pub fn glyph(&self) -> char {
+        match self {
+            Self::Seen => '✓',
+            Self::Answered => '↩',
+            Self::Flagged => '★',
+            Self::Draft => '✎',
+            Self::Deleted => '✗',
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Seen => "seen",
+            Self::Answered => "answered",
+            Self::Flagged => "flagged",
+            Self::Draft => "draft",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+impl Priority {
+    /// the priority given by the first of the headers the mail has:
+    ///
+    /// - `X-Priority`: 1 (highest) to 5 (lowest), often followed by a name like "1 (Highest)"
+    /// - `Importance` (RFC 2156): `high`, `normal` or `low`
+    /// - `Priority` (RFC 2156): `urgent`, `normal` or `non-urgent`
+    ///
+    /// values that don't mean anything are skipped
+    pub fn from_headers(
+        x_priority: Option<&str>,
+        importance: Option<&str>,
+        priority: Option<&str>,
+    ) -> Self {
+        let x_priority = x_priority.and_then(|value| match value.trim().chars().next()? {
+            '1' | '2' => Some(Self::High),
+            '3' => Some(Self::Normal),
+            '4' | '5' => Some(Self::Low),
+            _ => None,
+        });
+        let named = |value: Option<&str>, high: &str, low: &str| {
+            let value = value?.trim().to_lowercase();
+            match value.as_str() {
+                "normal" => Some(Self::Normal),
+                _ if value == high => Some(Self::High),
+                _ if value == low => Some(Self::Low),
+                _ => None,
+            }
+        };
+
+        x_priority
+            .or_else(|| named(importance, "high", "low"))
+            .or_else(|| named(priority, "urgent", "non-urgent"))
+            .unwrap_or_default()
+    }
+
+    /// the glyph shown next to the flags of mails that aren't of normal priority
+    pub fn glyph(&self) -> Option<char> {
+        match self {
+            Self::High => Some('!'),
+            Self::Normal => None,
+            Self::Low => Some('↓'),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Normal => "normal",
+            Self::Low => "low",
+        }
+    }
+}
+
+/// the glyphs of `flags`, e.g. "✓★"
+pub fn flag_glyphs(flags: &[MailFlag]) -> String {
+    flags.iter().map(MailFlag::glyph).collect()
+}
+
+/// explains the glyphs of the flags and priorities of any of `mails`, e.g. "✓ seen  ★ flagged
+/// ! high priority", `None` if none of them has flags or a priority other than normal
+pub fn flags_legend(mails: &[anyhow::Result<Mail>]) -> Option<String> {
+    let flags = MailFlag::ALL
+        .iter()
+        .filter(|flag| mails.iter().flatten().any(|mail| mail.flags.contains(flag)))
+        .map(|flag| format!("{} {}", flag.glyph(), flag.name()));
+    let priorities = [Priority::High, Priority::Low]
+        .into_iter()
+        .filter(|priority| {
+            mails
+                .iter()
+                .flatten()
+                .any(|mail| mail.priority == *priority)
+        })
+        .filter_map(|priority| {
+            let glyph = priority.glyph()?;
+            Some(format!("{glyph} {} priority", priority.name()))
+        });
+    let legend = flags.chain(priorities).join("  ");
+
+    (!legend.is_empty()).then_some(legend)
+}
+
+/// `date` relative to `now` like "2 hours ago" or "yesterday", `None` for dates more than a week
+/// before `now` or after it. days are counted in the timezone of `now`
+pub fn relative_date<Tz: TimeZone>(
+    date: DateTime<FixedOffset>,
+    now: DateTime<Tz>,
+) -> Option<String> {
+    let plural = |count: i64, unit: &str| match count {
+        1 => format!("1 {unit} ago"),
+        _ => format!("{count} {unit}s ago"),
+    };
+
+    let elapsed = now.clone().signed_duration_since(date);
+    if elapsed < chrono::Duration::zero() {
+        return None;
+    }
+    if elapsed < chrono::Duration::minutes(1) {
+        return Some(String::from("just now"));
+    }
+    if elapsed < chrono::Duration::hours(1) {
+        return Some(plural(elapsed.num_minutes(), "minute"));
+    }
+    if elapsed < chrono::Duration::days(1) {
+        return Some(plural(elapsed.num_hours(), "hour"));
+    }
+
+    let days = (now.date_naive() - date.with_timezone(&now.timezone()).date_naive()).num_days();
+    match days {
+        1 => Some(String::from("yesterday")),
+        2..=7 => Some(plural(days, "day")),
+        _ => None,
+    }
+}
+
+/// the format a [`Mail`] is displayed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// the headers followed by the body
+    #[default]
+    Text,
+    /// a markdown document with the headers as YAML frontmatter and the subject as heading,
+    /// HTML bodies are converted to markdown
+    Markdown,
+    /// a JSON object per mail with the fields selected by `--fields`
+    Json,
+    /// the original messages as an mbox, for piping into tools like `formail` (see
+    /// [`write_mbox`])
+    Eml,
+}
+
+/// an IMAP system flag, shown as a single glyph (see [`MailFlag::glyph`]) wherever flags are
+/// displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MailFlag {
+    Seen,
+    Answered,
+    /// shown as starred by Gmail
+    Flagged,
+    Draft,
+    Deleted,
+}
+
+/// how urgent the sender marked a mail, from whichever of the `X-Priority`, `Importance` and
+/// `Priority` headers it has (see [`Priority::from_headers`])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
 }
 
+/// how a mailbox is opened before working with its mails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// `EXAMINE`, neither the mailbox nor its mails can be changed, e.g. the `\\Recent` flags
+    /// aren't cleared
+    ReadOnly,
+    /// `SELECT`, needed to change flags or move mails
+    ReadWrite,
+}
+
+/// a field of a [`Mail`] in the JSON output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MailField {
+    Uid,
+    From,
+    To,
+    Date,
+    Subject,
+    #[value(name = "message_id")]
+    MessageId,
+    Labels,
+    #[value(name = "thread_id")]
+    ThreadId,
+    Flagged,
+    Flags,
+    Priority,
+    Body,
+}
+
+/// how [`MailBox::delete`] deletes mails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deletion {
+    /// moved to the Trash, from where they can be restored
+    Trash,
+    /// marked as deleted and expunged
+    Permanent,
+}
+
+/// how the sender of a mail is displayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FromStyle {
+    /// only the display name, or the address if there is none
+    Name,
+    /// only the address
+    Address,
+    /// `Name <address>`
+    #[default]
+    Both,
+}
+
+/// the lines of the body that are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLines {
+    /// the first n lines
+    Head(usize),
+    /// the last n lines
+    Tail(usize),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ExtraHeaders {
+    #[default]
+    None,
+    /// the headers with these names (case-insensitive)
+    Named(Vec<String>),
+    All,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisplayTimezone {
+    /// the offset the sender used
+    #[default]
+    Sender,
+    /// the local timezone of the system
+    Local,
+    Utc,
+}
+
+/// a [`Mail`] together with the options it is displayed with
+pub struct MailDisplay<'a> {
+    mail: &'a Mail,
+    options: &'a DisplayOptions,
+}
+
+/// the name of the mailbox new mails are delivered to
+const INBOX: &str = "INBOX";
+
+/// default maximum number of mails fetched with a single IMAP command, some servers reject
+/// overly long command lines
+pub const DEFAULT_FETCH_BATCH_SIZE: usize = 200;
+
+/// default number of times a dropped connection is reopened while fetching
+pub const DEFAULT_FETCH_RETRIES: usize = 3;
+
+/// reopens a dropped connection, returning a new logged in session. it's passed a notice like
+/// "connection lost, reconnecting (1/3)" to show to the user
+pub type Reconnect<'a, T> = dyn FnMut(&str) -> anyhow::Result<Session<T>> + 'a;
+
+/// a mailbox that mails are read from and changed in over a session owned by the caller.
+///
+/// the caller connects and authenticates the [`Session`] however it likes, over any `Read +
+/// Write` transport. the methods only select the mailbox they work on, replacing whichever one
+/// was selected before, and neither log in nor out, so the session can be used further
 #[derive(Debug, Clone)]
 pub struct MailBox<'a> {
     name: &'a str,
+    progress: Option<Progress>,
+    batch_size: usize,
+    /// only fetch the headers of mails, leaving their bodies empty
+    headers_only: bool,
+    /// fetch without `PEEK` so the server marks the mails as seen
+    mark_seen: bool,
+    /// counts the bytes of the fetched mails
+    downloaded: Option<ByteCounter>,
+    /// how often the connection is reopened if it drops while fetching
+    retries: usize,
+    /// which mails count as the most recent ones
+    sort_by: SortBy,
+}
+
+/// what the most recent mails of a mailbox are determined by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortBy {
+    /// the `Date` header set by the sender, which needs the header of every mail to be fetched
+    /// and can be wrong for misdated or spoofed mails
+    #[default]
+    Date,
+    /// the order the server received the mails in, without fetching anything
+    Arrival,
 }
 
 impl<'a> MailBox<'a> {
     #[allow(dead_code)]
-    pub const INBOX: MailBox<'a> = MailBox { name: "Inbox" };
+    pub const INBOX: MailBox<'a> = MailBox {
+        name: INBOX,
+        progress: None,
+        batch_size: DEFAULT_FETCH_BATCH_SIZE,
+        headers_only: false,
+        mark_seen: false,
+        downloaded: None,
+        retries: 0,
+        sort_by: SortBy::Date,
+    };
 
+    /// the reserved name `INBOX` is case-insensitive and normalized to uppercase, the names of
+    /// all other mailboxes are case-sensitive and kept as they are
     pub fn new(name: &'a str) -> Self {
-        Self { name }
+        Self {
+            name: if name.eq_ignore_ascii_case(INBOX) {
+                INBOX
+            } else {
+                name
+            },
+            progress: None,
+            batch_size: DEFAULT_FETCH_BATCH_SIZE,
+            headers_only: false,
+            mark_seen: false,
+            downloaded: None,
+            retries: 0,
+            sort_by: SortBy::Date,
+        }
+    }
+
+    /// fetches at most `batch_size` mails per IMAP command
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// only fetches the headers of mails, which is faster if the body isn't displayed
+    pub fn with_headers_only(mut self, headers_only: bool) -> Self {
+        self.headers_only = headers_only;
+        self
+    }
+
+    /// lets the server set the `\\Seen` flag of fetched mails, like opening them in a mail client
+    /// would
+    pub fn with_mark_seen(mut self, mark_seen: bool) -> Self {
+        self.mark_seen = mark_seen;
+        self
+    }
+
+    /// adds the size of the fetched mails to `downloaded`
+    pub fn with_byte_counter(mut self, downloaded: Option<ByteCounter>) -> Self {
+        self.downloaded = downloaded;
+        self
+    }
+
+    /// reopens the connection at most `retries` times if it drops while fetching, see
+    /// [`MailBox::fetch_n_recent_mails_reconnecting`]
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// what decides which mails are the most recent ones
+    pub fn with_sort_by(mut self, sort_by: SortBy) -> Self {
+        self.sort_by = sort_by;
+        self
+    }
+
+    /// reports the number of parsed mails to `progress` while fetching
+    pub fn with_progress(mut self, progress: Option<Progress>) -> Self {
+        self.progress = progress;
+        self
     }
 
     pub fn name(&self) -> &str {
         self.name
     }
 
-    pub fn fetch_n_recent_mails(
+    #[allow(dead_code)]
+    pub fn fetch_n_recent_mails<T: Read + Write>(
+        &self,
+        n: usize,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<Vec<anyhow::Result<Mail>>> {
+        self.fetch_n_recent_mails_matching(n, "ALL", session)
+    }
+
+    /// like [`MailBox::fetch_n_recent_mails`] but only considers mails matching the IMAP search
+    /// `query`
+    pub fn fetch_n_recent_mails_matching<T: Read + Write>(
+        &self,
+        n: usize,
+        query: &str,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<Vec<anyhow::Result<Mail>>> {
+        self.fetch_n_recent_mails_reconnecting(n, query, session, None)
+    }
+
+    /// like [`MailBox::fetch_n_recent_mails_matching`] but if the connection drops while
+    /// searching or fetching the mails, it is reopened with `reconnect` (up to the configured
+    /// number of retries) and only the step that failed is repeated, batches that were already
+    /// fetched aren't fetched again.
+    ///
+    /// the mails are searched and fetched by UID, so the remaining mails are the same ones after
+    /// reconnecting even if other clients expunged mails in the meantime
+    pub fn fetch_n_recent_mails_reconnecting<T: Read + Write>(
         &self,
         n: usize,
-        session: &mut Session<TlsStream<TcpStream>>,
+        query: &str,
+        session: &mut Session<T>,
+        mut reconnect: Option<&mut Reconnect<T>>,
     ) -> anyhow::Result<Vec<anyhow::Result<Mail>>> {
-        session.select(self.name())?;
+        let mut retries = 0;
+        self.reconnecting(session, &mut reconnect, &mut retries, |session| {
+            Ok(self.read_mode().open(self.name(), session)?)
+        })?;
+
+        let recent_uids = self.reconnecting(session, &mut reconnect, &mut retries, |session| {
+            match self.sort_by {
+                SortBy::Date => get_mails_sorted_by_date(query, self.batch_size, session),
+                // UIDs are assigned in the order mails were added to the mailbox
+                SortBy::Arrival => Ok(session
+                    .uid_search(query)?
+                    .into_iter()
+                    .sorted()
+                    .rev()
+                    .collect()),
+            }
+        })?;
+        if recent_uids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let uids = recent_uids.into_iter().take(n).sorted().collect::<Vec<_>>();
+        if let Some(progress) = &self.progress {
+            progress.add_total(uids.len());
+        }
+
+        let mut mails = Vec::with_capacity(uids.len());
+        for batch in uids.chunks(self.batch_size) {
+            let mailbox_items =
+                self.reconnecting(session, &mut reconnect, &mut retries, |session| {
+                    Ok(session.uid_fetch(batch.iter().join(","), self.fetch_query())?)
+                })?;
+            mails.extend(
+                mailbox_items
+                    .iter()
+                    .inspect(|item| {
+                        if let Some(progress) = &self.progress {
+                            progress.inc();
+                        }
+                        if let Some(downloaded) = &self.downloaded {
+                            downloaded.add(item.body().or(item.header()).unwrap_or_default().len());
+                        }
+                    })
+                    .map(Mail::from_fetch),
+            );
+        }
+        mails.reverse();
+
+        if let Some(progress) = &self.progress {
+            progress.clear();
+        }
+
+        Ok(mails)
+    }
+
+    /// runs `step`, and if the connection drops while it does, reopens it with `reconnect` and
+    /// runs it again. `retries` counts the reconnects of all steps of a fetch, which are limited
+    /// by [`MailBox::with_retries`]
+    fn reconnecting<T: Read + Write, R>(
+        &self,
+        session: &mut Session<T>,
+        reconnect: &mut Option<&mut Reconnect<T>>,
+        retries: &mut usize,
+        mut step: impl FnMut(&mut Session<T>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        loop {
+            let err = match step(session) {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let lost = err
+                .downcast_ref::<imap::Error>()
+                .is_some_and(is_connection_lost);
+            let reconnect = match reconnect.as_deref_mut() {
+                Some(reconnect) if lost && *retries < self.retries => reconnect,
+                _ => return Err(err),
+            };
+
+            *retries += 1;
+            *session = reconnect(&format!(
+                "connection lost, reconnecting ({retries}/{max})",
+                max = self.retries
+            ))?;
+            self.read_mode().open(self.name(), session)?;
+        }
+    }
+
+    /// reading only has to open the mailbox for writing if the fetched mails are marked as seen
+    pub fn read_mode(&self) -> OpenMode {
+        match self.mark_seen {
+            true => OpenMode::ReadWrite,
+            false => OpenMode::ReadOnly,
+        }
+    }
+
+    /// the `FETCH` data items for fetching mails, `BODY.PEEK` leaves the `\\Seen` flag alone while
+    /// `BODY` sets it
+    pub fn fetch_query(&self) -> &'static str {
+        match (self.headers_only, self.mark_seen) {
+            (false, false) => "(UID FLAGS BODY.PEEK[])",
+            (false, true) => "(UID FLAGS BODY[])",
+            (true, false) => "(UID FLAGS BODY.PEEK[HEADER])",
+            (true, true) => "(UID FLAGS BODY[HEADER])",
+        }
+    }
+
+    /// number of mails matching the IMAP search `query`
+    pub fn count_matching<T: Read + Write>(
+        &self,
+        query: &str,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<usize> {
+        OpenMode::ReadOnly.open(self.name(), session)?;
+
+        Ok(session.search(query)?.len())
+    }
+
+    /// fetches the mail with the given `uid`
+    pub fn fetch_mail_by_uid<T: Read + Write>(
+        &self,
+        uid: u32,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<Mail> {
+        OpenMode::ReadOnly.open(self.name(), session)?;
+
+        let items = session.uid_fetch(uid.to_string(), "(UID FLAGS BODY.PEEK[])")?;
+        match items.first() {
+            Some(item) => Mail::from_fetch(item),
+            None => Err(anyhow!(
+                "no mail with UID {uid} in mailbox '{name}'",
+                name = self.name()
+            )),
+        }
+    }
+
+    /// the headers of the mails with the given `uids` in the same order, paired with their UID.
+    /// UIDs without a mail are left out
+    pub fn fetch_headers_by_uid<T: Read + Write>(
+        &self,
+        uids: &[u32],
+        session: &mut Session<T>,
+    ) -> anyhow::Result<Vec<(u32, anyhow::Result<Mail>)>> {
+        if uids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        OpenMode::ReadOnly.open(self.name(), session)?;
+        let items = session.uid_fetch(uids.iter().join(","), "(UID FLAGS BODY.PEEK[HEADER])")?;
+
+        Ok(uids
+            .iter()
+            .filter_map(|uid| {
+                let item = items.iter().find(|item| item.uid == Some(*uid))?;
+                Some((*uid, Mail::from_fetch(item)))
+            })
+            .collect())
+    }
+
+    /// whether this is a sent or drafts mailbox, based on the special-use attributes the server
+    /// returns for it from `LIST` or else its name
+    pub fn is_outgoing<T: Read + Write>(&self, session: &mut Session<T>) -> anyhow::Result<bool> {
+        let names = session.list(Some(""), Some(&quote(self.name())))?;
+
+        Ok(match names.first() {
+            Some(name) => is_outgoing_mailbox(name.name(), name.attributes()),
+            None => is_outgoing_mailbox(self.name(), &[]),
+        })
+    }
+
+    /// sets or clears the `\\Flagged` flag (starred on Gmail) of the mails with the given `uids`
+    pub fn set_flagged<T: Read + Write>(
+        &self,
+        uids: &[u32],
+        flagged: bool,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<()> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+
+        OpenMode::ReadWrite.open(self.name(), session)?;
+        session.uid_store(uid_set(uids), flagged_store_query(flagged))?;
+
+        Ok(())
+    }
 
-        let recent_ord_nums = get_mails_sorted_by_date(session)?;
-        let fetch_str = recent_ord_nums
+    /// archives the mails with the given `uids`.
+    ///
+    /// on Gmail this removes their `\\Inbox` label, other servers move them to `archive` or, if
+    /// not set, the mailbox with the `\\Archive` special-use attribute or named "Archive"
+    pub fn archive<T: Read + Write>(
+        &self,
+        uids: &[u32],
+        archive: Option<&str>,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<()> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+
+        if supports_gmail_ext(session)? {
+            OpenMode::ReadWrite.open(self.name(), session)?;
+            session.uid_store(uid_set(uids), remove_label_query("\\Inbox"))?;
+            return Ok(());
+        }
+
+        let target = match archive {
+            Some(archive) => archive.to_owned(),
+            None => find_special_mailbox("\\Archive", session)?.unwrap_or(String::from("Archive")),
+        };
+        self.move_mails(uids, &target, session)
+    }
+
+    /// moves the mails with the given `uids` to the mailbox with the `\\Trash` special-use
+    /// attribute, "[Gmail]/Trash" on Gmail or "Trash" if there is none
+    pub fn trash<T: Read + Write>(
+        &self,
+        uids: &[u32],
+        session: &mut Session<T>,
+    ) -> anyhow::Result<()> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+
+        let target = trash_mailbox(session)?;
+        self.move_mails(uids, &target, session)
+    }
+
+    /// how mails of this mailbox are deleted: moved to the Trash unless `permanent` is set or
+    /// this is the Trash already
+    pub fn deletion<T: Read + Write>(
+        &self,
+        permanent: bool,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<Deletion> {
+        Ok(match permanent || trash_mailbox(session)? == self.name() {
+            true => Deletion::Permanent,
+            false => Deletion::Trash,
+        })
+    }
+
+    /// deletes the mails with the given `uids` as decided by [`MailBox::deletion`]. permanently
+    /// deleted mails are marked as deleted and expunged, without `UIDPLUS` this fails before
+    /// changing anything if other mails are marked as deleted, see
+    /// [`MailBox::check_expunge_only`]
+    pub fn delete<T: Read + Write>(
+        &self,
+        uids: &[u32],
+        deletion: Deletion,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<()> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+
+        match deletion {
+            Deletion::Trash => self.trash(uids, session),
+            Deletion::Permanent => {
+                let uidplus = self.check_expunge_only(uids, session)?;
+                OpenMode::ReadWrite.open(self.name(), session)?;
+                session.uid_store(uid_set(uids), "+FLAGS.SILENT (\\Deleted)")?;
+                match uidplus {
+                    true => session.uid_expunge(uid_set(uids))?,
+                    false => session.expunge()?,
+                };
+                Ok(())
+            }
+        }
+    }
+
+    /// the UIDs of all mails in ascending order
+    pub fn uids<T: Read + Write>(&self, session: &mut Session<T>) -> anyhow::Result<Vec<u32>> {
+        OpenMode::ReadOnly.open(self.name(), session)?;
+
+        Ok(session.uid_search("ALL")?.into_iter().sorted().collect())
+    }
+
+    /// the UIDs of the mails marked as deleted, e.g. by other clients, in ascending order
+    pub fn deleted_uids<T: Read + Write>(
+        &self,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<Vec<u32>> {
+        OpenMode::ReadOnly.open(self.name(), session)?;
+
+        Ok(session
+            .uid_search("DELETED")?
             .into_iter()
-            .take(n)
-            .map(|x| x.to_string())
-            .collect::<Vec<_>>()
-            .join(",");
+            .sorted()
+            .collect())
+    }
+
+    /// permanently removes the mails with the given `uids` if they are marked as deleted and
+    /// returns the number of removed mails. without `UIDPLUS` this fails if other mails are
+    /// marked as deleted, see [`MailBox::check_expunge_only`]
+    pub fn expunge<T: Read + Write>(
+        &self,
+        uids: &[u32],
+        session: &mut Session<T>,
+    ) -> anyhow::Result<usize> {
+        if uids.is_empty() {
+            return Ok(0);
+        }
+
+        let uidplus = self.check_expunge_only(uids, session)?;
+        OpenMode::ReadWrite.open(self.name(), session)?;
+        let expunged = match uidplus {
+            true => session.uid_expunge(uid_set(uids))?,
+            false => session.expunge()?,
+        };
 
-        let mailbox_items = session.fetch(&fetch_str, "BODY.PEEK[]")?;
-        let mails: Vec<_> = mailbox_items
+        Ok(expunged.len())
+    }
+
+    /// moves the mails with the given `uids` to the mailbox `target`.
+    ///
+    /// servers without the `MOVE` extension get a copy, the originals are marked as deleted and
+    /// expunged. without `UIDPLUS` as well this fails before changing anything if other mails
+    /// are marked as deleted, see [`MailBox::check_expunge_only`]
+    pub fn move_mails<T: Read + Write>(
+        &self,
+        uids: &[u32],
+        target: &str,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<()> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+
+        let move_ext = session.capabilities()?.has_str("MOVE");
+        let uidplus = match move_ext {
+            true => true,
+            false => self.check_expunge_only(uids, session)?,
+        };
+
+        let uid_set = uid_set(uids);
+        OpenMode::ReadWrite.open(self.name(), session)?;
+        if move_ext {
+            session.uid_mv(&uid_set, target)?;
+            return Ok(());
+        }
+
+        session.uid_copy(&uid_set, target)?;
+        session.uid_store(&uid_set, "+FLAGS.SILENT (\\Deleted)")?;
+        match uidplus {
+            true => session.uid_expunge(&uid_set)?,
+            false => session.expunge()?,
+        };
+
+        Ok(())
+    }
+
+    /// an error if expunging the mails with the given `uids` would remove other mails as well,
+    /// returns whether the server supports `UIDPLUS`.
+    ///
+    /// without `UIDPLUS` only a plain `EXPUNGE` is possible, which removes every mail marked as
+    /// deleted, e.g. by other clients. it's only allowed if there are none besides `uids`, mails
+    /// marked after this check are still removed with them
+    fn check_expunge_only<T: Read + Write>(
+        &self,
+        uids: &[u32],
+        session: &mut Session<T>,
+    ) -> anyhow::Result<bool> {
+        if session.capabilities()?.has_str("UIDPLUS") {
+            return Ok(true);
+        }
+
+        let uids = uids.iter().collect::<HashSet<_>>();
+        let others = self
+            .deleted_uids(session)?
             .into_iter()
-            .map(|item| {
-                let msg_str = from_utf8(item.body().unwrap_or(&[])).map(|str| str.to_owned())?;
-                let Some(parsed_msg) = Message::parse(msg_str.as_bytes()) else { 
-                    return Err(anyhow!("failed to parse mail"))
-                };
+            .filter(|uid| !uids.contains(uid))
+            .count();
+        match others {
+            0 => Ok(false),
+            others => Err(anyhow!(
+                "'{}' has {others} other mail(s) marked as deleted, which the server (without \
+                UIDPLUS support) would remove as well",
+                self.name()
+            )),
+        }
+    }
+}
+
+/// the mailbox deleted mails are moved to: the one with the `\\Trash` special-use attribute,
+/// "[Gmail]/Trash" on Gmail or "Trash" if there is none
+pub fn trash_mailbox<T: Read + Write>(session: &mut Session<T>) -> anyhow::Result<String> {
+    Ok(match find_special_mailbox("\\Trash", session)? {
+        Some(trash) => trash,
+        None if supports_gmail_ext(session)? => String::from("[Gmail]/Trash"),
+        None => String::from("Trash"),
+    })
+}
+
+/// the name of the mailbox with the RFC 6154 special-use `attribute` (e.g. `\\Trash`), if the
+/// server announces one
+pub fn find_special_mailbox<T: Read + Write>(
+    attribute: &str,
+    session: &mut Session<T>,
+) -> anyhow::Result<Option<String>> {
+    let names = session.list(Some(""), Some("*"))?;
 
-                Ok(Mail::from_msg(parsed_msg, item.message))
-                
+    Ok(names
+        .iter()
+        .find(|name| {
+            name.attributes().iter().any(|attr| match attr {
+                NameAttribute::Custom(custom) => custom.eq_ignore_ascii_case(attribute),
+                _ => false,
             })
-            .rev()
-            .collect();
+        })
+        .map(|name| name.name().to_owned()))
+}
 
-        Ok(mails)
+/// whether a mailbox with the given `name` and `LIST` attributes holds mail sent (or to be sent)
+/// by the user.
+///
+/// the RFC 6154 special-use attributes `\\Sent` and `\\Drafts` are preferred, servers without
+/// them are recognized by common folder names like "Sent Items" or "INBOX.Drafts"
+pub fn is_outgoing_mailbox(name: &str, attributes: &[NameAttribute]) -> bool {
+    let special_use = attributes.iter().any(|attribute| match attribute {
+        NameAttribute::Custom(attribute) => ["\\Sent", "\\Drafts"]
+            .iter()
+            .any(|special| attribute.eq_ignore_ascii_case(special)),
+        _ => false,
+    });
+    if special_use {
+        return true;
+    }
+
+    let leaf = name
+        .rsplit(['/', '.'])
+        .next()
+        .unwrap_or(name)
+        .to_lowercase();
+    [
+        "sent",
+        "sent mail",
+        "sent items",
+        "sent messages",
+        "drafts",
+        "draft",
+    ]
+    .contains(&leaf.as_str())
+}
+
+/// the IMAP sequence set of `uids` with consecutive UIDs joined to ranges, e.g. `1:3,7,9:10`, so
+/// the commands stay short when many mails are affected
+pub fn uid_set(uids: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for uid in uids.iter().copied().sorted().dedup() {
+        match ranges.last_mut() {
+            Some((_, end)) if end.checked_add(1) == Some(uid) => *end = uid,
+            _ => ranges.push((uid, uid)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{start}:{end}"),
+        })
+        .join(",")
+}
+
+/// whether `err` means the connection to the server is gone, as opposed to the server rejecting
+/// a command
+fn is_connection_lost(err: &imap::Error) -> bool {
+    matches!(
+        err,
+        imap::Error::Io(_) | imap::Error::ConnectionLost | imap::Error::Tls(_)
+    )
+}
+
+/// the `STORE` data item adding or removing the `\\Flagged` flag without the server echoing the
+/// new flags back
+fn flagged_store_query(flagged: bool) -> &'static str {
+    if flagged {
+        "+FLAGS.SILENT (\\Flagged)"
+    } else {
+        "-FLAGS.SILENT (\\Flagged)"
     }
 }
 
 impl Mail {
+    /// parses a fetched `(UID FLAGS BODY[])` or `(UID FLAGS BODY[HEADER])` item.
+    ///
+    /// the raw bytes are handed to `mail_parser` as is, it decodes each part according to its
+    /// charset and replaces undecodable bytes instead of assuming the whole mail is UTF-8
+    pub fn from_fetch(item: &Fetch) -> anyhow::Result<Self> {
+        let Some(parsed_msg) = item.body().or(item.header()).and_then(Message::parse) else {
+            return Err(anyhow!("failed to parse message {seq}", seq = item.message));
+        };
+
+        let mut mail = Mail::from_msg(parsed_msg, item.message);
+        mail.uid = item.uid;
+        mail.flags = item
+            .flags()
+            .iter()
+            .filter_map(MailFlag::from_imap)
+            .sorted()
+            .dedup()
+            .collect();
+
+        Ok(mail)
+    }
+
     fn from_msg(msg: Message, ord_num: u32) -> Self {
+        let references = msg
+            .references()
+            .as_text_list()
+            .into_iter()
+            .flatten()
+            .chain(msg.in_reply_to().as_text_list().into_iter().flatten())
+            .map(|id| id.to_owned())
+            .unique()
+            .collect();
+        let html_body = msg
+            .html_bodies()
+            .filter(|part| part.is_text_html())
+            .filter_map(|part| part.text_contents())
+            .collect::<Vec<_>>();
+        // mails with only an HTML body list it as their text body as well, so only plain parts
+        // are taken from there and the HTML is converted if there are none
+        let plain_body = msg
+            .text_bodies()
+            .filter(|part| !part.is_text_html())
+            .filter_map(|part| part.text_contents())
+            .collect::<Vec<_>>();
+        let body = match (plain_body.is_empty(), html_body.is_empty()) {
+            (true, false) => html_to_text(&html_body.join("")),
+            _ => plain_body.join(""),
+        };
+
         Self {
             ord_num,
+            uid: None,
+            message_id: msg.message_id().map(|val| val.to_owned()),
+            references,
+            thread_id: None,
+            labels: Vec::new(),
             from: msg.header_raw("from").map(|val| val.to_owned()),
+            senders: addresses(msg.from())
+                .map(|addr| {
+                    let text = |val: &Option<Cow<str>>| {
+                        val.as_deref()
+                            .map(str::trim)
+                            .filter(|val| !val.is_empty())
+                            .map(|val| val.to_owned())
+                    };
+                    (text(&addr.name), text(&addr.address))
+                })
+                .collect(),
             to: msg.header_raw("to").map(|val| val.to_owned()),
-            date: msg.date().cloned(),
+            date: msg.date().and_then(to_chrono_date),
             subject: msg.subject().map(|val| val.to_owned()),
-            body: msg
-                .text_bodies()
-                .map(|b| b.text_contents().unwrap())
-                .collect::<Vec<_>>()
-                .join(""),
+            priority: Priority::from_headers(
+                msg.header_raw("X-Priority"),
+                msg.header_raw("Importance"),
+                msg.header_raw("Priority"),
+            ),
+            encryption: detect_encryption(&msg, &body),
+            body,
+            flags: Vec::new(),
+            list_unsubscribe: msg.header_raw("List-Unsubscribe").map(|val| val.to_owned()),
+            list_unsubscribe_post: msg
+                .header_raw("List-Unsubscribe-Post")
+                .map(|val| val.to_owned()),
+            html_body: (!html_body.is_empty()).then(|| html_body.join("")),
+            structure: mime_structure(&msg),
+            nested: nested_messages(&msg),
+            headers: msg
+                .headers_raw()
+                .map(|(name, value)| (name.to_owned(), unfold_header(value)))
+                .collect(),
+            mailbox: None,
+            outgoing: false,
+            raw: msg.raw_message.into_owned(),
         }
     }
-}
 
-impl Display for Mail {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let str = format!(
-            "From:       {from}
-To:         {to}
-Send Date:  {date}
+    /// a mail rebuilt from its raw `header` block and `flags`, e.g. from the summary cache
+    pub fn from_header(header: &[u8], flags: Vec<MailFlag>, ord_num: u32) -> anyhow::Result<Self> {
+        let msg = Message::parse(header).ok_or(anyhow!("failed to parse message {ord_num}"))?;
+        let mut mail = Mail::from_msg(msg, ord_num);
+        mail.flags = flags;
 
+        Ok(mail)
+    }
 
-Subject:    {sub}
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
 
-{body}",
-            from = self.from.as_ref().map(|val| val.trim()).unwrap_or("-"),
-            to = self.to.as_ref().map(|val| val.trim()).unwrap_or("-"),
-            date = self
-                .date
-                .as_ref()
-                .map(|date| date.to_string())
-                .unwrap_or(String::from("-"))
-                .trim(),
-            sub = self.subject.as_ref().map(|val| val.trim()).unwrap_or("-"),
-            body = self.body.trim()
-        );
+    pub fn set_uid(&mut self, uid: Option<u32>) {
+        self.uid = uid;
+    }
 
-        write!(f, "{str}")
+    pub fn mailbox(&self) -> Option<&str> {
+        self.mailbox.as_deref()
     }
-}
 
-/// returns ordering numbers of all mails in the selected mailbox order by date.
-/// the order is descending (newest -> oldest)
-fn get_mails_sorted_by_date(session: &mut Session<TlsStream<TcpStream>>) -> anyhow::Result<Vec<u32>> {
-    let all_ord_nums = session.search("ALL")?;
-    let fetch_str = all_ord_nums.into_iter().join(",");
-    let filter_str = HeaderFilter::new(HashSet::from([HeaderField::Date(None),]), false).filter_str().unwrap_or(String::new());
+    pub fn set_mailbox(&mut self, mailbox: &str) {
+        self.mailbox = Some(mailbox.to_owned());
+    }
 
-    let recent_ord_nums: Vec<_> = session.fetch(&fetch_str, format!("BODY.PEEK[{filter_str}]"))?
-        .into_iter()
-        .map(|item| {
-            let header_str = from_utf8(item.header().unwrap_or(&[])).unwrap().split_once(":").unwrap().1.trim();
-            let date = chrono::DateTime::parse_from_rfc2822(header_str).unwrap();
+    /// marks the mail as being in a sent or drafts mailbox (see [`is_outgoing_mailbox`])
+    pub fn set_outgoing(&mut self, outgoing: bool) {
+        self.outgoing = outgoing;
+    }
 
-            (date, item.message)
-    })
-    .sorted_by(|(date_a, _), (date_b, _)| date_a.cmp(&date_b))
-    .rev()
-    .map(|(_, num)| num)
-    .collect(); 
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.date
+    }
 
-    Ok(recent_ord_nums)
+    pub fn flags(&self) -> &[MailFlag] {
+        &self.flags
+    }
+
+    /// the glyphs of the flags followed by the one of the priority, e.g. "✓★!"
+    fn glyphs(&self) -> String {
+        flag_glyphs(&self.flags) + &self.priority.glyph().map(String::from).unwrap_or_default()
+    }
+
+    /// whether the `\\Flagged` flag is set
+    pub fn is_flagged(&self) -> bool {
+        self.flags.contains(&MailFlag::Flagged)
+    }
+
+    /// sets the provider assigned thread id (Gmail's `X-GM-THRID`)
+    pub fn set_thread_id(&mut self, thread_id: u64) {
+        self.thread_id = Some(thread_id);
+    }
+
+    /// sets the provider assigned labels (Gmail's `X-GM-LABELS`)
+    pub fn set_labels(&mut self, labels: Vec<String>) {
+        self.labels = labels;
+    }
+
+    /// the original bytes of the mail, including all headers and MIME parts, for exports that
+    /// have to keep it unchanged
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// the messages attached to the mail, e.g. the original of a bounce
+    pub fn nested(&self) -> &[NestedMessage] {
+        &self.nested
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// values of all headers named `name` (case-insensitive)
+    pub fn header<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// the SPF, DKIM and DMARC results recorded by the receiving server
+    pub fn auth_results(&self) -> AuthResults {
+        AuthResults::parse(
+            self.header("Authentication-Results"),
+            self.header("Received-SPF"),
+        )
+    }
+
+    /// the links in the body as it is displayed with `options`
+    pub fn links(&self, options: &DisplayOptions) -> Vec<String> {
+        extract_links(&self.display(options).body())
+    }
+
+    pub fn message_id(&self) -> Option<&str> {
+        self.message_id.as_deref()
+    }
+
+    /// message ids from the `In-Reply-To` and `References` headers, oldest first
+    pub fn references(&self) -> &[String] {
+        &self.references
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    /// the plain text body, or the HTML one converted to text if there is none
+    pub fn text(&self) -> &str {
+        &self.body
+    }
+
+    /// the ways of unsubscribing announced in the `List-Unsubscribe` header, preferring http(s)
+    pub fn unsubscribe_methods(&self) -> Vec<Unsubscribe> {
+        self.list_unsubscribe
+            .as_deref()
+            .map(|header| parse_list_unsubscribe(header, self.list_unsubscribe_post.as_deref()))
+            .unwrap_or_default()
+    }
+
+    /// key identifying the thread this mail belongs to.
+    ///
+    /// uses the provider assigned thread id if available and otherwise falls back to the root of
+    /// the `References`/`In-Reply-To` chain
+    pub fn thread_key(&self) -> String {
+        match (self.thread_id, self.references.first(), &self.message_id) {
+            (Some(thread_id), _, _) => format!("thrid:{thread_id}"),
+            (None, Some(root), _) => root.to_owned(),
+            (None, None, Some(message_id)) => message_id.to_owned(),
+            (None, None, None) => format!("seq:{}", self.ord_num),
+        }
+    }
+
+    pub fn display<'a>(&'a self, options: &'a DisplayOptions) -> MailDisplay<'a> {
+        MailDisplay {
+            mail: self,
+            options,
+        }
+    }
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            body_limit: None,
+            body_lines: None,
+            from_style: FromStyle::default(),
+            date_format: DEFAULT_DATE_FORMAT.to_owned(),
+            timezone: DisplayTimezone::default(),
+            extra_headers: ExtraHeaders::default(),
+            show_auth: false,
+            rich: false,
+            prefer_html: false,
+            format: OutputFormat::default(),
+            highlight: Vec::new(),
+            structure: false,
+            nested: false,
+            fields: Vec::new(),
+            relative_to: None,
+        }
+    }
+}
+
+impl DisplayOptions {
+    /// Errors: if `date_format` contains invalid format specifiers
+    pub fn new(
+        body_limit: Option<usize>,
+        date_format: String,
+        timezone: DisplayTimezone,
+    ) -> anyhow::Result<Self> {
+        if StrftimeItems::new(&date_format).any(|item| matches!(item, Item::Error)) {
+            return Err(anyhow!("invalid date format '{date_format}'"));
+        }
+
+        Ok(Self {
+            body_limit,
+            body_lines: None,
+            from_style: FromStyle::default(),
+            date_format,
+            timezone,
+            extra_headers: ExtraHeaders::default(),
+            show_auth: false,
+            rich: false,
+            prefer_html: false,
+            format: OutputFormat::default(),
+            highlight: Vec::new(),
+            structure: false,
+            nested: false,
+            fields: Vec::new(),
+            relative_to: None,
+        })
+    }
+}
+
+impl MailDisplay<'_> {
+    fn body(&self) -> String {
+        if let Some(encryption) = self.mail.encryption {
+            return format!("[encrypted message ({encryption})]");
+        }
+        if self.options.structure {
+            return render_structure(&self.mail.structure);
+        }
+
+        let rendered;
+        let body = match (&self.mail.html_body, self.options.format, self.options.rich) {
+            (Some(html), OutputFormat::Markdown, _) => {
+                rendered = html_to_markdown(html);
+                rendered.as_str()
+            }
+            (Some(html), OutputFormat::Text, true) => {
+                rendered = render_html(html);
+                rendered.as_str()
+            }
+            (Some(html), _, false) if self.options.prefer_html => {
+                rendered = html_to_text(html);
+                rendered.as_str()
+            }
+            _ => self.mail.body.trim(),
+        };
+        let sliced;
+        let body = match self.options.body_lines {
+            Some(lines) => {
+                sliced = slice_lines(body, lines);
+                sliced.as_str()
+            }
+            None => body,
+        };
+
+        let body = match self.options.body_limit {
+            Some(max_bytes) if body.len() > max_bytes => format!(
+                "{body}… (truncated)",
+                body = truncate_at_char_boundary(body, max_bytes)
+            ),
+            _ => body.to_owned(),
+        };
+
+        match self.options.nested && !self.mail.nested.is_empty() {
+            true => format!("{body}\n\n{}", render_nested(&self.mail.nested)),
+            false => body,
+        }
+    }
+
+    /// the headers selected by [`DisplayOptions::extra_headers`], one per line
+    fn extra_headers(&self) -> String {
+        let line = |name: &str, value: &str| format!("\n{name}: {value}");
+
+        match &self.options.extra_headers {
+            ExtraHeaders::None => String::new(),
+            ExtraHeaders::Named(names) => names
+                .iter()
+                .flat_map(|name| self.mail.header(name).map(|value| line(name, value)))
+                .collect(),
+            ExtraHeaders::All => self
+                .mail
+                .headers()
+                .iter()
+                .map(|(name, value)| line(name, value))
+                .collect(),
+        }
+    }
+
+    /// the senders in the [`FromStyle`] of the options, the raw `From` header if it has no
+    /// parsable addresses
+    pub fn from(&self) -> String {
+        let senders = self
+            .mail
+            .senders
+            .iter()
+            .filter_map(|sender| match (self.options.from_style, sender) {
+                (_, (None, None)) => None,
+                (FromStyle::Both, (Some(name), Some(address))) => {
+                    Some(format!("{name} <{address}>"))
+                }
+                (FromStyle::Address, (_, Some(address))) => Some(address.to_owned()),
+                (_, (Some(name), _)) => Some(name.to_owned()),
+                (_, (None, Some(address))) => Some(address.to_owned()),
+            })
+            .join(", ");
+
+        match senders.is_empty() {
+            true => self
+                .mail
+                .from
+                .as_deref()
+                .unwrap_or_default()
+                .trim()
+                .to_owned(),
+            false => senders,
+        }
+    }
+
+    fn date(&self) -> String {
+        self.mail
+            .date
+            .map(|date| {
+                if let Some(relative) = self
+                    .options
+                    .relative_to
+                    .and_then(|now| relative_date(date, now))
+                {
+                    return relative;
+                }
+
+                let format = &self.options.date_format;
+                match self.options.timezone {
+                    DisplayTimezone::Sender => date.format(format).to_string(),
+                    DisplayTimezone::Local => date.with_timezone(&Local).format(format).to_string(),
+                    DisplayTimezone::Utc => date.with_timezone(&Utc).format(format).to_string(),
+                }
+            })
+            .unwrap_or(String::from("-"))
+    }
+
+    /// the mail as JSON object with the fields in [`DisplayOptions::fields`] as keys, missing
+    /// values are `null`
+    pub fn json(&self) -> serde_json::Value {
+        let mail = self.mail;
+        let fields = match self.options.fields.is_empty() {
+            true => MailField::value_variants(),
+            false => self.options.fields.as_slice(),
+        };
+        let text = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| serde_json::Value::from(value.trim()))
+                .unwrap_or_default()
+        };
+
+        fields
+            .iter()
+            .map(|field| {
+                let value = match field {
+                    MailField::Uid => mail.uid.into(),
+                    MailField::From => text(&mail.from),
+                    MailField::To => text(&mail.to),
+                    MailField::Date => text(&mail.date.map(|_| self.date())),
+                    MailField::Subject => text(&mail.subject),
+                    MailField::MessageId => text(&mail.message_id),
+                    MailField::Labels => mail.labels.clone().into(),
+                    MailField::ThreadId => mail.thread_id.into(),
+                    MailField::Flagged => mail.is_flagged().into(),
+                    MailField::Flags => mail.flags.iter().map(MailFlag::name).collect(),
+                    MailField::Priority => mail.priority.name().into(),
+                    MailField::Body => self.body().into(),
+                };
+
+                (field.key().to_owned(), value)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    /// the mail as markdown document, the headers go into the YAML frontmatter and the subject
+    /// becomes the heading
+    fn markdown(&self) -> String {
+        let mail = self.mail;
+        // JSON strings are valid YAML strings and take care of escaping
+        let quote = |value: &str| serde_json::to_string(value.trim()).unwrap_or_default();
+
+        let mut frontmatter = Vec::new();
+        if let Some(uid) = mail.uid {
+            frontmatter.push(format!("uid: {uid}"));
+        }
+        frontmatter.push(format!("from: {}", quote(&self.from())));
+        frontmatter.push(format!(
+            "to: {}",
+            quote(mail.to.as_deref().unwrap_or_default())
+        ));
+        if mail.date.is_some() {
+            frontmatter.push(format!("date: {}", quote(&self.date())));
+        }
+        if !mail.labels.is_empty() {
+            let labels = mail.labels.iter().map(|label| quote(label)).join(", ");
+            frontmatter.push(format!("labels: [{labels}]"));
+        }
+        if !mail.flags.is_empty() {
+            let flags = mail.flags.iter().map(MailFlag::name).join(", ");
+            frontmatter.push(format!("flags: [{flags}]"));
+        }
+        if mail.priority != Priority::Normal {
+            frontmatter.push(format!("priority: {}", mail.priority.name()));
+        }
+        if self.options.show_auth {
+            frontmatter.push(format!("auth: {}", quote(&mail.auth_results().to_string())));
+        }
+
+        let headers: Vec<(&str, &str)> = match &self.options.extra_headers {
+            ExtraHeaders::None => vec![],
+            ExtraHeaders::Named(names) => names
+                .iter()
+                .flat_map(|name| mail.header(name).map(|value| (name.as_str(), value)))
+                .collect(),
+            ExtraHeaders::All => mail
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect(),
+        };
+        if !headers.is_empty() {
+            frontmatter.push(String::from("headers:"));
+            frontmatter.extend(
+                headers
+                    .into_iter()
+                    .map(|(name, value)| format!("  {}: {}", quote(name), quote(value))),
+            );
+        }
+
+        format!(
+            "---\n{frontmatter}\n---\n\n# {sub}\n\n{body}",
+            frontmatter = frontmatter.join("\n"),
+            sub = mail.subject.as_ref().map(|val| val.trim()).unwrap_or("-"),
+            body = self.body()
+        )
+    }
+}
+
+impl Display for MailDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.options.format {
+            OutputFormat::Markdown => return write!(f, "{}", self.markdown()),
+            OutputFormat::Json => return write!(f, "{}", self.json()),
+            OutputFormat::Text | OutputFormat::Eml => {}
+        }
+
+        let mail = self.mail;
+        let highlight = |text: &str| {
+            highlight_matches(text, &self.options.highlight, |matched| {
+                matched.yellow().bold().to_string()
+            })
+        };
+
+        let from = self.from();
+        let from = format!(
+            "From:       {}",
+            highlight(match from.is_empty() {
+                true => "-",
+                false => &from,
+            })
+        );
+        let to = format!(
+            "To:         {}",
+            mail.to.as_ref().map(|val| val.trim()).unwrap_or("-")
+        );
+        let (first, second) = if mail.outgoing {
+            (to, from)
+        } else {
+            (from, to)
+        };
+
+        let str = format!(
+            "{first}
+{second}
+Send Date:  {date}{uid}{priority}{labels}{auth}{headers}
+
+
+Subject:    {flags}{sub}
+
+{body}",
+            date = self.date(),
+            uid = match mail.uid {
+                Some(uid) => format!("\nUID:        {uid}"),
+                None => String::new(),
+            },
+            priority = match mail.priority {
+                Priority::Normal => String::new(),
+                priority => format!("\nPriority:   {}", priority.name()),
+            },
+            labels = if mail.labels.is_empty() {
+                String::new()
+            } else {
+                format!("\nLabels:     {}", mail.labels.join(", "))
+            },
+            auth = if self.options.show_auth {
+                format!("\nAuth:       {}", mail.auth_results())
+            } else {
+                String::new()
+            },
+            headers = self.extra_headers(),
+            flags = match mail.glyphs() {
+                glyphs if glyphs.is_empty() => String::new(),
+                glyphs => format!("{glyphs} "),
+            },
+            sub = highlight(mail.subject.as_ref().map(|val| val.trim()).unwrap_or("-")),
+            body = highlight(&self.body())
+        );
+
+        write!(f, "{str}")
+    }
+}
+
+impl Display for Mail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display(&DisplayOptions::default()))
+    }
+}
+
+impl Display for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pgp => write!(f, "PGP"),
+            Self::Smime => write!(f, "S/MIME"),
+        }
+    }
+}
+
+/// replaces every case-insensitive occurrence of one of `terms` in `text` with `paint` applied to
+/// it, longer terms are preferred if they overlap
+pub fn highlight_matches(text: &str, terms: &[String], paint: impl Fn(&str) -> String) -> String {
+    let pattern = terms
+        .iter()
+        .filter(|term| !term.is_empty())
+        .sorted_by_key(|term| std::cmp::Reverse(term.len()))
+        .map(|term| regex::escape(term))
+        .join("|");
+    if pattern.is_empty() {
+        return text.to_owned();
+    }
+
+    match RegexBuilder::new(&pattern).case_insensitive(true).build() {
+        Ok(regex) => regex
+            .replace_all(text, |captures: &regex::Captures| paint(&captures[0]))
+            .into_owned(),
+        Err(_) => text.to_owned(),
+    }
+}
+
+/// formats the send date, sender and subject of each mail as a row of a table that fits into
+/// `width` terminal columns, cells that don't fit are truncated with an ellipsis.
+///
+/// the date column is as wide as the widest date, the sender only gets more than a third of the
+/// remaining width if the subjects fit into the rest. if any mail has flags their glyphs are
+/// shown in a column before the date, preceded by the UIDs like `[1234]` if they are known
+pub fn header_table(
+    mails: &[anyhow::Result<Mail>],
+    options: &DisplayOptions,
+    width: usize,
+) -> String {
+    const SEPARATOR: &str = "  ";
+
+    let rows = mails
+        .iter()
+        .map(|mail| match mail {
+            Ok(mail) => [
+                mail.display(options).date(),
+                match mail.display(options).from() {
+                    from if from.is_empty() => String::from("-"),
+                    from => from,
+                },
+                mail.subject.as_deref().unwrap_or("-").trim().to_owned(),
+            ],
+            Err(err) => [String::from("-"), String::from("-"), format!("[{err:#}]")],
+        })
+        .collect::<Vec<_>>();
+    let flags = mails
+        .iter()
+        .map(|mail| match mail {
+            Ok(mail) => mail.glyphs(),
+            Err(_) => String::new(),
+        })
+        .collect::<Vec<_>>();
+    let uids = mails
+        .iter()
+        .map(|mail| match mail.as_ref().ok().and_then(Mail::uid) {
+            Some(uid) => format!("[{uid}]"),
+            None => String::new(),
+        })
+        .collect::<Vec<_>>();
+    let uids_width = uids.iter().map(String::len).max().unwrap_or_default();
+    let flags_width = flags
+        .iter()
+        .map(|flags| measure_text_width(flags))
+        .max()
+        .unwrap_or_default();
+    let width = [uids_width, flags_width]
+        .into_iter()
+        .filter(|column| *column > 0)
+        .fold(width, |width, column| {
+            width.saturating_sub(column + SEPARATOR.len())
+        });
+
+    let column_width = |column: usize| {
+        rows.iter()
+            .map(|row| measure_text_width(&row[column]))
+            .max()
+            .unwrap_or_default()
+    };
+
+    let date_width = column_width(0).min(width);
+    let rest = width.saturating_sub(date_width + 2 * SEPARATOR.len());
+    let from_width = column_width(1).min((rest / 3).max(rest.saturating_sub(column_width(2))));
+    let subject_width = rest - from_width;
+
+    let cell = |str: &str, width: usize| truncate_to_width(str, width);
+    let padded = |str: &str, width: usize| {
+        pad_str(&cell(str, width), width, Alignment::Left, None).into_owned()
+    };
+
+    rows.iter()
+        .zip(uids.iter().zip(&flags))
+        .map(|([date, from, subject], (uid, flags))| {
+            (uids_width > 0)
+                .then(|| padded(uid, uids_width))
+                .into_iter()
+                .chain((flags_width > 0).then(|| padded(flags, flags_width)))
+                .chain([
+                    padded(date, date_width),
+                    padded(from, from_width),
+                    cell(subject, subject_width),
+                ])
+                .join(SEPARATOR)
+                .trim_end()
+                .to_owned()
+        })
+        .join("\n")
+}
+
+/// detects PGP/MIME (`multipart/encrypted`), inline PGP and S/MIME (`application/pkcs7-mime`)
+/// encrypted messages
+fn detect_encryption(msg: &Message, body: &str) -> Option<Encryption> {
+    let is_smime = |ct: &mail_parser::ContentType| {
+        ct.ctype().eq_ignore_ascii_case("application")
+            && ct.subtype().is_some_and(|subtype| {
+                ["pkcs7-mime", "x-pkcs7-mime"]
+                    .iter()
+                    .any(|smime| subtype.eq_ignore_ascii_case(smime))
+            })
+            && ct
+                .attribute("smime-type")
+                .is_none_or(|smime_type| !smime_type.eq_ignore_ascii_case("signed-data"))
+    };
+
+    if msg.is_content_type("multipart", "encrypted")
+        || body.trim_start().starts_with("-----BEGIN PGP MESSAGE-----")
+    {
+        Some(Encryption::Pgp)
+    } else if msg.content_type().is_some_and(is_smime) {
+        Some(Encryption::Smime)
+    } else {
+        None
+    }
+}
+
+/// removes all but the first mail with each Message-ID, mails without a Message-ID are kept.
+///
+/// returns the remaining mails together with the number of removed duplicates
+pub fn dedup_by_message_id(mails: Vec<anyhow::Result<Mail>>) -> (Vec<anyhow::Result<Mail>>, usize) {
+    let total = mails.len();
+    let mut seen = HashSet::new();
+    let mails = mails
+        .into_iter()
+        .filter(|mail| match mail.as_ref().map(|mail| mail.message_id()) {
+            Ok(Some(message_id)) => seen.insert(message_id.to_owned()),
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+
+    let hidden = total - mails.len();
+    (mails, hidden)
+}
+
+const BOLD: &str = "\x1b[1m";
+const BOLD_OFF: &str = "\x1b[22m";
+const ITALIC: &str = "\x1b[3m";
+const ITALIC_OFF: &str = "\x1b[23m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HtmlStyle {
+    Terminal,
+    Markdown,
+    Plain,
+}
+
+/// the lines of `body` selected by `lines`, with a note about how many lines were left out
+fn slice_lines(body: &str, lines: BodyLines) -> String {
+    let all = body.lines().collect::<Vec<_>>();
+    let n = match lines {
+        BodyLines::Head(n) | BodyLines::Tail(n) => n,
+    };
+    if all.len() <= n {
+        return body.to_owned();
+    }
+
+    let hidden = all.len() - n;
+    let noun = if hidden == 1 { "line" } else { "lines" };
+    let (shown, note) = match lines {
+        BodyLines::Head(_) => (&all[..n], format!("… ({hidden} more {noun})")),
+        BodyLines::Tail(_) => (&all[hidden..], format!("({hidden} earlier {noun}) …")),
+    };
+    let mut sliced = shown.to_vec();
+    match lines {
+        BodyLines::Head(_) => sliced.push(&note),
+        BodyLines::Tail(_) => sliced.insert(0, &note),
+    }
+
+    sliced.join("\n")
+}
+
+/// renders an HTML body for the terminal like a text-mode browser would.
+///
+/// headings and bold text are shown bold, emphasized text italic, list items are bulleted or
+/// numbered and the targets of links are collected into numbered footnotes after the text
+pub fn render_html(html: &str) -> String {
+    convert_html(html, HtmlStyle::Terminal)
+}
+
+/// converts an HTML body to markdown, keeping headings, emphasis, lists and inline links
+pub fn html_to_markdown(html: &str) -> String {
+    convert_html(html, HtmlStyle::Markdown)
+}
+
+/// converts an HTML body to plain text like [`render_html`] does, but without any formatting
+pub fn html_to_text(html: &str) -> String {
+    convert_html(html, HtmlStyle::Plain)
+}
+
+fn convert_html(html: &str, style: HtmlStyle) -> String {
+    let markdown = style == HtmlStyle::Markdown;
+    let (bold, bold_off, italic, italic_off) = match style {
+        HtmlStyle::Terminal => (BOLD, BOLD_OFF, ITALIC, ITALIC_OFF),
+        HtmlStyle::Markdown => ("**", "**", "_", "_"),
+        HtmlStyle::Plain => ("", "", "", ""),
+    };
+
+    let mut out = String::new();
+    let mut links: Vec<String> = Vec::new();
+    // the target of each open link and where its text starts in `out`
+    let mut open_links: Vec<(Option<String>, usize)> = Vec::new();
+    let mut lists: Vec<Option<usize>> = Vec::new();
+    let mut hidden: usize = 0;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.split_once("-->").map_or("", |(_, rest)| rest);
+        } else if let Some(tag) = rest.strip_prefix('<') {
+            let (tag, after) = tag.split_once('>').unwrap_or((tag, ""));
+            rest = after;
+
+            let closing = tag.starts_with('/');
+            let tag = tag.trim_start_matches('/').trim_end_matches('/');
+            let name = tag
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+
+            match (name.as_str(), closing) {
+                ("script" | "style" | "head" | "title", false) => hidden += 1,
+                ("script" | "style" | "head" | "title", true) => hidden = hidden.saturating_sub(1),
+                ("br", _) => break_line(&mut out, false),
+                ("p" | "blockquote" | "table", _) => break_line(&mut out, true),
+                ("div" | "tr", _) => break_line(&mut out, false),
+                ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                    break_line(&mut out, true);
+                    match markdown {
+                        true => out
+                            .push_str(&format!("{} ", "#".repeat(name[1..].parse().unwrap_or(1)))),
+                        false => out.push_str(bold),
+                    }
+                }
+                ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => {
+                    if !markdown {
+                        out.push_str(bold_off);
+                    }
+                    break_line(&mut out, true);
+                }
+                ("b" | "strong", false) => out.push_str(bold),
+                ("b" | "strong", true) => out.push_str(bold_off),
+                ("i" | "em", false) => out.push_str(italic),
+                ("i" | "em", true) => out.push_str(italic_off),
+                ("ul" | "ol", false) => {
+                    break_line(&mut out, lists.is_empty());
+                    lists.push((name == "ol").then_some(0));
+                }
+                ("ul" | "ol", true) => {
+                    lists.pop();
+                    break_line(&mut out, lists.is_empty());
+                }
+                ("li", false) => {
+                    break_line(&mut out, false);
+                    out.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                    match lists.last_mut() {
+                        Some(Some(num)) => {
+                            *num += 1;
+                            out.push_str(&format!("{num}. "));
+                        }
+                        _ if markdown => out.push_str("- "),
+                        _ => out.push_str("• "),
+                    }
+                }
+                ("a", false) => open_links.push((attribute(tag, "href"), out.len())),
+                ("a", true) => {
+                    let Some((Some(href), start)) = open_links.pop() else {
+                        continue;
+                    };
+                    if href.is_empty() || href.starts_with('#') {
+                        continue;
+                    }
+
+                    let href = decode_entities(&href);
+                    if markdown {
+                        out.insert(start.min(out.len()), '[');
+                        out.push_str(&format!("]({href})"));
+                    } else {
+                        links.push(href);
+                        out.push_str(&format!("[{}]", links.len()));
+                    }
+                }
+                _ => {}
+            }
+        } else {
+            let (text, after) = rest.split_at(rest.find('<').unwrap_or(rest.len()));
+            rest = after;
+
+            if hidden == 0 {
+                push_text(&mut out, &decode_entities(text));
+            }
+        }
+    }
+
+    let mut rendered = out
+        .lines()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_owned();
+
+    if !links.is_empty() {
+        rendered.push('\n');
+        for (idx, link) in links.iter().enumerate() {
+            rendered.push_str(&format!("\n[{num}] {link}", num = idx + 1));
+        }
+    }
+
+    rendered
+}
+
+/// appends `text` collapsing runs of whitespace like a browser does
+fn push_text(out: &mut String, text: &str) {
+    let starts_with_space = text.starts_with(char::is_whitespace);
+    let ends_with_space = text.ends_with(char::is_whitespace);
+    let words = text.split_whitespace().join(" ");
+
+    if starts_with_space && !out.is_empty() && !out.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+    if words.is_empty() {
+        return;
+    }
+
+    out.push_str(&words);
+    if ends_with_space {
+        out.push(' ');
+    }
+}
+
+/// ends the current line, leaving an empty line after it if `blank` is set
+fn break_line(out: &mut String, blank: bool) {
+    if out.trim().is_empty() {
+        return;
+    }
+
+    while out.ends_with(' ') {
+        out.pop();
+    }
+
+    let wanted = if blank { 2 } else { 1 };
+    let present = out.len() - out.trim_end_matches('\n').len();
+    for _ in present..wanted {
+        out.push('\n');
+    }
+}
+
+/// value of the attribute `name` in the contents of a tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let start = lower.match_indices(name).map(|(idx, _)| idx).find(|idx| {
+        lower[..*idx].ends_with(char::is_whitespace)
+            && lower[idx + name.len()..].trim_start().starts_with('=')
+    })?;
+
+    let value = tag[start + name.len()..].trim_start()[1..].trim_start();
+    match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next().map(|val| val.to_owned()),
+        _ => value.split_whitespace().next().map(|val| val.to_owned()),
+    }
+}
+
+/// decodes the most common named and all numeric HTML character references
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest[1..].split_once(';').and_then(|(entity, _)| {
+            let char = match entity {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => ' ',
+                _ => {
+                    let num = entity.strip_prefix('#')?;
+                    let code = match num.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => num.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((char, entity.len() + 2))
+        });
+
+        match decoded {
+            Some((char, len)) => {
+                out.push(char);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// all distinct http(s) urls in `text` in the order they appear in
+pub fn extract_links(text: &str) -> Vec<String> {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    let url = URL.get_or_init(|| Regex::new(r#"https?://[^\s<>"'()\[\]{}]+"#).unwrap());
+
+    url.find_iter(text)
+        // punctuation directly after a url usually belongs to the sentence
+        .map(|url| {
+            url.as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?'])
+        })
+        .unique()
+        .map(|url| url.to_owned())
+        .collect()
+}
+
+/// joins the lines of a folded header value and trims it
+fn unfold_header(value: &str) -> String {
+    value
+        .split(['\r', '\n'])
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .join(" ")
+}
+
+/// all addresses in an address header, including those in groups
+pub fn addresses<'a>(value: &'a HeaderValue) -> Box<dyn Iterator<Item = &'a Addr<'a>> + 'a> {
+    match value {
+        HeaderValue::Address(addr) => Box::new(std::iter::once(addr)),
+        HeaderValue::AddressList(list) => Box::new(list.iter()),
+        HeaderValue::Group(group) => Box::new(group.addresses.iter()),
+        HeaderValue::GroupList(groups) => {
+            Box::new(groups.iter().flat_map(|group| group.addresses.iter()))
+        }
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+/// writes the raw `mails` in the mboxrd format: each mail starts with a `From ` line and lines
+/// in it that already start with `From ` (after any number of `>`) are quoted with another `>`,
+/// so the mails can be split again without ambiguity
+pub fn write_mbox<'a>(
+    out: &mut impl Write,
+    mails: impl IntoIterator<Item = &'a Mail>,
+) -> std::io::Result<()> {
+    for mail in mails {
+        let sender = mail
+            .from
+            .as_deref()
+            .map(|from| {
+                from.rsplit_once('<')
+                    .map_or(from, |(_, addr)| addr.trim().trim_end_matches('>'))
+                    .trim()
+            })
+            .filter(|sender| !sender.is_empty() && !sender.contains(char::is_whitespace))
+            .unwrap_or("MAILER-DAEMON");
+        let date = mail
+            .date
+            .map(|date| date.with_timezone(&Utc))
+            .unwrap_or_default()
+            .format("%a %b %e %H:%M:%S %Y");
+        writeln!(out, "From {sender} {date}")?;
+
+        // the bytes are written as they are except for line endings, mbox files use `\n`
+        for line in mail.raw().split(|byte| *byte == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let unquoted = line
+                .iter()
+                .position(|byte| *byte != b'>')
+                .unwrap_or(line.len());
+            if line[unquoted..].starts_with(b"From ") {
+                out.write_all(b">")?;
+            }
+            out.write_all(line)?;
+            out.write_all(b"\n")?;
+        }
+        // the blank line separating mails is missing if the mail doesn't end with a line break
+        if !mail.raw().ends_with(b"\n") {
+            out.write_all(b"\n")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// converts a parsed date header into a chrono date keeping the senders offset
+fn to_chrono_date(date: &mail_parser::DateTime) -> Option<DateTime<FixedOffset>> {
+    let offset = (date.tz_hour as i32 * 3600 + date.tz_minute as i32 * 60)
+        * if date.tz_before_gmt { -1 } else { 1 };
+    let naive = NaiveDateTime::from_timestamp_opt(date.to_timestamp(), 0)?;
+
+    Some(FixedOffset::east_opt(offset)?.from_utc_datetime(&naive))
+}
+
+/// sets the Gmail thread id (`X-GM-THRID`) and labels (`X-GM-LABELS`) on each of the mails
+pub fn set_gmail_attributes<'a, T: Read + Write>(
+    mails: impl IntoIterator<Item = &'a mut Mail>,
+    session: &mut Session<T>,
+    gmail_attributes: &GmailAttributeStore,
+) -> anyhow::Result<()> {
+    let mails = mails.into_iter().collect::<Vec<_>>();
+    if mails.is_empty() {
+        return Ok(());
+    }
+
+    let seq_set = mails.iter().map(|mail| mail.ord_num).join(",");
+    let mut attributes = fetch_gmail_attributes(session, gmail_attributes, &seq_set)?;
+
+    for mail in mails {
+        if let Some(attrs) = attributes.remove(&mail.ord_num) {
+            if let Some(thread_id) = attrs.thread_id {
+                mail.set_thread_id(thread_id);
+            }
+            mail.set_labels(attrs.labels.unwrap_or_default());
+        }
+    }
+
+    Ok(())
+}
+
+/// groups mails into threads keeping the order in which each thread first appears
+pub fn group_by_thread(mails: Vec<Mail>) -> Vec<Vec<Mail>> {
+    let mut threads: Vec<(String, Vec<Mail>)> = Vec::new();
+
+    for mail in mails {
+        let key = mail.thread_key();
+        match threads
+            .iter_mut()
+            .find(|(thread_key, _)| *thread_key == key)
+        {
+            Some((_, thread)) => thread.push(mail),
+            None => threads.push((key, vec![mail])),
+        }
+    }
+
+    threads.into_iter().map(|(_, thread)| thread).collect()
+}
+
+/// returns the UIDs of all mails matching `query` in the selected mailbox order by date.
+/// the order is descending (newest -> oldest)
+fn get_mails_sorted_by_date<T: Read + Write>(
+    query: &str,
+    batch_size: usize,
+    session: &mut Session<T>,
+) -> anyhow::Result<Vec<u32>> {
+    let all_uids = session.uid_search(query)?;
+    if all_uids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let all_uids = all_uids.into_iter().sorted().collect::<Vec<_>>();
+    let filter_str = HeaderFilter::new(HashSet::from([HeaderField::Date(None)]), false)
+        .filter_str()
+        .unwrap_or_default();
+
+    let mut dates = Vec::with_capacity(all_uids.len());
+    for batch in all_uids.chunks(batch_size) {
+        let items =
+            session.uid_fetch(batch.iter().join(","), format!("BODY.PEEK[{filter_str}]"))?;
+        dates.extend(
+            items
+                .iter()
+                .filter_map(|item| Some((sort_date(item.header().unwrap_or(&[])), item.uid?))),
+        );
+    }
+
+    // mails without a usable date are sorted as the oldest instead of failing the whole fetch
+    let recent_uids: Vec<_> = dates
+        .into_iter()
+        .sorted_by(|(date_a, _), (date_b, _)| date_a.cmp(date_b))
+        .rev()
+        .map(|(_, uid)| uid)
+        .collect();
+
+    Ok(recent_uids)
+}
+
+/// the date of a fetched `Date` header field, `None` if it's missing or not a valid RFC 2822
+/// date. bytes that aren't UTF-8 are replaced since the date itself is always ASCII
+fn sort_date(header: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let header = String::from_utf8_lossy(header);
+    let (_, value) = header.split_once(':')?;
+
+    DateTime::parse_from_rfc2822(unfold_header(value).trim()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gmail::GmailExtStream,
+        mock_imap::{connect, mock_session, MockImapStream, MockMail},
+    };
+
+    fn mock_mail(subject: &str, date: &str) -> MockMail {
+        MockMail::new(&format!(
+            "From: Alice <alice@example.com>\nTo: bob@example.com\nDate: {date}\nSubject: {subject}\n\n{subject} body\n"
+        ))
+    }
+
+    fn mailbox_fixture() -> Vec<MockMail> {
+        vec![
+            mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000"),
+            mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+            mock_mail("third", "Wed, 3 May 2023 09:00:00 +0200"),
+        ]
+    }
+
+    #[test]
+    fn normalizes_priority_headers() {
+        let priority = |x_priority, importance, priority| {
+            Priority::from_headers(x_priority, importance, priority)
+        };
+
+        for high in [
+            priority(Some("1"), None, None),
+            priority(Some(" 1 (Highest)"), None, None),
+            priority(Some("2 (High)"), None, None),
+            priority(None, Some("High"), None),
+            priority(None, Some("high"), Some("non-urgent")),
+            priority(None, None, Some("urgent")),
+            // a value without meaning doesn't hide the next header
+            priority(Some("urgent"), Some("high"), None),
+        ] {
+            assert_eq!(high, Priority::High);
+        }
+        assert_eq!(priority(Some("5 (Lowest)"), None, None), Priority::Low);
+        assert_eq!(priority(None, Some("low"), None), Priority::Low);
+        assert_eq!(priority(None, None, Some("Non-Urgent")), Priority::Low);
+        // the first header that means anything wins
+        assert_eq!(
+            priority(Some("3 (Normal)"), Some("high"), None),
+            Priority::Normal
+        );
+        assert_eq!(priority(None, None, None), Priority::Normal);
+    }
+
+    #[test]
+    fn shows_priority_of_urgent_mails() {
+        let mail = |headers: &str| {
+            let raw = format!(
+                "Date: Tue, 20 Jun 2023 10:00:00 +0200\r\n{headers}Subject: hi\r\n\r\nbody\r\n"
+            );
+            Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1)
+        };
+        let urgent = mail("X-Priority: 1 (Highest)\r\n");
+        let low = mail("Importance: low\r\n");
+        let normal = mail("");
+
+        assert!(urgent.to_string().contains("\nPriority:   high\n"));
+        assert!(urgent.to_string().contains("Subject:    ! hi"));
+        assert!(!normal.to_string().contains("Priority:"));
+
+        let mails = vec![Ok(urgent.clone()), Ok(low), Ok(normal)];
+        let options = DisplayOptions {
+            date_format: String::from("%d.%m."),
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            header_table(&mails, &options, 80),
+            "!  20.06.  -  hi\n↓  20.06.  -  hi\n   20.06.  -  hi"
+        );
+        assert_eq!(
+            flags_legend(&mails).as_deref(),
+            Some("! high priority  ↓ low priority")
+        );
+        assert_eq!(urgent.display(&options).json()["priority"], "high");
+    }
+
+    #[test]
+    fn shows_attached_messages_after_body() {
+        let raw = "Subject: Fwd: plans\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            see below\r\n\
+            --b\r\n\
+            Content-Type: message/rfc822\r\n\
+            \r\n\
+            From: bob@example.com\r\n\
+            Subject: plans\r\n\
+            \r\n\
+            see you there\r\n\
+            --b--\r\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        assert_eq!(mail.nested().len(), 1);
+
+        let mut options = DisplayOptions::default();
+        assert!(!mail.display(&options).body().contains("attached message"));
+        options.nested = true;
+        assert!(mail
+            .display(&options)
+            .body()
+            .starts_with("see below\n\n┌ attached message\n│ From:       bob@example.com\n"));
+    }
+
+    #[test]
+    fn shows_uids_of_fetched_mails() {
+        let mut mails = mailbox_fixture();
+        mails.extend(
+            (0..8).map(|idx| mock_mail(&format!("old {idx}"), "Mon, 1 May 2000 10:00:00 +0000")),
+        );
+        let (mut session, _) = mock_session(vec![("INBOX", mails)]);
+
+        let mails = MailBox::INBOX
+            .fetch_n_recent_mails(2, &mut session)
+            .unwrap();
+        let uids = mails
+            .iter()
+            .map(|mail| mail.as_ref().unwrap().uid())
+            .collect::<Vec<_>>();
+        assert_eq!(uids, [Some(3), Some(1)]);
+
+        let options = DisplayOptions::default();
+        let third = mails[0].as_ref().unwrap();
+        assert!(third.to_string().contains("\nUID:        3\n"));
+        assert_eq!(third.display(&options).json()["uid"], 3);
+        let table = header_table(&mails, &options, 80);
+        assert!(table
+            .lines()
+            .all(|row| row.starts_with("[3]  ") || row.starts_with("[1]  ")));
+
+        // the header and UID of the 11th mail take up the same room
+        let wide = MailBox::INBOX.fetch_mail_by_uid(11, &mut session).unwrap();
+        assert_eq!(wide.uid(), Some(11));
+        let table = header_table(&[Ok(wide), Ok(third.clone())], &options, 80);
+        assert!(table.lines().nth(1).unwrap().starts_with("[3]   "));
+
+        // mails that weren't fetched from a mailbox have no UID to show
+        let parsed = Mail::from_header(b"Subject: a\r\n\r\n", Vec::new(), 1).unwrap();
+        assert!(!parsed.to_string().contains("UID:"));
+        assert!(header_table(&[Ok(parsed)], &options, 80).starts_with("-  "));
+    }
+
+    #[test]
+    fn fetches_headers_in_order_of_uids() {
+        let (mut session, _) = mock_session(vec![("INBOX", mailbox_fixture())]);
+
+        let mails = MailBox::INBOX
+            .fetch_headers_by_uid(&[3, 9, 1], &mut session)
+            .unwrap();
+        let subjects = mails
+            .iter()
+            .map(|(uid, mail)| (*uid, mail.as_ref().unwrap().subject().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(subjects, [(3, "third"), (1, "second")]);
+    }
+
+    #[test]
+    fn sorts_mails_by_date_header() {
+        let (mut session, _) = mock_session(vec![("INBOX", mailbox_fixture())]);
+        session.select("INBOX").unwrap();
+
+        assert_eq!(
+            get_mails_sorted_by_date("ALL", DEFAULT_FETCH_BATCH_SIZE, &mut session).unwrap(),
+            vec![3, 1, 2]
+        );
+    }
+
+    #[test]
+    fn sorts_mails_with_odd_date_headers() {
+        assert_eq!(
+            sort_date(b"Date: Tue, 2 May 2023\r\n 10:00:00 +0000 (\xff\xfe)\r\n"),
+            DateTime::parse_from_rfc2822("Tue, 2 May 2023 10:00:00 +0000").ok()
+        );
+        assert_eq!(sort_date(b"Date: \xc3\x28 yesterday\r\n"), None);
+        assert_eq!(sort_date(b"\r\n"), None);
+        assert_eq!(sort_date(b""), None);
+
+        let mails = vec![
+            MockMail::new("Subject: undated\n\nbody\n"),
+            mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+            mock_mail("garbled", "sometime in May"),
+            mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000"),
+        ];
+        let (mut session, _) = mock_session(vec![("INBOX", mails)]);
+        session.select("INBOX").unwrap();
+
+        let sorted = get_mails_sorted_by_date("ALL", DEFAULT_FETCH_BATCH_SIZE, &mut session);
+        assert_eq!(sorted.unwrap()[..2], [4, 2]);
+    }
+
+    #[test]
+    fn picks_recent_mails_by_date_or_arrival() {
+        let subjects = |sort_by: SortBy| {
+            let (mut session, commands) = mock_session(vec![("INBOX", mailbox_fixture())]);
+            let mails = MailBox::INBOX
+                .with_sort_by(sort_by)
+                .fetch_n_recent_mails(2, &mut session)
+                .unwrap()
+                .into_iter()
+                .map(|mail| mail.unwrap().subject.unwrap())
+                .collect::<Vec<_>>();
+            let fetched_dates = commands
+                .borrow()
+                .iter()
+                .any(|cmd| cmd.contains("HEADER.FIELDS"));
+
+            (mails, fetched_dates)
+        };
+
+        assert_eq!(
+            subjects(SortBy::Date),
+            (vec![String::from("third"), String::from("second")], true)
+        );
+        // "first" arrived after "second" even though it was sent before it
+        assert_eq!(
+            subjects(SortBy::Arrival),
+            (vec![String::from("third"), String::from("first")], false)
+        );
+    }
+
+    #[test]
+    fn fetches_n_most_recent_mails() {
+        let (mut session, commands) = mock_session(vec![("INBOX", mailbox_fixture())]);
+
+        let mails = MailBox::new("INBOX")
+            .fetch_n_recent_mails(2, &mut session)
+            .unwrap()
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        let subjects = mails
+            .iter()
+            .map(|mail| mail.subject.as_deref().unwrap())
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(subjects, vec!["second", "third"]);
+        assert!(mails.iter().any(|mail| mail.body.trim() == "third body"));
+        assert!(commands
+            .borrow()
+            .iter()
+            .any(|cmd| cmd.starts_with("UID FETCH") && cmd.contains("BODY.PEEK[]")));
+    }
+
+    #[test]
+    fn highlights_all_matches() {
+        let terms = vec![
+            String::from("invoice"),
+            String::from("Über"),
+            String::from("invoices"),
+        ];
+        let paint = |matched: &str| format!("[{matched}]");
+
+        assert_eq!(
+            highlight_matches(
+                "Invoices for ÜBER: see invoice 3 (not inVOICE.*)",
+                &terms,
+                paint
+            ),
+            "[Invoices] for [ÜBER]: see [invoice] 3 (not [inVOICE].*)"
+        );
+        assert_eq!(
+            highlight_matches("a.*b", &[String::from(".*")], paint),
+            "a[.*]b"
+        );
+        assert_eq!(
+            highlight_matches("nothing", &[String::new()], paint),
+            "nothing"
+        );
+    }
+
+    #[test]
+    fn normalizes_only_inbox() {
+        let (mut session, commands) = mock_session(vec![("INBOX", mailbox_fixture())]);
+
+        assert_eq!(MailBox::new("inbox").name(), "INBOX");
+        assert_eq!(MailBox::new("Inbox").name(), "INBOX");
+        assert_eq!(MailBox::INBOX.name(), "INBOX");
+        assert_eq!(MailBox::new("Work").name(), "Work");
+        assert_eq!(MailBox::new("inbox/Work").name(), "inbox/Work");
+
+        MailBox::new("inbox")
+            .fetch_n_recent_mails(1, &mut session)
+            .unwrap();
+        assert!(commands
+            .borrow()
+            .iter()
+            .any(|cmd| cmd == "EXAMINE \"INBOX\""));
+    }
+
+    #[test]
+    fn opens_read_only_unless_changing_mails() {
+        let (mut session, commands) = mock_session(vec![("INBOX", mailbox_fixture())]);
+        let mut opened = |run: &dyn Fn(&mut Session<MockImapStream>)| {
+            commands.borrow_mut().clear();
+            run(&mut session);
+            commands
+                .borrow()
+                .iter()
+                .filter_map(|cmd| cmd.split_once(' ').map(|(name, _)| name.to_owned()))
+                .find(|name| name == "SELECT" || name == "EXAMINE")
+        };
+        let mailbox = MailBox::new("INBOX");
+
+        let read = opened(&|session| {
+            mailbox.fetch_n_recent_mails(1, session).unwrap();
+        });
+        let count = opened(&|session| {
+            mailbox.count_matching("ALL", session).unwrap();
+        });
+        let by_uid = opened(&|session| {
+            mailbox.fetch_mail_by_uid(1, session).unwrap();
+        });
+        let read_marking_seen = opened(&|session| {
+            mailbox
+                .clone()
+                .with_mark_seen(true)
+                .fetch_n_recent_mails(1, session)
+                .unwrap();
+        });
+        let star = opened(&|session| mailbox.set_flagged(&[1], true, session).unwrap());
+
+        assert_eq!(
+            [read, count, by_uid, read_marking_seen, star].map(Option::unwrap),
+            ["EXAMINE", "EXAMINE", "EXAMINE", "SELECT", "SELECT"]
+        );
+    }
+
+    #[test]
+    fn peeks_unless_marking_seen() {
+        let mailbox = MailBox::new("INBOX");
+
+        assert_eq!(mailbox.fetch_query(), "(UID FLAGS BODY.PEEK[])");
+        assert_eq!(
+            mailbox.clone().with_mark_seen(true).fetch_query(),
+            "(UID FLAGS BODY[])"
+        );
+        assert_eq!(
+            mailbox.clone().with_headers_only(true).fetch_query(),
+            "(UID FLAGS BODY.PEEK[HEADER])"
+        );
+        assert_eq!(
+            mailbox
+                .with_headers_only(true)
+                .with_mark_seen(true)
+                .fetch_query(),
+            "(UID FLAGS BODY[HEADER])"
+        );
+    }
+
+    #[test]
+    fn counts_downloaded_bytes() {
+        let (mut session, _) = mock_session(vec![("INBOX", mailbox_fixture())]);
+        let downloaded = ByteCounter::default();
+
+        let mails = MailBox::new("INBOX")
+            .with_batch_size(1)
+            .with_byte_counter(Some(downloaded.clone()))
+            .fetch_n_recent_mails(2, &mut session)
+            .unwrap();
+
+        // the two most recent ones are the first and last mail
+        let fixture = mailbox_fixture();
+        let expected = fixture[0].raw().len() + fixture[2].raw().len();
+        assert_eq!(mails.len(), 2);
+        assert_eq!(downloaded.total(), expected);
+    }
+
+    #[test]
+    fn fetches_only_headers() {
+        let (mut session, commands) = mock_session(vec![("INBOX", mailbox_fixture())]);
+
+        let mails = MailBox::new("INBOX")
+            .with_headers_only(true)
+            .fetch_n_recent_mails(1, &mut session)
+            .unwrap();
+
+        let mail = mails[0].as_ref().unwrap();
+        assert_eq!(mail.subject.as_deref(), Some("third"));
+        assert!(mail.body.is_empty());
+        assert!(!commands
+            .borrow()
+            .iter()
+            .any(|cmd| cmd.contains("BODY.PEEK[]")));
+    }
+
+    #[test]
+    fn sets_gmail_thread_ids_and_labels() {
+        let mails = vec![
+            mock_mail("a", "Mon, 1 May 2023 10:00:00 +0000")
+                .with_thread_id(1)
+                .with_labels(&["Work", "Some Label"]),
+            mock_mail("b", "Tue, 2 May 2023 10:00:00 +0000").with_thread_id(2),
+            mock_mail("re: a", "Wed, 3 May 2023 10:00:00 +0000").with_thread_id(1),
+        ];
+        let store = GmailAttributeStore::default();
+        let stream = MockImapStream::new(vec![("INBOX", mails)])
+            .with_capabilities(&["IMAP4rev1", crate::gmail::GMAIL_EXT_CAPABILITY]);
+        let mut session = connect(GmailExtStream::new(stream, store.clone()))
+            .login("user", "password")
+            .map_err(|(err, _)| err)
+            .unwrap();
+
+        let mut mails = MailBox::new("INBOX")
+            .fetch_n_recent_mails(3, &mut session)
+            .unwrap()
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        set_gmail_attributes(&mut mails, &mut session, &store).unwrap();
+
+        let labelled = mails
+            .iter()
+            .find(|mail| mail.subject.as_deref() == Some("a"))
+            .unwrap();
+        assert_eq!(labelled.labels, vec!["Work", "Some Label"]);
+        assert!(labelled
+            .to_string()
+            .contains("Labels:     Work, Some Label"));
+
+        let threads = group_by_thread(mails)
+            .into_iter()
+            .map(|thread| {
+                thread
+                    .iter()
+                    .map(|mail| mail.subject.clone().unwrap())
+                    .sorted()
+                    .collect::<Vec<_>>()
+            })
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(threads, vec![vec!["a", "re: a"], vec!["b"]]);
+    }
+
+    #[test]
+    fn groups_by_references_without_thread_ids() {
+        let raw = [
+            "Message-ID: <root@x>\nSubject: a\n\nbody\n",
+            "Message-ID: <other@x>\nSubject: b\n\nbody\n",
+            "Message-ID: <reply@x>\nIn-Reply-To: <root@x>\nReferences: <root@x>\nSubject: re: a\n\nbody\n",
+        ];
+        let mails = raw
+            .iter()
+            .enumerate()
+            .map(|(idx, raw)| Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), idx as u32))
+            .collect();
+
+        let sizes = group_by_thread(mails)
+            .iter()
+            .map(|thread| thread.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![2, 1]);
+    }
+
+    #[test]
+    fn detects_encrypted_mails() {
+        let parse = |raw: &str| Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let pgp_mime = parse(
+            "Subject: secret\nContent-Type: multipart/encrypted; \
+            protocol=\"application/pgp-encrypted\"; boundary=\"b\"\n\n\
+            --b\nContent-Type: application/pgp-encrypted\n\nVersion: 1\n\
+            --b\nContent-Type: application/octet-stream\n\n\
+            -----BEGIN PGP MESSAGE-----\nhQEMA\n-----END PGP MESSAGE-----\n--b--\n",
+        );
+        let inline_pgp = parse(
+            "Subject: secret\n\n-----BEGIN PGP MESSAGE-----\nhQEMA\n-----END PGP MESSAGE-----\n",
+        );
+        let smime = parse(
+            "Subject: secret\nContent-Type: application/pkcs7-mime; smime-type=enveloped-data\n\
+            Content-Transfer-Encoding: base64\n\nMIAGCSqGSIb3\n",
+        );
+        let plain = parse("Subject: hello\n\nnothing to hide\n");
+
+        assert_eq!(pgp_mime.encryption, Some(Encryption::Pgp));
+        assert_eq!(inline_pgp.encryption, Some(Encryption::Pgp));
+        assert_eq!(smime.encryption, Some(Encryption::Smime));
+        assert_eq!(plain.encryption, None);
+
+        let shown = inline_pgp.to_string();
+        assert!(shown.contains("Subject:    secret"));
+        assert!(shown.ends_with("[encrypted message (PGP)]"));
+    }
+
+    #[test]
+    fn maps_flags_to_glyphs() {
+        let flags = [
+            Flag::Deleted,
+            Flag::Seen,
+            Flag::Custom("$Forwarded".into()),
+            Flag::Flagged,
+            Flag::Answered,
+            Flag::Draft,
+            Flag::Recent,
+        ]
+        .iter()
+        .filter_map(MailFlag::from_imap)
+        .sorted()
+        .collect::<Vec<_>>();
+
+        assert_eq!(flags, MailFlag::ALL);
+        assert_eq!(flag_glyphs(&flags), "✓↩★✎✗");
+        assert_eq!(flag_glyphs(&[]), "");
+
+        let raw = "Date: Tue, 20 Jun 2023 10:00:00 +0200\r\nSubject: hi\r\n\r\nbody\r\n";
+        let mut flagged = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        flagged.flags = vec![MailFlag::Seen, MailFlag::Flagged];
+        let plain = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 2);
+        let mails = vec![Ok(flagged.clone()), Ok(plain)];
+        let options = DisplayOptions {
+            date_format: String::from("%d.%m."),
+            ..DisplayOptions::default()
+        };
+
+        assert_eq!(flags_legend(&mails).as_deref(), Some("✓ seen  ★ flagged"));
+        assert_eq!(flags_legend(&mails[1..]), None);
+        assert_eq!(
+            header_table(&mails, &options, 80),
+            "✓★  20.06.  -  hi\n    20.06.  -  hi"
+        );
+        assert!(flagged.to_string().contains("Subject:    ✓★ hi"));
+        assert_eq!(
+            flagged.display(&options).json()["flags"],
+            serde_json::json!(["seen", "flagged"])
+        );
+    }
+
+    #[test]
+    fn builds_flagged_store_query() {
+        assert_eq!(flagged_store_query(true), "+FLAGS.SILENT (\\Flagged)");
+        assert_eq!(flagged_store_query(false), "-FLAGS.SILENT (\\Flagged)");
+    }
+
+    #[test]
+    fn archives_gmail_mails_by_removing_inbox_label() {
+        let stream = MockImapStream::new(vec![(
+            "INBOX",
+            vec![
+                mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+                mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000"),
+            ],
+        )])
+        .with_capabilities(&["IMAP4rev1", "X-GM-EXT-1", "MOVE"]);
+        let commands = stream.command_log();
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        MailBox::new("INBOX")
+            .archive(&[1, 2], None, &mut session)
+            .unwrap();
+
+        let commands = commands.borrow();
+        assert!(commands.contains(&String::from("UID STORE 1:2 -X-GM-LABELS.SILENT (\\Inbox)")));
+        assert!(!commands
+            .iter()
+            .any(|command| command.starts_with("UID MOVE")));
+    }
+
+    #[test]
+    fn moves_without_move_extension_only_if_nothing_else_is_expunged() {
+        let mails = vec![
+            mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+            mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000").with_flags(&["\\Deleted"]),
+            mock_mail("third", "Wed, 3 May 2023 10:00:00 +0000"),
+        ];
+        let (mut session, commands) = mock_session(vec![("INBOX", mails), ("Archive", Vec::new())]);
+
+        // "second" was marked as deleted by another client and would be expunged as well
+        assert!(MailBox::INBOX
+            .move_mails(&[1], "Archive", &mut session)
+            .is_err());
+        assert!(!commands
+            .borrow()
+            .iter()
+            .any(|command| command.starts_with("UID COPY") || command == "EXPUNGE"));
+
+        MailBox::INBOX
+            .move_mails(&[1, 2], "Archive", &mut session)
+            .unwrap();
+        assert!(commands.borrow().iter().any(|command| command == "EXPUNGE"));
+        assert_eq!(MailBox::INBOX.uids(&mut session).unwrap(), vec![1]);
+        assert_eq!(
+            MailBox::new("Archive").uids(&mut session).unwrap(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn moves_trashed_mails_to_trash_mailbox() {
+        let stream = MockImapStream::new(vec![
+            (
+                "INBOX",
+                vec![
+                    mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+                    mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000"),
+                ],
+            ),
+            ("Deleted Items", Vec::new()),
+        ])
+        .with_capabilities(&["IMAP4rev1", "MOVE"])
+        .with_mailbox_attributes("Deleted Items", &["\\Trash"]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        MailBox::new("INBOX").trash(&[1], &mut session).unwrap();
+
+        let trashed = MailBox::new("Deleted Items")
+            .fetch_n_recent_mails(10, &mut session)
+            .unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert!(trashed[0].as_ref().unwrap().to_string().contains("first"));
+        let left = MailBox::INBOX
+            .fetch_n_recent_mails(10, &mut session)
+            .unwrap();
+        assert_eq!(left.len(), 1);
+    }
+
+    #[test]
+    fn deletes_to_trash_unless_permanent() {
+        let stream = MockImapStream::new(vec![
+            (
+                "INBOX",
+                vec![
+                    mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+                    mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000"),
+                    mock_mail("third", "Wed, 3 May 2023 10:00:00 +0000"),
+                ],
+            ),
+            ("Deleted Items", Vec::new()),
+        ])
+        .with_capabilities(&["IMAP4rev1", "MOVE", "UIDPLUS"])
+        .with_mailbox_attributes("Deleted Items", &["\\Trash"]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+        let trash = MailBox::new("Deleted Items");
+        let count = |mailbox: &MailBox, session: &mut Session<_>| {
+            mailbox.fetch_n_recent_mails(10, session).unwrap().len()
+        };
+
+        let deletion = MailBox::INBOX.deletion(false, &mut session).unwrap();
+        assert_eq!(deletion, Deletion::Trash);
+        MailBox::INBOX.delete(&[1], deletion, &mut session).unwrap();
+        assert_eq!(count(&MailBox::INBOX, &mut session), 2);
+        assert_eq!(count(&trash, &mut session), 1);
+
+        let deletion = MailBox::INBOX.deletion(true, &mut session).unwrap();
+        assert_eq!(deletion, Deletion::Permanent);
+        MailBox::INBOX.delete(&[1], deletion, &mut session).unwrap();
+        assert_eq!(count(&MailBox::INBOX, &mut session), 1);
+        assert_eq!(count(&trash, &mut session), 1);
+
+        // deleting from the Trash can't be undone
+        let deletion = trash.deletion(false, &mut session).unwrap();
+        assert_eq!(deletion, Deletion::Permanent);
+        trash.delete(&[1], deletion, &mut session).unwrap();
+        assert_eq!(count(&trash, &mut session), 0);
+    }
+
+    #[test]
+    fn expunges_only_counted_mails() {
+        let deleted = |subject: &str| {
+            mock_mail(subject, "Mon, 1 May 2023 10:00:00 +0000").with_flags(&["\\Deleted"])
+        };
+        let mails = vec![
+            deleted("first"),
+            mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000"),
+            deleted("third"),
+            deleted("fourth"),
+        ];
+        let stream = MockImapStream::new(vec![("INBOX", mails.clone())])
+            .with_capabilities(&["IMAP4rev1", "UIDPLUS"]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        assert_eq!(
+            MailBox::INBOX.deleted_uids(&mut session).unwrap(),
+            vec![1, 3, 4]
+        );
+        assert_eq!(MailBox::INBOX.expunge(&[1, 3], &mut session).unwrap(), 2);
+        // the UIDs of the mock are sequence numbers, so "fourth" is now 2
+        assert_eq!(MailBox::INBOX.deleted_uids(&mut session).unwrap(), vec![2]);
+        assert_eq!(MailBox::INBOX.expunge(&[], &mut session).unwrap(), 0);
+
+        // without UIDPLUS every mail marked as deleted would be removed
+        let (mut session, commands) = mock_session(vec![("INBOX", mails)]);
+        assert!(MailBox::INBOX.expunge(&[1], &mut session).is_err());
+        assert!(MailBox::INBOX
+            .delete(&[2], Deletion::Permanent, &mut session)
+            .is_err());
+        assert!(!commands
+            .borrow()
+            .iter()
+            .any(|command| command.starts_with("UID STORE") || command == "EXPUNGE"));
+        assert_eq!(MailBox::INBOX.expunge(&[1, 3, 4], &mut session).unwrap(), 3);
+        assert!(commands.borrow().iter().any(|command| command == "EXPUNGE"));
+        assert!(MailBox::INBOX
+            .deleted_uids(&mut session)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn stars_and_unstars_mails() {
+        let (mut session, commands) = mock_session(vec![(
+            "INBOX",
+            vec![
+                mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+                mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000").with_flags(&["\\Flagged"]),
+            ],
+        )]);
+        let mailbox = MailBox::new("INBOX");
+
+        mailbox.set_flagged(&[1], true, &mut session).unwrap();
+        mailbox.set_flagged(&[2], false, &mut session).unwrap();
+        assert!(commands
+            .borrow()
+            .contains(&String::from("UID STORE 1 +FLAGS.SILENT (\\Flagged)")));
+
+        let mails = mailbox.fetch_n_recent_mails(2, &mut session).unwrap();
+        let subjects = mails
+            .iter()
+            .map(|mail| mail.as_ref().unwrap().to_string())
+            .map(|shown| {
+                shown
+                    .lines()
+                    .find(|line| line.starts_with("Subject:"))
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(subjects, vec!["Subject:    second", "Subject:    ★ first"]);
+    }
+
+    #[test]
+    fn fetches_unsubscribe_methods_by_uid() {
+        let (mut session, _) = mock_session(vec![(
+            "INBOX",
+            vec![
+                mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+                MockMail::new(
+                    "Subject: news\nList-Unsubscribe: <mailto:leave@example.com>\n\nnews\n",
+                ),
+            ],
+        )]);
+        let mailbox = MailBox::new("INBOX");
+
+        assert_eq!(
+            mailbox
+                .fetch_mail_by_uid(2, &mut session)
+                .unwrap()
+                .unsubscribe_methods(),
+            vec![Unsubscribe::Mailto(String::from(
+                "mailto:leave@example.com"
+            ))]
+        );
+        assert!(mailbox
+            .fetch_mail_by_uid(1, &mut session)
+            .unwrap()
+            .unsubscribe_methods()
+            .is_empty());
+        assert!(mailbox.fetch_mail_by_uid(3, &mut session).is_err());
+    }
+
+    #[test]
+    fn removes_duplicate_message_ids() {
+        let parse = |subject: &str, message_id: Option<&str>| {
+            let header = message_id
+                .map(|id| format!("Message-ID: <{id}>\n"))
+                .unwrap_or_default();
+            let raw = format!("{header}Subject: {subject}\n\nbody\n");
+            Ok(Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1))
+        };
+        let mails = vec![
+            parse("inbox", Some("a@example.com")),
+            parse("other", Some("b@example.com")),
+            parse("no id", None),
+            parse("all mail", Some("a@example.com")),
+            parse("no id either", None),
+            Err(anyhow!("failed to parse mail")),
+        ];
+
+        let (mails, hidden) = dedup_by_message_id(mails);
+        let subjects = mails
+            .iter()
+            .filter_map(|mail| mail.as_ref().ok()?.subject.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(hidden, 1);
+        assert_eq!(mails.len(), 5);
+        assert_eq!(subjects, vec!["inbox", "other", "no id", "no id either"]);
+    }
+
+    #[test]
+    fn exposes_raw_headers() {
+        let raw = "Received: from a.example.com\n\tby b.example.com\n\
+            Authentication-Results: mx.example.com; dkim=pass\nSubject: a\n\nbody\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+
+        assert_eq!(
+            mail.header("authentication-results").collect::<Vec<_>>(),
+            vec!["mx.example.com; dkim=pass"]
+        );
+        assert_eq!(
+            mail.header("Received").collect::<Vec<_>>(),
+            vec!["from a.example.com by b.example.com"]
+        );
+        assert_eq!(mail.headers().len(), 3);
+
+        let named = DisplayOptions {
+            extra_headers: ExtraHeaders::Named(vec![String::from("Received")]),
+            ..DisplayOptions::default()
+        };
+        assert!(mail
+            .display(&named)
+            .to_string()
+            .contains("\nReceived: from a.example.com by b.example.com\n"));
+        assert!(!mail.to_string().contains("Received:"));
+    }
+
+    #[test]
+    fn shows_auth_results() {
+        let raw = "Authentication-Results: mx.example.com; spf=fail; dkim=pass\n\
+            Subject: a\n\nbody\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let options = DisplayOptions {
+            show_auth: true,
+            ..DisplayOptions::default()
+        };
+
+        assert!(mail
+            .display(&options)
+            .to_string()
+            .contains("\nAuth:       ✗ SPF fail, DKIM pass, DMARC none\n"));
+        assert!(!mail.to_string().contains("Auth:"));
+    }
+
+    #[test]
+    fn compresses_uid_sets() {
+        assert_eq!(uid_set(&[]), "");
+        assert_eq!(uid_set(&[4]), "4");
+        assert_eq!(uid_set(&[3, 1, 2, 7, 9, 10, 2]), "1:3,7,9:10");
+        assert_eq!(uid_set(&(1..=100_000).collect::<Vec<_>>()), "1:100000");
+        assert_eq!(
+            uid_set(&[u32::MAX - 1, u32::MAX]),
+            format!("{}:{}", u32::MAX - 1, u32::MAX)
+        );
+    }
+
+    #[test]
+    fn resumes_fetching_after_connection_drop() {
+        let mails = || {
+            (1..=5)
+                .map(|day| mock_mail(&day.to_string(), &format!("{day} May 2023 10:00:00 +0000")))
+                .collect::<Vec<_>>()
+        };
+        // the 3 fetches of the dates and the first batch of mails go through
+        let stream = MockImapStream::new(vec![("INBOX", mails())]).with_drop_after_fetches(4);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        let mut reconnected = None;
+        let mut reconnect = |notice: &str| {
+            assert_eq!(notice, "connection lost, reconnecting (1/1)");
+            let (session, commands) = mock_session(vec![("INBOX", mails())]);
+            reconnected = Some(commands);
+            Ok(session)
+        };
+        let mails = MailBox::new("INBOX")
+            .with_batch_size(2)
+            .with_retries(1)
+            .fetch_n_recent_mails_reconnecting(4, "ALL", &mut session, Some(&mut reconnect))
+            .unwrap();
+
+        let subjects = mails
+            .iter()
+            .map(|mail| mail.as_ref().unwrap().subject.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(subjects, vec!["5", "4", "3", "2"]);
+        let commands = reconnected.unwrap().borrow().clone();
+        assert_eq!(
+            commands[1..],
+            ["EXAMINE \"INBOX\"", "UID FETCH 4,5 (UID FLAGS BODY.PEEK[])"]
+        );
+    }
+
+    #[test]
+    fn reconnects_while_sorting_by_date() {
+        let mails = || {
+            (1..=3)
+                .map(|day| mock_mail(&day.to_string(), &format!("{day} May 2023 10:00:00 +0000")))
+                .collect::<Vec<_>>()
+        };
+        // drops on the first fetch of the dates
+        let stream = MockImapStream::new(vec![("INBOX", mails())]).with_drop_after_fetches(0);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        let mut reconnected = None;
+        let mut reconnect = |_: &str| {
+            let (session, commands) = mock_session(vec![("INBOX", mails())]);
+            reconnected = Some(commands);
+            Ok(session)
+        };
+        let mails = MailBox::new("INBOX")
+            .with_retries(1)
+            .fetch_n_recent_mails_reconnecting(2, "ALL", &mut session, Some(&mut reconnect))
+            .unwrap();
+
+        let subjects = mails
+            .iter()
+            .map(|mail| mail.as_ref().unwrap().subject.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(subjects, vec!["3", "2"]);
+        let commands = reconnected.unwrap().borrow().clone();
+        assert_eq!(
+            commands[1..],
+            [
+                "EXAMINE \"INBOX\"",
+                "UID SEARCH ALL",
+                "UID FETCH 1,2,3 BODY.PEEK[HEADER.FIELDS (DATE )]",
+                "UID FETCH 2,3 (UID FLAGS BODY.PEEK[])"
+            ]
+        );
+    }
+
+    #[test]
+    fn retries_opening_unavailable_mailbox() {
+        let stream =
+            MockImapStream::new(vec![("INBOX", mailbox_fixture())]).with_unavailable_opens(1);
+        let commands = stream.command_log();
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        let opened = OpenMode::ReadWrite
+            .open_retrying("INBOX", &mut session, Duration::ZERO)
+            .unwrap();
+        assert_eq!(opened.exists, 3);
+        assert_eq!(
+            commands.borrow()[1..],
+            ["SELECT \"INBOX\"", "SELECT \"INBOX\""]
+        );
+
+        let stream = MockImapStream::new(vec![("INBOX", mailbox_fixture())])
+            .with_unavailable_opens(SESSION_RETRIES as usize + 1);
+        let mut session = connect(stream).login("user", "password").unwrap();
+        assert!(matches!(
+            OpenMode::ReadOnly.open_retrying("INBOX", &mut session, Duration::ZERO),
+            Err(imap::Error::No(_))
+        ));
+    }
+
+    #[test]
+    fn gives_up_after_retries() {
+        let stream = MockImapStream::new(vec![(
+            "INBOX",
+            vec![mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000")],
+        )])
+        .with_drop_after_fetches(1);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        let mut reconnect = |_: &str| Err(anyhow!("unreachable"));
+        assert!(MailBox::new("INBOX")
+            .fetch_n_recent_mails_reconnecting(1, "ALL", &mut session, Some(&mut reconnect))
+            .is_err());
+    }
+
+    #[test]
+    fn fetches_in_batches() {
+        let mails = (1..=5)
+            .map(|day| mock_mail(&day.to_string(), &format!("{day} May 2023 10:00:00 +0000")))
+            .collect();
+        let (mut session, commands) = mock_session(vec![("INBOX", mails)]);
+
+        let mails = MailBox::new("INBOX")
+            .with_batch_size(2)
+            .fetch_n_recent_mails(4, &mut session)
+            .unwrap();
+        let subjects = mails
+            .iter()
+            .map(|mail| mail.as_ref().unwrap().subject.clone().unwrap())
+            .collect::<Vec<_>>();
+        let fetches = commands
+            .borrow()
+            .iter()
+            .filter(|command| command.starts_with("UID FETCH"))
+            .map(|command| command.split(' ').nth(2).unwrap().to_owned())
+            .collect::<Vec<_>>();
+
+        assert_eq!(subjects, vec!["5", "4", "3", "2"]);
+        assert_eq!(fetches, vec!["1,2", "3,4", "5", "2,3", "4,5"]);
+    }
+
+    #[test]
+    fn renders_html_with_link_footnotes() {
+        let html = "<html><head><title>news</title><style>p { color: red; }</style></head>\
+            <body><h1>Weekly &amp; more</h1>\
+            <p>Read the <a href=\"https://example.com/post?a=1&amp;b=2\">new post</a> or \
+            <b>skip</b> it.</p>\
+            <ul><li>first</li><li><a href='https://example.com/2'>second</a></li></ul>\
+            <ol><li>one</li><li>two</li></ol>\
+            <!-- tracking --><p><a href=\"#top\">back to top</a></p></body></html>";
+
+        assert_eq!(
+            render_html(html),
+            "\x1b[1mWeekly & more\x1b[22m\n\n\
+            Read the new post[1] or \x1b[1mskip\x1b[22m it.\n\n\
+            • first\n\
+            • second[2]\n\n\
+            1. one\n\
+            2. two\n\n\
+            back to top\n\n\
+            [1] https://example.com/post?a=1&b=2\n\
+            [2] https://example.com/2"
+        );
+    }
+
+    #[test]
+    fn ignores_unmatched_closing_tags() {
+        assert_eq!(
+            html_to_text("</style><p>still shown</p></head>"),
+            "still shown"
+        );
+    }
+
+    #[test]
+    fn displays_mail_as_markdown() {
+        let raw = "From: Alice <alice@example.com>\r\n\
+            To: bob@example.com\r\n\
+            Date: Tue, 20 Jun 2023 10:00:00 +0200\r\n\
+            Subject: Weekly \"news\"\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <h1>News</h1><p>See <a href=\"https://example.com\">here</a>.</p>\r\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let options = DisplayOptions {
+            format: OutputFormat::Markdown,
+            ..DisplayOptions::default()
+        };
+
+        assert_eq!(
+            mail.display(&options).to_string(),
+            "---\n\
+            from: \"Alice <alice@example.com>\"\n\
+            to: \"bob@example.com\"\n\
+            date: \"Tue, 20 Jun 2023 10:00 +02:00\"\n\
+            ---\n\n\
+            # Weekly \"news\"\n\n\
+            # News\n\n\
+            See [here](https://example.com)."
+        );
+    }
+
+    #[test]
+    fn displays_selected_fields_as_json() {
+        let raw = "From: Alice <alice@example.com>\r\n\
+            To: bob@example.com\r\n\
+            Subject: hi\r\n\
+            \r\n\
+            hello\r\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let options = DisplayOptions {
+            format: OutputFormat::Json,
+            fields: vec![MailField::Subject, MailField::From, MailField::Date],
+            ..DisplayOptions::default()
+        };
+
+        assert_eq!(
+            mail.display(&options).json(),
+            serde_json::json!({
+                "subject": "hi",
+                "from": "Alice <alice@example.com>",
+                "date": null,
+            })
+        );
+
+        let all = DisplayOptions {
+            fields: Vec::new(),
+            ..options
+        };
+        let json = mail.display(&all).json();
+        let keys = json.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys.len(), MailField::value_variants().len());
+        assert_eq!(json["body"], "hello");
+    }
+
+    #[test]
+    fn detects_outgoing_mailboxes() {
+        let sent = [NameAttribute::Custom("\\Sent".into())];
+        let drafts = [NameAttribute::Custom("\\drafts".into())];
+        let junk = [NameAttribute::Custom("\\Junk".into())];
+
+        assert!(is_outgoing_mailbox("Gesendet", &sent));
+        assert!(is_outgoing_mailbox("Entwürfe", &drafts));
+        assert!(!is_outgoing_mailbox("Spam", &junk));
+        assert!(is_outgoing_mailbox("[Gmail]/Sent Mail", &[]));
+        assert!(is_outgoing_mailbox("INBOX.Sent", &[]));
+        assert!(is_outgoing_mailbox(
+            "Sent Items",
+            &[NameAttribute::NoInferiors]
+        ));
+        assert!(!is_outgoing_mailbox("INBOX", &[]));
+        assert!(!is_outgoing_mailbox("Sent Stuff/Archive", &[]));
+    }
+
+    #[test]
+    fn shows_recipient_first_for_sent_mails() {
+        let stream = MockImapStream::new(vec![
+            (
+                "INBOX",
+                vec![mock_mail("hi", "Mon, 1 May 2023 10:00:00 +0000")],
+            ),
+            ("Gesendet", Vec::new()),
+        ])
+        .with_mailbox_attributes("Gesendet", &["\\Sent"]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        assert!(MailBox::new("Gesendet").is_outgoing(&mut session).unwrap());
+        assert!(!MailBox::new("INBOX").is_outgoing(&mut session).unwrap());
+
+        let mut mail = MailBox::INBOX
+            .fetch_n_recent_mails(1, &mut session)
+            .unwrap();
+        let mut mail = mail.remove(0).unwrap();
+        mail.set_outgoing(true);
+        assert!(mail
+            .to_string()
+            .starts_with("To:         bob@example.com\nFrom:"));
+    }
+
+    #[test]
+    fn truncates_header_table_columns() {
+        let raw = |from: &str, subject: &str| {
+            format!("From: {from}\r\nDate: Tue, 20 Jun 2023 10:00:00 +0200\r\nSubject: {subject}\r\n\r\n")
+        };
+        let mails = vec![
+            Ok(Mail::from_msg(
+                Message::parse(raw("Alice <alice@example.com>", "Lunch on friday?").as_bytes())
+                    .unwrap(),
+                1,
+            )),
+            Ok(Mail::from_msg(
+                Message::parse(raw("bob@b.c", "Re: Übersicht").as_bytes()).unwrap(),
+                2,
+            )),
+            Err(anyhow!("failed to parse message 3")),
+        ];
+        let options = DisplayOptions {
+            date_format: String::from("%d.%m. %H:%M"),
+            ..DisplayOptions::default()
+        };
+
+        assert_eq!(
+            header_table(&mails, &options, 80),
+            "20.06. 10:00  Alice <alice@example.com>  Lunch on friday?\n\
+            20.06. 10:00  bob@b.c                    Re: Übersicht\n\
+            -             -                          [failed to parse message 3]"
+        );
+        assert_eq!(
+            header_table(&mails, &options, 40),
+            "20.06. 10:00  Alice <…  Lunch on friday?\n\
+            20.06. 10:00  bob@b.c   Re: Übersicht\n\
+            -             -         [failed to pars…"
+        );
+        assert_eq!(
+            header_table(&mails, &options, 20),
+            "20.06. 10:00  …  Lu…\n\
+            20.06. 10:00  …  Re…\n\
+            -             -  [f…"
+        );
+    }
+
+    #[test]
+    fn converts_html_to_markdown() {
+        let html = "<h2>Weekly</h2><p>Read the <a href=\"https://example.com/post\">new \
+            <b>post</b></a> or <em>don't</em>.</p><ul><li>first</li><li>second</li></ul>";
+
+        assert_eq!(
+            html_to_markdown(html),
+            "## Weekly\n\n\
+            Read the [new **post**](https://example.com/post) or _don't_.\n\n\
+            - first\n\
+            - second"
+        );
+    }
+
+    #[test]
+    fn rich_display_uses_html_part() {
+        let raw = "Subject: a\nContent-Type: multipart/alternative; boundary=\"b\"\n\n\
+            --b\nContent-Type: text/plain\n\nplain version\n\
+            --b\nContent-Type: text/html\n\n<p>see <a href=\"https://example.com\">this</a></p>\n\
+            --b--\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let rich = DisplayOptions {
+            rich: true,
+            ..DisplayOptions::default()
+        };
+
+        assert!(mail.to_string().ends_with("plain version"));
+        assert!(mail
+            .display(&rich)
+            .to_string()
+            .ends_with("see this[1]\n\n[1] https://example.com"));
+    }
+
+    #[test]
+    fn prefers_plain_over_html_part() {
+        let raw = "Subject: a\nContent-Type: multipart/alternative; boundary=\"b\"\n\n\
+            --b\nContent-Type: text/plain\n\nplain version\n\
+            --b\nContent-Type: text/html\n\n<p>html <b>version</b></p>\n\
+            --b--\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let prefer_html = DisplayOptions {
+            prefer_html: true,
+            ..DisplayOptions::default()
+        };
+
+        assert_eq!(mail.body.trim(), "plain version");
+        assert!(mail.to_string().ends_with("plain version"));
+        assert!(mail
+            .display(&prefer_html)
+            .to_string()
+            .ends_with("\nhtml version"));
+
+        let html_only = "Subject: a\nContent-Type: text/html\n\n<p>only <i>html</i></p>\n";
+        let mail = Mail::from_msg(Message::parse(html_only.as_bytes()).unwrap(), 1);
+        assert_eq!(mail.body, "only html");
+    }
+
+    #[test]
+    fn keeps_raw_message() {
+        let raw = "Received: from a\r\n\tby b; Mon, 1 May 2023 10:00:00 +0000\r\n\
+            Date: Mon, 1 May 2023 10:00:00 +0000\r\nSubject: =?utf-8?q?caf=C3=A9?=\r\nX-Custom:  spaced   value\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+            --b\r\nContent-Type: text/plain\r\n\r\nbody\r\n\
+            --b\r\nContent-Type: application/pdf\r\n\r\nJVBERi0=\r\n--b--\r\n";
+        let (mut session, _) = mock_session(vec![("INBOX", vec![MockMail::new(raw)])]);
+
+        let mails = MailBox::new("INBOX")
+            .fetch_n_recent_mails(1, &mut session)
+            .unwrap();
+        let mail = mails[0].as_ref().unwrap();
+        assert_eq!(mail.raw(), raw.as_bytes());
+
+        let exported = Mail::from_msg(Message::parse(mail.raw()).unwrap(), 1);
+        assert_eq!(exported.headers(), mail.headers());
+        assert_eq!(exported.structure, mail.structure);
+    }
+
+    #[test]
+    fn frames_mails_as_mbox() {
+        let parse = |raw: &[u8]| Mail::from_msg(Message::parse(raw).unwrap(), 1);
+        let first = parse(
+            b"From: Alice <alice@example.com>\r\nDate: Mon, 1 May 2023 12:00:00 +0200\r\n\
+            Subject: a\r\n\r\nFrom the start\r\n>From quoted\r\nnot From here\r\n",
+        );
+        let second = parse(b"Subject: b\n\ncaf\xe9 without line break");
+
+        let mut out = Vec::new();
+        write_mbox(&mut out, [&first, &second]).unwrap();
+        assert_eq!(
+            out,
+            b"From alice@example.com Mon May  1 10:00:00 2023\n\
+            From: Alice <alice@example.com>\nDate: Mon, 1 May 2023 12:00:00 +0200\n\
+            Subject: a\n\n>From the start\n>>From quoted\nnot From here\n\n\
+            From MAILER-DAEMON Thu Jan  1 00:00:00 1970\n\
+            Subject: b\n\ncaf\xe9 without line break\n\n"
+        );
+    }
+
+    #[test]
+    fn shows_head_or_tail_of_body() {
+        let body = "one\ntwo\nthree\nfour";
+
+        assert_eq!(
+            slice_lines(body, BodyLines::Head(2)),
+            "one\ntwo\n… (2 more lines)"
+        );
+        assert_eq!(
+            slice_lines(body, BodyLines::Tail(1)),
+            "(3 earlier lines) …\nfour"
+        );
+        // shorter bodies are shown completely, without a note
+        assert_eq!(slice_lines(body, BodyLines::Head(4)), body);
+        assert_eq!(slice_lines(body, BodyLines::Tail(10)), body);
+        assert_eq!(slice_lines("", BodyLines::Head(3)), "");
+        assert_eq!(slice_lines(body, BodyLines::Head(0)), "… (4 more lines)");
+        assert_eq!(
+            slice_lines(body, BodyLines::Head(3)),
+            "one\ntwo\nthree\n… (1 more line)"
+        );
+        assert_eq!(
+            slice_lines(body, BodyLines::Tail(3)),
+            "(1 earlier line) …\ntwo\nthree\nfour"
+        );
+
+        let raw = "Subject: a\n\nfirst\nsecond\nthird\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let head = DisplayOptions {
+            body_lines: Some(BodyLines::Head(1)),
+            ..DisplayOptions::default()
+        };
+        assert!(mail
+            .display(&head)
+            .to_string()
+            .ends_with("first\n… (2 more lines)"));
+    }
+
+    #[test]
+    fn shows_sender_in_each_style() {
+        let parse = |from: &str| {
+            let raw = format!("From: {from}\nSubject: a\n\nbody\n");
+            Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1)
+        };
+        let from = |mail: &Mail, from_style| {
+            let options = DisplayOptions {
+                from_style,
+                ..DisplayOptions::default()
+            };
+            mail.display(&options).from()
+        };
+
+        let named = parse("\"Alice Doe\" <alice@example.com>");
+        assert_eq!(from(&named, FromStyle::Name), "Alice Doe");
+        assert_eq!(from(&named, FromStyle::Address), "alice@example.com");
+        assert_eq!(
+            from(&named, FromStyle::Both),
+            "Alice Doe <alice@example.com>"
+        );
+
+        let bare = parse("bob@example.com");
+        assert_eq!(from(&bare, FromStyle::Name), "bob@example.com");
+        assert_eq!(from(&bare, FromStyle::Both), "bob@example.com");
+
+        // encoded display names are decoded
+        let encoded = parse("=?UTF-8?B?SsO8cmdlbg==?= <j@example.com>");
+        assert_eq!(from(&encoded, FromStyle::Name), "Jürgen");
+
+        let options = DisplayOptions {
+            from_style: FromStyle::Address,
+            ..DisplayOptions::default()
+        };
+        let table = header_table(&[Ok(named)], &options, 80);
+        assert!(table.contains("alice@example.com") && !table.contains("Alice"));
+    }
+
+    #[test]
+    fn decodes_legacy_charsets() {
+        let mut latin1 = b"Date: Mon, 1 May 2023 10:00:00 +0000\r\nSubject: caf\xe9\r\n\
+            Content-Type: text/plain; charset=iso-8859-1\r\n\r\n"
+            .to_vec();
+        latin1.extend(b"Gr\xfc\xdfe aus K\xf6ln\r\n");
+        let (mut session, _) = mock_session(vec![("INBOX", vec![MockMail::from_bytes(latin1)])]);
+
+        let mails = MailBox::new("INBOX")
+            .fetch_n_recent_mails(1, &mut session)
+            .unwrap();
+
+        assert_eq!(mails[0].as_ref().unwrap().body.trim(), "Grüße aus Köln");
+    }
+
+    #[test]
+    fn extracts_links() {
+        let body = "Confirm your account: https://example.com/confirm?token=abc&x=1.\n\
+            (or visit <https://example.com/help>, http://example.org/a_b-c/)\n\
+            again: https://example.com/confirm?token=abc&x=1 and ftp://example.com";
+
+        assert_eq!(
+            extract_links(body),
+            vec![
+                "https://example.com/confirm?token=abc&x=1",
+                "https://example.com/help",
+                "http://example.org/a_b-c/",
+            ]
+        );
+        assert!(extract_links("no links here").is_empty());
+    }
+
+    #[test]
+    fn formats_dates() {
+        let raw = "Date: Wed, 3 May 2023 09:05:00 +0200\nSubject: a\n\nbody\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let format = |date_format: &str| {
+            let options =
+                DisplayOptions::new(None, date_format.to_owned(), DisplayTimezone::Sender).unwrap();
+            mail.display(&options).date()
+        };
+
+        assert_eq!(format("%Y-%m-%d %H:%M"), "2023-05-03 09:05");
+        assert_eq!(format("%d.%m.%y %:z"), "03.05.23 +02:00");
+        assert_eq!(format(DEFAULT_DATE_FORMAT), "Wed, 03 May 2023 09:05 +02:00");
+        assert!(DisplayOptions::new(None, String::from("%Q"), DisplayTimezone::Sender).is_err());
+    }
+
+    #[test]
+    fn shows_relative_dates() {
+        let now = Utc.with_ymd_and_hms(2023, 5, 10, 12, 0, 0).unwrap();
+        let relative = |date: &str| relative_date(DateTime::parse_from_rfc3339(date).unwrap(), now);
+
+        assert_eq!(relative("2023-05-10T11:59:30Z").unwrap(), "just now");
+        assert_eq!(relative("2023-05-10T11:59:00Z").unwrap(), "1 minute ago");
+        assert_eq!(relative("2023-05-10T11:15:00Z").unwrap(), "45 minutes ago");
+        // the sender's offset doesn't matter
+        assert_eq!(relative("2023-05-10T09:59:30-02:00").unwrap(), "just now");
+        assert_eq!(relative("2023-05-10T02:00:00Z").unwrap(), "10 hours ago");
+        assert_eq!(relative("2023-05-09T08:00:00Z").unwrap(), "yesterday");
+        assert_eq!(relative("2023-05-07T20:00:00Z").unwrap(), "3 days ago");
+        assert_eq!(relative("2023-05-03T00:00:00Z").unwrap(), "7 days ago");
+        assert_eq!(relative("2023-05-02T23:00:00Z"), None);
+        assert_eq!(relative("2023-05-11T12:00:00Z"), None);
+    }
+
+    #[test]
+    fn converts_dates_between_timezones() {
+        let raw = "Date: Wed, 3 May 2023 23:30:00 -0200\nSubject: a\n\nbody\n";
+        let mail = Mail::from_msg(Message::parse(raw.as_bytes()).unwrap(), 1);
+        let format = |timezone| {
+            let options =
+                DisplayOptions::new(None, String::from("%Y-%m-%d %H:%M %:z"), timezone).unwrap();
+            mail.display(&options).date()
+        };
+
+        assert_eq!(format(DisplayTimezone::Sender), "2023-05-03 23:30 -02:00");
+        assert_eq!(format(DisplayTimezone::Utc), "2023-05-04 01:30 +00:00");
+        assert_eq!(
+            format(DisplayTimezone::Local),
+            mail.date
+                .unwrap()
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M %:z")
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn sorts_by_instant_across_offsets() {
+        let mails = vec![
+            // 07:00 UTC
+            mock_mail("earlier", "Wed, 3 May 2023 09:00:00 +0200"),
+            // 08:00 UTC
+            mock_mail("later", "Wed, 3 May 2023 08:00:00 +0000"),
+            // 06:30 UTC
+            mock_mail("earliest", "Wed, 3 May 2023 01:30:00 -0500"),
+        ];
+        let (mut session, _) = mock_session(vec![("INBOX", mails)]);
+        session.select("INBOX").unwrap();
+
+        assert_eq!(
+            get_mails_sorted_by_date("ALL", DEFAULT_FETCH_BATCH_SIZE, &mut session).unwrap(),
+            vec![2, 1, 3]
+        );
+    }
+
+    #[test]
+    fn fetching_from_unknown_mailbox_fails() {
+        let (mut session, _) = mock_session(vec![("INBOX", mailbox_fixture())]);
+
+        assert!(MailBox::new("Archive")
+            .fetch_n_recent_mails(1, &mut session)
+            .is_err());
+    }
 }
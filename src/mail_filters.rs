@@ -1,13 +1,24 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    hash::{Hash, Hasher},
+    io::Write,
+    process::{Command, Stdio},
+    str::FromStr,
+};
 
-use chrono::{DateTime, FixedOffset};
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::mail::Mail;
 
 pub struct HeaderFilter {
     fields: HashSet<HeaderField>,
     negated: bool,
 }
 
-#[derive(Debug, Clone, Hash, Eq)]
+#[derive(Debug, Clone, Eq)]
 #[allow(dead_code)]
 pub enum HeaderField {
     Subject(Option<String>),
@@ -20,9 +31,11 @@ impl PartialEq for HeaderField {
     fn eq(&self, other: &Self) -> bool {
         std::mem::discriminant(self) == std::mem::discriminant(other)
     }
+}
 
-    fn ne(&self, other: &Self) -> bool {
-        std::mem::discriminant(self) != std::mem::discriminant(other)
+impl Hash for HeaderField {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state)
     }
 }
 
@@ -44,7 +57,7 @@ impl HeaderFilter {
             .iter()
             .map(|field| field.filter_str())
             .collect::<Vec<_>>()
-            .join(", ");
+            .join(" ");
 
         Some(format!("HEADER.FIELDS{negated} ({fields})"))
     }
@@ -60,7 +73,7 @@ impl HeaderField {
                 format!("TO {}", to.as_ref().unwrap_or(&String::new()))
             }
             HeaderField::From(from) => {
-                format!("TO {}", from.as_ref().unwrap_or(&String::new()))
+                format!("FROM {}", from.as_ref().unwrap_or(&String::new()))
             }
             HeaderField::Date(date) => {
                 format!(
@@ -71,3 +84,409 @@ impl HeaderField {
         }
     }
 }
+
+/// the start of a time range, either a date or a time span before now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Since {
+    /// the start of the day in the local timezone
+    Date(NaiveDate),
+    Ago(Duration),
+}
+
+impl Since {
+    /// the point in time this refers to, relative to `now`
+    pub fn start(&self, now: DateTime<Local>) -> DateTime<Local> {
+        match self {
+            Since::Date(date) => {
+                let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+                Local
+                    .from_local_datetime(&midnight)
+                    .earliest()
+                    .unwrap_or_else(|| Local.from_utc_datetime(&midnight))
+            }
+            // spans too long for `now` were rejected when parsing, but `now` may differ
+            Since::Ago(duration) => now
+                .checked_sub_signed(*duration)
+                .unwrap_or_else(|| Local.from_utc_datetime(&NaiveDateTime::default())),
+        }
+    }
+
+    /// the `SINCE` search key, IMAP only compares dates so mails sent earlier on the day of
+    /// [`Since::start`] have to be filtered out by the client
+    pub fn search_key(&self, now: DateTime<Local>) -> String {
+        format!("SINCE {}", self.start(now).format("%d-%b-%Y"))
+    }
+}
+
+impl FromStr for Since {
+    type Err = String;
+
+    /// parses a date (YYYY-MM-DD) or a time span like "30m", "24h", "7d" or "2w"
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        if let Ok(date) = NaiveDate::parse_from_str(str, "%Y-%m-%d") {
+            return Ok(Since::Date(date));
+        }
+
+        let invalid = || {
+            format!(
+                "invalid time '{str}', expected a date (YYYY-MM-DD) or a number followed by m \
+                (minutes), h, d or w (e.g. 7d)"
+            )
+        };
+        parse_time_span(str)
+            .unwrap_or_else(|| Err(invalid()))
+            .map(Since::Ago)
+    }
+}
+
+/// parses a time span like "30m", "24h", "7d" or "2w", `None` if `str` isn't one at all.
+///
+/// spans reaching back further than a date can are an error
+fn parse_time_span(str: &str) -> Option<Result<Duration, String>> {
+    let str = str.trim();
+    let (idx, unit) = str.char_indices().last()?;
+    let amount = str[..idx].parse::<u32>().ok()? as i64;
+
+    let unit_secs = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let duration = amount.checked_mul(unit_secs).map(Duration::seconds);
+    match duration.filter(|duration| Local::now().checked_sub_signed(*duration).is_some()) {
+        Some(duration) => Some(Ok(duration)),
+        None => Some(Err(format!("time span '{str}' reaches too far back"))),
+    }
+}
+
+/// how old mails may be at most, a time span like "30d" that is always counted back from now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MaxAge(pub Duration);
+
+impl FromStr for MaxAge {
+    type Err = String;
+
+    /// parses a time span like "30m", "24h", "7d" or "2w"
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid age '{str}', expected a number followed by m (minutes), h, d or w \
+                (e.g. 30d)"
+            )
+        };
+
+        parse_time_span(str)
+            .unwrap_or_else(|| Err(invalid()))
+            .map(MaxAge)
+    }
+}
+
+impl TryFrom<String> for MaxAge {
+    type Error = String;
+
+    fn try_from(str: String) -> Result<Self, Self::Error> {
+        str.parse()
+    }
+}
+
+/// whichever of `since` and `max_age` starts later relative to `now`, mails have to be sent
+/// after both
+pub fn latest_start(
+    since: Option<Since>,
+    max_age: Option<MaxAge>,
+    now: DateTime<Local>,
+) -> Option<Since> {
+    since
+        .into_iter()
+        .chain(max_age.map(|MaxAge(age)| Since::Ago(age)))
+        .max_by_key(|since| since.start(now))
+}
+
+/// the search keys mails have to match all of, standard IMAP ones (e.g. from
+/// [`contains_search`]) and Gmail specific ones (see [`crate::gmail::raw_search`])
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Search {
+    pub keys: Vec<String>,
+    pub gmail: Vec<String>,
+}
+
+impl Search {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.gmail.is_empty()
+    }
+
+    /// all keys joined into one `SEARCH` query, `ALL` if there are none
+    pub fn query(&self) -> String {
+        match self.is_empty() {
+            true => String::from("ALL"),
+            false => self.keys.iter().chain(&self.gmail).join(" "),
+        }
+    }
+}
+
+/// quotes `str` as an IMAP quoted string
+pub fn quote(str: &str) -> String {
+    format!("\"{}\"", str.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// builds a `SEARCH` key like `SUBJECT "invoice"` matching mails containing `value` (ignoring
+/// case) in the part of the mail selected by `key`
+pub fn contains_search(key: &str, value: &str) -> String {
+    format!("{key} {}", quote(value))
+}
+
+/// the `SEARCH` keys for the filters of `read`, which a server combines with AND: mails since
+/// `since`, only flagged ones if `flagged` and those containing each value of `text_search` in
+/// the part selected by its key
+pub fn read_search_keys(
+    since: Option<Since>,
+    flagged: bool,
+    text_search: &[(&str, String)],
+    now: DateTime<Local>,
+) -> Vec<String> {
+    since
+        .iter()
+        .map(|since| since.search_key(now))
+        .chain(flagged.then(|| String::from("FLAGGED")))
+        .chain(
+            text_search
+                .iter()
+                .map(|(key, value)| contains_search(key, value)),
+        )
+        .collect()
+}
+
+/// builds a `SEARCH` key matching the mail with the given Message-ID, the id may be given with
+/// or without its angle brackets
+pub fn message_id_search(message_id: &str) -> String {
+    let id = message_id
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    format!("HEADER Message-ID {}", quote(&format!("<{id}>")))
+}
+
+/// keeps the mails for which the shell `command` exits successfully when given their raw
+/// content on stdin, like a sieve or spam filter would. mails that failed to parse are kept so
+/// they are still reported
+pub fn filter_by_command(
+    mails: Vec<anyhow::Result<Mail>>,
+    command: &str,
+) -> anyhow::Result<Vec<anyhow::Result<Mail>>> {
+    let mut kept = Vec::with_capacity(mails.len());
+    for mail in mails {
+        let keep = match &mail {
+            Ok(mail) => passes_command(command, mail.raw())?,
+            Err(_) => true,
+        };
+        if keep {
+            kept.push(mail);
+        }
+    }
+
+    Ok(kept)
+}
+
+/// runs `command` with `input` on its stdin, its output is discarded so it doesn't mix with the
+/// displayed mails
+fn passes_command(command: &str, input: &[u8]) -> anyhow::Result<bool> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    let mut child = shell
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|err| anyhow!("failed to run filter command '{command}': {err}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // commands like `grep -q` may exit before reading all of the mail
+        match stdin.write_all(input) {
+            Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+    }
+
+    Ok(child.wait()?.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mail::MailBox,
+        mock_imap::{mock_session, MockMail},
+    };
+
+    #[test]
+    fn empty_filter_has_no_filter_str() {
+        assert_eq!(HeaderFilter::new(HashSet::new(), false).filter_str(), None);
+    }
+
+    #[test]
+    fn filter_str_lists_fields() {
+        let filter = HeaderFilter::new(HashSet::from([HeaderField::Date(None)]), false);
+        assert_eq!(filter.filter_str().unwrap(), "HEADER.FIELDS (DATE )");
+
+        let filter = HeaderFilter::new(
+            HashSet::from([HeaderField::From(Some(String::from("a@b.c")))]),
+            true,
+        );
+        assert_eq!(
+            filter.filter_str().unwrap(),
+            "HEADER.FIELDS.NOT (FROM a@b.c)"
+        );
+    }
+
+    #[test]
+    fn fields_are_unique_by_kind() {
+        let fields = HashSet::from([
+            HeaderField::Subject(Some(String::from("a"))),
+            HeaderField::Subject(Some(String::from("b"))),
+            HeaderField::To(None),
+        ]);
+
+        assert_eq!(fields.len(), 2);
+    }
+
+    #[test]
+    fn parses_since_specs() {
+        assert_eq!("30m".parse(), Ok(Since::Ago(Duration::minutes(30))));
+        assert_eq!("24h".parse(), Ok(Since::Ago(Duration::hours(24))));
+        assert_eq!("7d".parse(), Ok(Since::Ago(Duration::days(7))));
+        assert_eq!("2w".parse(), Ok(Since::Ago(Duration::weeks(2))));
+        assert_eq!(
+            "2023-05-01".parse(),
+            Ok(Since::Date(NaiveDate::from_ymd_opt(2023, 5, 1).unwrap()))
+        );
+
+        for invalid in ["", "d", "7", "7x", "7é", "é", "-3d", "1.5h", "2023-13-01"] {
+            assert!(invalid.parse::<Since>().is_err(), "{invalid}");
+        }
+        assert_eq!(
+            "4294967295w".parse::<Since>(),
+            Err(String::from("time span '4294967295w' reaches too far back"))
+        );
+    }
+
+    #[test]
+    fn since_is_relative_to_now() {
+        let now = Local.with_ymd_and_hms(2023, 5, 3, 1, 30, 0).unwrap();
+
+        let since = Since::Ago(Duration::hours(2));
+        assert_eq!(
+            since.start(now),
+            Local.with_ymd_and_hms(2023, 5, 2, 23, 30, 0).unwrap()
+        );
+        assert_eq!(since.search_key(now), "SINCE 02-May-2023");
+
+        let since = Since::Date(NaiveDate::from_ymd_opt(2023, 4, 1).unwrap());
+        assert_eq!(since.search_key(now), "SINCE 01-Apr-2023");
+    }
+
+    #[test]
+    fn cuts_off_mails_older_than_max_age() {
+        let now = Local.with_ymd_and_hms(2023, 5, 31, 12, 0, 0).unwrap();
+        let max_age = "30d".parse::<MaxAge>().unwrap();
+        assert_eq!(max_age, MaxAge(Duration::days(30)));
+        assert!("2023-05-01".parse::<MaxAge>().is_err());
+        assert!("30".parse::<MaxAge>().is_err());
+        assert!("30é".parse::<MaxAge>().is_err());
+        assert_eq!(
+            "4294967295w".parse::<MaxAge>(),
+            Err(String::from("time span '4294967295w' reaches too far back"))
+        );
+        // a span that was valid when parsing still has a start later on
+        let huge = MaxAge(Duration::weeks(i32::MAX as i64));
+        assert!(latest_start(None, Some(huge), now).is_some());
+
+        assert_eq!(
+            latest_start(None, Some(max_age), now),
+            Some(Since::Ago(Duration::days(30)))
+        );
+        // the later start wins, whichever of the two it is
+        let recent = Since::Ago(Duration::days(2));
+        assert_eq!(latest_start(Some(recent), Some(max_age), now), Some(recent));
+        let old = Since::Date(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap());
+        let start = latest_start(Some(old), Some(max_age), now).unwrap();
+        assert_eq!(
+            start.start(now),
+            Local.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap()
+        );
+        assert_eq!(start.search_key(now), "SINCE 01-May-2023");
+        assert_eq!(latest_start(None, None, now), None);
+
+        let config: crate::config::Config = toml::from_str("max_age = \"2w\"").unwrap();
+        assert_eq!(config.max_age, Some(MaxAge(Duration::weeks(2))));
+        assert!(toml::from_str::<crate::config::Config>("max_age = \"soon\"").is_err());
+    }
+
+    #[test]
+    fn combines_flagged_with_other_filters() {
+        let now = Local.with_ymd_and_hms(2023, 5, 3, 12, 0, 0).unwrap();
+        let search = Search {
+            keys: read_search_keys(None, true, &[("FROM", String::from("boss"))], now),
+            ..Search::default()
+        };
+        assert_eq!(search.query(), "FLAGGED FROM \"boss\"");
+
+        let keys = read_search_keys(Some(Since::Ago(Duration::days(1))), false, &[], now);
+        assert_eq!(keys, ["SINCE 02-May-2023"]);
+    }
+
+    #[test]
+    fn builds_message_id_search() {
+        assert_eq!(
+            message_id_search("abc@example.com"),
+            "HEADER Message-ID \"<abc@example.com>\""
+        );
+        assert_eq!(
+            message_id_search(" <abc@example.com> "),
+            "HEADER Message-ID \"<abc@example.com>\""
+        );
+        assert_eq!(
+            message_id_search("a\"b\\c@example.com"),
+            "HEADER Message-ID \"<a\\\"b\\\\c@example.com>\""
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn filters_by_command_exit_status() {
+        let mail = |subject: &str| {
+            MockMail::new(&format!(
+                "Date: Mon, 1 May 2023 10:00:00 +0000\nSubject: {subject}\n\nbody\n"
+            ))
+        };
+        let (mut session, _) = mock_session(vec![(
+            "INBOX",
+            vec![mail("keep this"), mail("drop this"), mail("keep that")],
+        )]);
+        let mails = MailBox::INBOX
+            .fetch_n_recent_mails(10, &mut session)
+            .unwrap();
+
+        let kept = filter_by_command(mails, "grep -q '^Subject: keep'")
+            .unwrap()
+            .into_iter()
+            .map(|mail| mail.unwrap().raw().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(kept.len(), 2);
+        assert!(kept
+            .iter()
+            .all(|raw| String::from_utf8_lossy(raw).contains("Subject: keep")));
+
+        let failed = vec![Err(anyhow!("unparsable"))];
+        assert_eq!(filter_by_command(failed, "false").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,491 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use imap::{
+    types::{NameAttribute, StatusAttribute, UnsolicitedResponse},
+    Session,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gmail::supports_gmail_ext,
+    mail::{find_special_mailbox, trash_mailbox},
+    mail_filters::quote,
+    utils::{get_data_dir_path, write_atomically},
+};
+
+pub static UNREAD_CACHE_FILE_NAME: &str = "unread.toml";
+
+/// how long the unread counts of an account are reused before asking the server again
+pub const UNREAD_CACHE_TTL_SECS: i64 = 60;
+
+/// the number of unread mails in a mailbox
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnreadCount {
+    pub mailbox: String,
+    /// `None` for mailboxes that can't be selected
+    pub unseen: Option<u32>,
+}
+
+/// a mailbox returned by `LIST`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedMailbox {
+    pub name: String,
+    /// `false` for mailboxes that only contain other mailboxes, like Gmail's `[Gmail]`, which
+    /// can't be read from
+    pub selectable: bool,
+}
+
+/// a common mailbox found by its RFC 6154 special-use attribute instead of its name, which
+/// differs between providers and languages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MailboxRole {
+    Sent,
+    Drafts,
+    Trash,
+    Junk,
+    Archive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedCounts {
+    /// unix timestamp at which the counts were fetched
+    fetched_at: i64,
+    counts: Vec<UnreadCount>,
+}
+
+/// the unread counts of the last mailbox listing of each account, so listing again shortly after
+/// doesn't send a `STATUS` command for every mailbox
+#[derive(Debug, Clone)]
+pub struct UnreadCache {
+    path: PathBuf,
+    accounts: HashMap<String, CachedCounts>,
+}
+
+impl UnreadCache {
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(get_data_dir_path()?.join(UNREAD_CACHE_FILE_NAME))
+    }
+
+    /// loads the cache at `path`, a missing or unreadable cache is treated as empty since it can
+    /// always be rebuilt
+    pub fn load_from(path: PathBuf) -> anyhow::Result<Self> {
+        let accounts = match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).unwrap_or_default(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self { path, accounts })
+    }
+
+    pub fn store(&self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        write_atomically(
+            &self.path,
+            toml::to_string_pretty(&self.accounts)?.as_bytes(),
+        )
+    }
+
+    /// the counts of `email` if they were fetched less than [`UNREAD_CACHE_TTL_SECS`] before
+    /// `now`
+    pub fn fresh(&self, email: &str, now: DateTime<Utc>) -> Option<&[UnreadCount]> {
+        self.accounts
+            .get(email)
+            .filter(|cached| {
+                (0..UNREAD_CACHE_TTL_SECS).contains(&(now.timestamp() - cached.fetched_at))
+            })
+            .map(|cached| cached.counts.as_slice())
+    }
+
+    pub fn insert(&mut self, email: String, counts: Vec<UnreadCount>, now: DateTime<Utc>) {
+        let fetched_at = now.timestamp();
+        self.accounts
+            .insert(email, CachedCounts { fetched_at, counts });
+    }
+}
+
+impl MailboxRole {
+    /// the special-use attribute of mailboxes with the role
+    pub fn attribute(&self) -> &'static str {
+        match self {
+            Self::Sent => "\\Sent",
+            Self::Drafts => "\\Drafts",
+            Self::Trash => "\\Trash",
+            Self::Junk => "\\Junk",
+            Self::Archive => "\\Archive",
+        }
+    }
+
+    /// the name of the mailbox with the role on Gmail with English as its language, `None` for
+    /// archives as Gmail archives by removing the inbox label
+    pub fn gmail_name(&self) -> Option<&'static str> {
+        match self {
+            Self::Sent => Some("[Gmail]/Sent Mail"),
+            Self::Drafts => Some("[Gmail]/Drafts"),
+            Self::Trash => Some("[Gmail]/Trash"),
+            Self::Junk => Some("[Gmail]/Spam"),
+            Self::Archive => None,
+        }
+    }
+
+    /// the name of the mailbox with the role. servers that don't announce special-use
+    /// attributes are only supported if they are Gmail
+    pub fn resolve<T: Read + Write>(&self, session: &mut Session<T>) -> anyhow::Result<String> {
+        if let Some(mailbox) = find_special_mailbox(self.attribute(), session)? {
+            return Ok(mailbox);
+        }
+
+        match self.gmail_name() {
+            Some(name) if supports_gmail_ext(session)? => Ok(name.to_owned()),
+            _ => Err(anyhow!(
+                "the server doesn't mark any mailbox as {attribute}, pass its name with \
+                `--mailbox` instead",
+                attribute = self.attribute()
+            )),
+        }
+    }
+}
+
+/// the mailbox `empty-trash` deletes all mails from: the one deleted mails are moved to, or with
+/// `spam` the one marked as junk
+pub fn mailbox_to_empty<T: Read + Write>(
+    spam: bool,
+    session: &mut Session<T>,
+) -> anyhow::Result<String> {
+    match spam {
+        true => MailboxRole::Junk.resolve(session),
+        false => trash_mailbox(session),
+    }
+}
+
+/// the total number of unread mails in `counts`
+pub fn total_unread(counts: &[UnreadCount]) -> u32 {
+    counts.iter().filter_map(|count| count.unseen).sum()
+}
+
+/// splits `mailboxes` into at most `n` chunks of about the same size, keeping their order so
+/// the chunks can be queried over separate sessions and joined again
+pub fn split_evenly<T>(mailboxes: Vec<T>, n: usize) -> Vec<Vec<T>> {
+    let size = mailboxes.len().div_ceil(n.max(1)).max(1);
+    let mut mailboxes = mailboxes.into_iter().peekable();
+
+    let mut chunks = Vec::new();
+    while mailboxes.peek().is_some() {
+        chunks.push(mailboxes.by_ref().take(size).collect());
+    }
+
+    chunks
+}
+
+/// whether a mailbox with the `LIST` `attributes` can be selected, servers mark mailboxes that
+/// only exist as parents of others with `\\Noselect` or `\\NonExistent` (RFC 5258)
+pub fn is_selectable(attributes: &[NameAttribute]) -> bool {
+    !attributes.iter().any(|attribute| match attribute {
+        NameAttribute::NoSelect => true,
+        NameAttribute::Custom(attribute) => attribute.eq_ignore_ascii_case("\\NonExistent"),
+        _ => false,
+    })
+}
+
+/// all mailboxes of the account
+pub fn list_mailboxes<T: Read + Write>(
+    session: &mut Session<T>,
+) -> anyhow::Result<Vec<ListedMailbox>> {
+    let names = session.list(Some(""), Some("*"))?;
+
+    Ok(names
+        .iter()
+        .map(|name| ListedMailbox {
+            name: name.name().to_owned(),
+            selectable: is_selectable(name.attributes()),
+        })
+        .collect())
+}
+
+/// errors if one of `mailboxes` can't be selected, instead of letting the server reject reading
+/// from it with a less helpful message. mailboxes the server doesn't know are left to fail later
+pub fn check_selectable<T: Read + Write>(
+    session: &mut Session<T>,
+    mailboxes: &[String],
+) -> anyhow::Result<()> {
+    for mailbox in mailboxes {
+        let names = session.list(Some(""), Some(&quote(mailbox)))?;
+        if names.iter().any(|name| !is_selectable(name.attributes())) {
+            return Err(anyhow!(
+                "'{mailbox}' only contains other mailboxes and can't be read from, \
+                see `mail-cli mailboxes` for the ones that can"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// the number of unread mails in each of `mailboxes`, in the same order. mailboxes that can't
+/// be selected are skipped as the server rejects `STATUS` for them
+pub fn unread_counts<T: Read + Write>(
+    session: &mut Session<T>,
+    mailboxes: &[ListedMailbox],
+) -> anyhow::Result<Vec<UnreadCount>> {
+    mailboxes
+        .iter()
+        .map(|mailbox| {
+            let unseen = match mailbox.selectable {
+                true => Some(unseen_count(session, &mailbox.name)?),
+                false => None,
+            };
+
+            Ok(UnreadCount {
+                mailbox: mailbox.name.clone(),
+                unseen,
+            })
+        })
+        .collect()
+}
+
+/// asks the server for the number of mails in `mailbox` without the `\\Seen` flag
+fn unseen_count<T: Read + Write>(session: &mut Session<T>, mailbox: &str) -> anyhow::Result<u32> {
+    // the name is quoted by `status` itself
+    session.status(mailbox, "(UNSEEN)")?;
+
+    // `imap` hands the untagged `STATUS` response to the unsolicited responses instead of
+    // returning it
+    let unseen = session
+        .unsolicited_responses
+        .try_iter()
+        .filter_map(|response| match response {
+            UnsolicitedResponse::Status { attributes, .. } => {
+                attributes
+                    .into_iter()
+                    .find_map(|attribute| match attribute {
+                        StatusAttribute::Unseen(unseen) => Some(unseen),
+                        _ => None,
+                    })
+            }
+            _ => None,
+        })
+        .last();
+
+    unseen.ok_or(anyhow!(
+        "the server didn't report unread mails for '{mailbox}'"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::{
+        mail::{Deletion, MailBox},
+        mock_imap::{connect, mock_session, MockImapStream, MockMail},
+    };
+
+    #[test]
+    fn counts_unread_mails_per_mailbox() {
+        let mail = |flags: &[&str]| MockMail::new("Subject: a\n\nbody\n").with_flags(flags);
+        let (mut session, _) = mock_session(vec![
+            ("INBOX", vec![mail(&[]), mail(&["\\Seen"]), mail(&[])]),
+            ("Archive", vec![mail(&["\\Seen"])]),
+            ("Lists", vec![mail(&["\\Flagged"])]),
+        ]);
+
+        let mailboxes = list_mailboxes(&mut session).unwrap();
+        let chunks = split_evenly(mailboxes, 2);
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| chunk.iter().map(|mailbox| mailbox.name.as_str()).collect())
+                .collect::<Vec<Vec<_>>>(),
+            vec![vec!["INBOX", "Archive"], vec!["Lists"]]
+        );
+
+        let counts = chunks
+            .iter()
+            .map(|chunk| unread_counts(&mut session, chunk).unwrap())
+            .collect::<Vec<_>>()
+            .concat();
+        assert_eq!(
+            counts
+                .iter()
+                .map(|count| (count.mailbox.as_str(), count.unseen))
+                .collect::<Vec<_>>(),
+            vec![("INBOX", Some(2)), ("Archive", Some(0)), ("Lists", Some(1))]
+        );
+        assert_eq!(total_unread(&counts), 3);
+    }
+
+    #[test]
+    fn skips_unselectable_mailboxes() {
+        let mail = |flags: &[&str]| MockMail::new("Subject: a\n\nbody\n").with_flags(flags);
+        let stream = MockImapStream::new(vec![("INBOX", vec![mail(&[])]), ("[Gmail]", Vec::new())])
+            .with_mailbox_attributes("[Gmail]", &["\\Noselect", "\\HasChildren"]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        let mailboxes = list_mailboxes(&mut session).unwrap();
+        assert_eq!(
+            mailboxes
+                .iter()
+                .filter(|mailbox| !mailbox.selectable)
+                .map(|mailbox| mailbox.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["[Gmail]"]
+        );
+
+        let counts = unread_counts(&mut session, &mailboxes).unwrap();
+        assert_eq!(
+            counts
+                .iter()
+                .map(|count| (count.mailbox.as_str(), count.unseen))
+                .collect::<Vec<_>>(),
+            vec![("INBOX", Some(1)), ("[Gmail]", None)]
+        );
+        assert_eq!(total_unread(&counts), 1);
+    }
+
+    #[test]
+    fn empties_trash_or_spam() {
+        let mail = || MockMail::new("Subject: a\n\nbody\n");
+        let stream = MockImapStream::new(vec![
+            ("INBOX", vec![mail()]),
+            ("Papierkorb", vec![mail(), mail()]),
+            ("Junk-E-Mail", vec![mail(), mail(), mail()]),
+        ])
+        .with_capabilities(&["IMAP4rev1", "UIDPLUS"])
+        .with_mailbox_attributes("Papierkorb", &["\\Trash"])
+        .with_mailbox_attributes("Junk-E-Mail", &["\\Junk"]);
+        let commands = stream.command_log();
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        let trash = mailbox_to_empty(false, &mut session).unwrap();
+        assert_eq!(trash, "Papierkorb");
+        let spam = mailbox_to_empty(true, &mut session).unwrap();
+        assert_eq!(spam, "Junk-E-Mail");
+
+        // the mails are counted before asking, only those are deleted
+        let uids = MailBox::new(&trash).uids(&mut session).unwrap();
+        assert_eq!(uids, [1, 2]);
+        MailBox::new(&trash)
+            .delete(&uids, Deletion::Permanent, &mut session)
+            .unwrap();
+        assert!(MailBox::new(&trash).uids(&mut session).unwrap().is_empty());
+        // however many mails there are, the commands name them as a range
+        assert!(commands
+            .borrow()
+            .iter()
+            .any(|command| command == "UID STORE 1:2 +FLAGS.SILENT (\\Deleted)"));
+        assert!(commands
+            .borrow()
+            .iter()
+            .any(|command| command == "UID EXPUNGE 1:2"));
+        assert_eq!(MailBox::new(&spam).uids(&mut session).unwrap().len(), 3);
+        assert_eq!(MailBox::INBOX.uids(&mut session).unwrap().len(), 1);
+
+        // without special-use attributes only the Trash has a fallback
+        let stream = MockImapStream::new(vec![("INBOX", Vec::new()), ("Trash", Vec::new())]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+        assert_eq!(mailbox_to_empty(false, &mut session).unwrap(), "Trash");
+        assert!(mailbox_to_empty(true, &mut session).is_err());
+    }
+
+    #[test]
+    fn refuses_container_mailboxes() {
+        let stream = MockImapStream::new(vec![("INBOX", Vec::new()), ("[Gmail]", Vec::new())])
+            .with_mailbox_attributes("[Gmail]", &["\\Noselect"]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        assert!(check_selectable(&mut session, &[String::from("INBOX")]).is_ok());
+        let err = check_selectable(
+            &mut session,
+            &[String::from("INBOX"), String::from("[Gmail]")],
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("'[Gmail]' only contains other mailboxes"));
+
+        assert!(is_selectable(&[NameAttribute::NoInferiors]));
+        assert!(!is_selectable(&[NameAttribute::Custom(
+            "\\NonExistent".into()
+        )]));
+    }
+
+    #[test]
+    fn resolves_roles_to_mailboxes() {
+        let stream = MockImapStream::new(vec![
+            ("INBOX", Vec::new()),
+            ("Gesendet", Vec::new()),
+            ("Papierkorb", Vec::new()),
+        ])
+        .with_mailbox_attributes("Gesendet", &["\\Sent"])
+        .with_mailbox_attributes("Papierkorb", &["\\HasNoChildren", "\\Trash"]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+
+        assert_eq!(MailboxRole::Sent.resolve(&mut session).unwrap(), "Gesendet");
+        assert_eq!(
+            MailboxRole::Trash.resolve(&mut session).unwrap(),
+            "Papierkorb"
+        );
+        assert!(MailboxRole::Junk
+            .resolve(&mut session)
+            .unwrap_err()
+            .to_string()
+            .contains("\\Junk"));
+
+        let stream = MockImapStream::new(vec![("INBOX", Vec::new())])
+            .with_capabilities(&["IMAP4rev1", "X-GM-EXT-1"]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+        assert_eq!(
+            MailboxRole::Junk.resolve(&mut session).unwrap(),
+            "[Gmail]/Spam"
+        );
+        assert!(MailboxRole::Archive.resolve(&mut session).is_err());
+    }
+
+    #[test]
+    fn splits_into_at_most_n_chunks() {
+        let names = |n: usize| (0..n).map(|idx| idx.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(split_evenly(names(5), 2).len(), 2);
+        assert_eq!(split_evenly(names(2), 4).len(), 2);
+        assert_eq!(split_evenly(names(3), 0), vec![names(3)]);
+        assert!(split_evenly(names(0), 4).is_empty());
+    }
+
+    #[test]
+    fn reuses_counts_until_expired() {
+        let mut cache = UnreadCache {
+            path: PathBuf::new(),
+            accounts: HashMap::new(),
+        };
+        let now = Utc::now();
+        let counts = vec![UnreadCount {
+            mailbox: String::from("INBOX"),
+            unseen: Some(4),
+        }];
+        cache.insert(String::from("a@gmail.com"), counts.clone(), now);
+
+        assert_eq!(
+            cache.fresh("a@gmail.com", now + Duration::seconds(30)),
+            Some(counts.as_slice())
+        );
+        assert_eq!(cache.fresh("b@gmail.com", now), None);
+        assert_eq!(
+            cache.fresh(
+                "a@gmail.com",
+                now + Duration::seconds(UNREAD_CACHE_TTL_SECS)
+            ),
+            None
+        );
+    }
+}
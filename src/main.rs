@@ -1,17 +1,60 @@
-use std::net::TcpStream;
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
 
 use anyhow::anyhow;
+use audit::AuditLog;
+use backup::backup_mailbox;
+use charset::OutputCharset;
+use chrono::{Local, Utc};
 use clap::Parser;
-use cli::{add_new_account, print_info, select_account, CliArgs, Commands};
+use cli::{
+    add_new_account, browse_mails, confirm_empty, confirm_expunge, confirm_permanent_delete,
+    expand_saved_search, load_accounts, print_info, reauthorize_account, select_account,
+    select_link, select_unsubscribe_method, CliArgs, Commands, ReadArgs, StatusFormat,
+};
+use config::Config;
+use dialoguer::console::Term;
+use digest::{fetch_digest_entries, Digest};
+use draft::Draft;
+use failure::{SessionFailure, SESSION_RETRIES, SESSION_RETRY_DELAY};
+use gmail::{
+    label_search, raw_search, supports_gmail_ext, GmailAttributeStore, GmailExtStream,
+    GMAIL_EXT_CAPABILITY,
+};
+use images::{render_images, GraphicsProtocol};
 use imap::Session;
-use mail::MailBox;
+use itertools::Itertools;
+use mail::{
+    dedup_by_message_id, flags_legend, group_by_thread, header_table, set_gmail_attributes,
+    trash_mailbox, write_mbox, BodyLines, Deletion, DisplayOptions, DisplayTimezone, ExtraHeaders,
+    Mail, MailBox, OpenMode, OutputFormat, Reconnect, DEFAULT_DATE_FORMAT,
+    DEFAULT_FETCH_BATCH_SIZE, DEFAULT_FETCH_RETRIES,
+};
+use mail_filters::{filter_by_command, latest_start, message_id_search, read_search_keys, Search};
+use mailboxes::{
+    check_selectable, list_mailboxes, mailbox_to_empty, split_evenly, total_unread, unread_counts,
+    UnreadCache,
+};
 use native_tls::TlsStream;
-use reqwest::Client;
-use store_accounts::{StoredAccountData, StoredAccounts};
+use progress::{ByteCounter, Progress};
+use reply::{compose_forward, compose_reply_all};
+use server::{capabilities, format_capabilities, server_id, Identity};
+use store_accounts::{StoredAccountData, StoredAccounts, REFRESH_FAILURES_BEFORE_RELOGIN};
+use summary_cache::{query_key, SummaryCache, DEFAULT_SUMMARY_CACHE_TTL_SECS};
+use transfer::transfer_mail;
+use utils::{file_name_safe, open_url, run_bounded};
+use watch::{poll_new_mails, Backoff, LastUidCache, Throttle, MIN_POLL_INTERVAL};
 
-use crate::google::{
-    refresh_google_oauth_token, GoogleOAuthParams, GoogleOAuthTokenRefreshResponse,
-    GOOGLE_IMAP_DOMAIN, GOOGLE_IMAP_PORT,
+use crate::{
+    google::{fetch_google_account_email, Google, GoogleOAuthParams},
+    provider::{OAuthProvider, Provider, RefreshedToken},
+    proxy::http_client,
+    yahoo::{Yahoo, YahooOAuthParams},
 };
 
 extern crate imap;
@@ -19,11 +62,34 @@ extern crate native_tls;
 extern crate rpassword;
 
 mod cli;
-mod google;
-mod mail;
-mod mail_filters;
-mod store_accounts;
-mod utils;
+
+#[cfg(test)]
+use mail_cli::mock_imap;
+use mail_cli::{
+    audit, backup, charset, config, digest, draft, failure, gmail, google, images, mail,
+    mail_filters, mailboxes, nested, progress, provider, proxy, reply, server, store_accounts,
+    summary_cache, transfer, unsubscribe, utils, watch, yahoo,
+};
+
+const LOGOUT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// width the `--no-body` table is fitted to if the terminal size is unknown, e.g. when piping
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+
+/// what a `.env` file is for, shown when it can't be loaded
+const DOTENV_HINT: &str = "failed to load the .env file, it is optional and only needed to set \
+    GOOGLE_CLIENT_ID, GOOGLE_CLIENT_SECRET, YAHOO_CLIENT_ID, YAHOO_CLIENT_SECRET or ALL_PROXY \
+    with one `NAME=value` line each";
+
+/// the outcome of loading the `.env` file: fine if there is none, an error explaining what
+/// the file is for if it is malformed or can't be read
+fn check_dotenv<T>(loaded: Result<T, dotenv::Error>) -> anyhow::Result<()> {
+    match loaded {
+        Ok(_) => Ok(()),
+        Err(err) if err.not_found() => Ok(()),
+        Err(err) => Err(anyhow::Error::new(err).context(DOTENV_HINT)),
+    }
+}
 
 struct ImapOAuth2Data {
     user: String,
@@ -40,121 +106,2095 @@ impl imap::Authenticator for ImapOAuth2Data {
     }
 }
 
+/// TLS transport with Gmail's extension attributes being recorded into a [`GmailAttributeStore`]
+type ImapStream = GmailExtStream<TlsStream<TcpStream>>;
+
 /// Errors: if credentials are invalid or access token is expired
 fn create_imap_session(
     domain: &str,
     port: u16,
     imap_auth: &ImapOAuth2Data,
-) -> anyhow::Result<Session<TlsStream<TcpStream>>> {
+    gmail_attributes: &GmailAttributeStore,
+) -> anyhow::Result<Session<ImapStream>> {
+    authenticate_imap_client(
+        connect_imap_client(domain, port, gmail_attributes)?,
+        imap_auth,
+    )
+}
+
+/// like [`create_imap_session`] but logs in with a plain password, e.g. an app password
+///
+/// Errors: if the credentials are invalid
+fn create_imap_session_with_password(
+    domain: &str,
+    port: u16,
+    user: &str,
+    password: &str,
+    gmail_attributes: &GmailAttributeStore,
+) -> anyhow::Result<Session<ImapStream>> {
+    connect_imap_client(domain, port, gmail_attributes)?
+        .login(user, password)
+        .map_err(|(err, _)| anyhow!(format!("{err:?}")))
+}
+
+/// opens a TLS connection to `domain`, through the proxy if one is set
+fn connect_tls(domain: &str, port: u16) -> anyhow::Result<TlsStream<TcpStream>> {
+    let domain = ascii_host(domain)?;
     let tls = native_tls::TlsConnector::builder().build()?;
-    let client = imap::connect((domain, port), domain, &tls)?;
+    let tcp = proxy::connect_tcp(&domain, port)?;
+
+    Ok(tls.connect(&domain, tcp)?)
+}
+
+/// opens a TLS connection to the IMAP server and reads its greeting
+fn connect_imap_client(
+    domain: &str,
+    port: u16,
+    gmail_attributes: &GmailAttributeStore,
+) -> anyhow::Result<imap::Client<ImapStream>> {
+    let stream = GmailExtStream::new(connect_tls(domain, port)?, gmail_attributes.clone());
+
+    let mut client = imap::Client::new(stream);
+    client.read_greeting()?;
 
+    Ok(client)
+}
+
+/// punycode encodes internationalized domain names, DNS and TLS only understand ASCII hosts
+fn ascii_host(domain: &str) -> anyhow::Result<String> {
+    idna::domain_to_ascii(domain).map_err(|_| anyhow!("invalid IMAP host '{domain}'"))
+}
+
+/// authenticates an already connected client, independent of the underlying transport
+/// (TLS, STARTTLS or a plain stream)
+///
+/// Errors: if credentials are invalid or access token is expired
+fn authenticate_imap_client<T: Read + Write>(
+    client: imap::Client<T>,
+    imap_auth: &ImapOAuth2Data,
+) -> anyhow::Result<Session<T>> {
     client
         .authenticate("XOAUTH2", imap_auth)
-        .map_err(|err| anyhow!(format!("{err:?}")))
+        .map_err(|(err, _)| err.into())
+}
+
+/// calls `connect` until it succeeds or fails for another reason than the server being
+/// unavailable for now, waiting longer after each transient failure
+async fn connect_retrying<S: Read + Write>(
+    connect: &impl Fn(&str, u16, &ImapOAuth2Data) -> anyhow::Result<Session<S>>,
+    domain: &str,
+    port: u16,
+    imap_auth: &ImapOAuth2Data,
+) -> anyhow::Result<Session<S>> {
+    let mut retries = 0;
+    loop {
+        match connect(domain, port, imap_auth) {
+            Err(err)
+                if retries < SESSION_RETRIES
+                    && SessionFailure::of(&err) == SessionFailure::Transient =>
+            {
+                tokio::time::sleep(SESSION_RETRY_DELAY * 2u32.pow(retries)).await;
+                retries += 1;
+            }
+            res => return res,
+        }
+    }
 }
 
-/// tries to create a session with the given credentials.
-/// if it fails to create a session tries to use the refresh token to acquire a new access
-/// token and updates the stored account data if it succeeds.
+/// tries to create a session with the given credentials on the IMAP server of `provider`.
+/// if the server rejects the access token tries to use the refresh token to acquire a new access
+/// token from `provider` and updates the account data in `accounts` if it succeeds and the
+/// token changed. attempts failing because the server is unavailable for now are retried with
+/// backoff instead, refreshing wouldn't help with those.
 ///
 /// Errors:
+/// - if creating the session fails for another reason than the access token, or the server
+///   stays unavailable
 /// - if it fails to retrieve new authentication parameters with the provided refresh token
 /// - if it fails to store the new access token to the file system after a successful refresh
 /// - if the creation of an IMAP session fails after acquiring and storing a new access token
-/// (due to a network error or other cause)
-async fn create_imap_session_with_refresh_on_err(
-    domain: &str,
-    port: u16,
-    imap_auth: &ImapOAuth2Data,
-    refresh_token: &str,
+///   (due to a network error or other cause)
+async fn create_imap_session_with_refresh_on_err<S: Read + Write>(
+    provider: &impl OAuthProvider,
     email: String,
-    accounts: &mut StoredAccounts,
-) -> anyhow::Result<Session<TlsStream<TcpStream>>> {
-    match create_imap_session(domain, port, imap_auth) {
-        Ok(session) => Ok(session),
-        Err(_) => {
-            let GoogleOAuthTokenRefreshResponse { access_token } = refresh_google_oauth_token(
-                &Client::new(),
-                &GoogleOAuthParams::default(),
-                refresh_token,
-            )
-            .await?;
+    account: &StoredAccountData,
+    accounts: Option<&mut StoredAccounts>,
+    audit: Option<&AuditLog>,
+    connect: impl Fn(&str, u16, &ImapOAuth2Data) -> anyhow::Result<Session<S>>,
+) -> anyhow::Result<Session<S>> {
+    let (domain, port) = provider.imap_host();
+    let imap_auth = ImapOAuth2Data {
+        user: email.clone(),
+        access_token: account.access_token.clone(),
+    };
 
-            accounts.insert(
-                email.clone(),
-                StoredAccountData::new(access_token.clone(), refresh_token.to_owned()),
-            )?;
+    match connect_retrying(&connect, domain, port, &imap_auth).await {
+        Err(err) if SessionFailure::of(&err) == SessionFailure::Auth => {
+            let refreshed = provider.refresh(&account.refresh_token).await;
+            if let Some(audit) = audit {
+                if let Err(err) = audit.record(Utc::now(), &email, &refreshed) {
+                    print_info(format!("failed to write the refresh audit log: {err:#}"));
+                }
+            }
+
+            let RefreshedToken {
+                access_token,
+                expires_in,
+            } = match refreshed {
+                Ok(token) => token,
+                Err(err) => {
+                    let refresh_failures = account.refresh_failures + 1;
+                    if let Some(accounts) = accounts {
+                        // only used for the hint below, the refresh error is what matters
+                        let stored = accounts.insert(
+                            email.clone(),
+                            StoredAccountData {
+                                refresh_failures,
+                                ..account.clone()
+                            },
+                        );
+                        if let Err(err) = stored {
+                            print_info(format!(
+                                "failed to store the refresh failure count: {err:#}"
+                            ));
+                        }
+                    }
+                    if refresh_failures >= REFRESH_FAILURES_BEFORE_RELOGIN {
+                        print_info(format!(
+                            "refreshing the login of {email} failed {refresh_failures} times in a \
+                            row, log in again with `mail-cli login {email}`"
+                        ));
+                    }
+
+                    return Err(err);
+                }
+            };
+
+            // providers may hand out the same token again while it is still valid, rewriting the
+            // accounts file with the same token would only contend for its lock
+            let changed = access_token != account.access_token || account.refresh_failures > 0;
+            if let Some(accounts) = accounts.filter(|_| changed) {
+                accounts.insert(
+                    email.clone(),
+                    StoredAccountData {
+                        access_token: access_token.clone(),
+                        refresh_failures: 0,
+                        ..account.clone()
+                    }
+                    .with_expiry(expires_in),
+                )?;
+            }
 
             let imap_auth = ImapOAuth2Data {
                 user: email,
                 access_token,
             };
 
-            create_imap_session(GOOGLE_IMAP_DOMAIN, GOOGLE_IMAP_PORT, &imap_auth)
+            connect_retrying(&connect, domain, port, &imap_auth).await
         }
+        res => res,
+    }
+}
+
+/// the stored account with the mail address `mail`, prompting the user to select one if `mail`
+/// isn't given or no account with it is stored
+///
+/// Errors: with a hint to log in first if no accounts are stored
+fn select_stored_account(
+    mail: Option<String>,
+    accounts: &StoredAccounts,
+) -> anyhow::Result<(String, StoredAccountData)> {
+    if accounts.stored_accounts().is_empty() {
+        let email = mail.as_deref().unwrap_or("<email>");
+        return Err(anyhow!(
+            "no accounts are logged in yet, log in to one with `mail-cli login {email}` first"
+        ));
+    }
+
+    match mail {
+        Some(mail) => match accounts.stored_accounts().get(&mail) {
+            Some(data) => Ok((mail, data.to_owned())),
+            None => {
+                print_info(format!("no account with mail '{mail}' found"));
+                select_account(accounts.stored_accounts()).ok_or(anyhow!("no account selected"))
+            }
+        },
+        None => select_account(accounts.stored_accounts()).ok_or(anyhow!("no account selected")),
     }
 }
 
+/// the account given by the environment (see [`StoredAccountData::from_env`]) if it matches
+/// `mail`, otherwise the selected stored account together with the loaded accounts
+fn resolve_account(
+    mail: Option<String>,
+) -> anyhow::Result<(String, StoredAccountData, Option<StoredAccounts>)> {
+    if let Some((email, account)) = StoredAccountData::from_env(|name| env::var(name).ok()) {
+        if mail.as_ref().is_none_or(|mail| *mail == email) {
+            return Ok((email, account, None));
+        }
+    }
+
+    let accounts = load_accounts()?;
+    let (email, account) = select_stored_account(mail, &accounts)?;
+    Ok((email, account, Some(accounts)))
+}
+
+/// creates a session for the account with the mail address `mail`, prompting the user to select
+/// one of the stored accounts if `mail` isn't set or no such account exists
+async fn open_session(
+    mail: Option<String>,
+    gmail_attributes: &GmailAttributeStore,
+) -> anyhow::Result<Session<ImapStream>> {
+    Ok(open_account_session(mail, gmail_attributes).await?.1)
+}
+
+/// like [`open_session`] but first errors if the account wasn't granted a scope that allows
+/// changing mails, see [`StoredAccountData::check_write_scope`]
+async fn open_writable_session(
+    mail: Option<String>,
+    gmail_attributes: &GmailAttributeStore,
+) -> anyhow::Result<Session<ImapStream>> {
+    let email = check_write_scope(mail)?;
+    open_session(Some(email), gmail_attributes).await
+}
+
+/// the mail address of the resolved account if it may change mails
+fn check_write_scope(mail: Option<String>) -> anyhow::Result<String> {
+    let (email, account, _) = resolve_account(mail)?;
+    account.check_write_scope(&email)?;
+    Ok(email)
+}
+
+/// like [`open_session`] but also returns the mail address of the account, which opens a session
+/// for the same account without prompting again
+async fn open_account_session(
+    mail: Option<String>,
+    gmail_attributes: &GmailAttributeStore,
+) -> anyhow::Result<(String, Session<ImapStream>)> {
+    let (email, account, mut accounts) = resolve_account(mail)?;
+    if let Some(left) = account.expires_soon(Utc::now()) {
+        print_info(format!(
+            "the login of {email} expires in {} minute(s), commands that take longer may have to \
+            refresh it and reconnect",
+            left.num_minutes().max(1)
+        ));
+    }
+
+    let connect = |domain: &str, port, imap_auth: &ImapOAuth2Data| {
+        create_imap_session(domain, port, imap_auth, gmail_attributes)
+    };
+
+    if let Some(password) = &account.app_password {
+        let (domain, port) = match account.provider {
+            Provider::Google => Google.imap_host(),
+            Provider::Yahoo => Yahoo.imap_host(),
+        };
+        let session =
+            create_imap_session_with_password(domain, port, &email, password, gmail_attributes)?;
+        return Ok((email, session));
+    }
+
+    let audit = AuditLog::from_config(&Config::load()?)?;
+    let session = match account.provider {
+        Provider::Google => {
+            create_imap_session_with_refresh_on_err(
+                &Google,
+                email.clone(),
+                &account,
+                accounts.as_mut(),
+                audit.as_ref(),
+                connect,
+            )
+            .await
+        }
+        Provider::Yahoo => {
+            create_imap_session_with_refresh_on_err(
+                &Yahoo,
+                email.clone(),
+                &account,
+                accounts.as_mut(),
+                audit.as_ref(),
+                connect,
+            )
+            .await
+        }
+    }?;
+
+    Ok((email, session))
+}
+
+/// Errors: if `accounts` can't be written to, which logging in needs
+fn check_writable(accounts: &StoredAccounts) -> anyhow::Result<()> {
+    match accounts.is_read_only() {
+        true => Err(anyhow!(
+            "logging in stores the account, run it without `--read-only-config`"
+        )),
+        false => Ok(()),
+    }
+}
+
+/// returns whether the server supports Gmail's extensions.
+///
+/// Errors: if `search` has Gmail specific keys but the server doesn't support Gmail's extensions
+fn check_gmail_search<T: Read + Write>(
+    session: &mut Session<T>,
+    search: &Search,
+) -> anyhow::Result<bool> {
+    // only Gmail announces this capability, for other servers threads are built from the
+    // headers by `group_by_thread`
+    let gmail = supports_gmail_ext(session)?;
+    if !gmail && !search.gmail.is_empty() {
+        return Err(anyhow!(
+            "labels and Gmail search queries are only supported for Gmail accounts \
+            (the server doesn't announce {GMAIL_EXT_CAPABILITY})"
+        ));
+    }
+
+    Ok(gmail)
+}
+
+/// counts the mails in all of the `mailboxes` matching `search` without fetching them
+fn count_mails<T: Read + Write>(
+    session: &mut Session<T>,
+    mailboxes: &[String],
+    search: &Search,
+) -> anyhow::Result<usize> {
+    check_gmail_search(session, search)?;
+
+    let query = search.query();
+
+    mailboxes.iter().try_fold(0, |count, mailbox| {
+        Ok(count + MailBox::new(mailbox).count_matching(&query, session)?)
+    })
+}
+
+/// fetches the `n` most recent mails of each of the `mailboxes` matching `search`.
+///
+/// `configure` sets the fetching options (batch size, progress, ...) of each [`MailBox`], with
+/// `reconnect` a dropped connection is reopened and fetching resumed (see
+/// [`MailBox::with_retries`]).
+///
+/// on Gmail the thread ids and labels of the mails are set as well
+fn fetch_mails<T: Read + Write>(
+    session: &mut Session<T>,
+    mailboxes: &[String],
+    n: usize,
+    search: &Search,
+    gmail_attributes: &GmailAttributeStore,
+    configure: impl Fn(MailBox) -> MailBox,
+    mut reconnect: Option<&mut Reconnect<T>>,
+) -> anyhow::Result<Vec<anyhow::Result<Mail>>> {
+    let gmail = check_gmail_search(session, search)?;
+
+    let mut all_mails = Vec::new();
+    for mailbox in mailboxes {
+        let mailbox = configure(MailBox::new(mailbox));
+        let outgoing = mailbox.is_outgoing(session)?;
+        let mut mails = mailbox.fetch_n_recent_mails_reconnecting(
+            n,
+            &search.query(),
+            session,
+            reconnect.as_deref_mut(),
+        )?;
+        for mail in mails.iter_mut().flatten() {
+            mail.set_outgoing(outgoing);
+            mail.set_mailbox(mailbox.name());
+        }
+
+        // the attributes are fetched by sequence number, so this has to happen while the mailbox
+        // is still selected
+        if gmail {
+            set_gmail_attributes(
+                mails.iter_mut().filter_map(|mail| mail.as_mut().ok()),
+                session,
+                gmail_attributes,
+            )?;
+        }
+
+        all_mails.append(&mut mails);
+    }
+
+    Ok(all_mails)
+}
+
+/// the file name stem the messages attached to the `idx`-th displayed `mail` are extracted with
+/// (see [`nested::extract_nested`]), `<mailbox>-<uid>` so mails of different mailboxes don't
+/// overwrite each other's. the mailbox is made file name safe by [`file_name_safe`], which keeps
+/// mailboxes apart that only differ in characters it replaces
+/// fetches the `n` mails of each of `mailboxes` matching `search`, at most `concurrency` of
+/// them at the same time. the already open `session` reads the first mailbox, the others get
+/// their own sessions
+async fn fetch_concurrently(
+    email: String,
+    session: (Session<ImapStream>, GmailAttributeStore),
+    mailboxes: Vec<String>,
+    n: usize,
+    search: &Search,
+    concurrency: usize,
+    configure: impl Fn(MailBox) -> MailBox + Clone + Send + 'static,
+) -> anyhow::Result<Vec<anyhow::Result<Mail>>> {
+    let sessions = std::iter::once(Some(session)).chain(std::iter::repeat_with(|| None));
+    let mails = run_bounded(
+        mailboxes.into_iter().zip(sessions),
+        concurrency,
+        |(mailbox, session)| {
+            let (email, search, configure) = (email.clone(), search.clone(), configure.clone());
+
+            async move {
+                let (session, gmail_attributes) = match session {
+                    Some(session) => session,
+                    None => {
+                        let gmail_attributes = GmailAttributeStore::default();
+                        let session = open_session(Some(email.clone()), &gmail_attributes).await?;
+                        (session, gmail_attributes)
+                    }
+                };
+
+                let runtime = tokio::runtime::Handle::current();
+                let (mut session, mails) = run_with_interrupt_logout(session, move |session| {
+                    // runs on a blocking thread, where waiting for the async login is allowed
+                    let mut reconnect = |notice: &str| {
+                        print_info(notice);
+                        runtime.block_on(open_session(Some(email.clone()), &gmail_attributes))
+                    };
+
+                    fetch_mails(
+                        session,
+                        &[mailbox],
+                        n,
+                        &search,
+                        &gmail_attributes,
+                        configure,
+                        Some(&mut reconnect),
+                    )
+                })
+                .await?;
+
+                // nothing is fetched after this, browsing can take longer than the server keeps
+                // an idle connection open
+                session.logout()?;
+                mails
+            }
+        },
+    )
+    .await?;
+
+    Ok(mails
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+fn extract_stem(mail: &Mail, idx: usize) -> String {
+    let uid = mail.uid().unwrap_or(idx as u32 + 1);
+    match mail.mailbox() {
+        Some(mailbox) => format!("{}-{uid}", file_name_safe(mailbox)),
+        None => uid.to_string(),
+    }
+}
+
+/// width of the terminal the output is shown in, [`DEFAULT_TERMINAL_WIDTH`] if unknown
+fn terminal_width() -> usize {
+    Term::stdout()
+        .size_checked()
+        .map(|(_, width)| width as usize)
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH)
+}
+
+/// displays a fetched mail, or a short marker if it couldn't be parsed so a single broken mail
+/// doesn't hide the others
+fn render_mail(mail: &anyhow::Result<Mail>, options: &DisplayOptions) -> String {
+    match mail {
+        Ok(mail) => mail.display(options).to_string(),
+        Err(err) => format!("[{err:#}]"),
+    }
+}
+
+/// runs `f` with the session on a blocking thread while listening for Ctrl-C.
+///
+/// on interruption it waits at most `LOGOUT_TIMEOUT` for the running IMAP command to finish and
+/// tries to log the session out before exiting, so the server doesn't keep the connection
+/// around. this is best-effort, the process exits even if the logout didn't go through
+async fn run_with_interrupt_logout<S, R, F>(
+    mut session: Session<S>,
+    f: F,
+) -> anyhow::Result<(Session<S>, R)>
+where
+    S: Read + Write + Send + 'static,
+    R: Send + 'static,
+    F: FnOnce(&mut Session<S>) -> R + Send + 'static,
+{
+    let mut task = tokio::task::spawn_blocking(move || {
+        let res = f(&mut session);
+        (session, res)
+    });
+
+    tokio::select! {
+        res = &mut task => Ok(res?),
+        _ = tokio::signal::ctrl_c() => {
+            print_info("interrupted, logging out");
+
+            let logout = async {
+                let (mut session, _) = task.await?;
+                tokio::task::spawn_blocking(move || session.logout()).await??;
+                anyhow::Ok(())
+            };
+
+            if !matches!(tokio::time::timeout(LOGOUT_TIMEOUT, logout).await, Ok(Ok(()))) {
+                print_info("failed to log out in time");
+            }
+
+            std::process::exit(130);
+        }
+    }
+}
+
+/// prints the mails arriving in `mailbox` of the account `email` until interrupted, polling
+/// as often as `backoff` allows. failing to connect or poll is reported and counts as a poll
+/// without new mails, the session is reopened for the next one.
+///
+/// with a `cache` the last seen mail is stored after each poll (if the flag is set) and the
+/// first poll continues after the one a previous run saw, otherwise only mails arriving from now
+/// on are printed
+async fn watch_mailbox(
+    email: String,
+    mailbox: String,
+    mut backoff: Backoff,
+    mut cache: Option<(LastUidCache, bool)>,
+    charset: &OutputCharset,
+) -> anyhow::Result<()> {
+    let display_options = DisplayOptions::default();
+    let mut throttle = Throttle::new(MIN_POLL_INTERVAL);
+    let mut last = cache
+        .as_ref()
+        .and_then(|(cache, _)| cache.get(&email, &mailbox));
+
+    let mut session = None;
+    loop {
+        throttle.wait().await;
+
+        let current = match session.take() {
+            Some(session) => session,
+            None => {
+                match open_session(Some(email.clone()), &GmailAttributeStore::default()).await {
+                    Ok(session) => session,
+                    Err(err) => {
+                        print_info(format!("failed to connect: {err:#}"));
+                        throttle.set_interval(backoff.next(false));
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let polled_mailbox = mailbox.clone();
+        let (current, polled) = run_with_interrupt_logout(current, move |session| {
+            poll_new_mails(session, &polled_mailbox, last)
+        })
+        .await?;
+
+        match polled {
+            Ok((uid, mails)) => {
+                throttle.set_interval(backoff.next(!mails.is_empty()));
+                for mail in mails {
+                    charset.println(format!("{}\n", render_mail(&mail, &display_options)))?;
+                }
+                if let Some((cache, store)) = cache.as_mut().filter(|_| last != Some(uid)) {
+                    cache.insert(email.clone(), mailbox.clone(), uid);
+                    if *store {
+                        cache.store()?;
+                    }
+                }
+                last = Some(uid);
+                session = Some(current);
+            }
+            Err(err) => {
+                print_info(format!("polling failed, reconnecting: {err:#}"));
+                throttle.set_interval(backoff.next(false));
+            }
+        }
+    }
+}
+
+/// the `uids` of mails in `mailbox` to `action` (e.g. "delete"): all of them, or with
+/// `confirm_each` those the user agrees to one by one
+async fn confirm_uids<S: Read + Write + Send + 'static>(
+    session: Session<S>,
+    mailbox: &str,
+    uids: Vec<u32>,
+    action: &str,
+    confirm_each: bool,
+) -> anyhow::Result<(Session<S>, Vec<u32>)> {
+    if !confirm_each {
+        return Ok((session, uids));
+    }
+
+    let name = mailbox.to_owned();
+    let (session, mails) = run_with_interrupt_logout(session, move |session| {
+        MailBox::new(&name).fetch_headers_by_uid(&uids, session)
+    })
+    .await?;
+    let uids = cli::confirm_each(action, &mails?)?;
+
+    Ok((session, uids))
+}
+
+/// stars or unstars the mails with the given `uids` in `mailbox`
+async fn set_flagged(
+    mail: Option<String>,
+    mailbox: String,
+    uids: Vec<u32>,
+    flagged: bool,
+    confirm_each: bool,
+) -> anyhow::Result<()> {
+    let session = open_writable_session(mail, &GmailAttributeStore::default()).await?;
+    let action = if flagged { "star" } else { "unstar" };
+    let (session, uids) = confirm_uids(session, &mailbox, uids, action, confirm_each).await?;
+
+    let (mut session, res) = run_with_interrupt_logout(session, move |session| {
+        MailBox::new(&mailbox).set_flagged(&uids, flagged, session)
+    })
+    .await?;
+    res?;
+
+    session.logout()?;
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenv::dotenv()?;
+    check_dotenv(dotenv::dotenv())?;
     let args = CliArgs::parse();
+    proxy::init(args.proxy)?;
+    if args.read_only_config {
+        store_accounts::set_read_only();
+    }
+
+    let read_only_config = args.read_only_config;
+    let charset = args.output_charset;
 
-    match args.command {
-        Commands::Login { email } => {
-            let mut existing_accounts = StoredAccounts::load_data()?;
-            add_new_account(email, &mut existing_accounts).await?;
+    let command = match args.command {
+        Commands::Saved { name, args } => Commands::Read(Box::new(expand_saved_search(
+            &Config::load()?.searches,
+            &name,
+            args,
+        )?)),
+        command => command,
+    };
+
+    match command {
+        Commands::Login {
+            email,
+            provider,
+            device,
+            app_password,
+            password_stdin,
+        } => {
+            let mut existing_accounts = load_accounts()?;
+            check_writable(&existing_accounts)?;
+            add_new_account(
+                email,
+                &mut existing_accounts,
+                provider,
+                device,
+                app_password,
+                password_stdin,
+            )
+            .await?;
         }
-        Commands::Read { n, mailbox, mail } => {
-            let mut accounts = StoredAccounts::load_data()?;
-            let account = match mail {
-                Some(mail) => match accounts.stored_accounts().get(&mail) {
-                    Some(data) => (mail, data.to_owned()),
-                    None => {
-                        print_info(format!("no account with mail '{mail}' found"));
-                        select_account(accounts.stored_accounts())
-                            .ok_or(anyhow!("no account selected"))?
+        Commands::Reauth {
+            email,
+            scope,
+            device,
+        } => {
+            let mut existing_accounts = load_accounts()?;
+            check_writable(&existing_accounts)?;
+            reauthorize_account(email, &mut existing_accounts, scope, device).await?;
+        }
+        Commands::AuthUrl { email, provider } => match provider {
+            Provider::Google => {
+                println!(
+                    "{}",
+                    GoogleOAuthParams::default().get_token_request_url(Some(&email))
+                )
+            }
+            Provider::Yahoo => {
+                println!(
+                    "{}",
+                    YahooOAuthParams::default().get_token_request_url(Some(&email))
+                )
+            }
+        },
+        Commands::Status { format } => {
+            // loaded without `load_accounts` which prompts to reset corrupted accounts
+            let accounts = StoredAccounts::load_data()?;
+            let now = Utc::now();
+            let statuses = accounts
+                .stored_accounts()
+                .iter()
+                .sorted_by(|(a, _), (b, _)| a.cmp(b))
+                .map(|(email, account)| account.status(email, now))
+                .collect::<Vec<_>>();
+
+            match format {
+                StatusFormat::Json => println!("{}", serde_json::to_string_pretty(&statuses)?),
+                StatusFormat::Text if statuses.is_empty() => print_info("no accounts logged in"),
+                StatusFormat::Text => {
+                    // only counts of a recent `mailboxes` listing are shown, this doesn't connect
+                    let unread = UnreadCache::load()?;
+                    for status in statuses {
+                        match unread.fresh(&status.email, now) {
+                            Some(counts) => {
+                                println!("{status}, {} unread", total_unread(counts))
+                            }
+                            None => println!("{status}"),
+                        }
                     }
-                },
-                None => select_account(accounts.stored_accounts())
-                    .ok_or(anyhow!("no account selected"))?,
+                }
+            }
+        }
+        Commands::Mailboxes {
+            mail,
+            concurrency,
+            refresh,
+        } => {
+            let gmail_attributes = GmailAttributeStore::default();
+            let (email, mut session) = open_account_session(mail, &gmail_attributes).await?;
+
+            let now = Utc::now();
+            let mut cache = UnreadCache::load()?;
+            let counts = match cache.fresh(&email, now) {
+                Some(counts) if !refresh => {
+                    session.logout()?;
+                    counts.to_vec()
+                }
+                _ => {
+                    let mailboxes = list_mailboxes(&mut session)?;
+                    // the first chunk is counted over the already open session
+                    let sessions =
+                        std::iter::once(Some(session)).chain(std::iter::repeat_with(|| None));
+                    let chunks = split_evenly(mailboxes, concurrency);
+
+                    let counts = run_bounded(
+                        chunks.into_iter().zip(sessions),
+                        concurrency,
+                        |(chunk, session)| {
+                            let email = email.clone();
+                            async move {
+                                let session = match session {
+                                    Some(session) => session,
+                                    None => {
+                                        open_session(Some(email), &GmailAttributeStore::default())
+                                            .await?
+                                    }
+                                };
+
+                                let (mut session, counts) =
+                                    run_with_interrupt_logout(session, move |session| {
+                                        unread_counts(session, &chunk)
+                                    })
+                                    .await?;
+                                session.logout()?;
+                                counts
+                            }
+                        },
+                    )
+                    .await?
+                    .into_iter()
+                    .collect::<anyhow::Result<Vec<_>>>()?
+                    .concat();
+
+                    cache.insert(email, counts.clone(), now);
+                    if !read_only_config {
+                        cache.store()?;
+                    }
+                    counts
+                }
             };
 
-            let (
-                email,
-                StoredAccountData {
-                    access_token,
-                    refresh_token,
+            let width = counts
+                .iter()
+                .map(|count| count.mailbox.len())
+                .max()
+                .unwrap_or_default();
+            for count in &counts {
+                match count.unseen {
+                    Some(unseen) => println!("{:width$}  {unseen} unread", count.mailbox),
+                    None => println!("{:width$}  not selectable", count.mailbox),
+                }
+            }
+            print_info(format!("{} unread in total", total_unread(&counts)));
+        }
+        Commands::Read(args) => {
+            let ReadArgs {
+                n,
+                mailbox: mut mailboxes,
+                mail,
+                role,
+                limit_body_bytes,
+                head,
+                tail,
+                filter_command,
+                from_style,
+                sort_by,
+                threads,
+                label,
+                gmail_search,
+                since,
+                max_age,
+                flagged,
+                subject,
+                from,
+                text,
+                no_color,
+                quiet,
+                date_format,
+                local,
+                utc,
+                relative_dates,
+                dedup,
+                show_header,
+                all_headers,
+                show_auth,
+                rich,
+                prefer_html,
+                format,
+                fields,
+                no_body,
+                offline,
+                structure,
+                inline_images,
+                nested,
+                extract_nested,
+                peek: _,
+                no_peek: mark_seen,
+                verbose,
+                open_links,
+                count_only,
+                browse,
+                concurrency,
+            } = *args;
+
+            let config = Config::load()?;
+            let n = config.mail_count(n);
+            let mut display_options = DisplayOptions::new(
+                limit_body_bytes,
+                date_format
+                    .or(config.date_format)
+                    .unwrap_or(DEFAULT_DATE_FORMAT.to_owned()),
+                match (local, utc) {
+                    (true, _) => DisplayTimezone::Local,
+                    (_, true) => DisplayTimezone::Utc,
+                    _ => DisplayTimezone::Sender,
                 },
-            ) = account;
+            )?;
+            display_options.extra_headers = match (all_headers, show_header.is_empty()) {
+                (true, _) => ExtraHeaders::All,
+                (false, false) => ExtraHeaders::Named(show_header),
+                (false, true) => ExtraHeaders::None,
+            };
+            display_options.show_auth = show_auth;
+            display_options.body_lines = match (head, tail) {
+                (Some(n), _) => Some(BodyLines::Head(n)),
+                (_, Some(n)) => Some(BodyLines::Tail(n)),
+                _ => None,
+            };
+            display_options.from_style = from_style;
+            display_options.rich = rich;
+            display_options.prefer_html = prefer_html;
+            display_options.format = format;
+            display_options.structure = structure;
+            display_options.nested = nested;
+            display_options.fields = fields;
 
-            let imap_auth = ImapOAuth2Data {
-                user: email.clone(),
-                access_token,
+            let batch_size = config.fetch_batch_size.unwrap_or(DEFAULT_FETCH_BATCH_SIZE);
+            let retries = config.fetch_retries.unwrap_or(DEFAULT_FETCH_RETRIES);
+
+            let gmail_search = label
+                .iter()
+                .map(|label| label_search(label))
+                .chain(gmail_search.iter().map(|query| raw_search(query)))
+                .collect::<Vec<_>>();
+
+            let now = Local::now();
+            let since = latest_start(since, max_age.or(config.max_age), now);
+            let text_search = [("SUBJECT", subject), ("FROM", from), ("TEXT", text)]
+                .into_iter()
+                .filter_map(|(key, value)| value.map(|value| (key, value)))
+                .collect::<Vec<_>>();
+            let search = Search {
+                keys: read_search_keys(since, flagged, &text_search, now),
+                gmail: gmail_search,
             };
+            display_options.highlight = text_search.into_iter().map(|(_, value)| value).collect();
+            display_options.relative_to = relative_dates.then_some(now);
+            if no_color {
+                colored::control::set_override(false);
+            }
 
-            let mut session = create_imap_session_with_refresh_on_err(
-                GOOGLE_IMAP_DOMAIN,
-                GOOGLE_IMAP_PORT,
-                &imap_auth,
-                &refresh_token,
-                email,
-                &mut accounts,
-            )
+            let summary_ttl = config
+                .summary_cache_ttl
+                .unwrap_or(DEFAULT_SUMMARY_CACHE_TTL_SECS);
+            let use_summaries = offline || (no_body && !mark_seen && summary_ttl > 0);
+            let mut summaries = match use_summaries {
+                true => Some(SummaryCache::load()?),
+                false => None,
+            };
+
+            let downloaded = ByteCounter::default();
+            let total_downloaded = downloaded.clone();
+            let mut mails = if offline {
+                let (email, _, _) = resolve_account(mail)?;
+                let query = query_key(&mailboxes, n, sort_by, &search);
+                let Some((mails, fetched_at)) = summaries
+                    .as_ref()
+                    .and_then(|summaries| summaries.last(&email, &query))
+                else {
+                    return Err(anyhow!(
+                        "the mails of this query aren't cached for {email}, run it once without \
+                        `--offline`"
+                    ));
+                };
+                print_info(format!(
+                    "showing the mails fetched at {}",
+                    fetched_at
+                        .with_timezone(&Local)
+                        .format(&display_options.date_format)
+                ));
+                mails
+            } else if let Some(mails) = summaries
+                .as_ref()
+                // the mailboxes of a role and the count are only known after logging in
+                .filter(|_| role.is_none() && !count_only)
+                .map(|summaries| -> anyhow::Result<_> {
+                    let (email, _, _) = resolve_account(mail.clone())?;
+                    let query = query_key(&mailboxes, n, sort_by, &search);
+                    Ok(summaries.fresh(&email, &query, summary_ttl, Utc::now()))
+                })
+                .transpose()?
+                .flatten()
+            {
+                // the mails of the same query are reused without connecting if it ran shortly
+                // before
+                mails
+            } else {
+                // marking mails as seen changes them
+                let mail = match mark_seen {
+                    true => Some(check_write_scope(mail)?),
+                    false => mail,
+                };
+                let gmail_attributes = GmailAttributeStore::default();
+                let (email, mut session) = open_account_session(mail, &gmail_attributes).await?;
+                if let Some(role) = role {
+                    mailboxes = vec![role.resolve(&mut session)?];
+                }
+                check_selectable(&mut session, &mailboxes)?;
+
+                if count_only {
+                    let (mut session, count) = run_with_interrupt_logout(session, move |session| {
+                        count_mails(session, &mailboxes, &search)
+                    })
+                    .await?;
+                    println!("{}", count?);
+
+                    session.logout()?;
+                    return Ok(());
+                }
+
+                // recorded with the mails, a fetch that sees another one drops the cached mails
+                // of the mailbox
+                let uid_validities = match summaries {
+                    Some(_) => Some(
+                        mailboxes
+                            .iter()
+                            .map(|mailbox| {
+                                let opened = OpenMode::ReadOnly.open(mailbox, &mut session)?;
+                                Ok((mailbox.clone(), opened.uid_validity.unwrap_or(0)))
+                            })
+                            .collect::<anyhow::Result<BTreeMap<_, _>>>()?,
+                    ),
+                    None => None,
+                };
+                let query = query_key(&mailboxes, n, sort_by, &search);
+
+                let progress = Progress::for_terminal("fetched", quiet);
+                let downloaded = downloaded.clone();
+                let mails = fetch_concurrently(
+                    email.clone(),
+                    (session, gmail_attributes),
+                    mailboxes,
+                    n,
+                    &search,
+                    concurrency,
+                    move |mailbox| {
+                        mailbox
+                            .with_batch_size(batch_size)
+                            .with_progress(progress.clone())
+                            .with_headers_only(no_body)
+                            .with_mark_seen(mark_seen)
+                            .with_byte_counter(Some(downloaded.clone()))
+                            .with_retries(retries)
+                            .with_sort_by(sort_by)
+                    },
+                )
+                .await?;
+
+                if let (Some(summaries), Some(uid_validities)) = (&mut summaries, uid_validities) {
+                    let now = Utc::now();
+                    summaries.prune(summary_ttl, now);
+                    summaries.insert(email, query, uid_validities, &mails, now);
+                    if !read_only_config {
+                        // the mails were fetched, they are shown even if they can't be reused
+                        if let Err(err) = summaries.store() {
+                            print_info(format!("failed to cache the fetched mails: {err:#}"));
+                        }
+                    }
+                }
+                mails
+            };
+
+            // `SINCE` only compares dates
+            if let Some(since) = since {
+                let start = since.start(now);
+                mails.retain(|mail| {
+                    mail.as_ref()
+                        .ok()
+                        .and_then(|mail| mail.date())
+                        .is_none_or(|date| date >= start)
+                });
+            }
+            if let Some(command) = &filter_command {
+                mails = filter_by_command(mails, command)?;
+            }
+            if dedup {
+                let hidden;
+                (mails, hidden) = dedup_by_message_id(mails);
+                if hidden > 0 {
+                    print_info(format!("hid {hidden} duplicate mail(s)"));
+                }
+            }
+
+            if browse && !Term::stdout().is_term() {
+                print_info("not browsing the mails, stdout isn't a terminal");
+            }
+
+            let total = mails.len();
+            let failed = mails.iter().filter(|mail| mail.is_err()).count();
+            // collected before the mails are consumed by displaying them
+            let links = match open_links {
+                true => mails
+                    .iter()
+                    .flatten()
+                    .flat_map(|mail| mail.links(&display_options))
+                    .unique()
+                    .collect::<Vec<_>>(),
+                false => Vec::new(),
+            };
+            if let Some(dir) = &extract_nested {
+                let extracted = mails
+                    .iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(idx, mail)| {
+                        nested::extract_nested(mail.nested(), dir, &extract_stem(mail, idx))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                print_info(format!(
+                    "saved {} attached message(s) to {}",
+                    extracted.iter().map(Vec::len).sum::<usize>(),
+                    dir.display()
+                ));
+            }
+            if display_options.format == OutputFormat::Json {
+                let json = mails
+                    .iter()
+                    .map(|mail| match mail {
+                        Ok(mail) => mail.display(&display_options).json(),
+                        Err(err) => serde_json::json!({ "error": format!("{err:#}") }),
+                    })
+                    .collect::<Vec<_>>();
+                // JSON is UTF-8 (RFC 8259), it's read by programs rather than the terminal
+                println!("{}", serde_json::to_string_pretty(&json)?);
+            } else if display_options.format == OutputFormat::Eml {
+                // stdout only gets the mbox, so it can be split by other tools
+                for err in mails.iter().filter_map(|mail| mail.as_ref().err()) {
+                    eprintln!("{err:#}");
+                }
+                write_mbox(&mut std::io::stdout().lock(), mails.iter().flatten())?;
+            } else if no_body {
+                if let Some(legend) = flags_legend(&mails) {
+                    print_info(legend);
+                }
+                charset.println(header_table(&mails, &display_options, terminal_width()))?;
+            } else if browse && Term::stdout().is_term() {
+                browse_mails(&mails, &display_options, charset, terminal_width())?;
+            } else if threads {
+                let (mails, failed): (Vec<_>, Vec<_>) = mails.into_iter().partition_result();
+                for thread in group_by_thread(mails) {
+                    print_info(format!("thread with {} mail(s)", thread.len()));
+                    for mail in thread {
+                        charset.println(format!("{}\n", mail.display(&display_options)))?;
+                    }
+                }
+                for err in failed {
+                    charset.println(render_mail(&Err(err), &display_options))?;
+                }
+            } else if inline_images {
+                let protocol = Term::stdout()
+                    .is_term()
+                    .then(|| GraphicsProtocol::detect(|name| env::var(name).ok()))
+                    .flatten();
+                for mail in mails {
+                    charset.println(render_mail(&mail, &display_options))?;
+                    if let Ok(mail) = mail {
+                        print!("{}", render_images(mail.raw(), protocol));
+                    }
+                }
+            } else {
+                for mail in mails {
+                    charset.println(render_mail(&mail, &display_options))?;
+                }
+            }
+
+            // with `--format eml` the errors were already printed to stderr
+            if failed > 0 && display_options.format != OutputFormat::Eml {
+                print_info(format!("{failed} of {total} mail(s) couldn't be parsed"));
+            }
+            if verbose {
+                print_info(format!("downloaded {total_downloaded}"));
+            }
+
+            if open_links {
+                if links.is_empty() {
+                    print_info("no links found");
+                } else if let Some(link) = select_link(&links)? {
+                    open_url(link)?;
+                }
+            }
+        }
+        Commands::Saved { .. } => unreachable!("saved searches are expanded to `read` above"),
+        Commands::Digest {
+            mail,
+            mailbox,
+            since,
+            json,
+        } => {
+            let since = since
+                .map(|since| since.start(Local::now()).date_naive())
+                .unwrap_or(Local::now().date_naive() - chrono::Duration::days(1));
+            let session = open_session(mail, &GmailAttributeStore::default()).await?;
+
+            let (mut session, entries) = run_with_interrupt_logout(session, move |session| {
+                fetch_digest_entries(session, &mailbox, since)
+            })
+            .await?;
+
+            let digest = Digest::new(since, entries?);
+            if json {
+                // JSON is UTF-8 (RFC 8259), it's read by programs rather than the terminal
+                println!("{}", serde_json::to_string_pretty(&digest)?);
+            } else {
+                charset.print(digest)?;
+            }
+
+            session.logout()?;
+        }
+        Commands::Star {
+            mail,
+            mailbox,
+            ids,
+            confirm_each,
+        } => set_flagged(mail, mailbox, ids, true, confirm_each).await?,
+        Commands::Unstar {
+            mail,
+            mailbox,
+            ids,
+            confirm_each,
+        } => set_flagged(mail, mailbox, ids, false, confirm_each).await?,
+        Commands::Archive {
+            mail,
+            mailbox,
+            ids,
+            confirm_each,
+        } => {
+            let archive = Config::load()?.archive_mailbox;
+            let session = open_writable_session(mail, &GmailAttributeStore::default()).await?;
+            let (session, ids) =
+                confirm_uids(session, &mailbox, ids, "archive", confirm_each).await?;
+
+            let (mut session, res) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&mailbox).archive(&ids, archive.as_deref(), session)
+            })
             .await?;
+            res?;
 
-            let mailbox = MailBox::new(&mailbox);
-            let mails = mailbox.fetch_n_recent_mails(n, &mut session)?;
+            session.logout()?;
+        }
+        Commands::Trash {
+            mail,
+            mailbox,
+            ids,
+            confirm_each,
+        } => {
+            let session = open_writable_session(mail, &GmailAttributeStore::default()).await?;
+            let (session, ids) =
+                confirm_uids(session, &mailbox, ids, "trash", confirm_each).await?;
+
+            let (mut session, res) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&mailbox).trash(&ids, session)
+            })
+            .await?;
+            res?;
+
+            session.logout()?;
+        }
+        Commands::Delete {
+            mail,
+            mailbox,
+            ids,
+            permanent,
+            confirm_each,
+        } => {
+            let session = open_writable_session(mail, &GmailAttributeStore::default()).await?;
+            let (session, ids) =
+                confirm_uids(session, &mailbox, ids, "delete", confirm_each).await?;
+
+            let name = mailbox.clone();
+            let (session, deletion) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&name).deletion(permanent, session)
+            })
+            .await?;
+            let deletion = deletion?;
+
+            let count = ids.len();
+            let mut session = match deletion {
+                _ if count == 0 => session,
+                Deletion::Permanent if !confirm_permanent_delete(&mailbox, count)? => session,
+                deletion => {
+                    let (session, res) = run_with_interrupt_logout(session, move |session| {
+                        MailBox::new(&mailbox).delete(&ids, deletion, session)
+                    })
+                    .await?;
+                    res?;
+
+                    print_info(match deletion {
+                        Deletion::Trash => format!(
+                            "moved {count} mail(s) to the Trash, `mail-cli restore` moves them back"
+                        ),
+                        Deletion::Permanent => format!("permanently deleted {count} mail(s)"),
+                    });
+                    session
+                }
+            };
+
+            session.logout()?;
+        }
+        Commands::Restore {
+            mail,
+            ids,
+            to,
+            confirm_each,
+        } => {
+            let session = open_writable_session(mail, &GmailAttributeStore::default()).await?;
+
+            let (session, trash) = run_with_interrupt_logout(session, trash_mailbox).await?;
+            let trash = trash?;
+            let (session, ids) =
+                confirm_uids(session, &trash, ids, "restore", confirm_each).await?;
+
+            let (mut session, res) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&trash).move_mails(&ids, &to, session)
+            })
+            .await?;
+            res?;
+
+            session.logout()?;
+        }
+        Commands::Expunge {
+            mail,
+            mailbox,
+            dry_run,
+        } => {
+            let session = if dry_run {
+                open_session(mail, &GmailAttributeStore::default()).await?
+            } else {
+                open_writable_session(mail, &GmailAttributeStore::default()).await?
+            };
+
+            let name = mailbox.clone();
+            let (session, deleted) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&name).deleted_uids(session)
+            })
+            .await?;
+            let deleted = deleted?;
+
+            let mut session = if dry_run {
+                print_info(format!(
+                    "{} mail(s) marked as deleted would be removed from '{mailbox}'",
+                    deleted.len()
+                ));
+                session
+            } else if confirm_expunge(&mailbox, deleted.len())? {
+                let (session, expunged) = run_with_interrupt_logout(session, move |session| {
+                    MailBox::new(&mailbox).expunge(&deleted, session)
+                })
+                .await?;
+                print_info(format!("removed {} mail(s)", expunged?));
+                session
+            } else {
+                session
+            };
 
+            session.logout()?;
+        }
+        Commands::EmptyTrash {
+            mail,
+            spam,
+            dry_run,
+        } => {
+            let session = if dry_run {
+                open_session(mail, &GmailAttributeStore::default()).await?
+            } else {
+                open_writable_session(mail, &GmailAttributeStore::default()).await?
+            };
+
+            let (session, found) = run_with_interrupt_logout(session, move |session| {
+                let mailbox = mailbox_to_empty(spam, session)?;
+                let uids = MailBox::new(&mailbox).uids(session)?;
+                anyhow::Ok((mailbox, uids))
+            })
+            .await?;
+            let (mailbox, uids) = found?;
+
+            let mut session = if dry_run {
+                print_info(format!(
+                    "{} mail(s) would be permanently deleted from '{mailbox}'",
+                    uids.len()
+                ));
+                session
+            } else if confirm_empty(&mailbox, uids.len())? {
+                let count = uids.len();
+                let name = mailbox.clone();
+                let (session, res) = run_with_interrupt_logout(session, move |session| {
+                    MailBox::new(&name).delete(&uids, Deletion::Permanent, session)
+                })
+                .await?;
+                res?;
+                print_info(format!("deleted {count} mail(s) from '{mailbox}'"));
+                session
+            } else {
+                session
+            };
+
+            session.logout()?;
+        }
+        Commands::ReplyAll { mail, mailbox, id } => {
+            let (email, session) =
+                open_account_session(mail, &GmailAttributeStore::default()).await?;
+
+            let (mut session, fetched) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&mailbox).fetch_mail_by_uid(id, session)
+            })
+            .await?;
+            session.logout()?;
+
+            print!("{}", compose_reply_all(&fetched?, &email)?);
+        }
+        Commands::Forward {
+            mail,
+            mailbox,
+            id,
+            to,
+        } => {
+            let (email, session) =
+                open_account_session(mail, &GmailAttributeStore::default()).await?;
+
+            let (mut session, fetched) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&mailbox).fetch_mail_by_uid(id, session)
+            })
+            .await?;
+            session.logout()?;
+
+            std::io::stdout().write_all(&compose_forward(&fetched?, &email, &to)?)?;
+        }
+        Commands::SaveDraft {
+            mail,
+            mailbox,
+            to,
+            cc,
+            subject,
+            body,
+        } => {
+            let body = match body {
+                Some(body) => body,
+                None => {
+                    let mut body = String::new();
+                    std::io::stdin().read_to_string(&mut body)?;
+                    body
+                }
+            };
+            let email = check_write_scope(mail)?;
+            let mut draft = Draft {
+                from: email.clone(),
+                to,
+                cc,
+                subject,
+                body,
+            };
+            draft.check()?;
+            let (from, session) =
+                open_account_session(Some(email), &GmailAttributeStore::default()).await?;
+            draft.from = from;
+
+            let (mut session, saved) = run_with_interrupt_logout(session, move |session| {
+                draft.save(mailbox.as_deref(), Local::now().fixed_offset(), session)
+            })
+            .await?;
+            session.logout()?;
+
+            print_info(format!("saved the draft to {}", saved?));
+        }
+        Commands::Transfer {
+            src_mail,
+            src_mailbox,
+            id,
+            dest_mail,
+            dest_mailbox,
+        } => {
+            let gmail_attributes = GmailAttributeStore::default();
+            // both sessions are opened first, so a failing login to the destination doesn't
+            // leave a fetched mail behind
+            let (src_email, src) = open_account_session(src_mail, &gmail_attributes).await?;
+            let dest = open_writable_session(Some(dest_mail.clone()), &gmail_attributes).await?;
+
+            let (mut src, (mut dest, res)) = run_with_interrupt_logout(src, move |src| {
+                let mut dest = dest;
+                let res = transfer_mail(src, &src_mailbox, id, &mut dest, &dest_mailbox);
+                (dest, res.map(|_| dest_mailbox))
+            })
+            .await?;
+            src.logout()?;
+            dest.logout()?;
+
+            print_info(format!(
+                "copied mail {id} of {src_email} to {} of {dest_mail}",
+                res?
+            ));
+        }
+        Commands::ShowId {
+            mail,
+            mailbox,
+            message_id,
+        } => {
+            let session = open_session(mail, &GmailAttributeStore::default()).await?;
+
+            let query = message_id_search(&message_id);
+            let (mut session, mails) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&mailbox).fetch_n_recent_mails_matching(usize::MAX, &query, session)
+            })
+            .await?;
+            session.logout()?;
+
+            let mails = mails?;
+            match mails.len() {
+                0 => return Err(anyhow!("no mail with Message-ID '{message_id}' found")),
+                1 => {}
+                n => print_info(format!("found {n} mails with Message-ID '{message_id}'")),
+            }
+
+            let display_options = DisplayOptions::default();
             for mail in mails {
-                let mail = mail?;
-                println!("{mail}");
+                charset.println(format!("{}\n", render_mail(&mail, &display_options)))?;
             }
+        }
+        Commands::Whoami { mail } => {
+            let (email, account, _) = resolve_account(mail)?;
+            let (domain, port) = match account.provider {
+                Provider::Google => Google.imap_host(),
+                Provider::Yahoo => Yahoo.imap_host(),
+            };
+            let server = format!("{domain}:{port}");
+
+            let gmail_attributes = GmailAttributeStore::default();
+            let mut session = open_session(Some(email.clone()), &gmail_attributes).await?;
+            let capabilities = capabilities(&mut session)?;
+            let server_id = match capabilities
+                .iter()
+                .any(|cap| cap.eq_ignore_ascii_case("ID"))
+            {
+                true => server_id(&mut session, &gmail_attributes)?,
+                false => Vec::new(),
+            };
+            session.logout()?;
+
+            // the server accepted the app password for the stored name. an access token is
+            // issued for an account the provider has to be asked for, the login may have
+            // refreshed it
+            let (username, username_confirmed) = match (&account.app_password, account.provider) {
+                (Some(_), _) => (email, true),
+                (None, Provider::Google) => {
+                    let (_, account, _) = resolve_account(Some(email.clone()))?;
+                    match fetch_google_account_email(&http_client()?, &account.access_token).await {
+                        Ok(username) => (username, true),
+                        Err(err) => {
+                            print_info(format!("{err:#}"));
+                            (email, false)
+                        }
+                    }
+                }
+                (None, Provider::Yahoo) => (email, false),
+            };
+
+            let identity = Identity {
+                username,
+                username_confirmed,
+                provider: account.provider,
+                auth: match account.app_password {
+                    Some(_) => String::from("app password"),
+                    None => String::from("OAuth 2.0 access token"),
+                },
+                server,
+                server_id,
+                capabilities,
+            };
+            println!("{identity}");
+        }
+        Commands::Capabilities { mail } => {
+            let mut session = open_session(mail, &GmailAttributeStore::default()).await?;
+            let capabilities = capabilities(&mut session)?;
+            session.logout()?;
+
+            println!("{}", format_capabilities(&capabilities));
+        }
+        Commands::Backup {
+            mail,
+            mailbox,
+            output_dir,
+            quiet,
+        } => {
+            let session = open_session(mail, &GmailAttributeStore::default()).await?;
+
+            let (mut session, exported) = run_with_interrupt_logout(session, move |session| {
+                backup_mailbox(
+                    session,
+                    &mailbox,
+                    &output_dir,
+                    Progress::for_terminal("exported", quiet),
+                )
+            })
+            .await?;
+            print_info(format!("exported {} new mail(s)", exported?));
 
             session.logout()?;
         }
+        Commands::Watch {
+            mail,
+            mailbox,
+            interval,
+        } => {
+            // resolved once so reconnecting never prompts
+            let (email, _, _) = resolve_account(mail)?;
+            let interval = Duration::from_secs(interval).max(MIN_POLL_INTERVAL);
+
+            watch_mailbox(
+                email,
+                mailbox,
+                Backoff::new(interval, interval),
+                None,
+                &charset,
+            )
+            .await?;
+        }
+        Commands::Poll {
+            mail,
+            mailbox,
+            interval,
+            max_interval,
+        } => {
+            // resolved once so reconnecting never prompts
+            let (email, _, _) = resolve_account(mail)?;
+            let interval = Duration::from_secs(interval).max(MIN_POLL_INTERVAL);
+            let cache = LastUidCache::load()?;
+
+            watch_mailbox(
+                email,
+                mailbox,
+                Backoff::new(interval, Duration::from_secs(max_interval)),
+                Some((cache, !read_only_config)),
+                &charset,
+            )
+            .await?;
+        }
+        Commands::Unsubscribe { mail, mailbox, id } => {
+            let session = open_session(mail, &GmailAttributeStore::default()).await?;
+
+            let (mut session, fetched) = run_with_interrupt_logout(session, move |session| {
+                MailBox::new(&mailbox).fetch_mail_by_uid(id, session)
+            })
+            .await?;
+            session.logout()?;
+
+            let methods = fetched?.unsubscribe_methods();
+            if methods.is_empty() {
+                print_info("the mail has no List-Unsubscribe header");
+            } else if let Some(method) = select_unsubscribe_method(methods)? {
+                method.run().await?;
+            }
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_imap::{connect, mock_client, MockImapStream, MockMail};
+
+    fn gmail_session(
+        mails: Vec<MockMail>,
+    ) -> (Session<GmailExtStream<MockImapStream>>, GmailAttributeStore) {
+        let store = GmailAttributeStore::default();
+        let stream = MockImapStream::new(vec![("INBOX", mails)])
+            .with_capabilities(&["IMAP4rev1", GMAIL_EXT_CAPABILITY]);
+        let session = connect(GmailExtStream::new(stream, store.clone()))
+            .login("user", "password")
+            .map_err(|(err, _)| err)
+            .unwrap();
+
+        (session, store)
+    }
+
+    fn mock_mail(subject: &str) -> MockMail {
+        MockMail::new(&format!(
+            "Date: Mon, 1 May 2023 10:00:00 +0000\nSubject: {subject}\n\nhello\n"
+        ))
+    }
+
+    #[test]
+    fn only_fails_on_malformed_dotenv() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_dotenv(dotenv::from_path(dir.path().join(".env"))).is_ok());
+
+        let malformed = dir.path().join(".env");
+        std::fs::write(&malformed, "MAIL_CLI_TEST_DOTENV value\n").unwrap();
+        let err = check_dotenv(dotenv::from_path(&malformed)).unwrap_err();
+        assert!(format!("{err:#}").starts_with(DOTENV_HINT));
+        assert!(format!("{err:#}").contains("MAIL_CLI_TEST_DOTENV value"));
+    }
+
+    struct FakeProvider;
+
+    impl OAuthProvider for FakeProvider {
+        fn imap_host(&self) -> (&str, u16) {
+            ("imap.example.com", 143)
+        }
+
+        async fn refresh(&self, refresh_token: &str) -> anyhow::Result<RefreshedToken> {
+            Ok(RefreshedToken {
+                access_token: format!("refreshed-{refresh_token}"),
+                expires_in: Some(3600),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn refreshes_expired_token_with_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(store_accounts::ACCOUNTS_FILE_NAME);
+        let mut accounts = StoredAccounts::load_from(path.clone()).unwrap();
+        let account = StoredAccountData::new(String::from("expired"), String::from("refresh"));
+        let hosts = std::cell::RefCell::new(Vec::new());
+        let audit_path = dir.path().join(audit::AUDIT_LOG_FILE_NAME);
+
+        let connect = |domain: &str, port, imap_auth: &ImapOAuth2Data| {
+            hosts.borrow_mut().push((domain.to_owned(), port));
+            match imap_auth.access_token.as_str() {
+                "refreshed-refresh" => {
+                    let (client, _) = mock_client(vec![("INBOX", Vec::new())]);
+                    authenticate_imap_client(client, imap_auth)
+                }
+                _ => Err(anyhow!("invalid credentials")),
+            }
+        };
+
+        create_imap_session_with_refresh_on_err(
+            &FakeProvider,
+            String::from("user@example.com"),
+            &account,
+            Some(&mut accounts),
+            Some(&AuditLog::at(audit_path.clone())),
+            connect,
+        )
+        .await
+        .unwrap();
+
+        // one entry for the one refresh, without the tokens
+        let audit = std::fs::read_to_string(audit_path).unwrap();
+        assert_eq!(audit.lines().count(), 1);
+        assert!(audit.contains(" refresh user@example.com pid="));
+        assert!(audit.ends_with(" ok\n"));
+        assert!(!audit.contains("expired") && !audit.contains("refreshed-refresh"));
+
+        assert_eq!(
+            *hosts.borrow(),
+            vec![(String::from("imap.example.com"), 143); 2]
+        );
+        let stored = StoredAccounts::load_from(path).unwrap();
+        let stored = &stored.stored_accounts()["user@example.com"];
+        assert_eq!(stored.access_token, "refreshed-refresh");
+        assert!(stored.expires_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn keeps_accounts_file_if_token_is_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(store_accounts::ACCOUNTS_FILE_NAME);
+        let mut accounts = StoredAccounts::load_from(path.clone()).unwrap();
+        let account =
+            StoredAccountData::new(String::from("refreshed-refresh"), String::from("refresh"));
+        let attempts = std::cell::Cell::new(0);
+
+        // the first attempt is rejected even though the token is still valid and the provider
+        // hands out the same one again
+        let connect = |_: &str, _, imap_auth: &ImapOAuth2Data| {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(anyhow!(
+                    "[AUTHENTICATIONFAILED] Invalid credentials (Failure)"
+                )),
+                _ => {
+                    let (client, _) = mock_client(vec![("INBOX", Vec::new())]);
+                    authenticate_imap_client(client, imap_auth)
+                }
+            }
+        };
+
+        create_imap_session_with_refresh_on_err(
+            &FakeProvider,
+            String::from("user@example.com"),
+            &account,
+            Some(&mut accounts),
+            None,
+            connect,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(attempts.get(), 2);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_transient_failures_without_refreshing() {
+        struct UnreachableProvider;
+
+        impl OAuthProvider for UnreachableProvider {
+            fn imap_host(&self) -> (&str, u16) {
+                ("imap.example.com", 143)
+            }
+
+            async fn refresh(&self, _: &str) -> anyhow::Result<RefreshedToken> {
+                panic!("the token must not be refreshed")
+            }
+        }
+
+        let account = StoredAccountData::new(String::from("valid"), String::from("refresh"));
+        let attempts = std::cell::Cell::new(0);
+        let connect = |_: &str, _, imap_auth: &ImapOAuth2Data| {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(
+                    imap::Error::No(String::from("[UNAVAILABLE] Please try again later")).into(),
+                ),
+                2 => Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into()),
+                _ => {
+                    let (client, _) = mock_client(vec![("INBOX", Vec::new())]);
+                    authenticate_imap_client(client, imap_auth)
+                }
+            }
+        };
+
+        let started = tokio::time::Instant::now();
+        create_imap_session_with_refresh_on_err(
+            &UnreachableProvider,
+            String::from("user@example.com"),
+            &account,
+            None,
+            None,
+            connect,
+        )
+        .await
+        .unwrap();
+        assert_eq!(attempts.get(), 3);
+        assert_eq!(started.elapsed(), SESSION_RETRY_DELAY * 3);
+
+        // other failures aren't retried
+        attempts.set(0);
+        let connect = |_: &str, _, _: &ImapOAuth2Data| -> anyhow::Result<Session<MockImapStream>> {
+            attempts.set(attempts.get() + 1);
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into())
+        };
+        create_imap_session_with_refresh_on_err(
+            &UnreachableProvider,
+            String::from("user@example.com"),
+            &account,
+            None,
+            None,
+            connect,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn counts_failed_refreshes() {
+        struct RevokedProvider;
+
+        impl OAuthProvider for RevokedProvider {
+            fn imap_host(&self) -> (&str, u16) {
+                ("imap.example.com", 143)
+            }
+
+            async fn refresh(&self, _: &str) -> anyhow::Result<RefreshedToken> {
+                Err(anyhow!("invalid_grant"))
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(store_accounts::ACCOUNTS_FILE_NAME);
+        let mut accounts = StoredAccounts::load_from(path.clone()).unwrap();
+        let connect = |_: &str, _, _: &ImapOAuth2Data| -> anyhow::Result<Session<MockImapStream>> {
+            Err(anyhow!("authentication failed"))
+        };
+
+        for failures in 1..=2 {
+            let account = accounts.stored_accounts().get("user@example.com").cloned();
+            let account = account.unwrap_or(StoredAccountData::new(
+                String::from("expired"),
+                String::from("revoked"),
+            ));
+            let err = create_imap_session_with_refresh_on_err(
+                &RevokedProvider,
+                String::from("user@example.com"),
+                &account,
+                Some(&mut accounts),
+                None,
+                connect,
+            )
+            .await
+            .unwrap_err();
+
+            assert_eq!(err.to_string(), "invalid_grant");
+            let stored = StoredAccounts::load_from(path.clone()).unwrap();
+            assert_eq!(
+                stored.stored_accounts()["user@example.com"].refresh_failures,
+                failures
+            );
+        }
+    }
+
+    #[test]
+    fn hints_to_log_in_without_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts =
+            StoredAccounts::load_from(dir.path().join(store_accounts::ACCOUNTS_FILE_NAME)).unwrap();
+
+        let err = select_stored_account(None, &accounts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no accounts are logged in yet, log in to one with `mail-cli login <email>` first"
+        );
+        let err = select_stored_account(Some(String::from("a@gmail.com")), &accounts).unwrap_err();
+        assert!(err.to_string().contains("`mail-cli login a@gmail.com`"));
+    }
+
+    #[test]
+    fn encodes_idn_hosts() {
+        assert_eq!(
+            ascii_host("imap.bücher.example").unwrap(),
+            "imap.xn--bcher-kva.example"
+        );
+        assert_eq!(ascii_host("imap.gmail.com").unwrap(), "imap.gmail.com");
+    }
+
+    #[test]
+    fn authenticates_over_any_transport() {
+        let (client, commands) = mock_client(vec![("INBOX", vec![mock_mail("hi")])]);
+        let imap_auth = ImapOAuth2Data {
+            user: String::from("user@example.com"),
+            access_token: String::from("token"),
+        };
+
+        let mut session = authenticate_imap_client(client, &imap_auth).unwrap();
+        let mails = MailBox::new("INBOX")
+            .fetch_n_recent_mails(1, &mut session)
+            .unwrap();
+
+        assert_eq!(mails.len(), 1);
+        assert_eq!(commands.borrow()[0], "AUTHENTICATE XOAUTH2");
+    }
+
+    #[test]
+    fn filters_by_gmail_label() {
+        let (mut session, store) = gmail_session(vec![
+            mock_mail("work").with_labels(&["Work"]),
+            mock_mail("private").with_labels(&["Private"]),
+        ]);
+
+        let search = Search {
+            gmail: vec![label_search("Work")],
+            ..Search::default()
+        };
+        let mails = fetch_mails(
+            &mut session,
+            &[String::from("INBOX")],
+            10,
+            &search,
+            &store,
+            |mailbox| mailbox,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].as_ref().unwrap().to_string().contains("work"));
+    }
+
+    #[test]
+    fn extracts_per_mailbox() {
+        let (client, _) = mock_client(vec![
+            ("INBOX", vec![mock_mail("hi")]),
+            ("Lists/rust", vec![mock_mail("release")]),
+        ]);
+        let mut session = client
+            .login("user", "password")
+            .map_err(|(err, _)| err)
+            .unwrap();
+
+        let mails = fetch_mails(
+            &mut session,
+            &[String::from("INBOX"), String::from("Lists/rust")],
+            10,
+            &Search::default(),
+            &GmailAttributeStore::default(),
+            |mailbox| mailbox,
+            None,
+        )
+        .unwrap();
+
+        // both mails have UID 1
+        let stems = mails
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(idx, mail)| extract_stem(mail, idx))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stems,
+            [
+                format!("{}-1", file_name_safe("INBOX")),
+                format!("{}-1", file_name_safe("Lists/rust"))
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_without_fetching() {
+        let (mut session, _) = gmail_session(vec![
+            mock_mail("work").with_labels(&["Work"]),
+            mock_mail("private").with_labels(&["Private"]),
+            mock_mail("more work").with_labels(&["Work"]),
+        ]);
+        let (client, commands) = mock_client(vec![("INBOX", vec![mock_mail("hi")])]);
+        let mut plain_session = client
+            .login("user", "password")
+            .map_err(|(err, _)| err)
+            .unwrap();
+
+        let search = Search {
+            gmail: vec![label_search("Work")],
+            ..Search::default()
+        };
+        assert_eq!(
+            count_mails(&mut session, &[String::from("INBOX")], &search).unwrap(),
+            2
+        );
+        assert_eq!(
+            count_mails(
+                &mut plain_session,
+                &[String::from("INBOX")],
+                &Search::default()
+            )
+            .unwrap(),
+            1
+        );
+        assert!(!commands
+            .borrow()
+            .iter()
+            .any(|command| command.to_ascii_uppercase().contains("FETCH")));
+    }
+
+    #[test]
+    fn renders_failed_mails_inline() {
+        let mut session = mock_client(vec![(
+            "INBOX",
+            vec![
+                mock_mail("good"),
+                MockMail::new("Date: Mon, 1 May 2023 09:00:00 +0000\nSubject: broken\n\n")
+                    .without_body(),
+                mock_mail("also good"),
+            ],
+        )])
+        .0
+        .login("user", "password")
+        .map_err(|(err, _)| err)
+        .unwrap();
+
+        let rendered = MailBox::new("INBOX")
+            .fetch_n_recent_mails(3, &mut session)
+            .unwrap()
+            .iter()
+            .map(|mail| render_mail(mail, &DisplayOptions::default()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(rendered.len(), 3);
+        assert_eq!(
+            rendered
+                .iter()
+                .filter(|mail| mail.starts_with("From:"))
+                .count(),
+            2
+        );
+        assert!(rendered
+            .iter()
+            .any(|mail| mail.starts_with("[failed to parse message 2")));
+    }
+
+    #[test]
+    fn gmail_search_requires_gmail() {
+        let (client, _) = mock_client(vec![("INBOX", vec![mock_mail("hi")])]);
+        let mut session = client
+            .login("user", "password")
+            .map_err(|(err, _)| err)
+            .unwrap();
+
+        let search = Search {
+            gmail: vec![raw_search("has:attachment")],
+            ..Search::default()
+        };
+        assert!(fetch_mails(
+            &mut session,
+            &[String::from("INBOX")],
+            10,
+            &search,
+            &GmailAttributeStore::default(),
+            |mailbox| mailbox,
+            None,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt::Display;
+
+use mail_parser::{Message, MessagePart, MimeHeaders, PartType};
+
+use crate::progress::format_bytes;
+
+/// a part of a mail's MIME tree, see [`mime_structure`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimePart {
+    /// nesting level, 0 for the root part
+    pub depth: usize,
+    pub content_type: String,
+    /// size of the still encoded body in bytes
+    pub size: usize,
+    /// the `Content-Transfer-Encoding`, if set
+    pub encoding: Option<String>,
+    pub filename: Option<String>,
+}
+
+/// the MIME parts of `msg` in depth-first order, parts of attached messages are included one
+/// level below the attachment
+pub fn mime_structure(msg: &Message) -> Vec<MimePart> {
+    let mut parts = Vec::new();
+    collect_parts(msg, 0, 0, &mut parts);
+
+    parts
+}
+
+fn collect_parts(msg: &Message, id: usize, depth: usize, out: &mut Vec<MimePart>) {
+    let Some(part) = msg.parts.get(id) else {
+        return;
+    };
+    out.push(MimePart::new(part, depth));
+
+    match &part.body {
+        PartType::Multipart(children) => {
+            for child in children {
+                collect_parts(msg, *child, depth + 1, out);
+            }
+        }
+        PartType::Message(nested) => collect_parts(nested, 0, depth + 1, out),
+        _ => {}
+    }
+}
+
+impl MimePart {
+    fn new(part: &MessagePart, depth: usize) -> Self {
+        let content_type = part
+            .content_type()
+            .map(|ct| match ct.subtype() {
+                Some(subtype) => format!("{}/{subtype}", ct.ctype()),
+                None => ct.ctype().to_owned(),
+            })
+            // RFC 2045 section 5.2
+            .unwrap_or(String::from("text/plain"))
+            .to_lowercase();
+
+        Self {
+            depth,
+            content_type,
+            size: part.offset_end.saturating_sub(part.offset_body),
+            encoding: part
+                .content_transfer_encoding()
+                .map(|encoding| encoding.to_lowercase()),
+            filename: part.attachment_name().map(|name| name.to_owned()),
+        }
+    }
+}
+
+impl Display for MimePart {
+    /// one line of the tree, e.g. `  application/pdf (1.2 KiB, base64, "invoice.pdf")`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let details = [Some(format_bytes(self.size)), self.encoding.clone()]
+            .into_iter()
+            .chain([self.filename.as_ref().map(|name| format!("{name:?}"))])
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        write!(
+            f,
+            "{indent}{content_type} ({details})",
+            indent = "  ".repeat(self.depth),
+            content_type = self.content_type
+        )
+    }
+}
+
+/// the parts as an indented tree, one part per line
+pub fn render_structure(parts: &[MimePart]) -> String {
+    parts
+        .iter()
+        .map(|part| part.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_multipart_tree() {
+        let raw = "From: a@b.c\r\n\
+            Subject: invoice\r\n\
+            Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+            \r\n\
+            --outer\r\n\
+            Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+            \r\n\
+            --inner\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            see attached\r\n\
+            --inner\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <p>see attached</p>\r\n\
+            --inner--\r\n\
+            --outer\r\n\
+            Content-Type: application/pdf\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            Content-Disposition: attachment; filename=\"invoice.pdf\"\r\n\
+            \r\n\
+            JVBERi0xLjQK\r\n\
+            --outer\r\n\
+            Content-Type: message/rfc822\r\n\
+            \r\n\
+            Subject: forwarded\r\n\
+            \r\n\
+            original\r\n\
+            --outer--\r\n";
+        let msg = Message::parse(raw.as_bytes()).unwrap();
+
+        let parts = mime_structure(&msg);
+        let rendered = render_structure(&parts);
+        let content_types = rendered
+            .lines()
+            .map(|line| line.split(" (").next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            content_types,
+            vec![
+                "multipart/mixed",
+                "  multipart/alternative",
+                "    text/plain",
+                "    text/html",
+                "  application/pdf",
+                "  message/rfc822",
+                "    text/plain",
+            ]
+        );
+
+        assert_eq!(
+            parts[4].to_string(),
+            "  application/pdf (12 B, base64, \"invoice.pdf\")"
+        );
+        assert_eq!(parts[2].encoding.as_deref(), Some("quoted-printable"));
+    }
+}
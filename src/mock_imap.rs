@@ -0,0 +1,656 @@
+//! a minimal in-process IMAP server used to exercise the mail fetching code in tests.
+//!
+//! the mock implements `Read + Write` so it can be handed to `imap::Client::new` in place of a
+//! real `TlsStream`. every command written to it is answered immediately with canned responses
+//! generated from the stored messages.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{Read, Write},
+    rc::Rc,
+};
+
+use imap::Session;
+use itertools::Itertools;
+
+#[derive(Debug, Clone)]
+pub struct MockMail {
+    raw: Vec<u8>,
+    thread_id: Option<u64>,
+    labels: Vec<String>,
+    flags: Vec<String>,
+    /// the `INTERNALDATE`, e.g. `03-Jul-2023 10:00:00 +0200`
+    internal_date: Option<String>,
+    body_missing: bool,
+}
+
+#[derive(Debug)]
+pub struct MockImapStream {
+    mailboxes: Vec<(String, Vec<MockMail>)>,
+    /// name attributes returned by `LIST` for each mailbox, e.g. `\\Sent`
+    mailbox_attributes: Vec<(String, Vec<String>)>,
+    capabilities: Vec<String>,
+    selected: Option<usize>,
+    pending_auth: Option<String>,
+    /// an `APPEND` waiting for its literal
+    pending_append: Option<PendingAppend>,
+    input: Vec<u8>,
+    output: VecDeque<u8>,
+    commands: Rc<RefCell<Vec<String>>>,
+    /// number of `FETCH` commands answered before the connection drops
+    fetches_until_drop: Option<usize>,
+    /// number of `SELECT` and `EXAMINE` commands still answered with `NO [UNAVAILABLE]`
+    unavailable_opens: usize,
+}
+
+/// the tag, target mailbox, flags, date and literal size of an `APPEND` command
+#[derive(Debug)]
+struct PendingAppend {
+    tag: String,
+    mailbox: usize,
+    flags: Vec<String>,
+    internal_date: Option<String>,
+    size: usize,
+}
+
+impl MockMail {
+    pub fn new(raw: &str) -> Self {
+        Self {
+            raw: raw.replace("\r\n", "\n").replace('\n', "\r\n").into_bytes(),
+            thread_id: None,
+            labels: Vec::new(),
+            flags: Vec::new(),
+            internal_date: None,
+            body_missing: false,
+        }
+    }
+
+    /// a mail with raw bytes that don't have to be valid UTF-8
+    pub fn from_bytes(raw: Vec<u8>) -> Self {
+        Self {
+            raw,
+            ..Self::new("")
+        }
+    }
+
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// the server leaves out the body when the full mail is fetched
+    pub fn without_body(mut self) -> Self {
+        self.body_missing = true;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: &[&str]) -> Self {
+        self.flags = flags.iter().map(|flag| flag.to_string()).collect();
+        self
+    }
+
+    pub fn with_internal_date(mut self, internal_date: &str) -> Self {
+        self.internal_date = Some(internal_date.to_owned());
+        self
+    }
+
+    pub fn with_labels(mut self, labels: &[&str]) -> Self {
+        self.labels = labels.iter().map(|label| label.to_string()).collect();
+        self
+    }
+
+    pub fn with_thread_id(mut self, thread_id: u64) -> Self {
+        self.thread_id = Some(thread_id);
+        self
+    }
+
+    /// returns the raw lines (including continuation lines) of all headers with a name in
+    /// `names`, terminated by an empty line like a real IMAP server does
+    fn header_fields(&self, names: &[String]) -> String {
+        let raw = String::from_utf8_lossy(&self.raw);
+        let header = raw.split("\r\n\r\n").next().unwrap_or_default();
+        let mut out = String::new();
+        let mut include = false;
+
+        for line in header.split("\r\n") {
+            if !line.starts_with([' ', '\t']) {
+                include = line
+                    .split_once(':')
+                    .map(|(name, _)| names.iter().any(|n| n.eq_ignore_ascii_case(name.trim())))
+                    .unwrap_or(false);
+            }
+
+            if include {
+                out.push_str(line);
+                out.push_str("\r\n");
+            }
+        }
+
+        out.push_str("\r\n");
+        out
+    }
+}
+
+impl MockImapStream {
+    pub fn new(mailboxes: Vec<(&str, Vec<MockMail>)>) -> Self {
+        Self {
+            mailboxes: mailboxes
+                .into_iter()
+                .map(|(name, mails)| (name.to_owned(), mails))
+                .collect(),
+            mailbox_attributes: Vec::new(),
+            capabilities: vec![String::from("IMAP4rev1")],
+            selected: None,
+            pending_auth: None,
+            pending_append: None,
+            input: Vec::new(),
+            output: VecDeque::from(b"* OK mock IMAP server ready\r\n".to_vec()),
+            commands: Rc::new(RefCell::new(Vec::new())),
+            fetches_until_drop: None,
+            unavailable_opens: 0,
+        }
+    }
+
+    pub fn with_capabilities(mut self, capabilities: &[&str]) -> Self {
+        self.capabilities = capabilities.iter().map(|cap| cap.to_string()).collect();
+        self
+    }
+
+    pub fn with_mailbox_attributes(mut self, mailbox: &str, attributes: &[&str]) -> Self {
+        self.mailbox_attributes.push((
+            mailbox.to_owned(),
+            attributes.iter().map(|attr| attr.to_string()).collect(),
+        ));
+        self
+    }
+
+    /// answers `fetches` `FETCH` commands, after that the connection is closed without a
+    /// response to the next one
+    pub fn with_drop_after_fetches(mut self, fetches: usize) -> Self {
+        self.fetches_until_drop = Some(fetches);
+        self
+    }
+
+    /// answers the first `opens` `SELECT` and `EXAMINE` commands like Gmail does while it is
+    /// unavailable for now
+    pub fn with_unavailable_opens(mut self, opens: usize) -> Self {
+        self.unavailable_opens = opens;
+        self
+    }
+
+    /// shared handle to the list of commands (without tags) received by the server
+    pub fn command_log(&self) -> Rc<RefCell<Vec<String>>> {
+        Rc::clone(&self.commands)
+    }
+
+    fn respond(&mut self, data: impl AsRef<[u8]>) {
+        self.output.extend(data.as_ref());
+    }
+
+    fn selected_mails(&self) -> &[MockMail] {
+        self.selected
+            .map(|idx| self.mailboxes[idx].1.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn fetch(&mut self, tag: &str, args: &str) {
+        match &mut self.fetches_until_drop {
+            // reading from a stream without output is like reading from a closed connection
+            Some(0) => return,
+            Some(remaining) => *remaining -= 1,
+            None => {}
+        }
+
+        let (set, query) = args.split_once(' ').unwrap_or((args, ""));
+        let response = parse_sequence_set(set, self.selected_mails().len())
+            .into_iter()
+            .flat_map(|seq| fetch_response(seq, &self.selected_mails()[seq as usize - 1], query))
+            .collect::<Vec<_>>();
+
+        self.respond(response);
+        self.respond(format!("{tag} OK done\r\n"));
+    }
+
+    fn list(&mut self, tag: &str, args: &str) {
+        // only the pattern is looked at, it matches a single mailbox or all with `*`
+        let pattern = args
+            .rsplit(' ')
+            .next()
+            .unwrap_or_default()
+            .trim_matches('"');
+        let response = self
+            .mailboxes
+            .iter()
+            .map(|(name, _)| name)
+            .filter(|name| pattern == "*" || name.eq_ignore_ascii_case(pattern))
+            .map(|name| {
+                let attributes = self
+                    .mailbox_attributes
+                    .iter()
+                    .find(|(mailbox, _)| mailbox == name)
+                    .map(|(_, attributes)| attributes.join(" "))
+                    .unwrap_or_default();
+                format!("* LIST ({attributes}) \"/\" \"{name}\"\r\n")
+            })
+            .collect::<String>();
+
+        self.respond(format!("{response}{tag} OK done\r\n"));
+    }
+
+    /// only `UNSEEN` is reported, whatever status items were asked for
+    fn status(&mut self, tag: &str, args: &str) {
+        let name = args.rsplit_once(" (").map_or(args, |(name, _)| name);
+        let name = name.trim_matches('"');
+        let Some((name, mails)) = self.mailboxes.iter().find(|(mailbox, _)| mailbox == name) else {
+            return self.respond(format!("{tag} NO no such mailbox\r\n"));
+        };
+
+        let unseen = mails
+            .iter()
+            .filter(|mail| !mail.flags.iter().any(|flag| flag == "\\Seen"))
+            .count();
+        self.respond(format!(
+            "* STATUS \"{name}\" (UNSEEN {unseen})\r\n{tag} OK done\r\n"
+        ));
+    }
+
+    /// moves the mails in the set to the mailbox given after it
+    /// moves the mails to the target mailbox, or copies them if `copy` is set
+    fn move_mails(&mut self, tag: &str, args: &str, copy: bool) {
+        let (set, target) = args.split_once(' ').unwrap_or((args, ""));
+        let target = target.trim_matches('"');
+        let (Some(idx), Some(target)) = (
+            self.selected,
+            self.mailboxes.iter().position(|(name, _)| name == target),
+        ) else {
+            return self.respond(format!("{tag} NO no such mailbox\r\n"));
+        };
+
+        let seqs = parse_sequence_set(set, self.mailboxes[idx].1.len());
+        let mut kept = Vec::new();
+        for (seq, mail) in (1..).zip(std::mem::take(&mut self.mailboxes[idx].1)) {
+            match (seqs.contains(&seq), copy) {
+                (true, true) => {
+                    self.mailboxes[target].1.push(mail.clone());
+                    kept.push(mail);
+                }
+                (true, false) => self.mailboxes[target].1.push(mail),
+                (false, _) => kept.push(mail),
+            }
+        }
+        self.mailboxes[idx].1 = kept;
+
+        self.respond(format!("{tag} OK done\r\n"));
+    }
+
+    fn search(&mut self, tag: &str, args: &str) {
+        // only `X-GM-RAW "label:..."` and `DELETED` are understood, everything else matches all
+        // mails
+        let label = args
+            .split_once("label:")
+            .map(|(_, label)| label.replace(['\\', '"'], ""));
+        let deleted = args.split_whitespace().any(|arg| arg == "DELETED");
+        let ids = (1..=self.selected_mails().len())
+            .filter(|id| match &label {
+                Some(label) => self.selected_mails()[id - 1].labels.contains(label),
+                None => true,
+            })
+            .filter(|id| {
+                !deleted
+                    || self.selected_mails()[id - 1]
+                        .flags
+                        .iter()
+                        .any(|flag| flag == "\\Deleted")
+            })
+            .map(|id| format!(" {id}"))
+            .collect::<String>();
+        self.respond(format!("* SEARCH{ids}\r\n{tag} OK done\r\n"));
+    }
+
+    /// removes the mails of the selected mailbox marked as deleted, only those in `uids` if given
+    fn expunge(&mut self, tag: &str, uids: Option<&str>) {
+        let Some(idx) = self.selected else {
+            return self.respond(format!("{tag} NO no mailbox selected\r\n"));
+        };
+        let mails = &mut self.mailboxes[idx].1;
+        let uids = uids.map(|uids| parse_sequence_set(uids, mails.len()));
+
+        let mut response = String::new();
+        let mut seq = 1;
+        let mut uid = 1;
+        mails.retain(|mail| {
+            let expunged = mail.flags.iter().any(|flag| flag == "\\Deleted")
+                && uids.as_ref().is_none_or(|uids| uids.contains(&uid));
+            uid += 1;
+            match expunged {
+                // later mails move up, so their sequence number stays the same
+                true => response.push_str(&format!("* {seq} EXPUNGE\r\n")),
+                false => seq += 1,
+            }
+            !expunged
+        });
+
+        self.respond(format!("{response}{tag} OK done\r\n"));
+    }
+
+    /// starts an `APPEND` of a literal to the mailbox given first, the mail is stored once the
+    /// literal was received
+    fn append(&mut self, tag: &str, args: &str) {
+        let (target, rest) = match args.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => args.split_once(' ').unwrap_or((args, "")),
+        };
+        let flags = rest
+            .split_once('(')
+            .and_then(|(_, flags)| flags.split_once(')'))
+            .map(|(flags, _)| {
+                flags
+                    .split_whitespace()
+                    .map(|flag| flag.to_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        // the date is the only quoted string after the mailbox
+        let internal_date = rest.split('"').nth(1).map(|date| date.to_owned());
+        let size = rest
+            .rsplit_once('{')
+            .and_then(|(_, size)| size.trim_end_matches('}').parse().ok());
+
+        let (Some(mailbox), Some(size)) = (
+            self.mailboxes.iter().position(|(name, _)| name == target),
+            size,
+        ) else {
+            return self.respond(format!("{tag} NO [TRYCREATE] no such mailbox\r\n"));
+        };
+        self.pending_append = Some(PendingAppend {
+            tag: tag.to_owned(),
+            mailbox,
+            flags,
+            internal_date,
+            size,
+        });
+        self.respond("+ ready for literal\r\n");
+    }
+
+    fn handle_line(&mut self, line: &str) {
+        if let Some(tag) = self.pending_auth.take() {
+            self.respond(format!("{tag} OK authenticated\r\n"));
+            return;
+        }
+
+        let (tag, command) = line.split_once(' ').unwrap_or((line, ""));
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        self.commands.borrow_mut().push(command.to_owned());
+
+        match name.to_ascii_uppercase().as_str() {
+            "LOGIN" | "NOOP" => self.respond(format!("{tag} OK done\r\n")),
+            "AUTHENTICATE" => {
+                self.pending_auth = Some(tag.to_owned());
+                self.respond("+ \r\n");
+            }
+            "CAPABILITY" => {
+                let capabilities = self.capabilities.join(" ");
+                self.respond(format!("* CAPABILITY {capabilities}\r\n{tag} OK done\r\n"));
+            }
+            "ID" => self.respond(format!(
+                "* ID (\"name\" \"mock\" \"version\" NIL)\r\n{tag} OK done\r\n"
+            )),
+            "LOGOUT" => self.respond(format!("* BYE logging out\r\n{tag} OK done\r\n")),
+            "SELECT" | "EXAMINE" if self.unavailable_opens > 0 => {
+                self.unavailable_opens -= 1;
+                self.respond(format!("{tag} NO [UNAVAILABLE] Please try again later\r\n"));
+            }
+            "SELECT" | "EXAMINE" => {
+                let name = args.trim_matches('"');
+                match self
+                    .mailboxes
+                    .iter()
+                    .position(|(mailbox, _)| mailbox.eq_ignore_ascii_case(name))
+                {
+                    Some(idx) => {
+                        self.selected = Some(idx);
+                        let exists = self.mailboxes[idx].1.len();
+                        self.respond(format!(
+                            "* {exists} EXISTS\r\n* OK [UIDVALIDITY 1] UIDs valid\r\n\
+                            {tag} OK done\r\n"
+                        ));
+                    }
+                    None => self.respond(format!("{tag} NO no such mailbox\r\n")),
+                }
+            }
+            "APPEND" => self.append(tag, args),
+            "LIST" => self.list(tag, args),
+            "STATUS" => self.status(tag, args),
+            "SEARCH" => self.search(tag, args),
+            "FETCH" => self.fetch(tag, args),
+            "EXPUNGE" => self.expunge(tag, None),
+            // uids are the same as sequence numbers, only silent stores are understood
+            "UID" => match args.split_once(' ') {
+                Some((sub, mv)) if sub.eq_ignore_ascii_case("MOVE") => {
+                    self.move_mails(tag, mv, false)
+                }
+                Some((sub, copy)) if sub.eq_ignore_ascii_case("COPY") => {
+                    self.move_mails(tag, copy, true)
+                }
+                Some((sub, search)) if sub.eq_ignore_ascii_case("SEARCH") => {
+                    self.search(tag, search)
+                }
+                Some((sub, uids)) if sub.eq_ignore_ascii_case("EXPUNGE") => {
+                    self.expunge(tag, Some(uids))
+                }
+                // real servers always include the UID in responses to UID FETCH
+                Some((sub, fetch)) if sub.eq_ignore_ascii_case("FETCH") => {
+                    self.fetch(tag, &format!("{fetch} UID"))
+                }
+                Some((sub, store)) if sub.eq_ignore_ascii_case("STORE") => {
+                    let (set, query) = store.split_once(' ').unwrap_or((store, ""));
+                    let flags = query
+                        .split_once('(')
+                        .map(|(_, flags)| flags.trim_end_matches(')'))
+                        .unwrap_or_default()
+                        .split(' ')
+                        .filter(|flag| !flag.is_empty())
+                        .map(|flag| flag.to_owned())
+                        .collect::<Vec<_>>();
+
+                    let Some(idx) = self.selected else {
+                        return self.respond(format!("{tag} NO no mailbox selected\r\n"));
+                    };
+                    let mails = &mut self.mailboxes[idx].1;
+                    for seq in parse_sequence_set(set, mails.len()) {
+                        let mail = &mut mails[seq as usize - 1];
+                        mail.flags.retain(|flag| !flags.contains(flag));
+                        if query.starts_with('+') {
+                            mail.flags.extend(flags.iter().cloned());
+                        }
+                    }
+
+                    self.respond(format!("{tag} OK done\r\n"));
+                }
+                _ => self.respond(format!("{tag} BAD unknown command\r\n")),
+            },
+            _ => self.respond(format!("{tag} BAD unknown command\r\n")),
+        }
+    }
+}
+
+impl Read for MockImapStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.output.len());
+        for (dst, src) in buf.iter_mut().zip(self.output.drain(..n)) {
+            *dst = src;
+        }
+
+        Ok(n)
+    }
+}
+
+impl Write for MockImapStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.input.extend_from_slice(buf);
+
+        // the literal of an `APPEND` is followed by the CRLF ending the command
+        if let Some(append) = self
+            .pending_append
+            .take_if(|append| self.input.len() >= append.size + 2)
+        {
+            let raw = self
+                .input
+                .drain(..append.size + 2)
+                .take(append.size)
+                .collect();
+            self.mailboxes[append.mailbox].1.push(MockMail {
+                flags: append.flags,
+                internal_date: append.internal_date,
+                ..MockMail::from_bytes(raw)
+            });
+            self.respond(format!("{} OK appended\r\n", append.tag));
+        }
+        if self.pending_append.is_some() {
+            return Ok(buf.len());
+        }
+
+        while let Some(pos) = self.input.windows(2).position(|w| w == b"\r\n") {
+            let line = String::from_utf8_lossy(&self.input[..pos]).into_owned();
+            self.input.drain(..pos + 2);
+            self.handle_line(&line);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// parses an IMAP sequence set like `1,3:5,*` into an ascending list of sequence numbers
+fn parse_sequence_set(set: &str, exists: usize) -> Vec<u32> {
+    let resolve = |str: &str| match str {
+        "*" => exists as u32,
+        _ => str.parse().unwrap_or(0),
+    };
+
+    set.split(',')
+        .filter(|part| !part.is_empty())
+        .flat_map(|part| match part.split_once(':') {
+            Some((start, end)) => {
+                let (start, end) = (resolve(start), resolve(end));
+                start.min(end)..=start.max(end)
+            }
+            None => resolve(part)..=resolve(part),
+        })
+        .filter(|seq| *seq >= 1 && *seq as usize <= exists)
+        .sorted()
+        .dedup()
+        .collect()
+}
+
+fn fetch_response(seq: u32, mail: &MockMail, query: &str) -> Vec<u8> {
+    let query = query.to_ascii_uppercase();
+    let mut items = Vec::new();
+
+    if query.contains("UID") {
+        items.push(format!("UID {seq}"));
+    }
+
+    if query.contains("FLAGS") {
+        items.push(format!("FLAGS ({})", mail.flags.join(" ")));
+    }
+
+    if let (true, Some(date)) = (query.contains("INTERNALDATE"), &mail.internal_date) {
+        items.push(format!("INTERNALDATE \"{date}\""));
+    }
+
+    if let (true, Some(thread_id)) = (query.contains("X-GM-THRID"), mail.thread_id) {
+        items.push(format!("X-GM-THRID {thread_id}"));
+    }
+
+    if query.contains("X-GM-LABELS") {
+        let labels = mail
+            .labels
+            .iter()
+            .map(|label| format!("\"{label}\""))
+            .join(" ");
+        items.push(format!("X-GM-LABELS ({labels})"));
+    }
+
+    if let Some(start) = query.find("HEADER.FIELDS (") {
+        let start = start + "HEADER.FIELDS (".len();
+        let end = start + query[start..].find(')').unwrap_or_default();
+        let names = query[start..end]
+            .split([' ', ','])
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_owned())
+            .collect::<Vec<_>>();
+        let data = mail.header_fields(&names);
+
+        items.push(format!(
+            "BODY[HEADER.FIELDS ({names})] {{{len}}}\r\n{data}",
+            names = names.join(" "),
+            len = data.len()
+        ));
+    }
+
+    let mut response = format!("* {seq} FETCH ({items}", items = items.join(" ")).into_bytes();
+    if (query.contains("BODY[]") || query.contains("BODY.PEEK[]")) && !mail.body_missing {
+        if !items.is_empty() {
+            response.push(b' ');
+        }
+        response.extend(format!("BODY[] {{{len}}}\r\n", len = mail.raw.len()).into_bytes());
+        response.extend(&mail.raw);
+    }
+    if query.contains("BODY.PEEK[HEADER]") || query.contains("BODY[HEADER]") {
+        let raw = String::from_utf8_lossy(&mail.raw);
+        let header = raw.split("\r\n\r\n").next().unwrap_or_default();
+        if !items.is_empty() {
+            response.push(b' ');
+        }
+        response.extend(
+            format!(
+                "BODY[HEADER] {{{len}}}\r\n{header}\r\n\r\n",
+                len = header.len() + 4
+            )
+            .into_bytes(),
+        );
+    }
+    response.extend(b")\r\n");
+
+    response
+}
+
+/// creates a client connected to a [`MockImapStream`] serving the given mailboxes, with the
+/// greeting already read.
+///
+/// returns the client together with the log of commands the server received
+pub fn mock_client(
+    mailboxes: Vec<(&str, Vec<MockMail>)>,
+) -> (imap::Client<MockImapStream>, Rc<RefCell<Vec<String>>>) {
+    let stream = MockImapStream::new(mailboxes);
+    let commands = stream.command_log();
+
+    (connect(stream), commands)
+}
+
+/// creates a client on top of any stream wrapping a [`MockImapStream`] and reads the greeting
+pub fn connect<S: Read + Write>(stream: S) -> imap::Client<S> {
+    let mut client = imap::Client::new(stream);
+    client.read_greeting().expect("mock greeting");
+
+    client
+}
+
+/// creates a logged in session on top of a [`MockImapStream`] serving the given mailboxes.
+///
+/// returns the session together with the log of commands the server received
+pub fn mock_session(
+    mailboxes: Vec<(&str, Vec<MockMail>)>,
+) -> (Session<MockImapStream>, Rc<RefCell<Vec<String>>>) {
+    let (client, commands) = mock_client(mailboxes);
+    let session = client
+        .login("user", "password")
+        .map_err(|(err, _)| err)
+        .expect("mock login");
+
+    (session, commands)
+}
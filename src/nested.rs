@@ -0,0 +1,317 @@
+//! messages attached to mails as `message/rfc822` parts, e.g. forwarded mails, bounces with the
+//! undeliverable original and spam reports
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use mail_parser::{Encoding, Message, PartType};
+
+use crate::{
+    mail::{addresses, html_to_text},
+    utils::write_atomically,
+};
+
+/// a message attached to a mail or to another attached message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NestedMessage {
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    pub date: Option<String>,
+    /// the plain text body, or the HTML one converted to text if there is none
+    pub body: String,
+    /// the messages attached to this one
+    pub attached: Vec<NestedMessage>,
+    /// the message exactly as attached
+    pub raw: Vec<u8>,
+}
+
+/// the messages attached to `msg`, with the ones attached to them in turn
+pub fn nested_messages(msg: &Message) -> Vec<NestedMessage> {
+    msg.parts
+        .iter()
+        .filter_map(|part| match &part.body {
+            PartType::Message(nested) => {
+                // unless the attached message had to be decoded, `mail_parser` parses it in place
+                // and it shares the raw bytes of the whole mail
+                let raw = match part.encoding {
+                    Encoding::None => msg.raw_message.get(part.offset_body..part.offset_end)?,
+                    _ => &nested.raw_message,
+                };
+                Some(NestedMessage::new(nested, raw))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+impl NestedMessage {
+    fn new(msg: &Message, raw: &[u8]) -> Self {
+        let header = |name: &str| {
+            msg.header_raw(name)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| value.to_owned())
+        };
+        // decoded like the headers of mails, the raw header is only shown if it can't be parsed
+        let from = addresses(msg.from())
+            .filter_map(
+                |addr| match (addr.name.as_deref(), addr.address.as_deref()) {
+                    (Some(name), Some(address)) => Some(format!("{name} <{address}>")),
+                    (name, address) => name.or(address).map(|value| value.to_owned()),
+                },
+            )
+            .collect::<Vec<_>>();
+        let from = match from.is_empty() {
+            true => header("from"),
+            false => Some(from.join(", ")),
+        };
+        // as for mails, HTML only messages list their HTML parts as text bodies as well
+        let plain_body = msg
+            .text_bodies()
+            .filter(|part| !part.is_text_html())
+            .filter_map(|part| part.text_contents())
+            .collect::<String>();
+        let body = match plain_body.is_empty() {
+            true => html_to_text(
+                &msg.html_bodies()
+                    .filter_map(|part| part.text_contents())
+                    .collect::<String>(),
+            ),
+            false => plain_body,
+        };
+
+        Self {
+            from,
+            subject: msg.subject().map(|subject| subject.trim().to_owned()),
+            date: msg.date().map(|date| date.to_rfc822()).or(header("date")),
+            body: body.trim().to_owned(),
+            attached: nested_messages(msg),
+            raw: raw.to_vec(),
+        }
+    }
+
+    /// the message as a block with a bar along its left side, messages attached to it are
+    /// nested blocks after its body
+    pub fn render(&self) -> String {
+        let mut lines = vec![String::from("┌ attached message")];
+        let mut push = |line: &str| match line.is_empty() {
+            true => lines.push(String::from("│")),
+            false => lines.push(format!("│ {line}")),
+        };
+
+        push(&format!(
+            "From:       {}",
+            self.from.as_deref().unwrap_or("-")
+        ));
+        if let Some(date) = &self.date {
+            push(&format!("Date:       {date}"));
+        }
+        push(&format!(
+            "Subject:    {}",
+            self.subject.as_deref().unwrap_or("-")
+        ));
+        if !self.body.is_empty() {
+            push("");
+            self.body.lines().for_each(&mut push);
+        }
+        for attached in &self.attached {
+            push("");
+            attached.render().lines().for_each(&mut push);
+        }
+        lines.push(String::from("└"));
+
+        lines.join("\n")
+    }
+}
+
+/// the blocks of `messages` (see [`NestedMessage::render`]) separated by empty lines
+pub fn render_nested(messages: &[NestedMessage]) -> String {
+    messages
+        .iter()
+        .map(NestedMessage::render)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// writes `messages` and the messages attached to them to `dir` as `<stem>-<n>.eml` files, the
+/// n-th message attached to `<stem>-<n>` becomes `<stem>-<n>-<n>.eml`. returns the written paths.
+///
+/// fails instead of overwriting files that already exist, e.g. from an earlier extraction
+pub fn extract_nested(
+    messages: &[NestedMessage],
+    dir: &Path,
+    stem: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
+    if messages.is_empty() {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(dir)?;
+
+    let mut paths = Vec::new();
+    for (idx, message) in messages.iter().enumerate() {
+        let stem = format!("{stem}-{}", idx + 1);
+        let path = dir.join(format!("{stem}.eml"));
+        if path.exists() {
+            return Err(anyhow!("refusing to overwrite '{}'", path.display()));
+        }
+        write_atomically(&path, &message.raw)?;
+        paths.push(path);
+        paths.extend(extract_nested(&message.attached, dir, &stem)?);
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+
+    use super::*;
+
+    /// a bounce with the undeliverable mail attached, which itself forwards another mail
+    const BOUNCE: &str = "From: MAILER-DAEMON@example.com\r\n\
+        Subject: Undelivered Mail Returned to Sender\r\n\
+        Content-Type: multipart/report; report-type=delivery-status; boundary=\"outer\"\r\n\
+        \r\n\
+        --outer\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        your mail couldn't be delivered\r\n\
+        --outer\r\n\
+        Content-Type: message/rfc822\r\n\
+        \r\n\
+        From: Me <me@example.com>\r\n\
+        Date: Mon, 3 Jul 2023 10:00:00 +0000\r\n\
+        Subject: Fwd: plans\r\n\
+        Content-Type: multipart/mixed; boundary=\"inner\"\r\n\
+        \r\n\
+        --inner\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        see below\r\n\
+        --inner\r\n\
+        Content-Type: message/rfc822\r\n\
+        \r\n\
+        From: bob@example.com\r\n\
+        Subject: plans\r\n\
+        Content-Type: text/html\r\n\
+        \r\n\
+        <p>see you there</p>\r\n\
+        --inner--\r\n\
+        --outer--\r\n";
+
+    #[test]
+    fn renders_nested_messages() {
+        let msg = Message::parse(BOUNCE.as_bytes()).unwrap();
+        let nested = nested_messages(&msg);
+        assert_eq!(nested.len(), 1);
+        assert_eq!(nested[0].subject.as_deref(), Some("Fwd: plans"));
+        assert_eq!(
+            nested[0].attached[0].from.as_deref(),
+            Some("bob@example.com")
+        );
+
+        assert_eq!(
+            render_nested(&nested),
+            "┌ attached message\n\
+            │ From:       Me <me@example.com>\n\
+            │ Date:       Mon, 3 Jul 2023 10:00:00 +0000\n\
+            │ Subject:    Fwd: plans\n\
+            │\n\
+            │ see below\n\
+            │\n\
+            │ ┌ attached message\n\
+            │ │ From:       bob@example.com\n\
+            │ │ Subject:    plans\n\
+            │ │\n\
+            │ │ see you there\n\
+            │ └\n\
+            └"
+        );
+    }
+
+    #[test]
+    fn extracts_nested_messages() {
+        let msg = Message::parse(BOUNCE.as_bytes()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        let paths = extract_nested(&nested_messages(&msg), dir.path(), "7").unwrap();
+        assert_eq!(
+            paths,
+            [dir.path().join("7-1.eml"), dir.path().join("7-1-1.eml")]
+        );
+        let forwarded = Message::parse(&fs::read(&paths[1]).unwrap())
+            .unwrap()
+            .subject()
+            .map(|subject| subject.to_owned());
+        assert_eq!(forwarded.as_deref(), Some("plans"));
+
+        assert!(fs::read_to_string(&paths[0])
+            .unwrap()
+            .starts_with("From: Me <me@example.com>\r\n"));
+
+        // a second extraction with the same stem doesn't replace the files
+        assert!(extract_nested(&nested_messages(&msg), dir.path(), "7").is_err());
+        assert_eq!(
+            extract_nested(&nested_messages(&msg), dir.path(), "8").unwrap(),
+            [dir.path().join("8-1.eml"), dir.path().join("8-1-1.eml")]
+        );
+
+        assert!(extract_nested(&[], &dir.path().join("none"), "8")
+            .unwrap()
+            .is_empty());
+        assert!(!dir.path().join("none").exists());
+    }
+
+    #[test]
+    fn decodes_nested_headers() {
+        let raw = "From: abuse@example.com\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: message/rfc822\r\n\
+            \r\n\
+            From: =?utf-8?q?J=C3=BCrgen?= <j@example.com>, bob@example.com\r\n\
+            Date: Mon, 3 Jul 2023 10:00:00 +0200 (CEST)\r\n\
+            Subject: report\r\n\
+            \r\n\
+            spam\r\n\
+            --b--\r\n";
+        let msg = Message::parse(raw.as_bytes()).unwrap();
+
+        let nested = nested_messages(&msg);
+        assert_eq!(
+            nested[0].from.as_deref(),
+            Some("Jürgen <j@example.com>, bob@example.com")
+        );
+        assert_eq!(
+            nested[0].date.as_deref(),
+            Some("Mon, 3 Jul 2023 10:00:00 +0200")
+        );
+    }
+
+    #[test]
+    fn extracts_encoded_messages_decoded() {
+        let original = "From: bob@example.com\r\nSubject: report\r\n\r\nspam\r\n";
+        let raw = format!(
+            "From: abuse@example.com\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: message/rfc822\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            {}\r\n\
+            --b--\r\n",
+            base64::engine::general_purpose::STANDARD.encode(original)
+        );
+        let msg = Message::parse(raw.as_bytes()).unwrap();
+
+        let nested = nested_messages(&msg);
+        assert_eq!(nested[0].subject.as_deref(), Some("report"));
+        assert_eq!(nested[0].raw, original.as_bytes());
+    }
+}
@@ -0,0 +1,56 @@
+//! proof key for code exchange (RFC 7636), binds an authorization code to the client that
+//! requested it so an intercepted code can't be exchanged for tokens by anyone else
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pkce {
+    /// the secret sent with the token request
+    pub verifier: String,
+}
+
+impl Pkce {
+    /// a new random verifier of 43 characters, the minimum length allowed. fails if the
+    /// operating system provides no randomness, a guessable verifier would be worthless
+    pub fn generate() -> anyhow::Result<Self> {
+        let mut bytes = [0; 32];
+        getrandom::getrandom(&mut bytes)
+            .map_err(|err| anyhow!("failed to generate a PKCE verifier: {err}"))?;
+
+        Ok(Self {
+            verifier: URL_SAFE_NO_PAD.encode(bytes),
+        })
+    }
+
+    /// the `S256` challenge sent with the authorization request
+    pub fn challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.verifier.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_s256_challenge() {
+        // the example from RFC 7636, appendix B
+        let pkce = Pkce {
+            verifier: String::from("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+        };
+        assert_eq!(
+            pkce.challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn generates_unique_verifiers() {
+        let (first, second) = (Pkce::generate().unwrap(), Pkce::generate().unwrap());
+
+        assert_eq!(first.verifier.len(), 43);
+        assert_ne!(first, second);
+    }
+}
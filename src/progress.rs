@@ -0,0 +1,140 @@
+use std::{
+    fmt::Display,
+    io::{stderr, stdout, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// a single line "label x/n" progress indicator drawn on stderr.
+///
+/// the counts are shared between its clones, so mailboxes fetched at the same time add up to
+/// one line instead of each drawing their own over it
+#[derive(Debug, Clone)]
+pub struct Progress {
+    label: &'static str,
+    done: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+}
+
+impl Progress {
+    /// returns `None` if `quiet` is set or stdout/stderr aren't terminals, so piped output stays
+    /// free of control characters
+    pub fn for_terminal(label: &'static str, quiet: bool) -> Option<Self> {
+        if quiet || !stdout().is_terminal() || !stderr().is_terminal() {
+            return None;
+        }
+
+        Some(Self::new(label))
+    }
+
+    fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            done: Arc::default(),
+            total: Arc::default(),
+        }
+    }
+
+    /// adds `total` to the number of items to be done, e.g. the mails found in one mailbox
+    pub fn add_total(&self, total: usize) {
+        self.total.fetch_add(total, Ordering::Relaxed);
+        self.draw();
+    }
+
+    pub fn inc(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        self.draw();
+    }
+
+    /// removes the indicator from the terminal once all items are done, clones still working on
+    /// theirs keep it
+    pub fn clear(&self) {
+        if self.done.load(Ordering::Relaxed) < self.total.load(Ordering::Relaxed) {
+            return;
+        }
+
+        eprint!("\r\x1b[2K");
+        let _ = stderr().flush();
+    }
+
+    fn draw(&self) {
+        eprint!("\r{}", self.line());
+        let _ = stderr().flush();
+    }
+
+    fn line(&self) -> String {
+        format!(
+            "{label} {done}/{total}",
+            label = self.label,
+            done = self.done.load(Ordering::Relaxed),
+            total = self.total.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// total number of bytes downloaded, shared between its clones
+#[derive(Debug, Clone, Default)]
+pub struct ByteCounter(Arc<AtomicUsize>);
+
+impl ByteCounter {
+    pub fn add(&self, bytes: usize) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Display for ByteCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format_bytes(self.total()))
+    }
+}
+
+/// `bytes` in the largest binary unit it is at least one of, e.g. "1.5 MiB"
+pub fn format_bytes(bytes: usize) -> String {
+    match ["KiB", "MiB", "GiB"]
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(idx, unit)| (1usize << (10 * (idx + 1)), unit))
+        .find(|(size, _)| bytes >= *size)
+    {
+        Some((size, unit)) => format!("{:.1} {unit}", bytes as f64 / size as f64),
+        None => format!("{bytes} B"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_bytes_across_clones() {
+        let counter = ByteCounter::default();
+        counter.add(512);
+        counter.clone().add(512);
+        assert_eq!(counter.total(), 1024);
+        assert_eq!(counter.to_string(), "1.0 KiB");
+
+        counter.add(3 << 20);
+        assert_eq!(counter.to_string(), "3.0 MiB");
+        assert_eq!(ByteCounter::default().to_string(), "0 B");
+    }
+
+    #[test]
+    fn counts_progress_across_clones() {
+        let progress = Progress::new("fetched");
+        let (inbox, lists) = (progress.clone(), progress.clone());
+        inbox.add_total(2);
+        lists.add_total(3);
+        inbox.inc();
+        lists.inc();
+        lists.inc();
+
+        assert_eq!(progress.line(), "fetched 3/5");
+    }
+}
@@ -0,0 +1,39 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// the mail provider an account belongs to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    Google,
+    Yahoo,
+}
+
+impl Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Provider::Google => write!(f, "Google"),
+            Provider::Yahoo => write!(f, "Yahoo"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RefreshedToken {
+    pub access_token: String,
+    /// seconds until the access token expires, if the provider says
+    pub expires_in: Option<i64>,
+}
+
+/// the provider specific parts of connecting to an OAuth authenticated IMAP server. the
+/// futures are only awaited by mail-cli's own login, which doesn't need them to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait OAuthProvider {
+    /// domain and port of the providers IMAP server
+    fn imap_host(&self) -> (&str, u16);
+
+    /// acquires a new access token using `refresh_token`
+    async fn refresh(&self, refresh_token: &str) -> anyhow::Result<RefreshedToken>;
+}
@@ -0,0 +1,350 @@
+//! replies and forwards composed from fetched mails, printed as RFC 5322 messages to be edited
+//! and sent with a mail client or `sendmail -t`
+
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use itertools::Itertools;
+use mail_parser::{Addr, HeaderValue, Message};
+
+use crate::{
+    headers::{check_single_line, encode_mailbox, encode_text, encode_typed_mailbox, single_line},
+    mail::{addresses, Mail},
+};
+
+/// the recipients of a reply
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recipients {
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+}
+
+/// the recipients of a reply to everyone involved in `msg`: the sender (or `Reply-To`) and the
+/// original `To` in `To` and the original `Cc` in `Cc`.
+///
+/// `own` (the address of the account replying) and addresses already listed are left out,
+/// compared case-insensitively
+pub fn reply_all_recipients(msg: &Message, own: &str) -> Recipients {
+    let senders = match msg.reply_to() {
+        HeaderValue::Empty => msg.from(),
+        reply_to => reply_to,
+    };
+
+    let mut seen = HashSet::from([own.to_lowercase()]);
+    let mut take = |value: &HeaderValue| {
+        addresses(value)
+            .filter_map(|addr| {
+                let address = addr.address.as_deref()?;
+                seen.insert(address.to_lowercase())
+                    .then(|| encode_mailbox(addr.name.as_deref(), address))
+            })
+            .collect_vec()
+    };
+
+    let mut to = take(senders);
+    to.extend(take(msg.to()));
+    let cc = take(msg.cc());
+
+    Recipients { to, cc }
+}
+
+/// a reply to everyone involved in `mail` sent from `own`, with the threading headers set and
+/// the original body quoted
+pub fn compose_reply_all(mail: &Mail, own: &str) -> anyhow::Result<String> {
+    let msg = Message::parse(mail.raw()).ok_or(anyhow!("failed to parse the mail"))?;
+    let Recipients { to, cc } = reply_all_recipients(&msg, own);
+    if to.is_empty() && cc.is_empty() {
+        return Err(anyhow!("the mail has no recipients other than {own}"));
+    }
+
+    let mut headers = vec![(String::from("From"), own.to_owned())];
+    headers.push((String::from("To"), to.join(", ")));
+    if !cc.is_empty() {
+        headers.push((String::from("Cc"), cc.join(", ")));
+    }
+    headers.push((
+        String::from("Subject"),
+        encode_text(&reply_subject(mail.subject())),
+    ));
+    if let Some(message_id) = mail.message_id() {
+        let references = mail
+            .references()
+            .iter()
+            .map(|id| id.as_str())
+            .chain([message_id])
+            .map(|id| format!("<{id}>"))
+            .join(" ");
+        headers.push((String::from("In-Reply-To"), format!("<{message_id}>")));
+        headers.push((String::from("References"), references));
+    }
+    headers.push((String::from("MIME-Version"), String::from("1.0")));
+    headers.push((
+        String::from("Content-Type"),
+        String::from("text/plain; charset=utf-8"),
+    ));
+    headers.push((
+        String::from("Content-Transfer-Encoding"),
+        String::from("8bit"),
+    ));
+
+    let attribution = match (mail.date(), addresses(msg.from()).next()) {
+        (Some(date), Some(sender)) => format!(
+            "On {}, {} wrote:",
+            date.format("%a, %d %b %Y %H:%M"),
+            sender_name(sender)
+        ),
+        (None, Some(sender)) => format!("{} wrote:", sender_name(sender)),
+        (_, None) => String::from("The original message:"),
+    };
+
+    let mut reply = headers
+        .into_iter()
+        .map(|(name, value)| format!("{name}: {}\n", single_line(&value)))
+        .collect::<String>();
+    reply.push('\n');
+    reply.push_str(&format!("\n\n{attribution}\n"));
+    for line in mail.text().lines() {
+        match line.is_empty() {
+            true => reply.push_str(">\n"),
+            false => reply.push_str(&format!("> {line}\n")),
+        }
+    }
+
+    Ok(reply)
+}
+
+/// headers of the original mail listed in the forwarded message block
+const FORWARDED_HEADERS: [&str; 5] = ["From", "Date", "Subject", "To", "Cc"];
+
+/// `mail` forwarded from `own` to `to`: the original headers and body quoted in a text part and
+/// the original attached unchanged as `message/rfc822`, which keeps its attachments.
+///
+/// fails if one of `to` contains line breaks
+pub fn compose_forward(mail: &Mail, own: &str, to: &[String]) -> anyhow::Result<Vec<u8>> {
+    for recipient in to {
+        check_single_line("recipient", recipient)?;
+    }
+
+    let raw = mail.raw();
+    let boundary = (0..)
+        .map(|idx| format!("mail-cli-forward-{idx}"))
+        .find(|boundary| !contains(raw, boundary.as_bytes()))
+        .unwrap_or_default();
+
+    let mut forward = format!(
+        "From: {own}\n\
+        To: {to}\n\
+        Subject: {subject}\n\
+        MIME-Version: 1.0\n\
+        Content-Type: multipart/mixed; boundary=\"{boundary}\"\n\n\
+        --{boundary}\n\
+        Content-Type: text/plain; charset=utf-8\n\
+        Content-Transfer-Encoding: 8bit\n\n\n\n\
+        ---------- Forwarded message ----------\n",
+        to = to.iter().map(|to| encode_typed_mailbox(to)).join(", "),
+        subject = encode_text(&prefixed_subject("Fwd:", mail.subject())),
+    );
+    for name in FORWARDED_HEADERS {
+        for value in mail.header(name) {
+            forward.push_str(&format!("{name}: {}\n", single_line(value)));
+        }
+    }
+    forward.push_str(&format!("\n{}\n", mail.text().trim_end()));
+    forward.push_str(&format!(
+        "\n--{boundary}\n\
+        Content-Type: message/rfc822\n\
+        Content-Disposition: attachment\n\
+        Content-Transfer-Encoding: 8bit\n\n"
+    ));
+
+    let mut forward = forward.into_bytes();
+    forward.extend(raw.iter().filter(|byte| **byte != b'\r'));
+    if !forward.ends_with(b"\n") {
+        forward.push(b'\n');
+    }
+    forward.extend(format!("--{boundary}--\n").into_bytes());
+
+    Ok(forward)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// `subject` with a single `Re: ` in front
+fn reply_subject(subject: Option<&str>) -> String {
+    prefixed_subject("Re:", subject)
+}
+
+/// `subject` with `prefix` (e.g. `Re:`) in front unless it already starts with it
+fn prefixed_subject(prefix: &str, subject: Option<&str>) -> String {
+    let subject = subject.unwrap_or_default().trim();
+    match subject.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => subject.to_owned(),
+        _ => format!("{prefix} {subject}"),
+    }
+}
+
+fn sender_name(addr: &Addr) -> String {
+    addr.name
+        .as_deref()
+        .or(addr.address.as_deref())
+        .unwrap_or("unknown")
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mail_parser::MimeHeaders;
+
+    use crate::{
+        mail::MailBox,
+        mock_imap::{mock_session, MockMail},
+    };
+
+    #[test]
+    fn replies_to_all_but_own_address() {
+        let raw = "From: Alice <alice@example.com>\n\
+            To: me@example.com, \"Bob\" <bob@example.com>\n\
+            Cc: carol@example.com, ME@example.com, Alice <ALICE@example.com>\n\
+            Subject: plans\n\
+            Message-ID: <second@x>\n\
+            References: <first@x>\n\
+            Date: Mon, 3 Jul 2023 10:00:00 +0000\n\n\
+            see you\n\nthere\n";
+        let msg = Message::parse(raw.as_bytes()).unwrap();
+
+        assert_eq!(
+            reply_all_recipients(&msg, "me@example.com"),
+            Recipients {
+                to: vec![
+                    String::from("\"Alice\" <alice@example.com>"),
+                    String::from("\"Bob\" <bob@example.com>"),
+                ],
+                cc: vec![String::from("carol@example.com")],
+            }
+        );
+
+        let with_reply_to = format!("Reply-To: list@example.com\n{raw}");
+        let msg = Message::parse(with_reply_to.as_bytes()).unwrap();
+        assert_eq!(
+            reply_all_recipients(&msg, "me@example.com").to[0],
+            "list@example.com"
+        );
+
+        let (mut session, _) = mock_session(vec![("INBOX", vec![MockMail::new(raw)])]);
+        let mail = MailBox::new("INBOX")
+            .fetch_mail_by_uid(1, &mut session)
+            .unwrap();
+        let reply = compose_reply_all(&mail, "me@example.com").unwrap();
+        assert!(reply.starts_with("From: me@example.com\n"));
+        assert!(reply.contains("\nSubject: Re: plans\n"));
+        assert!(reply.contains("\nIn-Reply-To: <second@x>\n"));
+        assert!(reply.contains("\nReferences: <first@x> <second@x>\n"));
+        assert!(reply.ends_with("On Mon, 03 Jul 2023 10:00, Alice wrote:\n> see you\n>\n> there\n"));
+
+        assert_eq!(reply_subject(Some("RE: plans")), "RE: plans");
+    }
+
+    #[test]
+    fn keeps_decoded_headers_on_one_line() {
+        let raw = "From: =?utf-8?q?Eve=0ABcc:_x@evil.com?= <eve@example.com>\n\
+            To: me@example.com, =?utf-8?q?J=C3=BCrgen?= <j@example.com>\n\
+            Subject: =?utf-8?q?Gr=C3=BC=C3=9Fe=0D=0ABcc:_y@evil.com?=\n\n\
+            hi\n";
+        let (mut session, _) = mock_session(vec![("INBOX", vec![MockMail::new(raw)])]);
+        let mail = MailBox::new("INBOX")
+            .fetch_mail_by_uid(1, &mut session)
+            .unwrap();
+
+        let reply = compose_reply_all(&mail, "me@example.com").unwrap();
+        let (headers, _) = reply.split_once("\n\n").unwrap();
+        assert!(!headers.contains("\nBcc:"));
+        assert!(headers.is_ascii());
+        assert!(headers.contains(
+            "\nTo: \"Eve Bcc: x@evil.com\" <eve@example.com>, =?utf-8?B?SsO8cmdlbg==?= <j@example.com>\n"
+        ));
+
+        let parsed = Message::parse(reply.as_bytes()).unwrap();
+        assert_eq!(parsed.subject(), Some("Re: Grüße  Bcc: y@evil.com"));
+        assert!(parsed.bcc().is_empty());
+    }
+
+    #[test]
+    fn forwards_with_original_attached() {
+        let raw = "From: Alice <alice@example.com>\n\
+            To: me@example.com\n\
+            Subject: report\n\
+            Date: Mon, 3 Jul 2023 10:00:00 +0000\n\
+            Content-Type: multipart/mixed; boundary=\"mail-cli-forward-0\"\n\n\
+            --mail-cli-forward-0\n\
+            Content-Type: text/plain\n\n\
+            attached\n\
+            --mail-cli-forward-0\n\
+            Content-Type: application/pdf\n\
+            Content-Disposition: attachment; filename=\"report.pdf\"\n\
+            Content-Transfer-Encoding: base64\n\n\
+            JVBERi0=\n\
+            --mail-cli-forward-0--\n";
+        let (mut session, _) = mock_session(vec![("INBOX", vec![MockMail::new(raw)])]);
+        let mail = MailBox::new("INBOX")
+            .fetch_mail_by_uid(1, &mut session)
+            .unwrap();
+
+        let to = [
+            String::from("bob@example.com"),
+            String::from("carol@example.com"),
+        ];
+        let forward =
+            String::from_utf8(compose_forward(&mail, "me@example.com", &to).unwrap()).unwrap();
+        assert!(forward.starts_with(
+            "From: me@example.com\nTo: bob@example.com, carol@example.com\nSubject: Fwd: report\n"
+        ));
+        assert!(forward.contains(
+            "---------- Forwarded message ----------\n\
+            From: Alice <alice@example.com>\n\
+            Date: Mon, 3 Jul 2023 10:00:00 +0000\n\
+            Subject: report\n\
+            To: me@example.com\n\nattached\n"
+        ));
+        // the boundary of the original isn't reused
+        assert!(forward.contains("boundary=\"mail-cli-forward-1\""));
+        assert!(forward.ends_with(&format!("{raw}--mail-cli-forward-1--\n")));
+
+        let parsed = Message::parse(forward.as_bytes()).unwrap();
+        let attached = parsed.attachment(0).unwrap().message().unwrap();
+        assert_eq!(attached.subject(), Some("report"));
+        assert_eq!(
+            attached.attachment(0).unwrap().attachment_name(),
+            Some("report.pdf")
+        );
+    }
+
+    #[test]
+    fn keeps_forward_headers_on_one_line() {
+        let raw = "From: Alice <alice@example.com>\n\
+            Subject: =?utf-8?q?Gr=C3=BC=C3=9Fe=0ABcc:_x@evil.com?=\n\n\
+            hi\n";
+        let (mut session, _) = mock_session(vec![("INBOX", vec![MockMail::new(raw)])]);
+        let mail = MailBox::new("INBOX")
+            .fetch_mail_by_uid(1, &mut session)
+            .unwrap();
+
+        let to = [String::from("Jürgen <j@example.com>")];
+        let forward =
+            String::from_utf8(compose_forward(&mail, "me@example.com", &to).unwrap()).unwrap();
+        let (headers, _) = forward.split_once("\n\n").unwrap();
+        assert!(headers.is_ascii());
+        assert!(headers.contains("\nTo: =?utf-8?B?SsO8cmdlbg==?= <j@example.com>\n"));
+
+        let parsed = Message::parse(forward.as_bytes()).unwrap();
+        assert_eq!(parsed.subject(), Some("Fwd: Grüße Bcc: x@evil.com"));
+        assert!(parsed.bcc().is_empty());
+
+        let to = [String::from("bob@example.com\nBcc: x@evil.com")];
+        assert!(compose_forward(&mail, "me@example.com", &to).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! what the IMAP server tells about itself
+
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use imap::Session;
+use imap_proto::types::Capability;
+use itertools::Itertools;
+
+use crate::{gmail::GmailAttributeStore, provider::Provider};
+
+/// who a session is logged in as and what it is connected to, see [`server_id`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// the user name the session authenticated with
+    pub username: String,
+    /// if the provider reported `username` as the owner of the access token, otherwise it is
+    /// only the name the account is stored under
+    pub username_confirmed: bool,
+    pub provider: Provider,
+    /// how the session authenticated, e.g. "OAuth 2.0"
+    pub auth: String,
+    /// `host:port` of the IMAP server
+    pub server: String,
+    /// the fields the server reported about itself in response to `ID`, empty if it doesn't
+    /// support the extension
+    pub server_id: Vec<(String, String)>,
+    pub capabilities: Vec<String>,
+}
+
+/// the capabilities announced by the server, sorted and without duplicates
+pub fn capabilities<T: Read + Write>(session: &mut Session<T>) -> anyhow::Result<Vec<String>> {
+    Ok(session
+        .capabilities()?
+        .iter()
+        .map(|capability| match capability {
+            Capability::Imap4rev1 => String::from("IMAP4rev1"),
+            Capability::Auth(mechanism) => format!("AUTH={mechanism}"),
+            Capability::Atom(atom) => atom.to_string(),
+        })
+        .sorted_by_key(|capability| capability.to_ascii_uppercase())
+        .dedup_by(|a, b| a.eq_ignore_ascii_case(b))
+        .collect())
+}
+
+/// the fields of the RFC 2971 `ID` the server reports to the authenticated `session`, empty if
+/// it doesn't support the extension. the client identifies itself as mail-cli in return.
+///
+/// `imap` can't parse `ID` responses, so `session` has to run on top of a [`crate::gmail::GmailExtStream`]
+/// that takes them out of the stream and records them in `store`
+pub fn server_id<T: Read + Write>(
+    session: &mut Session<T>,
+    store: &GmailAttributeStore,
+) -> anyhow::Result<Vec<(String, String)>> {
+    session.run_command_and_read_response(format!(
+        "ID (\"name\" \"mail-cli\" \"version\" \"{}\")",
+        env!("CARGO_PKG_VERSION")
+    ))?;
+
+    Ok(store
+        .take_id_response()
+        .map(|response| parse_id(&response))
+        .unwrap_or_default())
+}
+
+/// the field-value pairs of the untagged `ID` response in `response`, fields with a `NIL` value
+/// are left out
+fn parse_id(response: &str) -> Vec<(String, String)> {
+    let Some(list) = response
+        .lines()
+        .find_map(|line| line.strip_prefix("* ID "))
+        .and_then(|line| line.trim().strip_prefix('('))
+    else {
+        return Vec::new();
+    };
+
+    // quoted strings and `NIL`s, in order
+    let mut values = Vec::new();
+    let mut chars = list.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '"' => {
+                let mut value = String::new();
+                while let Some(char) = chars.next() {
+                    match char {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        char => value.push(char),
+                    }
+                }
+                values.push(Some(value));
+            }
+            'N' | 'n' => {
+                chars.nth(1);
+                values.push(None);
+            }
+            ')' => break,
+            _ => {}
+        }
+    }
+
+    values
+        .into_iter()
+        .tuples()
+        .filter_map(|(field, value)| Some((field?, value?)))
+        .collect()
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let server_id = match self.server_id.is_empty() {
+            true => String::from("not reported"),
+            false => self
+                .server_id
+                .iter()
+                .map(|(field, value)| format!("{field}={value}"))
+                .join(", "),
+        };
+
+        let unconfirmed = match self.username_confirmed {
+            true => "",
+            false => " (as stored, not confirmed by the provider)",
+        };
+
+        write!(
+            f,
+            "user:         {user}{unconfirmed}\n\
+            provider:     {provider}\n\
+            auth:         {auth}\n\
+            server:       {server}\n\
+            server id:    {server_id}\n\
+            capabilities: {capabilities}",
+            user = self.username,
+            provider = self.provider,
+            auth = self.auth,
+            server = self.server,
+            capabilities = self.capabilities.join(" "),
+        )
+    }
+}
+
+/// one capability per line, ready to be printed
+pub fn format_capabilities(capabilities: &[String]) -> String {
+    capabilities
+        .iter()
+        .map(|capability| format!("- {capability}"))
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gmail::GmailExtStream,
+        mock_imap::{connect, MockImapStream},
+    };
+
+    #[test]
+    fn parses_id_response() {
+        assert_eq!(
+            parse_id(
+                "* ID (\"name\" \"GImap\" \"vendor\" \"Google, Inc.\" \"support-url\" NIL \
+                \"remote-host\" \"1.2.3.4\" \"os\" \"say \\\"hi\\\"\")\r\n\
+                a1 OK Success\r\n"
+            ),
+            [
+                (String::from("name"), String::from("GImap")),
+                (String::from("vendor"), String::from("Google, Inc.")),
+                (String::from("remote-host"), String::from("1.2.3.4")),
+                (String::from("os"), String::from("say \"hi\"")),
+            ]
+        );
+        assert!(parse_id("* ID NIL\r\na1 OK done\r\n").is_empty());
+        assert!(parse_id("a1 BAD unknown command\r\n").is_empty());
+
+        let store = GmailAttributeStore::default();
+        let mut session = connect(GmailExtStream::new(
+            MockImapStream::new(Vec::new()),
+            store.clone(),
+        ))
+        .login("user", "password")
+        .unwrap();
+        assert_eq!(
+            server_id(&mut session, &store).unwrap(),
+            [(String::from("name"), String::from("mock"))]
+        );
+        // the session can still be used afterwards
+        assert!(capabilities(&mut session).is_ok());
+    }
+
+    #[test]
+    fn lists_capabilities_sorted() {
+        let stream = MockImapStream::new(Vec::new()).with_capabilities(&[
+            "IMAP4rev1",
+            "UNSELECT",
+            "IDLE",
+            "MOVE",
+            "X-GM-EXT-1",
+            "AUTH=XOAUTH2",
+            "CONDSTORE",
+        ]);
+        let mut session = connect(stream).login("user", "password").unwrap();
+        assert_eq!(
+            format_capabilities(&capabilities(&mut session).unwrap()),
+            "- AUTH=XOAUTH2\n- CONDSTORE\n- IDLE\n- IMAP4rev1\n- MOVE\n- UNSELECT\n- X-GM-EXT-1"
+        );
+        assert_eq!(format_capabilities(&[]), "");
+    }
+}
@@ -1,22 +1,139 @@
-use std::{collections::HashMap, fs};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::utils::get_data_dir_path;
+use crate::{
+    provider::Provider,
+    utils::{get_data_dir_path, write_atomically},
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct StoredAccounts(HashMap<String, StoredAccountData>);
+pub static ACCOUNTS_FILE_NAME: &str = "accounts.toml";
+
+pub static ENV_EMAIL: &str = "MAIL_CLI_EMAIL";
+pub static ENV_ACCESS_TOKEN: &str = "MAIL_CLI_ACCESS_TOKEN";
+pub static ENV_REFRESH_TOKEN: &str = "MAIL_CLI_REFRESH_TOKEN";
+
+/// how long before the access token expires the user is warned about it
+pub const EXPIRY_WARNING_SECS: i64 = 5 * 60;
+/// number of failed refreshes in a row after which the user is told to log in again
+pub const REFRESH_FAILURES_BEFORE_RELOGIN: u32 = 2;
+
+/// OAuth scopes that allow changing mails over IMAP, without trailing slashes
+const WRITE_SCOPES: [&str; 2] = ["https://mail.google.com", "mail-w"];
+
+/// accounts inserted while the accounts file is read-only (see [`set_read_only`]), `None` if it
+/// is written to. shared by all loads of the accounts so a token is only refreshed once per run
+static UNSTORED: Mutex<Option<HashMap<String, StoredAccountData>>> = Mutex::new(None);
+
+#[derive(Debug, Clone)]
+pub struct StoredAccounts {
+    path: PathBuf,
+    accounts: HashMap<String, StoredAccountData>,
+    /// keep inserted accounts in memory instead of writing them to the file
+    read_only: bool,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredAccountData {
+    /// empty for accounts logged in with an app password
+    #[serde(default)]
     pub access_token: String,
+    #[serde(default)]
     pub refresh_token: String,
+    /// password generated by the provider for a single app, used to log in instead of OAuth
+    /// for providers or accounts that don't allow it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_password: Option<String>,
+    /// accounts stored before providers were recorded are Gmail accounts
+    #[serde(default)]
+    pub provider: Provider,
+    /// unix timestamp at which the access token expires, unknown for accounts stored before
+    /// expiry times were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    /// number of times refreshing the access token failed since it last succeeded
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub refresh_failures: u32,
+    /// the space separated OAuth scopes granted at login, unknown for accounts stored before
+    /// they were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+/// whether the stored access token of an account can still be used, see
+/// [`StoredAccountData::status`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountStatus {
+    pub email: String,
+    pub provider: Provider,
+    /// `None` if the expiry time of the token is unknown
+    pub valid: Option<bool>,
+    /// RFC 3339 time at which the access token expires
+    pub expires_at: Option<String>,
+    /// the granted OAuth scopes, see [`StoredAccountData::scope`]
+    pub scope: Option<String>,
+}
+
+/// an exclusive advisory lock on the accounts file, released when dropped.
+///
+/// the lock is taken on a separate `.lock` file so it survives the accounts file being replaced
+/// by [`write_atomically`]
+#[derive(Debug)]
+pub struct AccountsLock {
+    _file: File,
+}
+
+/// the accounts file exists but can't be used, a copy of it was saved to `backup`
+#[derive(Debug, Clone)]
+pub struct AccountsCorrupted {
+    pub path: PathBuf,
+    pub backup: PathBuf,
+    pub reason: String,
+}
+
+/// never writes the accounts file for the rest of the run, e.g. if it's on a read-only mount.
+///
+/// refreshed tokens are only kept in memory, so they are refreshed again on the next run
+pub fn set_read_only() {
+    let mut unstored = UNSTORED.lock().unwrap_or_else(|err| err.into_inner());
+    unstored.get_or_insert_with(HashMap::new);
 }
 
 impl StoredAccounts {
+    /// loads the accounts file in the data directory, together with the accounts only kept in
+    /// memory if it is read-only
     pub fn load_data() -> anyhow::Result<Self> {
-        let path = get_data_dir_path()?.join("accounts.toml");
-        let data_str = match fs::read_to_string(path) {
+        let mut accounts = Self::load_from(get_data_dir_path()?.join(ACCOUNTS_FILE_NAME))?;
+
+        let unstored = UNSTORED.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(unstored) = unstored.as_ref() {
+            accounts.read_only = true;
+            accounts.accounts.extend(unstored.clone());
+        }
+
+        Ok(accounts)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// loads the accounts stored at `path`, a missing file is treated as no stored accounts.
+    ///
+    /// Errors:
+    /// - if the file can't be read
+    /// - with [`AccountsCorrupted`] if the file isn't valid toml or contains an account without
+    ///   tokens, in which case the file is backed up next to the original first
+    pub fn load_from(path: PathBuf) -> anyhow::Result<Self> {
+        let data_str = match fs::read_to_string(&path) {
             Ok(data) => data,
             Err(err) => match err.kind() {
                 std::io::ErrorKind::NotFound => String::new(),
@@ -24,23 +141,79 @@ impl StoredAccounts {
             },
         };
 
-        Ok(toml::from_str(&data_str)?)
+        let accounts = match parse_accounts(&data_str) {
+            Ok(accounts) => accounts,
+            Err(reason) => {
+                let backup = backup_path(&path);
+                fs::copy(&path, &backup)?;
+
+                return Err(AccountsCorrupted {
+                    path,
+                    backup,
+                    reason,
+                }
+                .into());
+            }
+        };
+
+        Ok(Self {
+            path,
+            accounts,
+            read_only: false,
+        })
+    }
+
+    /// removes the file at `path` and returns an empty set of accounts stored there
+    pub fn reset(path: PathBuf) -> anyhow::Result<Self> {
+        let _lock = AccountsLock::acquire(&path)?;
+
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+
+        Ok(Self {
+            path,
+            accounts: HashMap::new(),
+            read_only: false,
+        })
     }
 
     pub fn store_data(&self) -> anyhow::Result<()> {
-        let path = get_data_dir_path()?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
 
-        fs::create_dir_all(&path)?;
-        fs::write(path.join("accounts.toml"), toml::to_string_pretty(self)?)?;
+        write_atomically(
+            &self.path,
+            toml::to_string_pretty(&self.accounts)?.as_bytes(),
+        )?;
 
         Ok(())
     }
 
     pub fn stored_accounts(&self) -> &HashMap<String, StoredAccountData> {
-        &self.0
+        &self.accounts
     }
+
+    /// inserts an account and stores the result, read-only accounts are only updated in memory.
+    ///
+    /// the accounts file is reloaded while holding the lock before inserting, so accounts and
+    /// tokens stored by other processes since this one loaded the file aren't overwritten
     pub fn insert(&mut self, k: String, v: StoredAccountData) -> anyhow::Result<()> {
-        self.0.insert(k, v);
+        if self.read_only {
+            let mut unstored = UNSTORED.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some(unstored) = unstored.as_mut() {
+                unstored.insert(k.clone(), v.clone());
+            }
+            self.accounts.insert(k, v);
+            return Ok(());
+        }
+
+        let _lock = AccountsLock::acquire(&self.path)?;
+
+        self.accounts = Self::load_from(self.path.clone())?.accounts;
+        self.accounts.insert(k, v);
         self.store_data()
     }
 }
@@ -50,6 +223,473 @@ impl StoredAccountData {
         Self {
             access_token,
             refresh_token,
+            app_password: None,
+            provider: Provider::default(),
+            expires_at: None,
+            refresh_failures: 0,
+            scope: None,
+        }
+    }
+
+    /// an account of `provider` logged in to with `password` instead of OAuth tokens
+    pub fn with_app_password(password: String, provider: Provider) -> Self {
+        Self {
+            app_password: Some(password),
+            ..Self::new(String::new(), String::new()).with_provider(provider)
+        }
+    }
+
+    pub fn with_provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    pub fn with_scope(mut self, scope: String) -> Self {
+        self.scope = Some(scope);
+        self
+    }
+
+    /// Errors: if the account was only granted scopes that don't allow changing mails (e.g.
+    /// Gmail's read-only scope), as the server would reject the change with a less helpful
+    /// message. accounts with unknown scopes are assumed to be allowed to
+    pub fn check_write_scope(&self, email: &str) -> anyhow::Result<()> {
+        let Some(scope) = &self.scope else {
+            return Ok(());
+        };
+        if scope
+            .split_whitespace()
+            .any(|scope| WRITE_SCOPES.contains(&scope.trim_end_matches('/')))
+        {
+            return Ok(());
+        }
+
+        Err(anyhow!(
+            "{email} was only granted the scope '{scope}', which doesn't allow changing mails. \
+            re-authorize it with write access using `mail-cli reauth {email}`"
+        ))
+    }
+
+    /// the account with the tokens of a new login replaced by those of `login`, keeping its
+    /// provider and other data. providers don't always hand out a new refresh token when logging
+    /// in again, in which case the old one is kept
+    pub fn reauthorized(self, login: StoredAccountData) -> Self {
+        let refresh_token = match login.refresh_token.is_empty() {
+            true => self.refresh_token,
+            false => login.refresh_token,
+        };
+
+        Self {
+            access_token: login.access_token,
+            refresh_token,
+            expires_at: login.expires_at,
+            refresh_failures: 0,
+            scope: login.scope.or(self.scope),
+            ..self
+        }
+    }
+
+    /// whether the account can be logged in to, either with OAuth tokens or an app password
+    fn has_credentials(&self) -> bool {
+        self.app_password.is_some()
+            || (!self.access_token.is_empty() && !self.refresh_token.is_empty())
+    }
+
+    /// the account given by the `MAIL_CLI_EMAIL`, `MAIL_CLI_ACCESS_TOKEN` and
+    /// `MAIL_CLI_REFRESH_TOKEN` variables looked up with `var`, `None` unless all of them are set.
+    ///
+    /// meant for automation, such an account is used without being stored
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Option<(String, Self)> {
+        let email = var(ENV_EMAIL)?;
+        let account = Self::new(var(ENV_ACCESS_TOKEN)?, var(ENV_REFRESH_TOKEN)?);
+
+        Some((email, account))
+    }
+
+    /// records that the access token expires `expires_in` seconds from now
+    pub fn with_expiry(mut self, expires_in: Option<i64>) -> Self {
+        self.expires_at = expires_in.map(|secs| (Utc::now() + Duration::seconds(secs)).timestamp());
+        self
+    }
+
+    /// the time left until the access token expires if it is still valid at `now` but expires
+    /// within [`EXPIRY_WARNING_SECS`]
+    pub fn expires_soon(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let left = Duration::seconds(self.expires_at? - now.timestamp());
+        (Duration::zero() < left && left <= Duration::seconds(EXPIRY_WARNING_SECS)).then_some(left)
+    }
+
+    /// the status of the stored access token at `now`, without contacting the provider
+    pub fn status(&self, email: &str, now: DateTime<Utc>) -> AccountStatus {
+        let expires_at = self
+            .expires_at
+            .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single());
+
+        AccountStatus {
+            email: email.to_owned(),
+            provider: self.provider,
+            valid: expires_at.map(|expires_at| expires_at > now),
+            expires_at: expires_at.map(|expires_at| expires_at.to_rfc3339()),
+            scope: self.scope.clone(),
+        }
+    }
+}
+
+impl Display for AccountStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match (self.valid, &self.expires_at) {
+            (Some(true), Some(expires_at)) => format!("valid until {expires_at}"),
+            (Some(false), Some(expires_at)) => format!("expired at {expires_at}"),
+            _ => String::from("unknown expiry"),
+        };
+
+        write!(f, "{} ({:?}): {status}", self.email, self.provider)?;
+        match &self.scope {
+            Some(scope) => write!(f, ", scope {scope}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl AccountsLock {
+    /// blocks until the lock for the accounts file at `path` is acquired
+    pub fn acquire(path: &Path) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(".lock");
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.with_file_name(name))?;
+        lock_exclusive(&file)?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(unix)]
+fn lock_exclusive(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        // SAFETY: the file descriptor is valid for as long as `file` is borrowed, the lock is
+        // released when the file is closed
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_exclusive(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+impl Display for AccountsCorrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the stored accounts in '{path}' are corrupted ({reason}), a backup was saved to \
+            '{backup}'. delete '{path}' to reset and log in to your accounts again",
+            path = self.path.display(),
+            backup = self.backup.display(),
+            reason = self.reason
+        )
+    }
+}
+
+impl std::error::Error for AccountsCorrupted {}
+
+fn parse_accounts(data_str: &str) -> Result<HashMap<String, StoredAccountData>, String> {
+    let accounts: HashMap<String, StoredAccountData> =
+        toml::from_str(data_str).map_err(|err| err.message().to_owned())?;
+
+    match accounts.iter().find(|(_, data)| !data.has_credentials()) {
+        Some((email, _)) => Err(format!("the account '{email}' has no tokens")),
+        None => Ok(accounts),
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".bak");
+
+    path.with_file_name(name)
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_file_has_no_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = StoredAccounts::load_from(dir.path().join(ACCOUNTS_FILE_NAME)).unwrap();
+
+        assert!(accounts.stored_accounts().is_empty());
+    }
+
+    #[test]
+    fn corrupted_file_is_backed_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ACCOUNTS_FILE_NAME);
+        let broken = "[\"a@b.c\"]\naccess_token = \"abc";
+        fs::write(&path, broken).unwrap();
+
+        let err = StoredAccounts::load_from(path.clone()).unwrap_err();
+        let corrupted = err.downcast_ref::<AccountsCorrupted>().unwrap();
+
+        assert_eq!(corrupted.backup, dir.path().join("accounts.toml.bak"));
+        assert_eq!(fs::read_to_string(&corrupted.backup).unwrap(), broken);
+        assert_eq!(fs::read_to_string(&path).unwrap(), broken);
+    }
+
+    #[test]
+    fn accounts_without_tokens_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ACCOUNTS_FILE_NAME);
+        fs::write(
+            &path,
+            "[\"a@b.c\"]\naccess_token = \"\"\nrefresh_token = \"def\"\n",
+        )
+        .unwrap();
+
+        let err = StoredAccounts::load_from(path).unwrap_err();
+
+        assert!(err.downcast_ref::<AccountsCorrupted>().is_some());
+    }
+
+    #[test]
+    fn stores_and_loads_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join(ACCOUNTS_FILE_NAME);
+
+        let mut accounts = StoredAccounts::load_from(path.clone()).unwrap();
+        accounts
+            .insert(
+                String::from("a@b.c"),
+                StoredAccountData::new(String::from("abc"), String::from("def")),
+            )
+            .unwrap();
+
+        let loaded = StoredAccounts::load_from(path).unwrap();
+        assert_eq!(loaded.stored_accounts()["a@b.c"].refresh_token, "def");
+    }
+
+    #[test]
+    fn stores_app_password_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ACCOUNTS_FILE_NAME);
+
+        let mut accounts = StoredAccounts::load_from(path.clone()).unwrap();
+        accounts
+            .insert(
+                String::from("a@yahoo.com"),
+                StoredAccountData::with_app_password(String::from("pass"), Provider::Yahoo),
+            )
+            .unwrap();
+
+        let loaded = StoredAccounts::load_from(path).unwrap();
+        let account = &loaded.stored_accounts()["a@yahoo.com"];
+        assert_eq!(account.app_password.as_deref(), Some("pass"));
+        assert_eq!(account.provider, Provider::Yahoo);
+    }
+
+    #[test]
+    fn reads_account_from_env() {
+        let env = HashMap::from([
+            (ENV_EMAIL, "ci@example.com"),
+            (ENV_ACCESS_TOKEN, "abc"),
+            (ENV_REFRESH_TOKEN, "def"),
+        ]);
+        let var = |name: &str| env.get(name).map(|val| val.to_string());
+
+        let (email, account) = StoredAccountData::from_env(var).unwrap();
+        assert_eq!(email, "ci@example.com");
+        assert_eq!(account.access_token, "abc");
+        assert_eq!(account.refresh_token, "def");
+
+        let partial = |name: &str| (name != ENV_REFRESH_TOKEN).then(|| var(name)).flatten();
+        assert!(StoredAccountData::from_env(partial).is_none());
+    }
+
+    #[test]
+    fn reports_token_status() {
+        let now = Utc::now();
+        let mut account = StoredAccountData::new(String::from("abc"), String::from("def"));
+
+        assert_eq!(account.status("a@b.c", now).valid, None);
+
+        account.expires_at = Some(now.timestamp() + 60);
+        assert_eq!(account.status("a@b.c", now).valid, Some(true));
+
+        account.expires_at = Some(now.timestamp() - 60);
+        let status = account.status("a@b.c", now);
+        assert_eq!(status.valid, Some(false));
+        assert!(status.to_string().starts_with("a@b.c (Google): expired at"));
+    }
+
+    #[test]
+    fn warns_shortly_before_expiry() {
+        let now = Utc::now();
+        let mut account = StoredAccountData::new(String::from("abc"), String::from("def"));
+        let expiring_in = |account: &mut StoredAccountData, secs: i64| {
+            account.expires_at = Some(now.timestamp() + secs);
+            account.expires_soon(now)
+        };
+
+        assert_eq!(account.expires_soon(now), None);
+        assert_eq!(expiring_in(&mut account, 3600), None);
+        assert_eq!(expiring_in(&mut account, EXPIRY_WARNING_SECS + 1), None);
+        assert_eq!(
+            expiring_in(&mut account, EXPIRY_WARNING_SECS),
+            Some(Duration::seconds(EXPIRY_WARNING_SECS))
+        );
+        assert_eq!(expiring_in(&mut account, 1), Some(Duration::seconds(1)));
+        // expired tokens are refreshed without a warning
+        assert_eq!(expiring_in(&mut account, 0), None);
+        assert_eq!(expiring_in(&mut account, -60), None);
+    }
+
+    #[test]
+    fn reauthorizing_keeps_account_data() {
+        let account = StoredAccountData {
+            expires_at: Some(10),
+            refresh_failures: 3,
+            ..StoredAccountData::new(String::from("old"), String::from("old refresh"))
+                .with_provider(Provider::Yahoo)
+        };
+
+        let login = StoredAccountData::new(String::from("new"), String::from("new refresh"))
+            .with_expiry(Some(3600));
+        let expires_at = login.expires_at;
+        let reauthorized = account.clone().reauthorized(login);
+        assert_eq!(reauthorized.access_token, "new");
+        assert_eq!(reauthorized.refresh_token, "new refresh");
+        assert_eq!(reauthorized.expires_at, expires_at);
+        assert_eq!(reauthorized.provider, Provider::Yahoo);
+        assert_eq!(reauthorized.refresh_failures, 0);
+        assert_eq!(reauthorized.scope, None);
+
+        let without_refresh = StoredAccountData::new(String::from("new"), String::new());
+        assert_eq!(
+            account.reauthorized(without_refresh).refresh_token,
+            "old refresh"
+        );
+    }
+
+    #[test]
+    fn checks_scope_allows_changing_mails() {
+        let account = StoredAccountData::new(String::from("token"), String::from("refresh"));
+        assert!(account.check_write_scope("a@b.c").is_ok());
+
+        for scope in [
+            "https://mail.google.com/",
+            "openid mail-w",
+            "https://mail.google.com",
+        ] {
+            let account = account.clone().with_scope(scope.to_owned());
+            assert!(account.check_write_scope("a@b.c").is_ok(), "{scope}");
         }
+
+        let read_only = account.clone().with_scope(String::from(
+            "https://www.googleapis.com/auth/gmail.readonly",
+        ));
+        let err = read_only.check_write_scope("a@b.c").unwrap_err();
+        assert!(err.to_string().contains("mail-cli reauth a@b.c"));
+        assert!(account
+            .with_scope(String::from("mail-r"))
+            .check_write_scope("a@b.c")
+            .is_err());
+
+        let reauthorized =
+            read_only.reauthorized(StoredAccountData::new(String::new(), String::new()));
+        assert!(reauthorized.check_write_scope("a@b.c").is_err());
+    }
+
+    #[test]
+    fn insert_keeps_accounts_stored_by_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ACCOUNTS_FILE_NAME);
+
+        let mut first = StoredAccounts::load_from(path.clone()).unwrap();
+        let mut second = StoredAccounts::load_from(path.clone()).unwrap();
+        first
+            .insert(
+                String::from("a@b.c"),
+                StoredAccountData::new(String::from("abc"), String::from("def")),
+            )
+            .unwrap();
+        second
+            .insert(
+                String::from("x@y.z"),
+                StoredAccountData::new(String::from("uvw"), String::from("xyz")),
+            )
+            .unwrap();
+
+        let loaded = StoredAccounts::load_from(path).unwrap();
+        assert_eq!(loaded.stored_accounts().len(), 2);
+    }
+
+    #[test]
+    fn read_only_insert_doesnt_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data").join(ACCOUNTS_FILE_NAME);
+        let mut accounts = StoredAccounts::load_from(path.clone()).unwrap();
+        accounts.read_only = true;
+
+        accounts
+            .insert(
+                String::from("a@b.c"),
+                StoredAccountData::new(String::from("refreshed"), String::from("def")),
+            )
+            .unwrap();
+        assert_eq!(
+            accounts.stored_accounts()["a@b.c"].access_token,
+            "refreshed"
+        );
+        // not even the lock or data directory were created
+        assert!(!dir.path().join("data").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn insert_waits_for_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ACCOUNTS_FILE_NAME);
+        let lock = AccountsLock::acquire(&path).unwrap();
+
+        let writer = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let mut accounts = StoredAccounts::load_from(path).unwrap();
+                accounts
+                    .insert(
+                        String::from("a@b.c"),
+                        StoredAccountData::new(String::from("abc"), String::from("def")),
+                    )
+                    .unwrap();
+            }
+        });
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!path.exists());
+
+        drop(lock);
+        writer.join().unwrap();
+        assert!(path.exists());
     }
 }
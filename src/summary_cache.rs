@@ -0,0 +1,363 @@
+//! the mails of recent `read --no-body` queries, so running one again shortly after doesn't fetch
+//! anything and `--offline` can show them without connecting
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    mail::{Mail, MailFlag, SortBy},
+    mail_filters::Search,
+    utils::{get_data_dir_path, write_atomically},
+};
+
+pub static SUMMARY_CACHE_FILE_NAME: &str = "summaries.toml";
+
+/// how long the mails of a query are reused if `summary_cache_ttl` isn't set in the config file
+pub const DEFAULT_SUMMARY_CACHE_TTL_SECS: i64 = 300;
+
+/// identifies a query by everything that decides which mails it finds
+pub fn query_key(mailboxes: &[String], n: usize, sort_by: SortBy, search: &Search) -> String {
+    format!(
+        "{mailboxes:?} n={n} sort={sort_by:?} keys={:?} gmail={:?}",
+        search.keys, search.gmail
+    )
+}
+
+/// the header and flags of a mail, enough to show it in the header table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Summary {
+    /// the base64 encoded raw header block, which doesn't have to be valid UTF-8
+    header: String,
+    flags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uid: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedSummaries {
+    /// unix timestamp at which the mails were fetched
+    fetched_at: i64,
+    /// the `UIDVALIDITY` of each read mailbox by name when the mails were fetched, 0 if the
+    /// server didn't send one
+    uid_validities: BTreeMap<String, u32>,
+    summaries: Vec<Summary>,
+}
+
+/// the mails of the last run of each query by account
+#[derive(Debug, Clone)]
+pub struct SummaryCache {
+    path: PathBuf,
+    accounts: HashMap<String, HashMap<String, CachedSummaries>>,
+}
+
+impl SummaryCache {
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(get_data_dir_path()?.join(SUMMARY_CACHE_FILE_NAME))
+    }
+
+    /// loads the cache at `path`, a missing or unreadable cache is treated as empty since it can
+    /// always be rebuilt
+    pub fn load_from(path: PathBuf) -> anyhow::Result<Self> {
+        let accounts = match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).unwrap_or_default(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self { path, accounts })
+    }
+
+    pub fn store(&self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        write_atomically(
+            &self.path,
+            toml::to_string_pretty(&self.accounts)?.as_bytes(),
+        )
+    }
+
+    /// the mails `query` found for `email` if they were fetched less than `ttl_secs` before
+    /// `now`. entries whose mailboxes got a new `UIDVALIDITY` since are dropped by
+    /// [`SummaryCache::insert`] once a fetch sees it
+    pub fn fresh(
+        &self,
+        email: &str,
+        query: &str,
+        ttl_secs: i64,
+        now: DateTime<Utc>,
+    ) -> Option<Vec<anyhow::Result<Mail>>> {
+        self.get(email, query)
+            .filter(|cached| cached.is_fresh(ttl_secs, now))
+            .map(CachedSummaries::mails)
+    }
+
+    /// the mails `query` last found for `email` together with the time they were fetched at,
+    /// however old they are
+    pub fn last(
+        &self,
+        email: &str,
+        query: &str,
+    ) -> Option<(Vec<anyhow::Result<Mail>>, DateTime<Utc>)> {
+        self.get(email, query).map(|cached| {
+            let fetched_at = Utc
+                .timestamp_opt(cached.fetched_at, 0)
+                .single()
+                .unwrap_or_default();
+            (cached.mails(), fetched_at)
+        })
+    }
+
+    /// records the mails `query` found for `email`. nothing is recorded if any of them couldn't
+    /// be parsed, as the error would be lost. the other queries of `email` are dropped if they
+    /// read a mailbox with another `UIDVALIDITY` than `uid_validities`, their UIDs may refer to
+    /// other mails now
+    pub fn insert(
+        &mut self,
+        email: String,
+        query: String,
+        uid_validities: BTreeMap<String, u32>,
+        mails: &[anyhow::Result<Mail>],
+        now: DateTime<Utc>,
+    ) {
+        let Some(summaries) = mails
+            .iter()
+            .map(|mail| {
+                let mail = mail.as_ref().ok()?;
+                Some(Summary {
+                    header: STANDARD.encode(mail.raw()),
+                    flags: mail
+                        .flags()
+                        .iter()
+                        .map(|flag| flag.name().to_owned())
+                        .collect(),
+                    uid: mail.uid(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+
+        let queries = self.accounts.entry(email).or_default();
+        queries.retain(|_, cached| {
+            cached.uid_validities.iter().all(|(mailbox, uid_validity)| {
+                uid_validities
+                    .get(mailbox)
+                    .is_none_or(|current| current == uid_validity)
+            })
+        });
+        queries.insert(
+            query,
+            CachedSummaries {
+                fetched_at: now.timestamp(),
+                uid_validities,
+                summaries,
+            },
+        );
+    }
+
+    /// drops the mails fetched `ttl_secs` or longer before `now`, so the cache doesn't keep
+    /// growing with queries that are never run again
+    pub fn prune(&mut self, ttl_secs: i64, now: DateTime<Utc>) {
+        for queries in self.accounts.values_mut() {
+            queries.retain(|_, cached| cached.is_fresh(ttl_secs, now));
+        }
+        self.accounts.retain(|_, queries| !queries.is_empty());
+    }
+
+    fn get(&self, email: &str, query: &str) -> Option<&CachedSummaries> {
+        self.accounts.get(email)?.get(query)
+    }
+}
+
+impl CachedSummaries {
+    fn is_fresh(&self, ttl_secs: i64, now: DateTime<Utc>) -> bool {
+        (0..ttl_secs).contains(&(now.timestamp() - self.fetched_at))
+    }
+
+    fn mails(&self) -> Vec<anyhow::Result<Mail>> {
+        self.summaries
+            .iter()
+            .enumerate()
+            .map(|(idx, summary)| {
+                let flags = summary
+                    .flags
+                    .iter()
+                    .filter_map(|name| MailFlag::from_name(name))
+                    .collect();
+                let mut mail =
+                    Mail::from_header(&STANDARD.decode(&summary.header)?, flags, idx as u32 + 1)?;
+                mail.set_uid(summary.uid);
+                Ok(mail)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::{
+        mail::MailBox,
+        mock_imap::{mock_session, MockMail},
+    };
+
+    #[test]
+    fn reuses_mails_until_expired_or_invalidated() {
+        let (mut session, _) = mock_session(vec![(
+            "INBOX",
+            vec![
+                MockMail::new("Subject: first\nDate: Mon, 1 May 2023 10:00:00 +0000\n\nbody\n")
+                    .with_flags(&["\\Seen", "\\Flagged"]),
+                MockMail::new("Subject: second\nDate: Tue, 2 May 2023 10:00:00 +0000\n\nbody\n"),
+            ],
+        )]);
+        let mails = MailBox::new("INBOX")
+            .with_headers_only(true)
+            .fetch_n_recent_mails(10, &mut session)
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SUMMARY_CACHE_FILE_NAME);
+        let mut cache = SummaryCache::load_from(path.clone()).unwrap();
+        let query = query_key(
+            &[String::from("INBOX")],
+            10,
+            SortBy::Date,
+            &Search::default(),
+        );
+        let now = Utc::now();
+        let validities =
+            |uid_validity: u32| BTreeMap::from([(String::from("INBOX"), uid_validity)]);
+        cache.insert(
+            String::from("a@b.c"),
+            query.clone(),
+            validities(7),
+            &mails,
+            now,
+        );
+        cache.store().unwrap();
+
+        let mut cache = SummaryCache::load_from(path).unwrap();
+        let cached = cache
+            .fresh("a@b.c", &query, 60, now + Duration::seconds(30))
+            .unwrap();
+        let subjects = |mails: &[anyhow::Result<Mail>]| {
+            mails
+                .iter()
+                .map(|mail| mail.as_ref().unwrap().subject().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(subjects(&cached), subjects(&mails));
+        assert_eq!(
+            cached[0].as_ref().unwrap().flags(),
+            mails[0].as_ref().unwrap().flags()
+        );
+        assert_eq!(cached[0].as_ref().unwrap().uid(), Some(2));
+
+        // expired
+        assert!(cache
+            .fresh("a@b.c", &query, 60, now + Duration::seconds(60))
+            .is_none());
+        assert!(cache.fresh("other@b.c", &query, 60, now).is_none());
+        // other queries aren't mixed up
+        let other = query_key(
+            &[String::from("INBOX")],
+            5,
+            SortBy::Date,
+            &Search::default(),
+        );
+        assert!(cache.fresh("a@b.c", &other, 60, now).is_none());
+
+        // `--offline` takes them however old they are
+        let (offline, fetched_at) = cache.last("a@b.c", &query).unwrap();
+        assert_eq!(offline.len(), 2);
+        assert_eq!(fetched_at.timestamp(), now.timestamp());
+
+        // a fetch of another query that sees the same UIDVALIDITY keeps it, one that sees
+        // reassigned UIDs drops it
+        cache.insert(
+            String::from("a@b.c"),
+            other.clone(),
+            validities(7),
+            &mails,
+            now,
+        );
+        assert!(cache.fresh("a@b.c", &query, 60, now).is_some());
+        cache.insert(
+            String::from("a@b.c"),
+            other.clone(),
+            validities(8),
+            &mails,
+            now,
+        );
+        assert!(cache.fresh("a@b.c", &query, 60, now).is_none());
+        assert!(cache.fresh("a@b.c", &other, 60, now).is_some());
+    }
+
+    #[test]
+    fn prunes_expired_queries() {
+        let (mut session, _) = mock_session(vec![(
+            "INBOX",
+            vec![MockMail::new(
+                "Subject: first\nDate: Mon, 1 May 2023 10:00:00 +0000\n\nbody\n",
+            )],
+        )]);
+        let mails = MailBox::new("INBOX")
+            .with_headers_only(true)
+            .fetch_n_recent_mails(10, &mut session)
+            .unwrap();
+
+        let mut cache = SummaryCache::load_from(PathBuf::new()).unwrap();
+        let now = Utc::now();
+        let earlier = now - Duration::seconds(90);
+        for (query, fetched_at) in [("old", earlier), ("new", now)] {
+            cache.insert(
+                String::from("a@b.c"),
+                String::from(query),
+                BTreeMap::new(),
+                &mails,
+                fetched_at,
+            );
+        }
+        cache.insert(
+            String::from("d@e.f"),
+            String::from("old"),
+            BTreeMap::new(),
+            &mails,
+            earlier,
+        );
+
+        cache.prune(60, now);
+        assert!(cache.last("a@b.c", "old").is_none());
+        assert!(cache.last("a@b.c", "new").is_some());
+        assert!(!cache.accounts.contains_key("d@e.f"));
+    }
+
+    #[test]
+    fn doesnt_cache_unparsable_mails() {
+        let mut cache = SummaryCache::load_from(PathBuf::new()).unwrap();
+        let now = Utc::now();
+        let mails = vec![Err(anyhow::anyhow!("failed to parse message 1"))];
+        cache.insert(
+            String::from("a@b.c"),
+            String::from("q"),
+            BTreeMap::new(),
+            &mails,
+            now,
+        );
+
+        assert!(cache.last("a@b.c", "q").is_none());
+    }
+}
@@ -0,0 +1,115 @@
+//! copying mails from one account to another, e.g. when moving to a new address
+
+use std::io::{Read, Write};
+
+use anyhow::anyhow;
+use chrono::{DateTime, FixedOffset};
+use imap::{types::Flag, Session};
+
+use crate::mail::OpenMode;
+
+/// a mail as it is stored on the server, to be appended to another one unchanged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredMail {
+    pub raw: Vec<u8>,
+    /// all flags except `\Recent`, which only the server sets
+    pub flags: Vec<Flag<'static>>,
+    /// the `INTERNALDATE`, when the server received the mail
+    pub internal_date: Option<DateTime<FixedOffset>>,
+}
+
+impl StoredMail {
+    /// fetches the mail with `uid` in `mailbox` without marking it as seen
+    pub fn fetch<T: Read + Write>(
+        mailbox: &str,
+        uid: u32,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<Self> {
+        OpenMode::ReadOnly.open(mailbox, session)?;
+
+        let items = session.uid_fetch(uid.to_string(), "(UID FLAGS INTERNALDATE BODY.PEEK[])")?;
+        let item = items
+            .first()
+            .ok_or(anyhow!("no mail with UID {uid} in mailbox '{mailbox}'"))?;
+        let raw = item
+            .body()
+            .ok_or(anyhow!("the server didn't send the content of mail {uid}"))?;
+
+        Ok(Self {
+            raw: raw.to_vec(),
+            flags: item
+                .flags()
+                .iter()
+                .filter(|flag| **flag != Flag::Recent)
+                .map(|flag| Flag::from(flag.to_string()))
+                .collect(),
+            internal_date: item.internal_date(),
+        })
+    }
+
+    /// appends the mail to `mailbox` with its flags and internal date
+    pub fn append<T: Read + Write>(
+        &self,
+        mailbox: &str,
+        session: &mut Session<T>,
+    ) -> anyhow::Result<()> {
+        session.append_with_flags_and_date(mailbox, &self.raw, &self.flags, self.internal_date)?;
+
+        Ok(())
+    }
+}
+
+/// copies the mail with `uid` in `src_mailbox` of the account of `src` to `dest_mailbox` of the
+/// account of `dest`. the mail is left unchanged in the source account
+pub fn transfer_mail<S: Read + Write, D: Read + Write>(
+    src: &mut Session<S>,
+    src_mailbox: &str,
+    uid: u32,
+    dest: &mut Session<D>,
+    dest_mailbox: &str,
+) -> anyhow::Result<()> {
+    StoredMail::fetch(src_mailbox, uid, src)?.append(dest_mailbox, dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_imap::{mock_session, MockMail};
+
+    #[test]
+    fn appends_mail_of_one_account_to_another() {
+        let raw = "From: a@b.c\r\nSubject: keep me\r\n\r\nbody\r\n";
+        let (mut src, src_commands) = mock_session(vec![(
+            "Archive",
+            vec![
+                MockMail::new("Subject: other\r\n\r\n"),
+                MockMail::new(raw)
+                    .with_flags(&["\\Seen", "\\Flagged", "\\Recent", "$Important"])
+                    .with_internal_date("03-Jul-2023 10:00:00 +0200"),
+            ],
+        )]);
+        let (mut dest, dest_commands) = mock_session(vec![("INBOX", vec![]), ("Old", vec![])]);
+
+        transfer_mail(&mut src, "Archive", 2, &mut dest, "Old").unwrap();
+
+        // the source is only read
+        assert!(src_commands
+            .borrow()
+            .iter()
+            .all(|command| !command.starts_with("SELECT") && !command.contains("STORE")));
+        assert_eq!(
+            dest_commands.borrow().last().unwrap(),
+            &format!(
+                "APPEND \"Old\" (\\Seen \\Flagged $Important) \"03-Jul-2023 10:00:00 +0200\" {{{}}}",
+                raw.len()
+            )
+        );
+
+        let copied = StoredMail::fetch("Old", 1, &mut dest).unwrap();
+        assert_eq!(copied, StoredMail::fetch("Archive", 2, &mut src).unwrap());
+        assert_eq!(copied.raw, raw.as_bytes());
+
+        assert!(transfer_mail(&mut src, "Archive", 3, &mut dest, "Old").is_err());
+        assert!(transfer_mail(&mut src, "Archive", 1, &mut dest, "Missing").is_err());
+    }
+}
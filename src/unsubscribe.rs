@@ -0,0 +1,140 @@
+use std::fmt::Display;
+
+use anyhow::anyhow;
+use reqwest::StatusCode;
+
+use crate::{
+    proxy::http_client,
+    utils::{checked_url, open_url},
+};
+
+/// a way of unsubscribing announced in a `List-Unsubscribe` header (RFC 2369)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unsubscribe {
+    /// sending a mail to this `mailto:` url
+    Mailto(String),
+    /// visiting this url, or posting to it if `one_click` is set (RFC 8058)
+    Http { url: String, one_click: bool },
+}
+
+/// parses the raw `List-Unsubscribe` header, `list_unsubscribe_post` is the raw
+/// `List-Unsubscribe-Post` header.
+///
+/// http(s) urls are returned before `mailto:` urls since they don't need a mail to be sent.
+/// urls that [`checked_url`] refuses are left out, the header comes from the sender
+pub fn parse_list_unsubscribe(
+    list_unsubscribe: &str,
+    list_unsubscribe_post: Option<&str>,
+) -> Vec<Unsubscribe> {
+    let one_click = list_unsubscribe_post.is_some_and(|post| {
+        post.trim()
+            .eq_ignore_ascii_case("List-Unsubscribe=One-Click")
+    });
+
+    let mut methods = list_unsubscribe
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(url, _)| url.split_whitespace().collect::<String>())
+        .filter_map(|url| {
+            let scheme = checked_url(&url).ok()?.scheme().to_owned();
+            match scheme.as_str() {
+                "mailto" => Some(Unsubscribe::Mailto(url)),
+                "http" | "https" => Some(Unsubscribe::Http {
+                    one_click: one_click && scheme == "https",
+                    url,
+                }),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    methods.sort_by_key(|method| matches!(method, Unsubscribe::Mailto(_)));
+    methods
+}
+
+impl Unsubscribe {
+    /// unsubscribes with a one-click `POST` if supported, otherwise opens the url with the
+    /// systems default handler (browser or mail client)
+    pub async fn run(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Http {
+                url,
+                one_click: true,
+            } => {
+                let res = http_client()?
+                    .post(url)
+                    .form(&[("List-Unsubscribe", "One-Click")])
+                    .send()
+                    .await?;
+
+                match res.status() {
+                    StatusCode::OK | StatusCode::ACCEPTED | StatusCode::NO_CONTENT => Ok(()),
+                    status => Err(anyhow!(
+                        "one-click unsubscribe failed, status code {status}",
+                        status = status.as_u16()
+                    )),
+                }
+            }
+            Self::Http { url, .. } | Self::Mailto(url) => open_url(url),
+        }
+    }
+}
+
+impl Display for Unsubscribe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mailto(url) => write!(f, "send an unsubscribe mail ({url})"),
+            Self::Http {
+                url,
+                one_click: true,
+            } => write!(f, "unsubscribe with one click ({url})"),
+            Self::Http { url, .. } => write!(f, "open the unsubscribe page ({url})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mailto() {
+        assert_eq!(
+            parse_list_unsubscribe(" <mailto:leave@list.example.com?subject=unsubscribe>", None),
+            vec![Unsubscribe::Mailto(String::from(
+                "mailto:leave@list.example.com?subject=unsubscribe"
+            ))]
+        );
+    }
+
+    #[test]
+    fn parses_http_before_mailto() {
+        let header = " <mailto:leave@list.example.com>,\r\n <https://list.example.com/u?id=1>";
+
+        assert_eq!(
+            parse_list_unsubscribe(header, Some(" List-Unsubscribe=One-Click")),
+            vec![
+                Unsubscribe::Http {
+                    url: String::from("https://list.example.com/u?id=1"),
+                    one_click: true
+                },
+                Unsubscribe::Mailto(String::from("mailto:leave@list.example.com")),
+            ]
+        );
+        assert_eq!(
+            parse_list_unsubscribe("<http://list.example.com/u>", None),
+            vec![Unsubscribe::Http {
+                url: String::from("http://list.example.com/u"),
+                one_click: false
+            }]
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_schemes() {
+        assert!(parse_list_unsubscribe("<ftp://example.com>, garbage", None).is_empty());
+        assert!(parse_list_unsubscribe("<javascript:alert(1)>, <file:///etc>", None).is_empty());
+        assert!(parse_list_unsubscribe("<https://exa\x01mple.com>", None).is_empty());
+    }
+}
@@ -1,6 +1,17 @@
-use std::path::PathBuf;
+use std::{
+    fs::{self, File},
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
 
 use anyhow::anyhow;
+use dialoguer::console::measure_text_width;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use tokio::{sync::Semaphore, task::JoinSet};
 
 /// writes user data to `user.toml` file creating all parent directories in the process
 pub fn get_data_dir_path() -> anyhow::Result<PathBuf> {
@@ -10,3 +21,282 @@ pub fn get_data_dir_path() -> anyhow::Result<PathBuf> {
         Err(anyhow!("failed to find home directory"))
     }
 }
+
+/// returns the longest prefix of `str` that is at most `max_bytes` long without splitting a
+/// UTF-8 character
+pub fn truncate_at_char_boundary(str: &str, max_bytes: usize) -> &str {
+    if str.len() <= max_bytes {
+        return str;
+    }
+
+    let end = (0..=max_bytes)
+        .rev()
+        .find(|idx| str.is_char_boundary(*idx))
+        .unwrap_or(0);
+
+    &str[..end]
+}
+
+/// shortens `str` to at most `width` terminal columns, ending it with an ellipsis if anything
+/// was cut off
+pub fn truncate_to_width(str: &str, width: usize) -> String {
+    if measure_text_width(str) <= width {
+        return str.to_owned();
+    }
+
+    let mut out = String::new();
+    let mut used = 0;
+    for char in str.chars() {
+        let char_width = measure_text_width(char.encode_utf8(&mut [0; 4]));
+        // leave room for the ellipsis
+        if used + char_width + 1 > width {
+            break;
+        }
+        out.push(char);
+        used += char_width;
+    }
+    if width > 0 {
+        out.push('…');
+    }
+
+    out
+}
+
+/// `name` (e.g. a mailbox name) usable as a single file name that no other name is turned into.
+///
+/// characters that could form a path, like the hierarchy delimiter, are replaced by `_` to keep
+/// it readable. the hash of `name` appended after a `-` tells apart names that only differ in
+/// those characters, or in case on file systems that ignore it
+pub fn file_name_safe(name: &str) -> String {
+    let readable = name
+        .chars()
+        .map(|c| match c.is_alphanumeric() || "-_ ".contains(c) {
+            true => c,
+            false => '_',
+        })
+        .collect::<String>();
+    let hash = Sha256::digest(name.as_bytes())
+        .iter()
+        .take(4)
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    format!("{readable}-{hash}")
+}
+
+/// writes `contents` to `path` by writing to a temporary file in the same directory first and
+/// renaming it afterwards, so `path` either has its old or its new contents even if the process
+/// is interrupted while writing
+pub fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let tmp_path = atomic_write_tmp_path(path);
+    let write = || -> anyhow::Result<()> {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    };
+
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+fn atomic_write_tmp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.{pid}.tmp", pid = std::process::id()))
+}
+
+/// `url` parsed, if it is an `http`, `https` or `mailto` url without whitespace or control
+/// characters. urls come from mails, so anything else is refused instead of being handed to
+/// the system
+pub fn checked_url(url: &str) -> anyhow::Result<Url> {
+    if url
+        .chars()
+        .any(|char| char.is_whitespace() || char.is_control())
+    {
+        return Err(anyhow!("refusing to open '{}'", url.escape_debug()));
+    }
+
+    let parsed = Url::parse(url).map_err(|err| anyhow!("invalid url '{url}': {err}"))?;
+    match parsed.scheme() {
+        "http" | "https" | "mailto" => Ok(parsed),
+        scheme => Err(anyhow!(
+            "refusing to open '{url}', unsupported scheme '{scheme}'"
+        )),
+    }
+}
+
+/// opens `url` with the systems default handler for its scheme, see [`checked_url`] for the
+/// urls that are allowed.
+///
+/// the url is passed as a single argument to a program that doesn't go through a shell, so
+/// characters like `&` in it are never interpreted
+pub fn open_url(url: &str) -> anyhow::Result<()> {
+    let url = checked_url(url)?;
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        Command::new("explorer.exe")
+    } else {
+        Command::new("xdg-open")
+    };
+
+    let status = command.arg(url.as_str()).status()?;
+    // `explorer.exe` exits with 1 even when it opened the url
+    match status.success() || cfg!(windows) {
+        true => Ok(()),
+        false => Err(anyhow!("failed to open '{url}'")),
+    }
+}
+
+/// runs the futures created by `task` for each of the `items` on the runtime, with at most
+/// `limit` of them running at the same time. the results are in the order of `items`
+pub async fn run_bounded<I, T, F, Fut>(
+    items: impl IntoIterator<Item = I>,
+    limit: usize,
+    task: F,
+) -> anyhow::Result<Vec<T>>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(limit.max(1)));
+    let mut tasks = JoinSet::new();
+    for (idx, item) in items.into_iter().enumerate() {
+        let semaphore = Arc::clone(&semaphore);
+        let future = task(item);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await?;
+            anyhow::Ok((idx, future.await))
+        });
+    }
+
+    let mut results = Vec::with_capacity(tasks.len());
+    while let Some(result) = tasks.join_next().await {
+        results.push(result??);
+    }
+    results.sort_by_key(|(idx, _)| *idx);
+
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn bounds_concurrent_tasks() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let results = run_bounded(0..10u64, 3, |idx| {
+            let running = Arc::clone(&running);
+            let max_running = Arc::clone(&max_running);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                // later items finish first, the results still keep the order of the items
+                tokio::time::sleep(Duration::from_millis(20 - 2 * idx)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+
+                idx * 2
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(results, (0..10).map(|idx| idx * 2).collect::<Vec<_>>());
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn truncation_respects_char_boundaries() {
+        assert_eq!(truncate_at_char_boundary("hello", 10), "hello");
+        assert_eq!(truncate_at_char_boundary("hello", 3), "hel");
+        // 'ä' is two bytes long, cutting after the first byte would split it
+        assert_eq!(truncate_at_char_boundary("häl", 2), "h");
+        assert_eq!(truncate_at_char_boundary("häl", 3), "hä");
+        assert_eq!(truncate_at_char_boundary("🦀🦀", 5), "🦀");
+        assert_eq!(truncate_at_char_boundary("🦀", 0), "");
+    }
+
+    #[test]
+    fn truncation_to_width_counts_columns() {
+        assert_eq!(truncate_to_width("hello", 5), "hello");
+        assert_eq!(truncate_to_width("hello", 4), "hel…");
+        assert_eq!(truncate_to_width("hello", 1), "…");
+        assert_eq!(truncate_to_width("hello", 0), "");
+        // '🦀' takes up two columns
+        assert_eq!(truncate_to_width("🦀🦀🦀", 5), "🦀🦀…");
+        assert_eq!(truncate_to_width("🦀🦀🦀", 4), "🦀…");
+    }
+}
+
+#[cfg(test)]
+mod atomic_write_tests {
+    use super::*;
+
+    #[test]
+    fn keeps_file_names_apart() {
+        let names = ["Lists/rust", "Lists_rust", "Lists.rust", "lists/rust"].map(file_name_safe);
+
+        assert!(names[0].starts_with("Lists_rust-"));
+        assert_eq!(names[0], file_name_safe("Lists/rust"));
+        assert!(names.iter().all(|name| !name.contains(['/', '\\', '.'])));
+        for (idx, name) in names.iter().enumerate() {
+            assert!(names[idx + 1..]
+                .iter()
+                .all(|other| !other.eq_ignore_ascii_case(name)));
+        }
+    }
+
+    #[test]
+    fn only_opens_web_and_mail_urls() {
+        assert_eq!(
+            checked_url("https://example.com/a?b=1&c=2")
+                .unwrap()
+                .as_str(),
+            "https://example.com/a?b=1&c=2"
+        );
+        assert!(checked_url("mailto:leave@list.example.com?subject=bye").is_ok());
+        assert!(checked_url("HTTP://example.com").is_ok());
+
+        assert!(checked_url("file:///etc/passwd").is_err());
+        assert!(checked_url("javascript:alert(1)").is_err());
+        assert!(checked_url("-a https://example.com").is_err());
+        assert!(checked_url("https://example.com/\r\nx").is_err());
+        assert!(checked_url("https://example.com/a b").is_err());
+        assert!(checked_url("no url").is_err());
+    }
+
+    #[test]
+    fn replaces_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.toml");
+        fs::write(&path, "old").unwrap();
+
+        write_atomically(&path, b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn failed_write_keeps_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.toml");
+        fs::write(&path, "old").unwrap();
+        // a directory in place of the temporary file makes creating it fail
+        fs::create_dir(atomic_write_tmp_path(&path)).unwrap();
+
+        assert!(write_atomically(&path, b"new").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use imap::Session;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::{
+    mail::{Mail, OpenMode},
+    utils::{get_data_dir_path, write_atomically},
+};
+
+/// minimum time between two polls or connection attempts, polling more often risks Gmail
+/// temporarily locking the account
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub static LAST_UID_CACHE_FILE_NAME: &str = "last_uids.toml";
+
+/// the highest UID seen in a mailbox, which only identifies the same mail as long as the
+/// mailbox keeps its `UIDVALIDITY`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastUid {
+    /// 0 if the server didn't send one
+    pub uid_validity: u32,
+    pub uid: u32,
+}
+
+/// the last UID `poll` saw in each mailbox by account, so a restarted `poll` reports the mails
+/// that arrived while it wasn't running
+#[derive(Debug, Clone)]
+pub struct LastUidCache {
+    path: PathBuf,
+    accounts: HashMap<String, HashMap<String, LastUid>>,
+}
+
+/// polls at `interval` while mails arrive and waits twice as long after each poll that found
+/// none or failed, up to `max_interval`
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    interval: Duration,
+    max_interval: Duration,
+    current: Duration,
+}
+
+/// enforces a minimum interval between attempts by sleeping until the interval since the
+/// previous attempt has passed
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    /// the interval the next attempt has to wait for after the previous one
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// waits until the next attempt is allowed
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.delay(Instant::now())).await;
+    }
+
+    /// how long an attempt at `now` has to wait, recording the attempt as happening after that
+    fn delay(&mut self, now: Instant) -> Duration {
+        let delay = self
+            .last
+            .map(|last| (last + self.interval).saturating_duration_since(now))
+            .unwrap_or_default();
+        self.last = Some(now + delay);
+
+        delay
+    }
+}
+
+impl Backoff {
+    pub fn new(interval: Duration, max_interval: Duration) -> Self {
+        Self {
+            interval,
+            max_interval: max_interval.max(interval),
+            current: interval,
+        }
+    }
+
+    /// how long to wait after a poll, which `found_new` mails or not
+    pub fn next(&mut self, found_new: bool) -> Duration {
+        let delay = match found_new {
+            true => self.interval,
+            false => self.current,
+        };
+        self.current = (delay * 2).min(self.max_interval);
+
+        delay
+    }
+}
+
+impl LastUidCache {
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(get_data_dir_path()?.join(LAST_UID_CACHE_FILE_NAME))
+    }
+
+    /// loads the cache at `path`, a missing or unreadable cache is treated as empty, which only
+    /// means the next poll starts from the newest mail
+    pub fn load_from(path: PathBuf) -> anyhow::Result<Self> {
+        let accounts = match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).unwrap_or_default(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self { path, accounts })
+    }
+
+    pub fn store(&self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        write_atomically(
+            &self.path,
+            toml::to_string_pretty(&self.accounts)?.as_bytes(),
+        )
+    }
+
+    pub fn get(&self, email: &str, mailbox: &str) -> Option<LastUid> {
+        self.accounts.get(email)?.get(mailbox).copied()
+    }
+
+    pub fn insert(&mut self, email: String, mailbox: String, last: LastUid) {
+        self.accounts
+            .entry(email)
+            .or_default()
+            .insert(mailbox, last);
+    }
+}
+
+/// fetches the mails in `mailbox` with a UID greater than the one of `last` and returns them
+/// together with the highest UID seen (0 for an empty mailbox).
+///
+/// without a `last` UID, or one from before the `UIDVALIDITY` of the mailbox changed, only the
+/// highest UID is returned so the first poll doesn't report the whole mailbox as new
+pub fn poll_new_mails<T: Read + Write>(
+    session: &mut Session<T>,
+    mailbox: &str,
+    last: Option<LastUid>,
+) -> anyhow::Result<(LastUid, Vec<anyhow::Result<Mail>>)> {
+    let uid_validity = OpenMode::ReadOnly
+        .open(mailbox, session)?
+        .uid_validity
+        .unwrap_or(0);
+    let last_uid = last
+        .filter(|last| last.uid_validity == uid_validity)
+        .map(|last| last.uid);
+
+    let query = match last_uid {
+        Some(uid) => format!("UID {}:*", uid + 1),
+        None => String::from("ALL"),
+    };
+    // `n:*` always matches the mail with the highest UID, even if it is lower than `n`
+    let uids = session
+        .uid_search(query)?
+        .into_iter()
+        .filter(|uid| last_uid.is_none_or(|last| *uid > last))
+        .sorted()
+        .collect::<Vec<_>>();
+
+    let highest = LastUid {
+        uid_validity,
+        uid: uids.last().copied().or(last_uid).unwrap_or_default(),
+    };
+    if last_uid.is_none() || uids.is_empty() {
+        return Ok((highest, Vec::new()));
+    }
+
+    let items = session.uid_fetch(uids.iter().join(","), "(UID FLAGS BODY.PEEK[])")?;
+    Ok((highest, items.iter().map(Mail::from_fetch).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_imap::{mock_session, MockMail};
+
+    #[test]
+    fn throttles_attempts() {
+        let mut throttle = Throttle::new(Duration::from_secs(30));
+        let start = Instant::now();
+
+        assert_eq!(throttle.delay(start), Duration::ZERO);
+        assert_eq!(
+            throttle.delay(start + Duration::from_secs(10)),
+            Duration::from_secs(20)
+        );
+        // the previous attempt only happens after its delay
+        assert_eq!(
+            throttle.delay(start + Duration::from_secs(40)),
+            Duration::from_secs(20)
+        );
+        assert_eq!(
+            throttle.delay(start + Duration::from_secs(100)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn reports_only_new_mails() {
+        let mail = |subject: &str| MockMail::new(&format!("Subject: {subject}\n\nbody\n"));
+        let (mut session, _) = mock_session(vec![("INBOX", vec![mail("a"), mail("b")])]);
+
+        let last = |uid| LastUid {
+            uid_validity: 1,
+            uid,
+        };
+
+        let (last_uid, mails) = poll_new_mails(&mut session, "INBOX", None).unwrap();
+        assert_eq!(last_uid, last(2));
+        assert!(mails.is_empty());
+
+        let (last_uid, mails) = poll_new_mails(&mut session, "INBOX", Some(last(1))).unwrap();
+        assert_eq!(last_uid, last(2));
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0]
+            .as_ref()
+            .unwrap()
+            .to_string()
+            .contains("Subject:    b"));
+
+        let (last_uid, mails) = poll_new_mails(&mut session, "INBOX", Some(last(2))).unwrap();
+        assert_eq!(last_uid, last(2));
+        assert!(mails.is_empty());
+
+        // the UIDs were reassigned, so 1 may not be the mail it was before
+        let stale = LastUid {
+            uid_validity: 7,
+            uid: 1,
+        };
+        let (last_uid, mails) = poll_new_mails(&mut session, "INBOX", Some(stale)).unwrap();
+        assert_eq!(last_uid, last(2));
+        assert!(mails.is_empty());
+    }
+
+    #[test]
+    fn detects_new_mails_over_successive_polls() {
+        let mail = |subject: &str| MockMail::new(&format!("Subject: {subject}\n\nbody\n"));
+        let subjects = |mails: Vec<anyhow::Result<Mail>>| {
+            mails
+                .into_iter()
+                .map(|mail| mail.unwrap().subject().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LAST_UID_CACHE_FILE_NAME);
+
+        // a first run only records where the mailbox ends
+        let (mut session, _) = mock_session(vec![("INBOX", vec![mail("a")])]);
+        let mut cache = LastUidCache::load_from(path.clone()).unwrap();
+        let (last, mails) =
+            poll_new_mails(&mut session, "INBOX", cache.get("a@b.c", "INBOX")).unwrap();
+        assert!(mails.is_empty());
+        cache.insert(String::from("a@b.c"), String::from("INBOX"), last);
+        cache.store().unwrap();
+
+        // a later run picks up where it stopped, with the mails that arrived in between
+        let (mut session, _) = mock_session(vec![("INBOX", vec![mail("a"), mail("b"), mail("c")])]);
+        let cache = LastUidCache::load_from(path).unwrap();
+        assert!(cache.get("a@b.c", "Other").is_none());
+        let (last, mails) =
+            poll_new_mails(&mut session, "INBOX", cache.get("a@b.c", "INBOX")).unwrap();
+        assert_eq!(subjects(mails), ["b", "c"]);
+        assert_eq!(last.uid, 3);
+        assert_eq!(
+            poll_new_mails(&mut session, "INBOX", Some(last))
+                .unwrap()
+                .1
+                .len(),
+            0
+        );
+    }
+
+    #[test]
+    fn backs_off_while_no_mails_arrive() {
+        let mut backoff = Backoff::new(Duration::from_secs(30), Duration::from_secs(100));
+        let delays = [false, false, false, false, true, false]
+            .map(|found_new| backoff.next(found_new).as_secs());
+
+        assert_eq!(delays, [30, 60, 100, 100, 30, 60]);
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::anyhow;
+use reqwest::{Client, StatusCode, Url};
+use serde::Deserialize;
+
+use crate::{
+    provider::{OAuthProvider, RefreshedToken},
+    proxy::http_client,
+};
+
+pub static YAHOO_AUTH_URL: &str = "https://api.login.yahoo.com/oauth2/request_auth";
+pub static YAHOO_TOKEN_URL: &str = "https://api.login.yahoo.com/oauth2/get_token";
+pub static YAHOO_IMAP_DOMAIN: &str = "imap.mail.yahoo.com";
+pub static YAHOO_IMAP_PORT: u16 = 993;
+
+/// Yahoo responds with a new refresh token to both token requests and refreshes, but the old
+/// one stays valid so only the access token is used after a refresh
+#[derive(Debug, Clone, Deserialize)]
+pub struct YahooOAuthTokenResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// seconds until the access token expires
+    pub expires_in: Option<i64>,
+    /// the space separated scopes the user granted, if Yahoo says
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+/// [`OAuthProvider`] for Yahoo Mail accounts, loads its [`YahooOAuthParams`] only once a token
+/// needs to be refreshed
+#[derive(Debug, Clone, Copy)]
+pub struct Yahoo;
+
+#[derive(Debug, Clone)]
+pub struct YahooOAuthParams {
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+    scopes: String,
+}
+
+impl Default for YahooOAuthParams {
+    /// loads `client_id` and `client_secret` from `.env` file
+    ///
+    /// Panics:
+    /// - if it can't load the `YAHOO_CLIENT_ID` or `YAHOO_CLIENT_SECRET` environment variables
+    fn default() -> Self {
+        let client_id = dotenv::var("YAHOO_CLIENT_ID").expect("failed to load YAHOO_CLIENT_ID");
+        let client_secret =
+            dotenv::var("YAHOO_CLIENT_SECRET").expect("failed to load YAHOO_CLIENT_SECRET");
+
+        Self {
+            client_id,
+            client_secret,
+            redirect_url: "oob".to_owned(),
+            scopes: "mail-w".to_owned(),
+        }
+    }
+}
+
+impl YahooOAuthParams {
+    /// requests `scopes` (space separated) instead of `mail-w`
+    pub fn with_scopes(mut self, scopes: String) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    pub fn scopes(&self) -> &str {
+        &self.scopes
+    }
+
+    pub fn to_form_request_params<'a>(&'a self, auth_code: &'a str) -> [(&'a str, &'a str); 5] {
+        [
+            ("grant_type", "authorization_code"),
+            ("redirect_uri", &self.redirect_url),
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret),
+            ("code", auth_code),
+        ]
+    }
+
+    pub fn to_form_refresh_params<'a>(&'a self, refresh_token: &'a str) -> [(&'a str, &'a str); 5] {
+        [
+            ("grant_type", "refresh_token"),
+            ("redirect_uri", &self.redirect_url),
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret),
+            ("refresh_token", refresh_token),
+        ]
+    }
+
+    /// the consent page to get an authorization code from, `login_hint` preselects the account
+    /// with that mail address
+    pub fn get_token_request_url(&self, login_hint: Option<&str>) -> String {
+        let mut url = Url::parse(YAHOO_AUTH_URL).expect("YAHOO_AUTH_URL is a valid URL");
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_url)
+            .append_pair("response_type", "code")
+            .append_pair("scope", &self.scopes);
+        if let Some(login_hint) = login_hint {
+            url.query_pairs_mut().append_pair("login_hint", login_hint);
+        }
+
+        url.to_string()
+    }
+}
+
+async fn request_yahoo_token(
+    client: &Client,
+    form: &[(&str, &str)],
+) -> anyhow::Result<YahooOAuthTokenResponse> {
+    let res = client.post(YAHOO_TOKEN_URL).form(form).send().await?;
+
+    match res.status() {
+        StatusCode::OK => Ok(res.json().await?),
+        _ => Err(anyhow!(
+            "an error occurred while trying to retrieve access token, status code {status}",
+            status = res.status().as_u16(),
+        )),
+    }
+}
+
+pub async fn request_yahoo_oauth_token(
+    client: &Client,
+    auth_params: &YahooOAuthParams,
+    auth_code: &str,
+) -> anyhow::Result<YahooOAuthTokenResponse> {
+    request_yahoo_token(client, &auth_params.to_form_request_params(auth_code)).await
+}
+
+pub async fn refresh_yahoo_oauth_token(
+    client: &Client,
+    auth_params: &YahooOAuthParams,
+    refresh_token: &str,
+) -> anyhow::Result<YahooOAuthTokenResponse> {
+    request_yahoo_token(client, &auth_params.to_form_refresh_params(refresh_token)).await
+}
+
+impl OAuthProvider for Yahoo {
+    fn imap_host(&self) -> (&str, u16) {
+        (YAHOO_IMAP_DOMAIN, YAHOO_IMAP_PORT)
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> anyhow::Result<RefreshedToken> {
+        let YahooOAuthTokenResponse {
+            access_token,
+            expires_in,
+            ..
+        } = refresh_yahoo_oauth_token(&http_client()?, &YahooOAuthParams::default(), refresh_token)
+            .await?;
+
+        Ok(RefreshedToken {
+            access_token,
+            expires_in,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_yahoo_endpoints() {
+        assert_eq!(Yahoo.imap_host(), ("imap.mail.yahoo.com", 993));
+
+        let params = YahooOAuthParams {
+            client_id: String::from("id"),
+            client_secret: String::from("secret"),
+            redirect_url: String::from("oob"),
+            scopes: String::from("mail-w"),
+        };
+
+        let url = params.get_token_request_url(Some("a@yahoo.com"));
+        assert_eq!(
+            url,
+            "https://api.login.yahoo.com/oauth2/request_auth?client_id=id&redirect_uri=oob\
+            &response_type=code&scope=mail-w&login_hint=a%40yahoo.com"
+        );
+        assert!(params
+            .to_form_refresh_params("token")
+            .contains(&("redirect_uri", "oob")));
+        assert_eq!(
+            YAHOO_TOKEN_URL,
+            "https://api.login.yahoo.com/oauth2/get_token"
+        );
+    }
+}
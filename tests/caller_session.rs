@@ -0,0 +1,81 @@
+//! reading mails through the public API over a session the caller connected and authenticated
+
+use std::io::{Read, Write};
+
+use mail_cli::{
+    mock_imap::{connect, MockImapStream, MockMail},
+    MailBox,
+};
+
+/// a transport of the caller the crate knows nothing about
+struct CallerStream(MockImapStream);
+
+impl Read for CallerStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for CallerStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+struct Plain;
+
+impl imap::Authenticator for Plain {
+    type Response = &'static str;
+    fn process(&self, _: &[u8]) -> Self::Response {
+        "\0user\0password"
+    }
+}
+
+fn mock_mail(subject: &str, date: &str) -> MockMail {
+    MockMail::new(&format!(
+        "From: Alice <alice@example.com>\nTo: bob@example.com\nDate: {date}\nSubject: {subject}\n\n{subject} body\n"
+    ))
+}
+
+#[test]
+fn reads_over_session_of_caller() {
+    let stream = MockImapStream::new(vec![
+        ("INBOX", Vec::new()),
+        (
+            "Lists",
+            vec![
+                mock_mail("second", "Tue, 2 May 2023 10:00:00 +0000"),
+                mock_mail("first", "Mon, 1 May 2023 10:00:00 +0000"),
+                mock_mail("third", "Wed, 3 May 2023 09:00:00 +0200"),
+            ],
+        ),
+    ]);
+    let commands = stream.command_log();
+    let mut session = connect(CallerStream(stream))
+        .authenticate("PLAIN", &Plain)
+        .map_err(|(err, _)| err)
+        .unwrap();
+    session.select("INBOX").unwrap();
+
+    let mails = MailBox::new("Lists")
+        .fetch_n_recent_mails(2, &mut session)
+        .unwrap();
+    let subjects = mails
+        .iter()
+        .map(|mail| mail.as_ref().unwrap().subject().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(subjects, ["third", "second"]);
+
+    // the crate only switched to its mailbox, the session is still the caller's
+    session.noop().unwrap();
+    let commands = commands.borrow();
+    assert!(commands.contains(&String::from("EXAMINE \"Lists\"")));
+    assert!(!commands
+        .iter()
+        .any(|command| command.starts_with("LOGIN") || command.starts_with("LOGOUT")));
+    assert_eq!(commands.last().unwrap(), "NOOP");
+}